@@ -0,0 +1,31 @@
+// Times PpuState::render_ntsc() at a few output widths. Run it with and without the
+// "simd" feature to compare the two decode paths; the checksum of the last frame
+// shows whether they still agree.
+
+use std::time::Instant;
+
+use rusticnes_core::ppu::PpuState;
+
+const FRAMES: u32 = 300;
+
+fn main() {
+    let mut ppu = PpuState::new();
+    // Every color and emphasis combination, in runs so the filter sees both edges and
+    // flat areas
+    for (i, pixel) in ppu.screen.iter_mut().enumerate() {
+        *pixel = ((i / 3) % 512) as u16;
+    }
+    let path = if cfg!(all(feature = "simd", target_arch = "x86_64")) {"simd"} else {"scalar"};
+    for &width in [256, 602, 1024, 2048].iter() {
+        ppu.render_ntsc(width);
+        let start = Instant::now();
+        for frame in 0 .. FRAMES {
+            ppu.frame_starting_cycle = frame as usize % 3;
+            ppu.render_ntsc(width);
+        }
+        let per_frame = start.elapsed() / FRAMES;
+        let checksum = ppu.filtered_screen[.. width * 240].iter()
+            .fold(0u64, |sum, &pixel| sum.wrapping_mul(31).wrapping_add(pixel as u64));
+        println!("{:>6} width {:>4}: {:>9.3?} per frame, checksum {:016X}", path, width, per_frame, checksum);
+    }
+}
@@ -0,0 +1,81 @@
+// A stable, side effect free view of memory for achievement runtimes. The flat
+// address space matches the layout rcheevos uses for the NES, which is simply the
+// CPU address space: $0000-$07FF system RAM (mirrored up to $1FFF), registers,
+// cartridge RAM at $6000 and ROM at $8000. Frontends hand peek() to rcheevos as
+// its memory callback and evaluate achievements from a frame hook.
+
+use crate::memory::debug_read_byte;
+use crate::nes::NesState;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MemoryRegionType {
+    SystemRam,
+    // Mirrors of other regions, which achievement sets shouldn't reference directly
+    VirtualRam,
+    HardwareController,
+    SaveRam,
+    ReadOnly,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryRegion {
+    pub start: u32,
+    pub end: u32,
+    pub region_type: MemoryRegionType,
+    pub description: &'static str,
+}
+
+pub const NES_MEMORY_REGIONS: [MemoryRegion; 11] = [
+    MemoryRegion{start: 0x0000, end: 0x07FF, region_type: MemoryRegionType::SystemRam, description: "System RAM"},
+    MemoryRegion{start: 0x0800, end: 0x0FFF, region_type: MemoryRegionType::VirtualRam, description: "Mirror RAM"},
+    MemoryRegion{start: 0x1000, end: 0x17FF, region_type: MemoryRegionType::VirtualRam, description: "Mirror RAM"},
+    MemoryRegion{start: 0x1800, end: 0x1FFF, region_type: MemoryRegionType::VirtualRam, description: "Mirror RAM"},
+    MemoryRegion{start: 0x2000, end: 0x2007, region_type: MemoryRegionType::HardwareController, description: "PPU Register"},
+    MemoryRegion{start: 0x2008, end: 0x3FFF, region_type: MemoryRegionType::VirtualRam, description: "Mirrored PPU Register"},
+    MemoryRegion{start: 0x4000, end: 0x4017, region_type: MemoryRegionType::HardwareController, description: "APU and I/O register"},
+    MemoryRegion{start: 0x4018, end: 0x401F, region_type: MemoryRegionType::HardwareController, description: "APU and I/O test register"},
+    MemoryRegion{start: 0x4020, end: 0x5FFF, region_type: MemoryRegionType::ReadOnly, description: "Cartridge data"},
+    MemoryRegion{start: 0x6000, end: 0x7FFF, region_type: MemoryRegionType::SaveRam, description: "Cartridge RAM"},
+    MemoryRegion{start: 0x8000, end: 0xFFFF, region_type: MemoryRegionType::ReadOnly, description: "Cartridge ROM"},
+];
+
+pub const FLAT_MEMORY_SIZE: u32 = 0x10000;
+
+// Reads up to 4 bytes, little endian, as rcheevos' peek callback expects.
+// Out of range addresses read as 0.
+pub fn peek(nes: &NesState, address: u32, num_bytes: u32) -> u32 {
+    let mut value = 0u32;
+    for i in 0 .. num_bytes.min(4) {
+        let byte_address = address + i;
+        if byte_address >= FLAT_MEMORY_SIZE {
+            break;
+        }
+        value |= (debug_read_byte(nes, byte_address as u16) as u32) << (8 * i);
+    }
+    return value;
+}
+
+// Bulk version of peek, for runtimes that prefer to copy whole regions
+pub fn read_block(nes: &NesState, address: u32, buffer: &mut [u8]) -> usize {
+    let mut count = 0;
+    for (i, byte) in buffer.iter_mut().enumerate() {
+        let byte_address = address + i as u32;
+        if byte_address >= FLAT_MEMORY_SIZE {
+            break;
+        }
+        *byte = debug_read_byte(nes, byte_address as u16);
+        count += 1;
+    }
+    return count;
+}
+
+// The 2k of system RAM, for runtimes which want a direct pointer-like view
+pub fn system_ram(nes: &NesState) -> &[u8] {
+    return &nes.memory.iram_raw;
+}
+
+// All of cartridge RAM, including banks not currently mapped at $6000. Empty for
+// mappers without any.
+pub fn cartridge_ram(nes: &NesState) -> Vec<u8> {
+    return nes.mapper.get_sram();
+}
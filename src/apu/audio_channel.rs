@@ -4,25 +4,49 @@
 
 use super::RingBuffer;
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum PlaybackRate {
     FundamentalFrequency { frequency: f32 },
     LfsrRate { index: usize, max: usize },
     SampleRate { frequency: f32 },
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum Volume {
     VolumeIndex { index: usize, max: usize },
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum Timbre {
     DutyIndex { index: usize, max: usize },
     LsfrMode { index: usize, max: usize },
     PatchIndex { index: usize, max: usize },
 }
 
+#[derive(Clone)]
+pub struct LfsrState {
+    pub shift_register: u16,
+    // Steps before the output repeats, starting from the current state
+    pub sequence_length: u16,
+}
+
+// A 2A03 style volume envelope, as FamiTracker's volume column and the $4000/$400C
+// writes set it up
+#[derive(Clone, PartialEq, Debug)]
+pub struct EnvelopeState {
+    // The low 4 bits of the register: the envelope's period, or the volume itself
+    // when constant_volume is set
+    pub period: u8,
+    pub constant_volume: bool,
+    pub looping: bool,
+    // Counts down from period; the decay level steps down each time it passes 0
+    pub divider: u8,
+    pub decay_level: u8,
+    // Set by a note starting, and cleared by the next quarter frame, which restarts
+    // the decay at 15
+    pub start_flag: bool,
+}
+
 pub trait AudioChannelState {
     fn name(&self) -> String;
     fn chip(&self) -> String;
@@ -36,11 +60,23 @@ pub trait AudioChannelState {
     fn muted(&self) -> bool;
     fn mute(&mut self);
     fn unmute(&mut self);
+    // Volume multiplier applied when mixing, 1.0 by default. Chips that only mix their
+    // channels as a whole (N163, 5B) ignore it.
+    fn gain(&self) -> f32 {return 1.0;}
+    fn set_gain(&mut self, _gain: f32) {}
 
     fn playing(&self) -> bool { return false; }
     fn rate(&self) -> PlaybackRate { return PlaybackRate::SampleRate{frequency: 0.0}; }
     fn volume(&self) -> Option<Volume> {return None}
     fn timbre(&self) -> Option<Timbre> {return None}
+    // For noise channels driven by a linear feedback shift register
+    fn lfsr(&self) -> Option<LfsrState> {return None}
+    // For pulse channels with a sweep unit: true while it silences the channel because
+    // the period, or the one it would sweep to, is out of range. The channel can look
+    // busy in every register and still output nothing.
+    fn sweep_muted(&self) -> bool {return false}
+    // For channels with a 2A03 style volume envelope
+    fn envelope(&self) -> Option<EnvelopeState> {return None}
     fn amplitude(&self) -> f32 {
         /* pre-mixed volume, allows chips using non-linear mixing to tailor this value.
            results should be based on 2A03 pulse, where 1.0 corresponds to 0xF */
@@ -0,0 +1,107 @@
+// Destinations for finished audio. The APU hands output samples to its sink in
+// small batches, independently of consume_samples(), so a sink sees the whole
+// stream whether or not the frontend also polls.
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufWriter, SeekFrom};
+
+pub trait AudioSink: Send {
+    // Samples at the APU's sample rate, in order; interleaved left / right pairs when
+    // the APU is in stereo mode
+    fn push_samples(&mut self, samples: &[i16]);
+    // Called when samples queued for consume_samples() go uncollected for so long
+    // that the oldest are dropped, ie the frontend is falling behind.
+    fn buffer_full(&mut self) {}
+}
+
+// Discards everything
+pub struct NullSink {}
+
+impl NullSink {
+    pub fn new() -> NullSink {
+        return NullSink {};
+    }
+}
+
+impl AudioSink for NullSink {
+    fn push_samples(&mut self, _samples: &[i16]) {}
+}
+
+const WAV_HEADER_SIZE: u32 = 44;
+
+// Writes 16-bit PCM to a .wav file. The sizes in the header are filled in by
+// finish(), or when the sink is dropped.
+pub struct WavFileSink {
+    writer: BufWriter<File>,
+    sample_rate: u32,
+    channels: u16,
+    samples_written: u32,
+}
+
+impl WavFileSink {
+    // Mono
+    pub fn new(filename: &str, sample_rate: u32) -> io::Result<WavFileSink> {
+        return WavFileSink::with_channels(filename, sample_rate, 1);
+    }
+
+    // Use 2 channels for an APU in stereo mode
+    pub fn with_channels(filename: &str, sample_rate: u32, channels: u16) -> io::Result<WavFileSink> {
+        let file = File::create(filename)?;
+        let mut sink = WavFileSink {
+            writer: BufWriter::new(file),
+            sample_rate: sample_rate,
+            channels: channels,
+            samples_written: 0,
+        };
+        sink.write_header()?;
+        return Ok(sink);
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let data_size = self.samples_written * 2;
+        let block_align = self.channels * 2;
+        let byte_rate = self.sample_rate * block_align as u32;
+        self.writer.write_all(b"RIFF")?;
+        self.writer.write_all(&(WAV_HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        self.writer.write_all(b"WAVEfmt ")?;
+        self.writer.write_all(&16u32.to_le_bytes())?;
+        self.writer.write_all(&1u16.to_le_bytes())?; // PCM
+        self.writer.write_all(&self.channels.to_le_bytes())?;
+        self.writer.write_all(&self.sample_rate.to_le_bytes())?;
+        self.writer.write_all(&byte_rate.to_le_bytes())?;
+        self.writer.write_all(&block_align.to_le_bytes())?;
+        self.writer.write_all(&16u16.to_le_bytes())?; // bits per sample
+        self.writer.write_all(b"data")?;
+        self.writer.write_all(&data_size.to_le_bytes())?;
+        return Ok(());
+    }
+
+    // Patches the header with the final sizes. Safe to call more than once; more
+    // samples can still be pushed afterwards.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.writer.seek(SeekFrom::End(0))?;
+        return self.writer.flush();
+    }
+}
+
+impl AudioSink for WavFileSink {
+    fn push_samples(&mut self, samples: &[i16]) {
+        for sample in samples {
+            if self.writer.write_all(&sample.to_le_bytes()).is_err() {
+                println!("WavFileSink: write failed, dropping audio");
+                return;
+            }
+            self.samples_written += 1;
+        }
+    }
+}
+
+impl Drop for WavFileSink {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
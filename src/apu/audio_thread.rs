@@ -0,0 +1,278 @@
+// Runs the output path (filtering and resampling) on a worker thread, so an
+// expensive filter chain costs audio latency rather than emulation time. The
+// channels themselves still run on the emulation thread: the CPU reads their length
+// counters and IRQ flags back, and expansion audio lives in the mapper. What crosses
+// over is each cycle's DAC level, in batches. Finished samples come back through a
+// lock-free ring that an audio callback can drain without ever blocking on the
+// emulator.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI16, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
+use std::thread::JoinHandle;
+
+use super::OutputPath;
+
+// CPU cycles per batch; about half a millisecond
+const BATCH_SIZE: usize = 1024;
+// Batches in flight before the emulation thread waits for the worker to catch up
+const MAX_QUEUED_BATCHES: usize = 64;
+
+// One CPU cycle of DAC output. right is unused in mono.
+#[derive(Clone, Copy)]
+struct DacLevel {
+    left: f32,
+    right: f32,
+    nearest_due: bool,
+}
+
+enum WorkerMessage {
+    // Filtered and resampled, then dropped rather than output if muted
+    Levels{levels: Vec<DacLevel>, muted: bool},
+    // New filter settings or channel count; the right path is only present in stereo
+    Reconfigure{left: OutputPath, right: Option<OutputPath>},
+}
+
+// Single producer, single consumer. Indices only ever count up; the slot for index n
+// is n % slots.len(). The producer owns write_index and the consumer read_index, so
+// neither side waits on the other.
+struct SampleRing {
+    slots: Vec<AtomicI16>,
+    read_index: AtomicUsize,
+    write_index: AtomicUsize,
+    // The producer can't move read_index, so to throw away what's queued it sets this,
+    // and the consumer skips ahead to it
+    discard_index: AtomicUsize,
+    channels: AtomicUsize,
+    samples_dropped: AtomicU64,
+    worker_alive: AtomicBool,
+}
+
+impl SampleRing {
+    fn new(capacity: usize) -> SampleRing {
+        let mut slots = Vec::with_capacity(capacity);
+        for _ in 0 .. capacity {
+            slots.push(AtomicI16::new(0));
+        }
+        return SampleRing {
+            slots: slots,
+            read_index: AtomicUsize::new(0),
+            write_index: AtomicUsize::new(0),
+            discard_index: AtomicUsize::new(0),
+            channels: AtomicUsize::new(1),
+            samples_dropped: AtomicU64::new(0),
+            worker_alive: AtomicBool::new(true),
+        };
+    }
+
+    // Where the consumer reads from next, after skipping anything discarded
+    fn next_read(&self, read_index: usize) -> usize {
+        let discard_index = self.discard_index.load(Ordering::Acquire);
+        return if (discard_index.wrapping_sub(read_index) as isize) > 0 {discard_index} else {read_index};
+    }
+
+    fn available(&self) -> usize {
+        let write_index = self.write_index.load(Ordering::Acquire);
+        let read_index = self.next_read(self.read_index.load(Ordering::Acquire));
+        return write_index.wrapping_sub(read_index);
+    }
+
+    // Producer side: everything pushed so far is dropped unread
+    fn discard(&self) {
+        let write_index = self.write_index.load(Ordering::Relaxed);
+        self.discard_index.store(write_index, Ordering::Release);
+    }
+
+    // Whatever doesn't fit is dropped, in whole left / right pairs when in stereo
+    fn push(&self, samples: &[i16]) {
+        let channels = self.channels.load(Ordering::Relaxed);
+        let read_index = self.read_index.load(Ordering::Acquire);
+        let write_index = self.write_index.load(Ordering::Relaxed);
+        let free = self.slots.len() - write_index.wrapping_sub(read_index);
+        let count = samples.len().min(free / channels * channels);
+        for i in 0 .. count {
+            let slot = write_index.wrapping_add(i) % self.slots.len();
+            self.slots[slot].store(samples[i], Ordering::Relaxed);
+        }
+        self.write_index.store(write_index.wrapping_add(count), Ordering::Release);
+        if count < samples.len() {
+            self.samples_dropped.fetch_add((samples.len() - count) as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn pop(&self, output: &mut [i16]) -> usize {
+        let write_index = self.write_index.load(Ordering::Acquire);
+        let read_index = self.next_read(self.read_index.load(Ordering::Relaxed));
+        let count = output.len().min(write_index.wrapping_sub(read_index));
+        for i in 0 .. count {
+            let slot = read_index.wrapping_add(i) % self.slots.len();
+            output[i] = self.slots[slot].load(Ordering::Relaxed);
+        }
+        self.read_index.store(read_index.wrapping_add(count), Ordering::Release);
+        return count;
+    }
+}
+
+// The audio callback's end of the worker's output. Never blocks or allocates.
+pub struct SampleConsumer {
+    ring: Arc<SampleRing>,
+}
+
+// It's handed to the audio callback, which usually runs on a thread of its own
+const fn assert_send<T: Send>() {}
+const _: () = assert_send::<SampleConsumer>();
+
+impl SampleConsumer {
+    // Copies as many of the oldest samples as fit into output, returning the number
+    // copied. In stereo, samples are interleaved left / right pairs; pass an even
+    // length to keep them together.
+    pub fn drain_samples(&self, output: &mut [i16]) -> usize {
+        return self.ring.pop(output);
+    }
+
+    pub fn samples_available(&self) -> usize {
+        return self.ring.available();
+    }
+
+    // 2 while the APU is in stereo mode
+    pub fn channels(&self) -> usize {
+        return self.ring.channels.load(Ordering::Relaxed);
+    }
+
+    // Samples thrown away because the ring was full, ie the callback fell behind
+    pub fn samples_dropped(&self) -> u64 {
+        return self.ring.samples_dropped.load(Ordering::Relaxed);
+    }
+
+    // False once the worker has stopped, either with the APU's audio thread or early,
+    // if it panicked. No more samples will arrive; ApuState::start_audio_thread()
+    // starts a fresh one.
+    pub fn worker_alive(&self) -> bool {
+        return self.ring.worker_alive.load(Ordering::Acquire);
+    }
+}
+
+// Marks the worker stopped however it ends, panics included
+struct WorkerExit {
+    ring: Arc<SampleRing>,
+}
+
+impl Drop for WorkerExit {
+    fn drop(&mut self) {
+        self.ring.worker_alive.store(false, Ordering::Release);
+    }
+}
+
+fn run_worker(receiver: Receiver<WorkerMessage>, ring: Arc<SampleRing>, mut left_path: OutputPath, mut right_path: Option<OutputPath>) {
+    let _exit = WorkerExit{ring: ring.clone()};
+    let mut output: Vec<i16> = Vec::new();
+    // Runs until the sender is dropped
+    for message in receiver.iter() {
+        match message {
+            WorkerMessage::Levels{levels, muted} => {
+                for level in levels.iter() {
+                    left_path.clock(level.left, level.nearest_due);
+                    if let Some(right_path) = right_path.as_mut() {
+                        right_path.clock(level.right, level.nearest_due);
+                    }
+                    while let Some(sample) = left_path.pop() {
+                        output.push((sample * 32767.0) as i16);
+                        if let Some(right_path) = right_path.as_mut() {
+                            let right_sample = right_path.pop().unwrap_or(0.0);
+                            output.push((right_sample * 32767.0) as i16);
+                        }
+                    }
+                }
+                if !muted {
+                    ring.push(&output);
+                }
+                output.clear();
+            },
+            WorkerMessage::Reconfigure{left, right} => {
+                let channels = if right.is_some() {2} else {1};
+                if channels != ring.channels.load(Ordering::Relaxed) {
+                    // Samples still queued in the old format would be read as the new one
+                    ring.discard();
+                    ring.channels.store(channels, Ordering::Release);
+                }
+                left_path = left;
+                right_path = right;
+            }
+        }
+    }
+}
+
+pub struct AudioThread {
+    sender: Option<SyncSender<WorkerMessage>>,
+    worker: Option<JoinHandle<()>>,
+    batch: Vec<DacLevel>,
+    batch_muted: bool,
+}
+
+impl AudioThread {
+    // capacity is the size of the output ring in samples, counting both halves of a
+    // stereo pair
+    pub fn new(capacity: usize, left_path: OutputPath, right_path: Option<OutputPath>) -> (AudioThread, SampleConsumer) {
+        let ring = Arc::new(SampleRing::new(capacity));
+        ring.channels.store(if right_path.is_some() {2} else {1}, Ordering::Relaxed);
+        let (sender, receiver) = sync_channel(MAX_QUEUED_BATCHES);
+        let worker_ring = ring.clone();
+        let worker = thread::spawn(move || run_worker(receiver, worker_ring, left_path, right_path));
+        let audio_thread = AudioThread {
+            sender: Some(sender),
+            worker: Some(worker),
+            batch: Vec::with_capacity(BATCH_SIZE),
+            batch_muted: false,
+        };
+        return (audio_thread, SampleConsumer{ring: ring});
+    }
+
+    pub fn push(&mut self, left: f32, right: f32, nearest_due: bool, muted: bool) {
+        if muted != self.batch_muted {
+            self.flush();
+            self.batch_muted = muted;
+        }
+        self.batch.push(DacLevel{left: left, right: right, nearest_due: nearest_due});
+        if self.batch.len() >= BATCH_SIZE {
+            self.flush();
+        }
+    }
+
+    // Hands any partial batch to the worker, ie when pausing
+    pub fn flush(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+        let levels = std::mem::replace(&mut self.batch, Vec::with_capacity(BATCH_SIZE));
+        self.send(WorkerMessage::Levels{levels: levels, muted: self.batch_muted});
+    }
+
+    // Takes effect after everything already pushed has gone through the old paths
+    pub fn reconfigure(&mut self, left_path: OutputPath, right_path: Option<OutputPath>) {
+        self.flush();
+        self.send(WorkerMessage::Reconfigure{left: left_path, right: right_path});
+    }
+
+    fn send(&mut self, message: WorkerMessage) {
+        let disconnected = match self.sender.as_ref() {
+            Some(sender) => sender.send(message).is_err(),
+            None => false,
+        };
+        if disconnected {
+            println!("Audio thread stopped unexpectedly");
+            self.sender = None;
+        }
+    }
+}
+
+impl Drop for AudioThread {
+    fn drop(&mut self) {
+        self.flush();
+        // Closing the channel ends the worker's loop
+        self.sender = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,84 @@
+// Band-limited step synthesis, in the style of blargg's blip_buf. Rather than
+// sampling the mixer output every CPU cycle and filtering 1.7 MHz down to the output
+// rate, each change in amplitude is drawn straight into an output rate buffer as a
+// band-limited step, positioned at the exact cycle it happened. Hard pulse and noise
+// edges then come out without the aliasing buzz of naive sampling, and the rest of the
+// filter chain only has to run at the output rate.
+// Reference: http://www.slack.net/~ant/bl-synth/
+
+use super::resampler::{kernel_table, HALF_TAPS, PHASES, TAPS};
+
+// Pending deltas; needs to cover the kernel plus however far reads fall behind writes
+const BUFFER_SIZE: usize = TAPS * 4;
+
+pub struct BlipBuffer {
+    // Output samples per CPU cycle
+    samples_per_clock: f64,
+    // Impulse responses; summing them gives the step
+    kernel: Vec<f32>,
+    // Deltas for output sample n accumulate at n % BUFFER_SIZE
+    deltas: Vec<f32>,
+    // The current CPU cycle, in output samples since the start
+    time: f64,
+    // Next output sample to be read
+    read_position: u64,
+    integrator: f32,
+    amplitude: f32,
+}
+
+impl BlipBuffer {
+    pub fn new(clock_rate: f32, sample_rate: f32) -> BlipBuffer {
+        return BlipBuffer {
+            samples_per_clock: sample_rate as f64 / clock_rate as f64,
+            // Just under Nyquist
+            kernel: kernel_table(0.5 * 0.95),
+            deltas: vec![0f32; BUFFER_SIZE],
+            // Starting a kernel's width in keeps the earliest taps from going negative
+            time: HALF_TAPS as f64,
+            read_position: 0,
+            integrator: 0.0,
+            amplitude: 0.0,
+        }
+    }
+
+    // The signal's level as of the current cycle. Cheap when nothing has changed, so
+    // it's fine to call every cycle.
+    pub fn set_amplitude(&mut self, amplitude: f32) {
+        if amplitude == self.amplitude {
+            return;
+        }
+        let delta = amplitude - self.amplitude;
+        self.amplitude = amplitude;
+
+        let center = self.time.floor() as u64;
+        let phase_position = (self.time - center as f64) * PHASES as f64;
+        let phase = phase_position.floor() as usize;
+        let blend = (phase_position - phase as f64) as f32;
+        // The first tap lands HALF_TAPS - 1 samples before the step
+        let first = center + 1 - HALF_TAPS as u64;
+        for tap in 0 .. TAPS {
+            let coefficient = self.kernel[phase * TAPS + tap] * (1.0 - blend) + self.kernel[(phase + 1) * TAPS + tap] * blend;
+            self.deltas[((first + tap as u64) as usize) % BUFFER_SIZE] += delta * coefficient;
+        }
+    }
+
+    // Advances to the next CPU cycle
+    pub fn clock(&mut self) {
+        self.time += self.samples_per_clock;
+    }
+
+    // Produces the next output sample once no future step can affect it anymore. Call
+    // until it returns None after every clock.
+    pub fn pop(&mut self) -> Option<f32> {
+        // Steps from now on start at or after this sample
+        let earliest_writable = self.time.floor() as u64 + 1 - HALF_TAPS as u64;
+        if self.read_position >= earliest_writable {
+            return None;
+        }
+        let index = (self.read_position as usize) % BUFFER_SIZE;
+        self.integrator += self.deltas[index];
+        self.deltas[index] = 0.0;
+        self.read_position += 1;
+        return Some(self.integrator);
+    }
+}
@@ -1,14 +1,18 @@
-use crate::mmc::mapper::Mapper;
 use crate::save_load::*;
 use super::audio_channel::AudioChannelState;
 use super::ring_buffer::RingBuffer;
 use super::filters;
 use super::filters::DspFilter;
 
+// CPU cycles between output bits for each rate in $4010, on NTSC
+pub const DMC_PERIODS: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+
 pub struct DmcState {
     pub name: String,
     pub chip: String,
     pub debug_disable: bool,
+    // Output volume multiplier, for mixing
+    pub gain: f32,
     pub output_buffer: RingBuffer,
     pub edge_buffer: RingBuffer,
     pub last_edge: bool,
@@ -33,6 +37,12 @@ pub struct DmcState {
     pub interrupt_flag: bool,
     pub rdy_line: bool,
     pub rdy_delay: u8,
+    // Set when the DMA unit wants the byte at current_address. The console performs
+    // the read on the CPU bus and hands it back with receive_sample().
+    pub fetch_requested: bool,
+    // Counts the samples that have had their last byte fetched, looping or not, for
+    // SignalTrigger::DmcSampleEnd. Wraps around.
+    pub samples_finished: u32,
 }
 
 impl DmcState {
@@ -41,6 +51,7 @@ impl DmcState {
             name: String::from(channel_name),
             chip: String::from(chip_name),
             debug_disable: false,
+            gain: 1.0,
             output_buffer: RingBuffer::new(32768),
             edge_buffer: RingBuffer::new(32768),
             last_edge: false,
@@ -64,6 +75,8 @@ impl DmcState {
             interrupt_flag: false,
             rdy_line: false,
             rdy_delay: 0,
+            fetch_requested: false,
+            samples_finished: 0,
         }
     }
 
@@ -73,14 +86,39 @@ impl DmcState {
             self.bytes_remaining, self.bits_remaining);
     }
 
-    pub fn read_next_sample(&mut self, mapper: &mut dyn Mapper) {
-        match mapper.read_cpu(0x8000 | (self.current_address & 0x7FFF)) {
-            Some(byte) => self.sample_buffer = byte,
-            None => self.sample_buffer = 0,
+    // The address wraps from $FFFF around to $8000, so samples always come from
+    // cartridge space
+    pub fn fetch_address(&self) -> u16 {
+        return 0x8000 | (self.current_address & 0x7FFF);
+    }
+
+    // CPU cycles until the last byte of the sample is fetched, which is when the IRQ
+    // is raised (or the sample loops), give or take the few cycles the fetch waits for
+    // the CPU. For checking a streaming engine's IRQ timing. None once there's
+    // nothing left to fetch.
+    pub fn cycles_until_last_fetch(&self) -> Option<u32> {
+        if self.bytes_remaining == 0 {
+            return None;
+        }
+        // An empty buffer is refilled right away. After that, the output unit empties
+        // it again once it's through its remaining bits, and every 8 bits after that.
+        let fetches_after_next = self.bytes_remaining as u32 - if self.sample_buffer_empty {1} else {0};
+        if fetches_after_next == 0 {
+            return Some(0);
         }
+        let steps = self.bits_remaining as u32 + 8 * (fetches_after_next - 1);
+        // The timer is clocked every other CPU cycle
+        let timer_clocks = (self.period_current as u32 + 1) + (steps - 1) * self.period_initial as u32;
+        return Some(timer_clocks * 2);
+    }
+
+    pub fn receive_sample(&mut self, byte: u8) {
+        self.fetch_requested = false;
+        self.sample_buffer = byte;
         self.current_address = self.current_address.wrapping_add(1);
         self.bytes_remaining -= 1;
         if self.bytes_remaining == 0 {
+            self.samples_finished = self.samples_finished.wrapping_add(1);
             if self.looping {
                 self.current_address = self.starting_address;
                 self.bytes_remaining = self.sample_length;
@@ -128,7 +166,7 @@ impl DmcState {
         }
     }
 
-    pub fn clock(&mut self, mapper: &mut dyn Mapper) {
+    pub fn clock(&mut self) {
         if self.period_current == 0 {
             self.period_current = self.period_initial - 1;
             self.update_output_unit();
@@ -139,7 +177,7 @@ impl DmcState {
             self.rdy_line = true;
             self.rdy_delay += 1;
             if self.rdy_delay > 2 {
-                self.read_next_sample(mapper);
+                self.fetch_requested = true;
             }
         } else {
             self.rdy_line = false;
@@ -236,6 +274,14 @@ impl AudioChannelState for DmcState {
         self.debug_disable = false;
     }
 
+    fn gain(&self) -> f32 {
+        return self.gain;
+    }
+
+    fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
     fn playing(&self) -> bool {
         return self.amplitude() > 0.0;
     }
@@ -170,6 +170,9 @@ pub struct ChainedFilter {
 
 pub struct FilterChain {
     filters: Vec<ChainedFilter>,
+    // How many samples the final filter has consumed, so callers can tell when
+    // its output has changed
+    output_count: u64,
 }
 
 impl FilterChain {
@@ -181,6 +184,7 @@ impl FilterChain {
                 sampling_period: 1.0,
                 period_counter: 0.0,
             }],
+            output_count: 0,
         }
     }
 
@@ -205,10 +209,22 @@ impl FilterChain {
                 self.filters[current].period_counter -= self.filters[current].sampling_period;
                 let previous_output = self.filters[previous].wrapped_filter.output();
                 self.filters[current].wrapped_filter.consume(previous_output);
+                if current == self.filters.len() - 1 {
+                    self.output_count += 1;
+                }
             }
         }
     }
 
+    pub fn output_count(&self) -> u64 {
+        return self.output_count;
+    }
+
+    // The rate the final filter runs at, once at least one filter has been added
+    pub fn output_sample_rate(&self) -> f32 {
+        return 1.0 / self.filters.last().unwrap().sampling_period;
+    }
+
     pub fn output(&self) -> f32 {
         let final_filter = self.filters.last().unwrap();
         return final_filter.wrapped_filter.output();
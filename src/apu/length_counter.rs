@@ -1,9 +1,18 @@
 use crate::save_load::*;
 
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20,  2, 40,  4, 80,  6, 160,  8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30];
+
 pub struct LengthCounterState {
     pub length: u8,
     pub halt_flag: bool,
     pub channel_enabled: bool,
+    // Writes through load_length() and load_halt_flag(), waiting for apply_writes().
+    // Only ever pending within a single cycle, so they aren't saved.
+    pub pending_length: Option<u8>,
+    pub length_before_load: u8,
+    pub pending_halt_flag: Option<bool>,
 }
 
 impl LengthCounterState{
@@ -12,6 +21,9 @@ impl LengthCounterState{
             length: 0,
             halt_flag: false,
             channel_enabled: false,
+            pending_length: None,
+            length_before_load: 0,
+            pending_halt_flag: None,
         }
     }
 
@@ -27,15 +39,40 @@ impl LengthCounterState{
 
     pub fn set_length(&mut self, index: u8) {
         if self.channel_enabled {
-            let table = [
-                10, 254, 20,  2, 40,  4, 80,  6, 160,  8, 60, 10, 14, 12, 26, 14,
-                12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30];
-            self.length = table[index as usize];
+            self.length = LENGTH_TABLE[index as usize];
         } else {
             self.length = 0
         }
     }
 
+    // The 2A03's length counters race the frame counter: a write landing on the same
+    // cycle as a half frame clock happens after it. A reload is dropped if the clock
+    // just counted the length down (it still goes through if the length was 0, or
+    // halted), and the clock still sees the old halt flag. The APU writes with these,
+    // then calls apply_writes() once the frame counter has had its turn.
+    // Reference: blargg's apu_test, and https://wiki.nesdev.com/w/index.php/APU_Length_Counter
+    pub fn load_length(&mut self, index: u8) {
+        if self.channel_enabled {
+            self.pending_length = Some(LENGTH_TABLE[index as usize]);
+            self.length_before_load = self.length;
+        }
+    }
+
+    pub fn load_halt_flag(&mut self, halt: bool) {
+        self.pending_halt_flag = Some(halt);
+    }
+
+    pub fn apply_writes(&mut self) {
+        if let Some(length) = self.pending_length.take() {
+            if self.channel_enabled && self.length == self.length_before_load {
+                self.length = length;
+            }
+        }
+        if let Some(halt) = self.pending_halt_flag.take() {
+            self.halt_flag = halt;
+        }
+    }
+
     pub fn save_state(&self, buff: &mut Vec<u8>) {
         save_u8(buff, self.length);
         save_bool(buff, self.halt_flag);
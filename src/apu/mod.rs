@@ -1,36 +1,100 @@
 use crate::mmc::mapper::Mapper;
 use crate::save_load::*;
 
-use std::fs::OpenOptions;
-use std::io::prelude::*;
+use std::io;
+
+use self::audio_thread::AudioThread;
 
 mod audio_channel;
+mod audio_sink;
+mod audio_thread;
+mod blip_buffer;
 mod dmc;
 pub mod filters;
 mod length_counter;
 mod noise;
+pub mod piano_roll;
 mod pulse;
+mod resampler;
 mod ring_buffer;
+pub mod spectrum;
+mod stem_capture;
 mod triangle;
 mod volume_envelope;
 
 pub use self::audio_channel::AudioChannelState;
+pub use self::audio_channel::EnvelopeState;
+pub use self::audio_channel::LfsrState;
+pub use self::audio_sink::AudioSink;
+pub use self::audio_sink::NullSink;
+pub use self::audio_sink::WavFileSink;
+pub use self::audio_thread::SampleConsumer;
+pub use self::blip_buffer::BlipBuffer;
 pub use self::audio_channel::PlaybackRate;
 pub use self::audio_channel::Volume;
 pub use self::audio_channel::Timbre;
 pub use self::dmc::DmcState;
+pub use self::dmc::DMC_PERIODS;
 pub use self::noise::NoiseChannelState;
 pub use self::pulse::PulseChannelState;
+pub use self::resampler::SincResampler;
 pub use self::ring_buffer::RingBuffer;
+pub use self::stem_capture::StemCapture;
 pub use self::triangle::TriangleChannelState;
 
 pub use self::filters::DspFilter;
 pub use self::filters::FilterChain;
 
+// Samples collected before each push to the audio sink
+const SINK_BATCH_SIZE: usize = 512;
+
+// Presets for the analog filtering between the DACs and the speaker
 #[derive(Clone, Copy)]
 pub enum FilterType {
+    // Frontloader NES
     Nes,
     FamiCom,
+    // No analog filtering at all, just what's needed to reach the output rate
+    Raw,
+}
+
+impl FilterType {
+    pub fn analog_filters(&self) -> Vec<AnalogFilter> {
+        return match self {
+            // The NES hardware follows the DACs with a surprisingly involved circuit that
+            // adds several low-pass and high-pass filters
+            FilterType::Nes => vec![
+                AnalogFilter::HighPass(90.0),
+                AnalogFilter::HighPass(440.0),
+                AnalogFilter::LowPass(14000.0),
+            ],
+            // The Famicom hardware instead ONLY specifies a first-order high-pass filter at
+            // 37 Hz, followed by the unknown (and varying) properties of the RF modulator
+            // and demodulator.
+            FilterType::FamiCom => vec![AnalogFilter::HighPass(37.0)],
+            FilterType::Raw => vec![],
+        };
+    }
+}
+
+// One first-order stage of the analog output filter, by cutoff frequency in Hz
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AnalogFilter {
+    HighPass(f32),
+    LowPass(f32),
+}
+
+// How the filtered signal is reduced to the output sample rate
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ResamplerType {
+    // Whatever the filter chain holds at the closest CPU cycle. Cheap.
+    Nearest,
+    // Windowed sinc interpolation at each sample's exact position. Alias free.
+    Sinc,
+    // Band-limited steps drawn directly at the output rate, blip buffer style. Also
+    // alias free, and cheaper than either of the above since the filter chain only
+    // runs at the output rate.
+    BandLimited,
 }
 
 pub struct ApuState {
@@ -51,10 +115,13 @@ pub struct ApuState {
     pub noise: NoiseChannelState,
     pub dmc: DmcState,
 
+    // Recent output, for waveform displays. Wraps freely.
     pub staging_buffer: RingBuffer,
     pub edge_buffer: RingBuffer,
-    pub output_buffer: Vec<i16>,
-    pub buffer_full: bool,
+    // Every sample generated since the last consume_samples() or drain_samples()
+    pending_samples: Vec<i16>,
+    // Samples thrown away because nobody collected them in time
+    pub samples_dropped: u64,
     pub sample_rate: u64,
     pub cpu_clock_rate: u64,
     pub generated_samples: u64,
@@ -68,8 +135,36 @@ pub struct ApuState {
     // also todo: make sure these are recreated when changing sample rate
 
     pub filter_type: FilterType,
-    pub filter_chain: FilterChain,
+    // Starts out as the filter_type preset; replace with set_analog_filters()
+    pub analog_filters: Vec<AnalogFilter>,
     pub filter_hq: bool,
+    pub resampler_type: ResamplerType,
+    // The mono mix, or the left side in stereo mode
+    pub output_path: OutputPath,
+    // Only present in stereo mode
+    pub right_output_path: Option<OutputPath>,
+
+    // Stereo position of the 2A03 channels, in channels() order: -1.0 is hard left, 1.0
+    // hard right. Unused unless stereo output is enabled.
+    pub channel_pans: [f32; 5],
+    // Expansion audio is panned as a single group
+    pub expansion_pan: f32,
+    // The expansion port's audio input, mixed and panned along with expansion audio
+    pub expansion_port_input: f32,
+
+    // Receives every output sample, in batches
+    pub audio_sink: Option<Box<dyn AudioSink>>,
+    pub sink_buffer: Vec<i16>,
+    // Independent of audio_sink, so a frontend can record while it plays
+    wav_capture: Option<WavFileSink>,
+    stem_capture: Option<StemCapture>,
+    // Filters and resamples in the background, in place of output_path; see
+    // start_audio_thread()
+    audio_thread: Option<AudioThread>,
+    // While set, output is still mixed and filtered but goes nowhere: not to the
+    // queue, the sink or any capture. Rollback sets it while running frames again
+    // whose audio has already been played.
+    pub output_muted: bool,
 }
 
 fn generate_pulse_table() -> Vec<f32> {
@@ -99,6 +194,17 @@ fn generate_tnd_table() -> Vec<f32> {
     return tnd_table;
 }
 
+// The mixer formulas from https://wiki.nesdev.com/w/index.php/APU_Mixer evaluated
+// directly, for channel levels that aren't whole numbers. Levels are in channels()
+// order: dmc, noise, triangle, pulse 1, pulse 2.
+fn mix_2a03_levels(levels: &[f32; 5]) -> f32 {
+    let combined_pulse = levels[3] + levels[4];
+    let pulse_output = if combined_pulse > 0.0 {95.52 / (8128.0 / combined_pulse + 100.0)} else {0.0};
+    let tnd_sum = (levels[2] / 8227.0) + (levels[1] / 12241.0) + (levels[0] / 22638.0);
+    let tnd_output = if tnd_sum > 0.0 {159.79 / ((1.0 / tnd_sum) + 100.0)} else {0.0};
+    return (pulse_output - 0.5) + (tnd_output - 0.5);
+}
+
 fn recommended_buffer_size(sample_rate: u64) -> usize {
     let samples_per_frame = sample_rate / 60;
     let mut buffer_size = 1;
@@ -111,7 +217,24 @@ fn recommended_buffer_size(sample_rate: u64) -> usize {
     return buffer_size as usize;
 }
 
-fn construct_hq_filter_chain(clock_rate: f32, target_sample_rate: f32, filter_type: FilterType) -> FilterChain {
+fn capture_running(setting: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::Other, format!("Can't change {} while an audio capture is running", setting));
+}
+
+fn add_analog_filters(chain: &mut FilterChain, sample_rate: f32, analog_filters: &[AnalogFilter]) {
+    for analog_filter in analog_filters {
+        match *analog_filter {
+            AnalogFilter::HighPass(cutoff_frequency) => {
+                chain.add(Box::new(filters::HighPassIIR::new(sample_rate, cutoff_frequency)), sample_rate);
+            },
+            AnalogFilter::LowPass(cutoff_frequency) => {
+                chain.add(Box::new(filters::LowPassIIR::new(sample_rate, cutoff_frequency)), sample_rate);
+            },
+        }
+    }
+}
+
+fn construct_hq_filter_chain(clock_rate: f32, target_sample_rate: f32, analog_filters: &[AnalogFilter]) -> FilterChain {
     // https://wiki.nesdev.org/w/index.php?title=APU_Mixer
 
     // First, no matter what the hardware specifies, we'll do a lightweight downsample to around 8x
@@ -123,23 +246,7 @@ fn construct_hq_filter_chain(clock_rate: f32, target_sample_rate: f32, filter_ty
     // to deal with any aliasing this leaves behind
     chain.add(Box::new(filters::LowPassIIR::new(clock_rate, intermediate_cutoff_frequency)), clock_rate);
 
-    match filter_type {
-        FilterType::Nes => {
-            //The NES hardware follows the DACs with a surprisingly involved circuit that adds several low-pass and high-pass filters:
-
-            // A first-order high-pass filter at 90 Hz
-            chain.add(Box::new(filters::HighPassIIR::new(intermediate_samplerate, 90.0)), intermediate_samplerate);
-            //  Another first-order high-pass filter at 440 Hz
-            chain.add(Box::new(filters::HighPassIIR::new(intermediate_samplerate, 440.0)), intermediate_samplerate);
-            // A first-order low-pass filter at 14 kHz
-            chain.add(Box::new(filters::LowPassIIR::new(intermediate_samplerate, 14000.0)), intermediate_samplerate);
-        },
-        FilterType::FamiCom => {
-            // The Famicom hardware instead ONLY specifies a first-order high-pass filter at 37 Hz, 
-            // followed by the unknown (and varying) properties of the RF modulator and demodulator. 
-            chain.add(Box::new(filters::HighPassIIR::new(intermediate_samplerate, 37.0)), intermediate_samplerate);
-        }
-    }
+    add_analog_filters(&mut chain, intermediate_samplerate, analog_filters);
 
     // Finally, perform a high-quality low pass, the result of which will be decimated to become the final output
     // TODO: 160 is huge! That was needed when going from 1.7 MHz -> 44.1 kHz; is it still needed when the source
@@ -151,7 +258,7 @@ fn construct_hq_filter_chain(clock_rate: f32, target_sample_rate: f32, filter_ty
     return chain;
 }
 
-fn construct_lq_filter_chain(clock_rate: f32, target_sample_rate: f32, filter_type: FilterType) -> FilterChain {
+fn construct_lq_filter_chain(clock_rate: f32, target_sample_rate: f32, analog_filters: &[AnalogFilter]) -> FilterChain {
     // https://wiki.nesdev.org/w/index.php?title=APU_Mixer
 
     // Quicker and more dirty. Will sound somewhat muffled.
@@ -160,25 +267,108 @@ fn construct_lq_filter_chain(clock_rate: f32, target_sample_rate: f32, filter_ty
 
     chain.add(Box::new(filters::LowPassIIR::new(clock_rate, cutoff_frequency)), clock_rate);
 
-    match filter_type {
-        FilterType::Nes => {
-            //The NES hardware follows the DACs with a surprisingly involved circuit that adds several low-pass and high-pass filters:
+    add_analog_filters(&mut chain, target_sample_rate, analog_filters);
+
+    return chain;
+}
+
+// For band-limited synthesis, which arrives already at the output rate
+fn construct_output_filter_chain(target_sample_rate: f32, analog_filters: &[AnalogFilter]) -> FilterChain {
+    let mut chain = FilterChain::new();
+    add_analog_filters(&mut chain, target_sample_rate, analog_filters);
+    return chain;
+}
+
+// Everything between the DAC and one output channel: filtering, then reducing the
+// CPU rate signal to the output sample rate
+pub struct OutputPath {
+    pub filter_chain: FilterChain,
+    resampler: Option<SincResampler>,
+    resampler_input_count: u64,
+    blip_buffer: Option<BlipBuffer>,
+    clock_period: f32,
+    sample_period: f32,
+    nearest_ready: bool,
+}
 
-            // A first-order high-pass filter at 90 Hz
-            chain.add(Box::new(filters::HighPassIIR::new(target_sample_rate, 90.0)), target_sample_rate);
-            //  Another first-order high-pass filter at 440 Hz
-            chain.add(Box::new(filters::HighPassIIR::new(target_sample_rate, 440.0)), target_sample_rate);
-            // A first-order low-pass filter at 14 kHz
-            chain.add(Box::new(filters::LowPassIIR::new(target_sample_rate, 14000.0)), target_sample_rate);
-        },
-        FilterType::FamiCom => {
-            // The Famicom hardware instead ONLY specifies a first-order high-pass filter at 37 Hz, 
-            // followed by the unknown (and varying) properties of the RF modulator and demodulator. 
-            chain.add(Box::new(filters::HighPassIIR::new(target_sample_rate, 37.0)), target_sample_rate);
+impl OutputPath {
+    pub fn new(clock_rate: u64, sample_rate: u64, resampler_type: ResamplerType, hq: bool, analog_filters: &[AnalogFilter]) -> OutputPath {
+        let filter_chain = if resampler_type == ResamplerType::BandLimited {
+            construct_output_filter_chain(sample_rate as f32, analog_filters)
+        } else if hq {
+            construct_hq_filter_chain(clock_rate as f32, sample_rate as f32, analog_filters)
+        } else {
+            construct_lq_filter_chain(clock_rate as f32, sample_rate as f32, analog_filters)
+        };
+        let resampler = match resampler_type {
+            ResamplerType::Sinc => Some(SincResampler::new(filter_chain.output_sample_rate(), sample_rate as f32)),
+            _ => None,
+        };
+        let blip_buffer = match resampler_type {
+            ResamplerType::BandLimited => Some(BlipBuffer::new(clock_rate as f32, sample_rate as f32)),
+            _ => None,
+        };
+        return OutputPath {
+            filter_chain: filter_chain,
+            resampler: resampler,
+            resampler_input_count: 0,
+            blip_buffer: blip_buffer,
+            clock_period: 1.0 / (clock_rate as f32),
+            sample_period: 1.0 / (sample_rate as f32),
+            nearest_ready: false,
         }
     }
 
-    return chain;
+    // Feeds in one CPU cycle's worth of DAC output. nearest_due is true on the cycles
+    // the Nearest resampler should sample on; the others ignore it.
+    pub fn clock(&mut self, dac_sample: f32, nearest_due: bool) {
+        if let Some(blip_buffer) = self.blip_buffer.as_mut() {
+            blip_buffer.set_amplitude(dac_sample);
+            blip_buffer.clock();
+            return;
+        }
+
+        // apply filters NEW
+        self.filter_chain.consume(dac_sample, self.clock_period);
+
+        match self.resampler.as_mut() {
+            Some(resampler) => {
+                if self.filter_chain.output_count() != self.resampler_input_count {
+                    self.resampler_input_count = self.filter_chain.output_count();
+                    resampler.push(self.filter_chain.output());
+                }
+            },
+            None => {
+                self.nearest_ready = nearest_due;
+            }
+        }
+    }
+
+    // Output samples completed by the last clock(), if any. Call until it returns None.
+    pub fn pop(&mut self) -> Option<f32> {
+        if let Some(blip_buffer) = self.blip_buffer.as_mut() {
+            let sample = blip_buffer.pop()?;
+            // Already at the output rate, so each sample passes through every filter once
+            self.filter_chain.consume(sample, self.sample_period);
+            return Some(self.filter_chain.output());
+        }
+        if let Some(resampler) = self.resampler.as_mut() {
+            return resampler.pop();
+        }
+        if self.nearest_ready {
+            // decimate sample
+            self.nearest_ready = false;
+            return Some(self.filter_chain.output());
+        }
+        return None;
+    }
+}
+
+// How much of a channel goes to the left and right outputs. Centered channels go to
+// both at full volume, so a centered stereo mix matches the mono one.
+fn pan_weights(pan: f32) -> (f32, f32) {
+    let pan = pan.max(-1.0).min(1.0);
+    return ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0));
 }
 
 impl ApuState {
@@ -202,8 +392,8 @@ impl ApuState {
             dmc: DmcState::new("DMC", "2A03"),
             staging_buffer: RingBuffer::new(output_buffer_size),
             edge_buffer: RingBuffer::new(output_buffer_size),
-            output_buffer: vec!(0i16; output_buffer_size),
-            buffer_full: false,
+            pending_samples: Vec::new(),
+            samples_dropped: 0,
             sample_rate: default_samplerate,
             cpu_clock_rate: 1_789_773,
             generated_samples: 0,
@@ -212,12 +402,32 @@ impl ApuState {
             tnd_table: generate_tnd_table(),
 
             filter_type: FilterType::FamiCom,
-            filter_chain: construct_hq_filter_chain(1789773.0, 44100.0, FilterType::FamiCom),
+            analog_filters: FilterType::FamiCom.analog_filters(),
             filter_hq: true,
+            resampler_type: ResamplerType::Nearest,
+            output_path: OutputPath::new(1_789_773, 44100, ResamplerType::Nearest, true, &FilterType::FamiCom.analog_filters()),
+            right_output_path: None,
+            channel_pans: [0.0; 5],
+            expansion_pan: 0.0,
+            expansion_port_input: 0.0,
+            audio_sink: None,
+            sink_buffer: Vec::new(),
+            wav_capture: None,
+            stem_capture: None,
+            audio_thread: None,
+            output_muted: false,
         }
     }
 
     pub fn save_state(&self, buff: &mut Vec<u8>) {
+        self.save_emulated_state(buff);
+        save_u64(buff, self.generated_samples);
+        save_u64(buff, self.next_sample_at);
+    }
+
+    // save_state(), less the output sample clock, which depends on the frontend's
+    // sample rate rather than on anything the game did
+    pub fn save_emulated_state(&self, buff: &mut Vec<u8>) {
         save_u64(buff, self.current_cycle);
         save_u8(buff, self.frame_sequencer_mode);
         save_u16(buff, self.frame_sequencer);
@@ -229,8 +439,34 @@ impl ApuState {
         self.triangle.save_state(buff);
         self.noise.save_state(buff);
         self.dmc.save_state(buff);
-        save_u64(buff, self.generated_samples);
-        save_u64(buff, self.next_sample_at);
+    }
+
+    // Everything the game can see goes back to how it powers up; the output settings
+    // and the sample clock carry on, so the frontend's stream doesn't skip
+    pub fn power_cycle(&mut self) {
+        let current_cycle = self.current_cycle;
+        let mut buff = Vec::new();
+        ApuState::new().save_emulated_state(&mut buff);
+        save_u64(&mut buff, self.generated_samples);
+        save_u64(&mut buff, self.next_sample_at);
+        self.load_state(&mut buff);
+        self.current_cycle = current_cycle;
+    }
+
+    // The parts of a reset the APU sees apart from the $4015 and $4017 writes, which
+    // NesState::reset() makes through the bus: the triangle's sequencer goes back to
+    // the start of its waveform, the DMC's output loses all but its lowest bit and a
+    // pending frame IRQ is dropped
+    pub fn reset(&mut self) {
+        self.triangle.sequence_counter = 0;
+        self.dmc.output_level &= 0x01;
+        self.frame_interrupt = false;
+    }
+
+    // The byte that would put the frame counter back as it is now, were it written
+    // to $4017
+    pub fn frame_counter_byte(&self) -> u8 {
+        return (self.frame_sequencer_mode << 7) | ((self.disable_interrupt as u8) << 6);
     }
 
     pub fn load_state(&mut self, buff: &mut Vec<u8>) {
@@ -251,28 +487,82 @@ impl ApuState {
 
     pub fn set_buffer_size(&mut self, buffer_size: usize) {
         self.staging_buffer = RingBuffer::new(buffer_size);
-        self.output_buffer = vec!(0i16; buffer_size);
-        self.buffer_full = false;
+        self.edge_buffer = RingBuffer::new(buffer_size);
     }
 
-    pub fn set_sample_rate(&mut self, sample_rate: u64) {
+    // Refused while a WAV or stem capture is running, since the files' headers have the
+    // old rate; stop the capture first
+    pub fn set_sample_rate(&mut self, sample_rate: u64) -> io::Result<()> {
+        if self.wav_capture.is_some() || self.stem_capture.is_some() {
+            return Err(capture_running("the sample rate"));
+        }
         self.sample_rate = sample_rate;
         self.update_filter();
         let output_buffer_size = recommended_buffer_size(sample_rate);
         self.set_buffer_size(output_buffer_size);
+        return Ok(());
     }
 
     pub fn set_filter(&mut self, filter_type: FilterType, hq: bool) {
         self.filter_type = filter_type;
+        self.analog_filters = filter_type.analog_filters();
         self.filter_hq = hq;
         self.update_filter();
     }
 
+    // A custom chain, in place of the filter_type preset. Stages run in order on the
+    // final mix, including expansion audio.
+    pub fn set_analog_filters(&mut self, analog_filters: Vec<AnalogFilter>) {
+        self.analog_filters = analog_filters;
+        self.update_filter();
+    }
+
+    pub fn set_resampler(&mut self, resampler_type: ResamplerType) {
+        self.resampler_type = resampler_type;
+        self.update_filter();
+    }
+
     pub fn update_filter(&mut self) {
-        if self.filter_hq {
-            self.filter_chain = construct_hq_filter_chain(self.cpu_clock_rate as f32, self.sample_rate as f32, self.filter_type);
-        } else {
-            self.filter_chain = construct_lq_filter_chain(self.cpu_clock_rate as f32, self.sample_rate as f32, self.filter_type);
+        self.output_path = self.new_output_path();
+        if self.right_output_path.is_some() {
+            self.right_output_path = Some(self.new_output_path());
+        }
+        self.reconfigure_audio_thread();
+    }
+
+    fn new_output_path(&self) -> OutputPath {
+        return OutputPath::new(self.cpu_clock_rate, self.sample_rate, self.resampler_type, self.filter_hq, &self.analog_filters);
+    }
+
+    // In stereo mode, queued samples and those pushed to the audio sink are interleaved
+    // left / right pairs, and sample counts include both halves. Refused while a WAV
+    // capture is running, which would come out garbled; stems are mono either way.
+    pub fn set_stereo(&mut self, stereo: bool) -> io::Result<()> {
+        if self.wav_capture.is_some() {
+            return Err(capture_running("stereo output"));
+        }
+        // Anything queued in the old format would come out garbled
+        self.pending_samples.clear();
+        // Both sides start fresh, so they stay in step
+        self.output_path = self.new_output_path();
+        self.right_output_path = if stereo {Some(self.new_output_path())} else {None};
+        self.reconfigure_audio_thread();
+        return Ok(());
+    }
+
+    pub fn stereo(&self) -> bool {
+        return self.right_output_path.is_some();
+    }
+
+    pub fn output_channels(&self) -> usize {
+        return if self.stereo() {2} else {1};
+    }
+
+    // channel_index as for mute_channel(); only the 2A03's own channels can be panned
+    // individually, see expansion_pan for the rest
+    pub fn set_pan(&mut self, channel_index: usize, pan: f32) {
+        if channel_index < self.channel_pans.len() {
+            self.channel_pans[channel_index] = pan;
         }
     }
 
@@ -316,7 +606,7 @@ impl ApuState {
                     status += 0b0001_0000;
                 }
 
-                if self.frame_interrupt {
+                if self.frame_interrupt || self.frame_interrupt_rising() {
                     status += 0b0100_0000;
                 }
                 if self.dmc.interrupt_flag {
@@ -328,11 +618,14 @@ impl ApuState {
         }
     }
 
+    // Bit 5 of $4015 is open bus, which the caller fills in
     pub fn read_register(&mut self, address: u16) -> u8 {
         let data = self.debug_read_register(address);
         match address {
             0x4015 => {
-                // Reading from this register resets frame_interrupt:
+                // Reading from this register resets frame_interrupt. A read on the same
+                // cycle the flag is raised sees it set, and the flag stays set, since
+                // it's raised again after the read.
                 self.frame_interrupt = false;                
             },
             _ => {}
@@ -340,6 +633,12 @@ impl ApuState {
         return data;
     }
 
+    // True when the frame sequencer is about to raise the frame interrupt this cycle,
+    // ie the CPU is reading at the same moment the flag goes up
+    fn frame_interrupt_rising(&self) -> bool {
+        return self.frame_sequencer_mode == 0 && !self.disable_interrupt && self.frame_sequencer == 29828 && self.frame_reset_delay == 0;
+    }
+
     pub fn write_register(&mut self, address: u16, data: u8) {
         let duty_table = [
             0b1000_0000,
@@ -355,7 +654,7 @@ impl ApuState {
                 let constant_volume = (data & 0b0001_0000) != 0;
 
                 self.pulse_1.duty = duty_table[duty_index as usize];
-                self.pulse_1.length_counter.halt_flag = length_disable;
+                self.pulse_1.length_counter.load_halt_flag(length_disable);
                 self.pulse_1.envelope.looping = length_disable;
                 self.pulse_1.envelope.enabled = !(constant_volume);
                 self.pulse_1.envelope.volume_register = data & 0b0000_1111;
@@ -376,7 +675,7 @@ impl ApuState {
                 let length_index = (data & 0b1111_1000) >> 3;
 
                 self.pulse_1.period_initial = (self.pulse_1.period_initial & 0x00FF) | period_high;
-                self.pulse_1.length_counter.set_length(length_index);
+                self.pulse_1.length_counter.load_length(length_index);
 
                 // Start this note
                 self.pulse_1.sequence_counter = 0;
@@ -390,7 +689,7 @@ impl ApuState {
                 let constant_volume = (data & 0b0001_0000) != 0;
 
                 self.pulse_2.duty = duty_table[duty_index as usize];
-                self.pulse_2.length_counter.halt_flag = length_disable;
+                self.pulse_2.length_counter.load_halt_flag(length_disable);
                 self.pulse_2.envelope.looping = length_disable;
                 self.pulse_2.envelope.enabled = !(constant_volume);
                 self.pulse_2.envelope.volume_register = data & 0b0000_1111;
@@ -411,7 +710,7 @@ impl ApuState {
                 let length_index =  (data & 0b1111_1000) >> 3;
 
                 self.pulse_2.period_initial = (self.pulse_2.period_initial & 0x00FF) | period_high;
-                self.pulse_2.length_counter.set_length(length_index);
+                self.pulse_2.length_counter.load_length(length_index);
 
                 // Start this note
                 self.pulse_2.sequence_counter = 0;
@@ -421,7 +720,7 @@ impl ApuState {
             // Triangle Channel
             0x4008 => {
                 self.triangle.control_flag           = (data & 0b1000_0000) != 0;
-                self.triangle.length_counter.halt_flag = self.triangle.control_flag;
+                self.triangle.length_counter.load_halt_flag(self.triangle.control_flag);
                 self.triangle.linear_counter_initial =  data & 0b0111_1111;
             },
             0x400A => {
@@ -433,7 +732,7 @@ impl ApuState {
                 let length_index =  (data & 0b1111_1000) >> 3;
 
                 self.triangle.period_initial = (self.triangle.period_initial & 0x00FF) | period_high;
-                self.triangle.length_counter.set_length(length_index);
+                self.triangle.length_counter.load_length(length_index);
 
                 // Start this note
                 self.triangle.linear_reload_flag = true;
@@ -444,7 +743,7 @@ impl ApuState {
                 let length_disable =  (data & 0b0010_0000) != 0;
                 let constant_volume = (data & 0b0001_0000) != 0;
 
-                self.noise.length_counter.halt_flag = length_disable;
+                self.noise.length_counter.load_halt_flag(length_disable);
                 self.noise.envelope.looping = length_disable;
                 self.noise.envelope.enabled = !(constant_volume);
                 self.noise.envelope.volume_register = data & 0b0000_1111;
@@ -460,7 +759,7 @@ impl ApuState {
             },
             0x400F => {
                 let length_index = (data & 0b1111_1000) >> 3;
-                self.noise.length_counter.set_length(length_index);
+                self.noise.length_counter.load_length(length_index);
 
                 // Restart the envelope
                 self.noise.envelope.start_flag = true;
@@ -468,8 +767,6 @@ impl ApuState {
 
             // DMC Channel
             0x4010 => {
-                let period_table = [
-                    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106,  84,  72,  54];
                 self.dmc.looping = (data & 0b0100_0000) != 0;
                 self.dmc.interrupt_enabled = (data & 0b1000_0000) != 0;
                 if !self.dmc.interrupt_enabled {
@@ -477,7 +774,7 @@ impl ApuState {
                     self.dmc.interrupt_flag = false;
                 }
                 let period_index = data & 0b0000_1111;
-                self.dmc.period_initial = period_table[period_index as usize] / 2;
+                self.dmc.period_initial = DMC_PERIODS[period_index as usize] / 2;
             },
             0x4011 => {
                 self.dmc.output_level = data & 0b0111_1111;
@@ -524,10 +821,18 @@ impl ApuState {
             0x4017 => {
                 self.frame_sequencer_mode = (data & 0b1000_0000) >> 7;
                 self.disable_interrupt =    (data & 0b0100_0000) != 0;
-                if (self.current_cycle & 0b1) != 0 {
-                    self.frame_reset_delay = 3;
-                } else {
+                // The sequencer restarts 3 CPU cycles after a write made during an APU
+                // cycle (one the pulses are clocked on), and 4 after one made between
+                // them. The write lands before clock_frame_sequencer() runs for this
+                // cycle, which counts one off already, so each delay is one longer here.
+                // https://www.nesdev.org/wiki/APU_Frame_Counter: "If the write occurs during
+                // an APU cycle, the effects occur 3 CPU cycles after the $4017 write cycle,
+                // and if the write occurs between APU cycles, the effects occurs 4 CPU
+                // cycles after the write cycle."
+                if (self.current_cycle & 0b1) == 0 {
                     self.frame_reset_delay = 4;
+                } else {
+                    self.frame_reset_delay = 5;
                 }
                 // If interrupts are disabled, clear the flag too:
                 if self.disable_interrupt {
@@ -604,8 +909,18 @@ impl ApuState {
         }
         
         self.frame_sequencer += 1;
+
+        // Length counter writes from this cycle land after its clocks
+        self.pulse_1.length_counter.apply_writes();
+        self.pulse_2.length_counter.apply_writes();
+        self.triangle.length_counter.apply_writes();
+        self.noise.length_counter.apply_writes();
     }
 
+    // Quarter frames drive the envelopes (pulse and noise) and the triangle's linear
+    // counter; half frames drive the sweep units and the length counters, which a set
+    // halt flag holds in place. Halt shares a bit with envelope looping, and with the
+    // triangle's linear counter control.
     pub fn clock_quarter_frame(&mut self) {
         self.pulse_1.envelope.clock();
         self.pulse_2.envelope.clock();
@@ -637,126 +952,359 @@ impl ApuState {
         if (self.current_cycle & 0b1) == 0 {
             self.pulse_1.clock();
             self.pulse_2.clock();
-            self.dmc.clock(mapper);
+            self.dmc.clock();
         }
         
-        // Collect current samples from the various channels
-        let pulse_1_sample = self.pulse_1.output();
-        let pulse_2_sample = self.pulse_2.output();
-        let triangle_sample = self.triangle.output();
-        let noise_sample = self.noise.output();
-        let dmc_sample = self.dmc.output();
-
-        // Mix samples, using the LUT we generated earlier, based on documentation here:
-        // https://wiki.nesdev.com/w/index.php/APU_Mixer
+        let current_2a03_sample = self.mix_2a03();
+        let nearest_due = self.current_cycle >= self.next_sample_at;
+        let (left_sample, right_sample) = if self.right_output_path.is_some() {
+            self.mix_stereo(mapper)
+        } else {
+            (mapper.mix_expansion_audio(current_2a03_sample) as f32 + self.expansion_port_input, 0.0)
+        };
+        if let Some(audio_thread) = self.audio_thread.as_mut() {
+            audio_thread.push(left_sample, right_sample, nearest_due, self.output_muted);
+        } else {
+            self.output_path.clock(left_sample, nearest_due);
+            if let Some(right_output_path) = self.right_output_path.as_mut() {
+                right_output_path.clock(right_sample, nearest_due);
+            }
+        }
+
+        if self.stem_capture.is_some() && !self.output_muted {
+            let levels = [
+                self.dmc.output() as f32,
+                self.noise.output() as f32,
+                self.triangle.output() as f32,
+                self.pulse_1.output() as f32,
+                self.pulse_2.output() as f32,
+            ];
+            self.stem_capture.as_mut().unwrap().clock(&levels, mapper, nearest_due);
+        }
+
+        if self.audio_thread.is_some() {
+            // The finished samples turn up on the worker; keep the sample clock and
+            // the waveform displays going here, with the unfiltered mix
+            if nearest_due {
+                let downmix = if self.stereo() {(left_sample + right_sample) / 2.0} else {left_sample};
+                self.staging_buffer.push((downmix * 32767.0) as i16);
+                self.edge_buffer.push(true as i16);
+                self.advance_sample_clock(mapper, current_2a03_sample);
+            }
+        }
+        while let Some(sample) = self.output_path.pop() {
+            // Both sides are clocked identically, so they always have samples together
+            let right_sample = self.right_output_path.as_mut().and_then(|output_path| output_path.pop());
+            self.emit_sample(mapper, sample, right_sample, current_2a03_sample);
+        }
+
+        self.current_cycle += 1;
+    }
+
+    // The combined output of the 2A03's own channels, as the console's DACs produce it.
+    // The two DACs are nonlinear: loud channels compress each other, and the DMC in
+    // particular audibly changes the volume of the triangle and noise. Both are looked
+    // up in the tables generated from the formulas here:
+    // https://wiki.nesdev.com/w/index.php/APU_Mixer
+    pub fn mix_2a03(&self) -> f32 {
+        if self.pulse_1.gain != 1.0 || self.pulse_2.gain != 1.0 || self.triangle.gain != 1.0 || self.noise.gain != 1.0 || self.dmc.gain != 1.0 {
+            return mix_2a03_levels(&self.channel_levels());
+        }
+
         let mut combined_pulse = 0;
         if !(self.pulse_1.debug_disable) {
-            combined_pulse += pulse_1_sample;
+            combined_pulse += self.pulse_1.output();
         }
         if !(self.pulse_2.debug_disable) {
-            combined_pulse += pulse_2_sample;
+            combined_pulse += self.pulse_2.output();
         }
         let pulse_output = self.pulse_table[combined_pulse as usize];
-        
-        let tri_output = if self.triangle.debug_disable {0} else {triangle_sample};
-        let noise_output = if self.noise.debug_disable {0} else {noise_sample};
-        let dmc_output = if self.dmc.debug_disable {0} else {dmc_sample};
+
+        let tri_output = if self.triangle.debug_disable {0} else {self.triangle.output()};
+        let noise_output = if self.noise.debug_disable {0} else {self.noise.output()};
+        let dmc_output = if self.dmc.debug_disable {0} else {self.dmc.output()};
         let tnd_output = self.tnd_table[full_tnd_index(tri_output as usize, noise_output as usize, dmc_output as usize)];
 
-        let current_2a03_sample = (pulse_output - 0.5) + (tnd_output - 0.5);
-        let current_dac_sample = mapper.mix_expansion_audio(current_2a03_sample) as f32;
+        return (pulse_output - 0.5) + (tnd_output - 0.5);
+    }
 
-        // apply filters NEW
-        self.filter_chain.consume(current_dac_sample, 1.0 / (self.cpu_clock_rate as f32));
+    // Each 2A03 channel's DAC input after muting and gain, in channels() order
+    fn channel_levels(&self) -> [f32; 5] {
+        return [
+            if self.dmc.debug_disable {0.0} else {self.dmc.output() as f32 * self.dmc.gain},
+            if self.noise.debug_disable {0.0} else {self.noise.output() as f32 * self.noise.gain},
+            if self.triangle.debug_disable {0.0} else {self.triangle.output() as f32 * self.triangle.gain},
+            if self.pulse_1.debug_disable {0.0} else {self.pulse_1.output() as f32 * self.pulse_1.gain},
+            if self.pulse_2.debug_disable {0.0} else {self.pulse_2.output() as f32 * self.pulse_2.gain},
+        ];
+    }
 
-        if self.current_cycle >= self.next_sample_at { 
-            // decimate sample
-            let composite_sample = (self.filter_chain.output() * 32767.0) as i16;
+    // Left and right mixes for stereo mode. The 2A03's DACs are still nonlinear, but
+    // with fractional channel levels they can't use the lookup tables.
+    fn mix_stereo(&self, mapper: &mut dyn Mapper) -> (f32, f32) {
+        let levels = self.channel_levels();
+        let mut left_levels = [0f32; 5];
+        let mut right_levels = [0f32; 5];
+        for i in 0 .. levels.len() {
+            let (left_weight, right_weight) = pan_weights(self.channel_pans[i]);
+            left_levels[i] = levels[i] * left_weight;
+            right_levels[i] = levels[i] * right_weight;
+        }
 
-            self.staging_buffer.push(composite_sample);
-            self.edge_buffer.push(true as i16);
+        // Expansion mixing is affine in the 2A03 sample; split it into the expansion
+        // audio on its own and how the 2A03 sample is scaled
+        let cartridge_sample = mapper.mix_expansion_audio(0.0);
+        let nes_weight = mapper.mix_expansion_audio(1.0) - cartridge_sample;
+        let expansion_sample = cartridge_sample + self.expansion_port_input;
+        let (expansion_left, expansion_right) = pan_weights(self.expansion_pan);
 
-            // Write debug buffers from these, regardless of enable / disable status
-            self.pulse_1.record_current_output();
-            self.pulse_2.record_current_output();
-            self.triangle.record_current_output();
-            self.noise.record_current_output();
-            self.dmc.record_current_output();
-            mapper.record_expansion_audio_output(current_2a03_sample);
+        let left_sample = mix_2a03_levels(&left_levels) * nes_weight + expansion_sample * expansion_left;
+        let right_sample = mix_2a03_levels(&right_levels) * nes_weight + expansion_sample * expansion_right;
+        return (left_sample, right_sample);
+    }
 
-            self.generated_samples += 1;
-            self.next_sample_at = ((self.generated_samples + 1) * self.cpu_clock_rate) / self.sample_rate;
+    // Hands one finished output sample to everything that wants it. right_sample is
+    // only present in stereo mode.
+    fn emit_sample(&mut self, mapper: &mut dyn Mapper, sample: f32, right_sample: Option<f32>, current_2a03_sample: f32) {
+        let composite_sample = (sample * 32767.0) as i16;
+        let right_composite_sample = right_sample.map(|right_sample| (right_sample * 32767.0) as i16);
+
+        match right_composite_sample {
+            Some(right_composite_sample) => {
+                // Waveform displays get the mono downmix
+                self.staging_buffer.push(((composite_sample as i32 + right_composite_sample as i32) / 2) as i16);
+                self.queue_sample(composite_sample);
+                self.queue_sample(right_composite_sample);
+            },
+            None => {
+                self.staging_buffer.push(composite_sample);
+                self.queue_sample(composite_sample);
+            }
+        }
+        self.edge_buffer.push(true as i16);
+        if (self.audio_sink.is_some() || self.wav_capture.is_some()) && !self.output_muted {
+            self.sink_buffer.push(composite_sample);
+            if let Some(right_composite_sample) = right_composite_sample {
+                self.sink_buffer.push(right_composite_sample);
+            }
+            if self.sink_buffer.len() >= SINK_BATCH_SIZE {
+                self.flush_audio_sink();
+            }
+        }
+
+        self.advance_sample_clock(mapper, current_2a03_sample);
+    }
+
+    fn advance_sample_clock(&mut self, mapper: &mut dyn Mapper, current_2a03_sample: f32) {
+        // Write debug buffers from these, regardless of enable / disable status
+        self.pulse_1.record_current_output();
+        self.pulse_2.record_current_output();
+        self.triangle.record_current_output();
+        self.noise.record_current_output();
+        self.dmc.record_current_output();
+        mapper.record_expansion_audio_output(current_2a03_sample);
+
+        self.generated_samples += 1;
+        self.next_sample_at = ((self.generated_samples + 1) * self.cpu_clock_rate) / self.sample_rate;
+    }
 
-            if self.staging_buffer.index() == 0 {
-                self.output_buffer.copy_from_slice(self.staging_buffer.buffer());
-                self.buffer_full = true;
+    fn queue_sample(&mut self, sample: i16) {
+        if self.output_muted {
+            return;
+        }
+        self.pending_samples.push(sample);
+        let limit = self.max_queued_samples();
+        if self.pending_samples.len() > limit {
+            // Nobody's collecting; drop the oldest chunk rather than growing forever. In
+            // stereo, keep left / right pairs together.
+            let channels = self.output_channels();
+            let excess = self.pending_samples.len() - limit + self.staging_buffer.buffer().len() * channels;
+            let excess = (excess + channels - 1) / channels * channels;
+            let excess = excess.min(self.pending_samples.len() / channels * channels);
+            self.pending_samples.drain(0 .. excess);
+            self.samples_dropped += excess as u64;
+            if let Some(sink) = self.audio_sink.as_mut() {
+                sink.buffer_full();
             }
         }
+    }
 
-        self.current_cycle += 1;
+    // How much audio is kept for the frontend before the oldest samples are dropped:
+    // two seconds, which is far more latency than any reasonable frontend allows
+    pub fn max_queued_samples(&self) -> usize {
+        return (self.sample_rate * 2) as usize * self.output_channels();
     }
 
     pub fn samples_queued(&self) -> usize {
-        let mut sample_count = self.staging_buffer.index();
-        if self.buffer_full {
-            sample_count += self.output_buffer.len();
+        return self.pending_samples.len();
+    }
+
+    // Replaces the current sink, if any, after handing it everything it hasn't seen yet
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.flush_audio_sink();
+        self.audio_sink = Some(sink);
+    }
+
+    pub fn take_audio_sink(&mut self) -> Option<Box<dyn AudioSink>> {
+        self.flush_audio_sink();
+        return self.audio_sink.take();
+    }
+
+    // Pushes any partial batch to the sink immediately, ie when pausing
+    pub fn flush_audio_sink(&mut self) {
+        if !self.sink_buffer.is_empty() {
+            if let Some(sink) = self.audio_sink.as_mut() {
+                sink.push_samples(&self.sink_buffer);
+            }
+            if let Some(wav_capture) = self.wav_capture.as_mut() {
+                wav_capture.push_samples(&self.sink_buffer);
+            }
         }
-        return sample_count;
+        self.sink_buffer.clear();
     }
 
-    pub fn dump_sample_buffer(&self) {
-        let mut file =
-            OpenOptions::new()
-            .write(true)
-            .create(true)
-            .append(true)
-            .open("audiodump.raw")
-            .unwrap();
+    // Records the final mix to a 16-bit PCM .wav file, in stereo if the APU is, until
+    // stop_wav_capture(). Replaces any capture already running.
+    pub fn start_wav_capture(&mut self, filename: &str) -> io::Result<()> {
+        self.stop_wav_capture()?;
+        self.wav_capture = Some(WavFileSink::with_channels(filename, self.sample_rate as u32, self.output_channels() as u16)?);
+        return Ok(());
+    }
 
-        // turn our sample buffer into a simple file buffer for output
-        let mut buffer = [0u8; 1024 * 2];
-        for i in 0 .. 1024 {
-            let sample = self.output_buffer[i];
-            buffer[i * 2]     = (((sample as u16) & 0xFF00) >> 8) as u8;
-            buffer[i * 2 + 1] = (((sample as u16) & 0x00FF)     ) as u8;
+    // Finishes the file. Does nothing if no capture is running.
+    pub fn stop_wav_capture(&mut self) -> io::Result<()> {
+        self.flush_audio_sink();
+        if let Some(mut wav_capture) = self.wav_capture.take() {
+            wav_capture.finish()?;
         }
+        return Ok(());
+    }
 
-        let _ = file.write_all(&buffer);
+    pub fn wav_capture_active(&self) -> bool {
+        return self.wav_capture.is_some();
     }
 
-    pub fn consume_samples(&mut self) -> Vec<i16> {
-        let mut output_buffer = vec!(0i16; 0);
-        if self.buffer_full {
-            output_buffer.extend(&self.output_buffer);
-            self.buffer_full = false;
+    // Records every channel to its own mono .wav file, named after the channel: a
+    // prefix of "song" writes song_pulse_1.wav, song_dmc.wav, song_vrc6.wav and so on.
+    // Channels play in the stems even while muted in the mix. Fairly expensive, since
+    // each stem is filtered separately.
+    pub fn start_stem_capture(&mut self, mapper: &dyn Mapper, filename_prefix: &str) -> io::Result<()> {
+        self.stop_stem_capture()?;
+        let channel_names: Vec<String> = self.channels().iter().map(|channel| channel.name()).collect();
+        let stem_capture = StemCapture::new(filename_prefix, self.sample_rate as u32, &channel_names,
+            mapper.expansion_chips(), &|| self.new_output_path())?;
+        self.stem_capture = Some(stem_capture);
+        return Ok(());
+    }
+
+    pub fn stop_stem_capture(&mut self) -> io::Result<()> {
+        if let Some(mut stem_capture) = self.stem_capture.take() {
+            stem_capture.finish()?;
         }
-        let staging_index = self.staging_buffer.index();
-        output_buffer.extend(&self.staging_buffer.buffer()[0 .. staging_index]);
-        self.staging_buffer.reset();
-        return output_buffer;
+        return Ok(());
     }
 
+    // Moves filtering and resampling to a worker thread, and returns the consumer its
+    // output arrives on. While the thread runs, finished samples go only to that
+    // consumer: not to consume_samples(), the audio sink or a WAV capture, and
+    // waveform displays show the mix before filtering. capacity is the consumer's
+    // buffer in samples, counting both halves of a stereo pair. Replaces any thread
+    // already running.
+    pub fn start_audio_thread(&mut self, capacity: usize) -> SampleConsumer {
+        self.stop_audio_thread();
+        let right_output_path = if self.stereo() {Some(self.new_output_path())} else {None};
+        let (audio_thread, consumer) = AudioThread::new(capacity, self.new_output_path(), right_output_path);
+        self.audio_thread = Some(audio_thread);
+        return consumer;
+    }
+
+    // Waits for the worker to finish what it has been given. Output goes back to the
+    // usual destinations.
+    pub fn stop_audio_thread(&mut self) {
+        self.audio_thread = None;
+    }
+
+    pub fn audio_thread_active(&self) -> bool {
+        return self.audio_thread.is_some();
+    }
+
+    // Hands the worker any partial batch of input, ie when pausing
+    pub fn flush_audio_thread(&mut self) {
+        if let Some(audio_thread) = self.audio_thread.as_mut() {
+            audio_thread.flush();
+        }
+    }
+
+    // The worker keeps its own copy of the output path; bring it in line with a
+    // settings change
+    fn reconfigure_audio_thread(&mut self) {
+        if self.audio_thread.is_some() {
+            let right_output_path = if self.stereo() {Some(self.new_output_path())} else {None};
+            let left_output_path = self.new_output_path();
+            self.audio_thread.as_mut().unwrap().reconfigure(left_output_path, right_output_path);
+        }
+    }
+
+    // Everything generated since the last call, oldest first
+    pub fn consume_samples(&mut self) -> Vec<i16> {
+        let capacity = self.pending_samples.capacity();
+        return std::mem::replace(&mut self.pending_samples, Vec::with_capacity(capacity));
+    }
+
+    // Copies as many of the oldest queued samples as fit into output and removes them
+    // from the queue, leaving the rest for next time. Returns the number copied. Never
+    // allocates, so it's suitable for calling from an audio callback.
+    pub fn drain_samples(&mut self, output: &mut [i16]) -> usize {
+        let count = output.len().min(self.pending_samples.len());
+        output[.. count].copy_from_slice(&self.pending_samples[.. count]);
+        self.pending_samples.drain(0 .. count);
+        return count;
+    }
+
+    // The frame and DMC interrupts combined. The CPU sees them as separate lines, see
+    // NesState::irq_lines(); the flags themselves are cleared by reading $4015 (frame)
+    // or writing $4015 / $4010 (DMC).
     pub fn irq_signal(&self) -> bool {
         return self.frame_interrupt || self.dmc.interrupt_flag;
     }
 
-    pub fn mute_channel(&mut self, mapper: &mut dyn Mapper, channel_index: usize) {
+    // Every channel on the console: the 2A03's own, followed by the cartridge's
+    // expansion audio, if any. Indices into this list are the channel_index used below,
+    // and are stable for a given cartridge.
+    pub fn all_channels<'a>(&'a self, mapper: &'a dyn Mapper) -> Vec<&'a dyn AudioChannelState> {
+        let mut channels: Vec<&dyn AudioChannelState> = Vec::new();
+        channels.extend(self.channels());
+        channels.extend(mapper.channels());
+        return channels;
+    }
+
+    pub fn all_channels_mut<'a>(&'a mut self, mapper: &'a mut dyn Mapper) -> Vec<&'a mut dyn AudioChannelState> {
         let mut channels: Vec<&mut dyn AudioChannelState> = Vec::new();
         channels.extend(self.channels_mut());
         channels.extend(mapper.channels_mut());
+        return channels;
+    }
+
+    pub fn mute_channel(&mut self, mapper: &mut dyn Mapper, channel_index: usize) {
+        let mut channels = self.all_channels_mut(mapper);
         if channel_index < channels.len() {
             channels[channel_index].mute();
         }
     }
 
     pub fn unmute_channel(&mut self, mapper: &mut dyn Mapper, channel_index: usize) {
-        let mut channels: Vec<&mut dyn AudioChannelState> = Vec::new();
-        channels.extend(self.channels_mut());
-        channels.extend(mapper.channels_mut());
+        let mut channels = self.all_channels_mut(mapper);
         if channel_index < channels.len() {
             channels[channel_index].unmute();
         }
     }
+
+    pub fn set_channel_gain(&mut self, mapper: &mut dyn Mapper, channel_index: usize, gain: f32) {
+        let mut channels = self.all_channels_mut(mapper);
+        if channel_index < channels.len() {
+            channels[channel_index].set_gain(gain);
+        }
+    }
 }
 
 // The APU itself counts as a channel, loosely, mostly for debugging purposes. Its output is a
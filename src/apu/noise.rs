@@ -3,6 +3,8 @@ use crate::save_load::*;
 use super::length_counter::LengthCounterState;
 use super::volume_envelope::VolumeEnvelopeState;
 use super::audio_channel::AudioChannelState;
+use super::audio_channel::EnvelopeState;
+use super::audio_channel::LfsrState;
 use super::audio_channel::PlaybackRate;
 use super::audio_channel::Volume;
 use super::audio_channel::Timbre;
@@ -14,6 +16,8 @@ pub struct NoiseChannelState {
     pub name: String,
     pub chip: String,
     pub debug_disable: bool,
+    // Output volume multiplier, for mixing
+    pub gain: f32,
     pub output_buffer: RingBuffer,
     pub edge_buffer: RingBuffer,
     pub last_edge: bool,
@@ -38,6 +42,7 @@ impl NoiseChannelState {
             name: String::from(channel_name),
             chip: String::from(chip_name),
             debug_disable: false,
+            gain: 1.0,
             output_buffer: RingBuffer::new(32768),
             edge_buffer: RingBuffer::new(32768),
             last_edge: false,
@@ -59,15 +64,7 @@ impl NoiseChannelState {
     pub fn clock(&mut self) {
         if self.period_current == 0 {
             self.period_current = self.period_initial - 1;
-
-            let mut feedback = self.shift_register & 0b1;
-            if self.mode == 1 {
-                feedback ^= (self.shift_register >> 6) & 0b1;
-            } else {
-                feedback ^= (self.shift_register >> 1) & 0b1;
-            }
-            self.shift_register = self.shift_register >> 1;
-            self.shift_register |= feedback << 14;
+            self.shift_register = next_lfsr_state(self.shift_register, self.mode);
             self.last_edge = true;
         } else {
             self.period_current -= 1;
@@ -75,15 +72,31 @@ impl NoiseChannelState {
     }
 
     pub fn output(&self) -> i16 {
-        if self.length_counter.length > 0 {
-            let mut sample = (self.shift_register & 0b1) as i16;
-            sample *= self.envelope.current_volume() as i16;
-            return sample;
+        // The channel is silenced while bit 0 is set
+        if self.length_counter.length > 0 && (self.shift_register & 0b1) == 0 {
+            return self.envelope.current_volume() as i16;
         } else {
             return 0;
         }
     }
 
+    // How many steps before the output pattern repeats. The normal mode is a maximal
+    // length LFSR, so that's 32767. Mode 1 taps bit 6 instead, which splits the states
+    // into loops of 93 and 31 steps; which one depends on the register's contents when
+    // the mode was switched, and it's the 93 step loop from power on.
+    pub fn sequence_length(&self) -> u16 {
+        if self.mode == 0 {
+            return if self.shift_register == 0 {1} else {32767};
+        }
+        let mut state = next_lfsr_state(self.shift_register, 1);
+        let mut length = 1;
+        while state != self.shift_register && length < 32767 {
+            state = next_lfsr_state(state, 1);
+            length += 1;
+        }
+        return length;
+    }
+
     pub fn save_state(&self, buff: &mut Vec<u8>) {
         save_u8(buff, self.length);
         save_bool(buff, self.length_halt_flag);
@@ -107,6 +120,16 @@ impl NoiseChannelState {
     }
 }
 
+fn next_lfsr_state(shift_register: u16, mode: u8) -> u16 {
+    let mut feedback = shift_register & 0b1;
+    if mode == 1 {
+        feedback ^= (shift_register >> 6) & 0b1;
+    } else {
+        feedback ^= (shift_register >> 1) & 0b1;
+    }
+    return (shift_register >> 1) | (feedback << 14);
+}
+
 impl AudioChannelState for NoiseChannelState {
     fn name(&self) -> String {
         return self.name.clone();
@@ -151,6 +174,14 @@ impl AudioChannelState for NoiseChannelState {
         self.debug_disable = false;
     }
 
+    fn gain(&self) -> f32 {
+        return self.gain;
+    }
+
+    fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
     fn playing(&self) -> bool {
         return 
             (self.length_counter.length > 0) &&
@@ -187,4 +218,12 @@ impl AudioChannelState for NoiseChannelState {
     fn timbre(&self) -> Option<Timbre> {
         return Some(Timbre::LsfrMode{index: self.mode as usize, max: 1});
     }
+
+    fn lfsr(&self) -> Option<LfsrState> {
+        return Some(LfsrState{shift_register: self.shift_register, sequence_length: self.sequence_length()});
+    }
+
+    fn envelope(&self) -> Option<EnvelopeState> {
+        return Some(self.envelope.state());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,176 @@
+// What every channel is playing, as notes rather than registers, for piano roll
+// displays and transcription. Each frame, every channel's rate, volume and timbre are
+// read into a ChannelNote, and the changes since the frame before become NoteEvents:
+// a note starting, stopping, or bending while it's held. NesState::start_piano_roll()
+// runs one.
+//
+// Nothing in the registers says where one note ends and the next begins, so that's
+// guessed: a jump in pitch of NEW_NOTE_INTERVAL or more between frames, or the volume
+// rising by NEW_NOTE_VOLUME_RISE (an envelope restarting), strikes a new note. Smaller
+// changes in pitch, as from vibrato, slides and sweeps, bend the note that's held.
+
+use super::AudioChannelState;
+use super::PlaybackRate;
+use super::Timbre;
+
+// In semitones
+pub const NEW_NOTE_INTERVAL: f32 = 0.75;
+// As a fraction of full volume
+pub const NEW_NOTE_VOLUME_RISE: f32 = 0.125;
+
+// Fractional MIDI note number of a frequency in Hz: 69.0 is A440, 60.0 middle C
+pub fn midi_note(frequency: f32) -> f32 {
+    return 69.0 + 12.0 * (frequency / 440.0).log2();
+}
+
+// One channel, on one frame
+#[derive(Clone, PartialEq, Debug)]
+pub struct ChannelNote {
+    // Index into ApuState::all_channels()
+    pub channel: usize,
+    pub sounding: bool,
+    // Channels with a pitch (pulse, triangle, most expansion audio) have it here as a
+    // fractional MIDI note number. Noise has its rate index instead, 0 - 15 from low to
+    // high, and sample playback 0.
+    pub key: f32,
+    pub tonal: bool,
+    // See AudioChannelState::amplitude()
+    pub volume: f32,
+    pub timbre: Option<Timbre>,
+}
+
+impl ChannelNote {
+    pub fn read(channel_index: usize, channel: &dyn AudioChannelState) -> ChannelNote {
+        let (key, tonal) = match channel.rate() {
+            PlaybackRate::FundamentalFrequency{frequency} if frequency > 0.0 => (midi_note(frequency), true),
+            PlaybackRate::LfsrRate{index, ..} => (index as f32, false),
+            _ => (0.0, false)
+        };
+        let volume = channel.amplitude();
+        return ChannelNote {
+            channel: channel_index,
+            sounding: channel.playing() && volume > 0.0,
+            key: key,
+            tonal: tonal,
+            volume: volume,
+            timbre: channel.timbre(),
+        };
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NoteEventKind {
+    On,
+    Off,
+    // The held note's bend, volume or timbre changed
+    Change,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct NoteEvent {
+    // PpuState::current_frame of the frame that ended with this
+    pub frame: u32,
+    pub channel: usize,
+    pub kind: NoteEventKind,
+    // The key the note was struck at, rounded to the nearest semitone for tonal
+    // channels; see ChannelNote::key
+    pub note: u8,
+    // How far the channel is from note now, in semitones; always 0 for untonal
+    // channels
+    pub bend: f32,
+    pub volume: f32,
+    pub timbre: Option<Timbre>,
+}
+
+pub struct PianoRoll {
+    // As of the last frame, in channel order
+    channels: Vec<ChannelNote>,
+    // The note each channel was struck at, while it's sounding
+    struck: Vec<Option<u8>>,
+    events: Vec<NoteEvent>,
+}
+
+impl PianoRoll {
+    pub fn new() -> PianoRoll {
+        return PianoRoll {
+            channels: Vec::new(),
+            struck: Vec::new(),
+            events: Vec::new(),
+        };
+    }
+
+    pub fn channels(&self) -> &[ChannelNote] {
+        return &self.channels;
+    }
+
+    // Every event since the last call
+    pub fn take_events(&mut self) -> Vec<NoteEvent> {
+        return std::mem::take(&mut self.events);
+    }
+
+    pub fn events(&self) -> &[NoteEvent] {
+        return &self.events;
+    }
+
+    fn event(&mut self, frame: u32, note: &ChannelNote, kind: NoteEventKind) {
+        let struck = self.struck[note.channel].unwrap_or(0);
+        self.events.push(NoteEvent {
+            frame: frame,
+            channel: note.channel,
+            kind: kind,
+            note: struck,
+            bend: if note.tonal {note.key - struck as f32} else {0.0},
+            volume: note.volume,
+            timbre: note.timbre.clone(),
+        });
+    }
+
+    fn strike(&mut self, frame: u32, note: &ChannelNote) {
+        let key = if note.tonal {note.key.round()} else {note.key};
+        self.struck[note.channel] = Some(key.max(0.0).min(127.0) as u8);
+        self.event(frame, note, NoteEventKind::On);
+    }
+
+    pub fn end_frame(&mut self, frame: u32, channels: &[&dyn AudioChannelState]) {
+        let current: Vec<ChannelNote> = channels.iter().enumerate()
+            .map(|(index, &channel)| ChannelNote::read(index, channel)).collect();
+        // The cartridge, and its channels, may have changed
+        if current.len() != self.channels.len() {
+            for previous in std::mem::take(&mut self.channels).iter().filter(|note| note.sounding) {
+                self.event(frame, previous, NoteEventKind::Off);
+            }
+            self.struck = vec![None; current.len()];
+        }
+        for note in current.iter() {
+            let previous = match self.channels.get(note.channel) {
+                Some(previous) if previous.sounding => Some(previous.clone()),
+                _ => None
+            };
+            let previous = match (previous, note.sounding) {
+                (Some(previous), true) => previous,
+                (Some(previous), false) => {
+                    self.event(frame, &previous, NoteEventKind::Off);
+                    self.struck[note.channel] = None;
+                    continue;
+                },
+                (None, true) => {
+                    self.strike(frame, note);
+                    continue;
+                },
+                (None, false) => continue
+            };
+            let jumped = if note.tonal {
+                (note.key - previous.key).abs() >= NEW_NOTE_INTERVAL
+            } else {
+                note.key != previous.key
+            };
+            if jumped || note.volume >= previous.volume + NEW_NOTE_VOLUME_RISE {
+                self.event(frame, &previous, NoteEventKind::Off);
+                self.strike(frame, note);
+            } else if note.key != previous.key || note.volume != previous.volume || note.timbre != previous.timbre {
+                self.event(frame, note, NoteEventKind::Change);
+            }
+        }
+        self.channels = current;
+    }
+}
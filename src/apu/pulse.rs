@@ -3,6 +3,7 @@ use crate::save_load::*;
 use super::length_counter::LengthCounterState;
 use super::volume_envelope::VolumeEnvelopeState;
 use super::audio_channel::AudioChannelState;
+use super::audio_channel::EnvelopeState;
 use super::audio_channel::PlaybackRate;
 use super::audio_channel::Volume;
 use super::audio_channel::Timbre;
@@ -14,6 +15,8 @@ pub struct PulseChannelState {
     pub name: String,
     pub chip: String,
     pub debug_disable: bool,
+    // Output volume multiplier, for mixing
+    pub gain: f32,
     pub output_buffer: RingBuffer,
     pub edge_buffer: RingBuffer,
     pub last_edge: bool,
@@ -45,6 +48,7 @@ impl PulseChannelState {
             name: String::from(channel_name),
             chip: String::from(chip_name),
             debug_disable: false,
+            gain: 1.0,
             output_buffer: RingBuffer::new(32768),
             edge_buffer: RingBuffer::new(32768),
             last_edge: false,
@@ -91,9 +95,7 @@ impl PulseChannelState {
 
     pub fn output(&self) -> i16 {
         if self.length_counter.length > 0 {
-            let target_period = self.target_period();
-            if target_period > 0x7FF || self.period_initial < 8 {
-                // Sweep unit mutes the channel, because the period is out of range
+            if self.sweep_muted() {
                 return 0;
             } else {
                 let mut sample = ((self.duty >> self.sequence_counter) & 0b1) as i16;
@@ -218,6 +220,14 @@ impl AudioChannelState for PulseChannelState {
         self.debug_disable = false;
     }
 
+    fn gain(&self) -> f32 {
+        return self.gain;
+    }
+
+    fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
     fn playing(&self) -> bool {
         return 
             (self.length_counter.length > 0) &&
@@ -226,6 +236,16 @@ impl AudioChannelState for PulseChannelState {
             (self.envelope.current_volume() > 0);
     }
 
+    fn sweep_muted(&self) -> bool {
+        // The sweep unit mutes the channel whenever the period is out of range, even
+        // with sweeps disabled
+        return self.target_period() > 0x7FF || self.period_initial < 8;
+    }
+
+    fn envelope(&self) -> Option<EnvelopeState> {
+        return Some(self.envelope.state());
+    }
+
     fn rate(&self) -> PlaybackRate {
         let frequency = self.cpu_clock_rate as f32 / (16.0 * (self.period_initial as f32 + 1.0));
         return PlaybackRate::FundamentalFrequency {frequency: frequency};
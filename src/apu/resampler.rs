@@ -0,0 +1,114 @@
+// Band-limited resampling from the filter chain's final rate to the output rate.
+// Picking the nearest sample instead (the default) is cheap, but its timing jitter
+// shows up as faint aliasing on bright, high pitched sounds; this interpolates each
+// output sample at its exact position with a windowed sinc kernel.
+// Reference: https://ccrma.stanford.edu/~jos/resample/
+
+use std::f64::consts::PI;
+
+// Kernel half-width, in input samples. Latency is this many input samples.
+pub(super) const HALF_TAPS: usize = 8;
+pub(super) const TAPS: usize = HALF_TAPS * 2;
+// The kernel is tabulated at this many fractional offsets, and interpolated between
+pub(super) const PHASES: usize = 64;
+// Input history; anything longer than the kernel gives some slack between push and pop
+const HISTORY: usize = TAPS * 4;
+
+fn blackman(x: f64) -> f64 {
+    // x in -1.0 ..= 1.0
+    let n = (x + 1.0) / 2.0;
+    return 0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+}
+
+fn windowed_sinc(x: f64, cutoff: f64) -> f64 {
+    if x.abs() >= HALF_TAPS as f64 {
+        return 0.0;
+    }
+    let window = blackman(x / HALF_TAPS as f64);
+    if x == 0.0 {
+        return 2.0 * cutoff * window;
+    }
+    return (2.0 * PI * cutoff * x).sin() / (PI * x) * window;
+}
+
+// PHASES + 1 rows of TAPS coefficients, each row normalized for unity gain at DC. Row
+// p is centered p / PHASES of a sample before tap HALF_TAPS - 1; the extra row makes
+// interpolating between phases simpler. cutoff is relative to the tap spacing.
+pub(super) fn kernel_table(cutoff: f64) -> Vec<f32> {
+    let mut kernel = vec![0f32; (PHASES + 1) * TAPS];
+    for phase in 0 ..= PHASES {
+        let fraction = phase as f64 / PHASES as f64;
+        let mut row = [0f64; TAPS];
+        for tap in 0 .. TAPS {
+            let offset = (tap as f64) - (HALF_TAPS as f64 - 1.0) - fraction;
+            row[tap] = windowed_sinc(offset, cutoff);
+        }
+        let sum: f64 = row.iter().sum();
+        for tap in 0 .. TAPS {
+            kernel[phase * TAPS + tap] = (row[tap] / sum) as f32;
+        }
+    }
+    return kernel;
+}
+
+pub struct SincResampler {
+    // Input samples consumed per output sample produced
+    ratio: f64,
+    // See kernel_table()
+    kernel: Vec<f32>,
+    // Input sample n lives at n % HISTORY
+    history: Vec<f32>,
+    // Total input samples received
+    input_count: u64,
+    // Position of the next output, in input samples since the start
+    next_output: f64,
+}
+
+impl SincResampler {
+    pub fn new(input_rate: f32, output_rate: f32) -> SincResampler {
+        let ratio = input_rate as f64 / output_rate as f64;
+        // Cutoff relative to the input rate: just under the output's Nyquist frequency
+        // when downsampling, and the input's when upsampling
+        let cutoff = 0.5 * (1.0 / ratio).min(1.0) * 0.95;
+        return SincResampler {
+            ratio: ratio,
+            kernel: kernel_table(cutoff),
+            history: vec![0f32; HISTORY],
+            input_count: 0,
+            next_output: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        self.history[(self.input_count % HISTORY as u64) as usize] = sample;
+        self.input_count += 1;
+    }
+
+    // Produces the next output sample once enough input has arrived to compute it.
+    // Call until it returns None after every push.
+    pub fn pop(&mut self) -> Option<f32> {
+        // The window covers HALF_TAPS input samples on either side of next_output
+        let center = self.next_output.floor() as i64;
+        let newest_needed = center + HALF_TAPS as i64;
+        if newest_needed >= self.input_count as i64 {
+            return None;
+        }
+        let fraction = self.next_output - center as f64;
+        let phase_position = fraction * PHASES as f64;
+        let phase = phase_position.floor() as usize;
+        let blend = (phase_position - phase as f64) as f32;
+
+        let oldest_needed = center - (HALF_TAPS as i64 - 1);
+        let mut output = 0f32;
+        for tap in 0 .. TAPS {
+            let coefficient = self.kernel[phase * TAPS + tap] * (1.0 - blend) + self.kernel[(phase + 1) * TAPS + tap] * blend;
+            // Samples from before the first push are silence
+            let sample_number = oldest_needed + tap as i64;
+            if sample_number >= 0 {
+                output += coefficient * self.history[(sample_number as usize) % HISTORY];
+            }
+        }
+        self.next_output += self.ratio;
+        return Some(output);
+    }
+}
@@ -0,0 +1,83 @@
+// Frequency analysis of the channels' debug buffers, for spectrograms and other
+// visualizers. The buffers fill at the APU's output sample rate, so bin n of a
+// size point spectrum is centered on n * sample_rate / size Hz.
+
+use std::f32::consts::PI;
+
+use super::AudioChannelState;
+use super::RingBuffer;
+
+// Magnitudes of the most recent size samples in buffer, as size / 2 bins from DC up
+// to just under Nyquist. size must be a power of two, no larger than the buffer. A
+// full scale sine at a bin's frequency reads as roughly full_scale there.
+pub fn magnitude_spectrum(buffer: &RingBuffer, size: usize, full_scale: f32) -> Vec<f32> {
+    assert!(size.is_power_of_two(), "spectrum size must be a power of two");
+    let samples = buffer.buffer();
+    assert!(size <= samples.len(), "spectrum size is larger than the buffer");
+
+    // Hann window, to keep strong tones from smearing across the whole spectrum
+    let mut real = vec![0f32; size];
+    let mut imaginary = vec![0f32; size];
+    let mut window_sum = 0f32;
+    let oldest = samples.len() + buffer.index() - size;
+    for i in 0 .. size {
+        let window = 0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos();
+        real[i] = samples[(oldest + i) % samples.len()] as f32 * window;
+        window_sum += window;
+    }
+    fft(&mut real, &mut imaginary);
+
+    // Doubled, since each bin's energy is split with its negative frequency mirror
+    let scale = 2.0 / (window_sum * full_scale);
+    return (0 .. size / 2).map(|bin| {
+        (real[bin] * real[bin] + imaginary[bin] * imaginary[bin]).sqrt() * scale
+    }).collect();
+}
+
+// magnitude_spectrum() of a channel's output, scaled to its sample range
+pub fn channel_spectrum(channel: &dyn AudioChannelState, size: usize) -> Vec<f32> {
+    let full_scale = (channel.max_sample() as f32).max(-(channel.min_sample() as f32)).max(1.0);
+    return magnitude_spectrum(channel.sample_buffer(), size, full_scale);
+}
+
+// Center frequency of a bin, in Hz
+pub fn bin_frequency(bin: usize, size: usize, sample_rate: u64) -> f32 {
+    return bin as f32 * sample_rate as f32 / size as f32;
+}
+
+// In place, iterative radix-2 Cooley-Tukey
+fn fft(real: &mut [f32], imaginary: &mut [f32]) {
+    let size = real.len();
+    // Bit reversed reordering
+    let mut j = 0;
+    for i in 1 .. size {
+        let mut bit = size >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            real.swap(i, j);
+            imaginary.swap(i, j);
+        }
+    }
+    let mut length = 2;
+    while length <= size {
+        let angle = -2.0 * PI / length as f32;
+        for start in (0 .. size).step_by(length) {
+            for k in 0 .. length / 2 {
+                let (twiddle_imaginary, twiddle_real) = (angle * k as f32).sin_cos();
+                let even = start + k;
+                let odd = even + length / 2;
+                let odd_real = real[odd] * twiddle_real - imaginary[odd] * twiddle_imaginary;
+                let odd_imaginary = real[odd] * twiddle_imaginary + imaginary[odd] * twiddle_real;
+                real[odd] = real[even] - odd_real;
+                imaginary[odd] = imaginary[even] - odd_imaginary;
+                real[even] += odd_real;
+                imaginary[even] += odd_imaginary;
+            }
+        }
+        length <<= 1;
+    }
+}
@@ -0,0 +1,110 @@
+// Records each channel to its own .wav file, for remixing or for checking the balance
+// of the mixer. Every stem runs through its own copy of the output filters, so they
+// sound like the final mix would with every other channel silenced. The 2A03 channels
+// get a stem each; expansion audio gets one per chip, since the chips don't all expose
+// their channels individually at mixing time.
+
+use std::io;
+
+use crate::mmc::mapper::ExpansionChip;
+use crate::mmc::mapper::Mapper;
+
+use super::AudioSink;
+use super::OutputPath;
+use super::WavFileSink;
+use super::mix_2a03_levels;
+
+pub enum StemSource {
+    // Index into the 2A03's channels(): dmc, noise, triangle, pulse 1, pulse 2
+    Channel(usize),
+    Expansion(ExpansionChip),
+}
+
+struct Stem {
+    source: StemSource,
+    output_path: OutputPath,
+    sink: WavFileSink,
+}
+
+pub struct StemCapture {
+    stems: Vec<Stem>,
+    chips: Vec<ExpansionChip>,
+    // The mapper's level for each of chips, while solo_expansion_output() has them down
+    saved_levels: Vec<f32>,
+}
+
+impl StemCapture {
+    // output_path makes a fresh output path, configured like the main mix
+    pub fn new(filename_prefix: &str, sample_rate: u32, channel_names: &[String], chips: Vec<ExpansionChip>,
+        output_path: &dyn Fn() -> OutputPath) -> io::Result<StemCapture> {
+        let mut stems = Vec::new();
+        for (i, name) in channel_names.iter().enumerate() {
+            stems.push(Stem {
+                source: StemSource::Channel(i),
+                output_path: output_path(),
+                sink: WavFileSink::new(&stem_filename(filename_prefix, name), sample_rate)?,
+            });
+        }
+        for chip in chips.iter() {
+            stems.push(Stem {
+                source: StemSource::Expansion(*chip),
+                output_path: output_path(),
+                sink: WavFileSink::new(&stem_filename(filename_prefix, &format!("{:?}", chip)), sample_rate)?,
+            });
+        }
+        return Ok(StemCapture {
+            stems: stems,
+            saved_levels: vec![1.0; chips.len()],
+            chips: chips,
+        });
+    }
+
+    // levels are the 2A03 channels' current outputs, in channels() order, ignoring
+    // mute and gain
+    pub fn clock(&mut self, levels: &[f32; 5], mapper: &mut dyn Mapper, nearest_due: bool) {
+        for stem in self.stems.iter_mut() {
+            let sample = match stem.source {
+                StemSource::Channel(index) => {
+                    let mut solo_levels = [0f32; 5];
+                    solo_levels[index] = levels[index];
+                    // Without the mix's usual offset, so silence is silence
+                    mix_2a03_levels(&solo_levels) + 1.0
+                },
+                StemSource::Expansion(chip) => solo_expansion_output(mapper, &self.chips, &mut self.saved_levels, chip),
+            };
+            stem.output_path.clock(sample, nearest_due);
+            while let Some(sample) = stem.output_path.pop() {
+                stem.sink.push_samples(&[(sample * 32767.0) as i16]);
+            }
+        }
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        for stem in self.stems.iter_mut() {
+            stem.sink.finish()?;
+        }
+        return Ok(());
+    }
+}
+
+// One chip's output on its own, found by briefly turning the others down
+fn solo_expansion_output(mapper: &mut dyn Mapper, chips: &[ExpansionChip], saved_levels: &mut [f32], solo_chip: ExpansionChip) -> f32 {
+    for (i, chip) in chips.iter().enumerate() {
+        if *chip != solo_chip {
+            saved_levels[i] = mapper.expansion_level(*chip);
+            mapper.set_expansion_level(*chip, 0.0);
+        }
+    }
+    let output = mapper.mix_expansion_audio(0.0);
+    for (i, chip) in chips.iter().enumerate() {
+        if *chip != solo_chip {
+            mapper.set_expansion_level(*chip, saved_levels[i]);
+        }
+    }
+    return output;
+}
+
+// "capture" and "Pulse 1" make capture_pulse_1.wav
+fn stem_filename(prefix: &str, name: &str) -> String {
+    return format!("{}_{}.wav", prefix, name.to_lowercase().replace(" ", "_"));
+}
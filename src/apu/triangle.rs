@@ -13,6 +13,8 @@ pub struct TriangleChannelState {
     pub name: String,
     pub chip: String,
     pub debug_disable: bool,
+    // Output volume multiplier, for mixing
+    pub gain: f32,
     pub output_buffer: RingBuffer,
     pub edge_buffer: RingBuffer,
     pub last_edge: bool,
@@ -38,6 +40,7 @@ impl TriangleChannelState {
             name: String::from(channel_name),
             chip: String::from(chip_name),
             debug_disable: false,
+            gain: 1.0,
             output_buffer: RingBuffer::new(32768),
             last_edge: false,
             debug_filter: filters::HighPassIIR::new(44100.0, 300.0),
@@ -178,6 +181,14 @@ impl AudioChannelState for TriangleChannelState {
         self.debug_disable = false;
     }
 
+    fn gain(&self) -> f32 {
+        return self.gain;
+    }
+
+    fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
     fn playing(&self) -> bool {
         return 
             self.length_counter.length > 0 && 
@@ -1,5 +1,7 @@
 use crate::save_load::*;
 
+use super::audio_channel::EnvelopeState;
+
 pub struct VolumeEnvelopeState {
     // Volume Envelope
     pub volume_register: u8,
@@ -30,6 +32,29 @@ impl VolumeEnvelopeState {
         }
     }
 
+    pub fn divider(&self) -> u8 {
+        return self.divider;
+    }
+
+    pub fn decay_level(&self) -> u8 {
+        return self.decay;
+    }
+
+    pub fn start_flag(&self) -> bool {
+        return self.start_flag;
+    }
+
+    pub fn state(&self) -> EnvelopeState {
+        return EnvelopeState {
+            period: self.volume_register,
+            constant_volume: !self.enabled,
+            looping: self.looping,
+            divider: self.divider(),
+            decay_level: self.decay_level(),
+            start_flag: self.start_flag(),
+        };
+    }
+
     pub fn clock(&mut self) {
         if self.start_flag {
             self.decay = 15;
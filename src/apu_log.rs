@@ -0,0 +1,120 @@
+// Logs every sound register write with the CPU cycle it happened on, for NSF rippers
+// and tracker transcription. Covers $4000 - $4017 plus the registers of whichever
+// expansion chips the cartridge has. Addresses are logged as written, before any
+// mirroring or pin swapping (VRC6b) the cartridge applies.
+
+use std::io::Write;
+
+use crate::mmc::mapper::ExpansionChip;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ApuLogFormat {
+    // One array of objects, one per line:
+    // [
+    // {"cycle":29781,"chip":"2A03","address":"$4000","data":"$3F"},
+    // {"cycle":29785,"chip":"Vrc6","address":"$9000","data":"$7F"}
+    // ]
+    Json,
+    // cycle,chip,address,data
+    // 29781,2A03,$4000,$3F
+    Csv,
+}
+
+pub struct ApuWriteLogger {
+    writer: Box<dyn Write + Send>,
+    pub format: ApuLogFormat,
+    chips: Vec<ExpansionChip>,
+    pub writes_logged: u64,
+}
+
+impl ApuWriteLogger {
+    pub fn new(writer: Box<dyn Write + Send>, format: ApuLogFormat, chips: Vec<ExpansionChip>) -> ApuWriteLogger {
+        let mut logger = ApuWriteLogger {
+            writer: writer,
+            format: format,
+            chips: chips,
+            writes_logged: 0,
+        };
+        let header = match format {
+            ApuLogFormat::Json => "[",
+            ApuLogFormat::Csv => "cycle,chip,address,data",
+        };
+        logger.write_str(header);
+        return logger;
+    }
+
+    fn write_str(&mut self, text: &str) {
+        if self.writer.write_all(text.as_bytes()).is_err() {
+            println!("ApuWriteLogger: write failed");
+        }
+    }
+
+    // Which chip, if any, is listening at this address
+    fn chip_name(&self, address: u16) -> Option<String> {
+        match address {
+            // $4014 and $4016 are OAM DMA and the controller strobe, not sound
+            0x4000 ..= 0x4013 | 0x4015 | 0x4017 => return Some("2A03".to_string()),
+            _ => {}
+        }
+        for chip in self.chips.iter() {
+            let listening = match chip {
+                ExpansionChip::Vrc6 => match address & 0xF003 {
+                    0x9000 ..= 0x9003 | 0xA000 ..= 0xA002 | 0xB000 ..= 0xB002 => true,
+                    _ => false,
+                },
+                ExpansionChip::Mmc5 => match address {
+                    0x5000 ..= 0x5015 => true,
+                    _ => false,
+                },
+                // Data port, and the address port (which shares its register with mirroring)
+                ExpansionChip::N163 => match address {
+                    0x4800 ..= 0x4FFF | 0xF800 ..= 0xFFFF => true,
+                    _ => false,
+                },
+                // Register select, and data
+                ExpansionChip::Sunsoft5B => match address {
+                    0xC000 ..= 0xFFFF => true,
+                    _ => false,
+                },
+                // Wave table, and the sound registers
+                ExpansionChip::Fds => match address {
+                    0x4040 ..= 0x408A => true,
+                    _ => false,
+                },
+            };
+            if listening {
+                return Some(format!("{:?}", chip));
+            }
+        }
+        return None;
+    }
+
+    // Called for every CPU write; ignores the ones no sound chip is listening to
+    pub fn log_write(&mut self, cpu_cycle: u64, address: u16, data: u8) {
+        let chip = match self.chip_name(address) {
+            Some(chip) => chip,
+            None => return,
+        };
+        let line = match self.format {
+            ApuLogFormat::Json => {
+                // Each entry finishes the line before it, so the last one has no comma
+                let separator = if self.writes_logged > 0 {","} else {""};
+                format!("{}\n{{\"cycle\":{},\"chip\":\"{}\",\"address\":\"${:04X}\",\"data\":\"${:02X}\"}}",
+                    separator, cpu_cycle, chip, address, data)
+            },
+            ApuLogFormat::Csv => format!("\n{},{},${:04X},${:02X}", cpu_cycle, chip, address, data),
+        };
+        self.write_str(&line);
+        self.writes_logged += 1;
+    }
+
+    // Closes the JSON array. Nothing more should be logged afterwards.
+    pub fn finish(&mut self) {
+        let footer = match self.format {
+            ApuLogFormat::Json => "\n]\n",
+            ApuLogFormat::Csv => "\n",
+        };
+        self.write_str(footer);
+        let _ = self.writer.flush();
+    }
+}
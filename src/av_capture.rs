@@ -0,0 +1,60 @@
+// Headless video capture, ie for encoding TAS playback. Runs the console for a number
+// of frames while writing raw video and audio to any two writers, which ffmpeg can
+// take directly:
+//
+//   ffmpeg -f rawvideo -pixel_format rgb24 -video_size 256x240 -framerate 60.0988 -i video.rgb
+//          -f s16le -sample_rate 44100 -channels 1 -i audio.pcm capture.mp4
+//
+// Video is 256x240 RGB24 through the standard NTSC palette, one frame after another.
+// Audio is signed 16 bit little endian PCM at the APU's sample rate, interleaved left
+// and right in stereo mode; see ffmpeg_arguments() for the settings to match.
+
+use std::io;
+use std::io::Write;
+
+use crate::nes::NesState;
+use crate::palettes::render_rgb24;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct AvCaptureStats {
+    pub frames: u32,
+    // Per channel
+    pub samples: u64,
+}
+
+// The input options ffmpeg needs to read this console's capture, given the files the
+// two streams were written to
+pub fn ffmpeg_arguments(nes: &NesState, video_path: &str, audio_path: &str) -> String {
+    return format!("-f rawvideo -pixel_format rgb24 -video_size 256x240 -framerate {} -i {} -f s16le -sample_rate {} -channels {} -i {}",
+        nes.region.frame_rate(), video_path, nes.apu.sample_rate, nes.apu.output_channels(), audio_path);
+}
+
+// Runs frames frames, writing each one and its audio as it finishes. Samples queued
+// before the capture starts are discarded so the two streams line up. Stops early,
+// with the frames written so far, if a breakpoint is hit.
+pub fn capture(nes: &mut NesState, frames: u32, video: &mut dyn Write, audio: &mut dyn Write) -> io::Result<AvCaptureStats> {
+    let mut stats = AvCaptureStats::default();
+    let mut rgb = vec![0u8; nes.ppu.screen.len() * 3];
+    let mut pcm = Vec::new();
+    let channels = nes.apu.output_channels() as u64;
+    nes.apu.consume_samples();
+    for _ in 0 .. frames {
+        nes.run_until_vblank();
+        if nes.breakpoints.triggered.is_some() {
+            break;
+        }
+        render_rgb24(&nes.ppu.screen, &mut rgb);
+        video.write_all(&rgb)?;
+        let samples = nes.apu.consume_samples();
+        pcm.clear();
+        for sample in samples.iter() {
+            pcm.extend_from_slice(&sample.to_le_bytes());
+        }
+        audio.write_all(&pcm)?;
+        stats.frames += 1;
+        stats.samples += samples.len() as u64 / channels;
+    }
+    video.flush()?;
+    audio.flush()?;
+    return Ok(stats);
+}
@@ -0,0 +1,271 @@
+// Conditional breakpoints and watchpoints. Conditions are evaluated inside the core,
+// at the exact moment of the access, so debuggers can express things like "break when
+// $2001 is written with bit 3 clear during rendering" without single-stepping and
+// polling from the outside.
+
+use crate::expression::Access;
+use crate::expression::Expression;
+use crate::nes::NesState;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AccessType {
+    Read,
+    Write,
+    Execute,
+}
+
+impl AccessType {
+    fn mask(&self) -> u8 {
+        match self {
+            AccessType::Read    => 0b0000_0001,
+            AccessType::Write   => 0b0000_0010,
+            AccessType::Execute => 0b0000_0100,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    S,
+    P,
+    Pc,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Comparison {
+    Equal(u16),
+    NotEqual(u16),
+    Less(u16),
+    LessOrEqual(u16),
+    Greater(u16),
+    GreaterOrEqual(u16),
+    // True when every bit in the mask is 1 (or 0) in the tested value
+    BitsSet(u16),
+    BitsClear(u16),
+}
+
+impl Comparison {
+    pub fn test(&self, value: u16) -> bool {
+        match *self {
+            Comparison::Equal(other) => value == other,
+            Comparison::NotEqual(other) => value != other,
+            Comparison::Less(other) => value < other,
+            Comparison::LessOrEqual(other) => value <= other,
+            Comparison::Greater(other) => value > other,
+            Comparison::GreaterOrEqual(other) => value >= other,
+            Comparison::BitsSet(mask) => (value & mask) == mask,
+            Comparison::BitsClear(mask) => (value & mask) == 0,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Condition {
+    // Compares a CPU register at the time of the access
+    Register{register: Register, comparison: Comparison},
+    // Compares the byte read or written. For execute breakpoints, this is the opcode.
+    Value(Comparison),
+    // The PRG ROM bank (in units of bank_size) the currently running code lives in
+    CodeBank{bank_size: usize, bank: usize},
+    // The PRG ROM bank (in units of bank_size) backing the accessed address
+    AddressBank{bank_size: usize, bank: usize},
+    // Inclusive range of PPU scanlines, 0-239 visible, 261 pre-render
+    ScanlineRange{first: u16, last: u16},
+    // Whether the PPU is actively rendering (visible or pre-render line with rendering enabled)
+    Rendering(bool),
+    // Anything else; see expression.rs
+    Expression(Expression),
+}
+
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    pub access: AccessType,
+    pub first_address: u16,
+    pub last_address: u16,
+    pub conditions: Vec<Condition>,
+    pub enabled: bool,
+    pub hit_count: u32,
+}
+
+impl Breakpoint {
+    pub fn new(access: AccessType, first_address: u16, last_address: u16) -> Breakpoint {
+        return Breakpoint {
+            access: access,
+            first_address: first_address,
+            last_address: last_address,
+            conditions: Vec::new(),
+            enabled: true,
+            hit_count: 0,
+        }
+    }
+
+    pub fn with_condition(mut self, condition: Condition) -> Breakpoint {
+        self.conditions.push(condition);
+        return self;
+    }
+
+    pub fn covers(&self, address: u16) -> bool {
+        return address >= self.first_address && address <= self.last_address;
+    }
+
+    fn conditions_met(&self, nes: &NesState, address: u16, data: u8, program_counter: u16) -> bool {
+        for condition in &self.conditions {
+            if !condition_met(nes, condition, self.access, address, data, program_counter) {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BreakpointHit {
+    pub index: usize,
+    pub access: AccessType,
+    pub address: u16,
+    pub data: u8,
+    pub program_counter: u16,
+    pub scanline: u16,
+    pub cycle: u16,
+}
+
+pub struct Breakpoints {
+    list: Vec<Breakpoint>,
+    // One entry per CPU address, with a bit set for each access type that has at
+    // least one enabled breakpoint. Keeps the common (no match) case cheap.
+    access_mask: Vec<u8>,
+    pub triggered: Option<BreakpointHit>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        return Breakpoints {
+            list: Vec::new(),
+            access_mask: vec![0u8; 0x10000],
+            triggered: None,
+        }
+    }
+
+    pub fn list(&self) -> &[Breakpoint] {
+        return &self.list;
+    }
+
+    pub fn add(&mut self, breakpoint: Breakpoint) -> usize {
+        self.list.push(breakpoint);
+        self.rebuild_mask();
+        return self.list.len() - 1;
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.list.len() {
+            self.list.remove(index);
+            self.rebuild_mask();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+        self.triggered = None;
+        self.rebuild_mask();
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if index < self.list.len() {
+            self.list[index].enabled = enabled;
+            self.rebuild_mask();
+        }
+    }
+
+    // Returns the pending hit, if any, and clears it so emulation may resume
+    pub fn take_hit(&mut self) -> Option<BreakpointHit> {
+        return self.triggered.take();
+    }
+
+    fn rebuild_mask(&mut self) {
+        for entry in self.access_mask.iter_mut() {
+            *entry = 0;
+        }
+        for breakpoint in self.list.iter().filter(|b| b.enabled) {
+            let mask = breakpoint.access.mask();
+            for address in breakpoint.first_address ..= breakpoint.last_address {
+                self.access_mask[address as usize] |= mask;
+            }
+        }
+    }
+}
+
+fn register_value(nes: &NesState, register: Register) -> u16 {
+    match register {
+        Register::A => nes.registers.a as u16,
+        Register::X => nes.registers.x as u16,
+        Register::Y => nes.registers.y as u16,
+        Register::S => nes.registers.s as u16,
+        Register::P => nes.registers.status_as_byte(false) as u16,
+        Register::Pc => nes.registers.pc,
+    }
+}
+
+fn in_bank(nes: &NesState, address: u16, bank_size: usize, bank: usize) -> bool {
+    if bank_size == 0 {
+        return false;
+    }
+    match nes.mapper.debug_prg_rom_address(address) {
+        Some(rom_address) => rom_address / bank_size == bank,
+        None => false
+    }
+}
+
+fn condition_met(nes: &NesState, condition: &Condition, access: AccessType, address: u16, data: u8, program_counter: u16) -> bool {
+    match *condition {
+        Condition::Register{register, comparison} => comparison.test(register_value(nes, register)),
+        Condition::Value(comparison) => comparison.test(data as u16),
+        Condition::CodeBank{bank_size, bank} => in_bank(nes, program_counter, bank_size, bank),
+        Condition::AddressBank{bank_size, bank} => in_bank(nes, address, bank_size, bank),
+        Condition::ScanlineRange{first, last} => {
+            nes.ppu.current_scanline >= first && nes.ppu.current_scanline <= last
+        },
+        Condition::Rendering(rendering) => {
+            let active_line = nes.ppu.current_scanline <= 239 || nes.ppu.current_scanline == 261;
+            (nes.ppu.rendering_enabled() && active_line) == rendering
+        },
+        Condition::Expression(ref expression) => {
+            expression.is_true(nes, Some(&Access{access: access, address: address, data: data}))
+        },
+    }
+}
+
+// Called by the memory bus for every live access. Records the first matching
+// breakpoint; frontends check nes.breakpoints.triggered (run_until_* stops early
+// when it is set) and call take_hit() to resume.
+pub fn snoop(nes: &mut NesState, access: AccessType, address: u16, data: u8) {
+    if (nes.breakpoints.access_mask[address as usize] & access.mask()) == 0 {
+        return;
+    }
+    let program_counter = match access {
+        AccessType::Execute => address,
+        _ => nes.registers.pc,
+    };
+    for index in 0 .. nes.breakpoints.list.len() {
+        let breakpoint = &nes.breakpoints.list[index];
+        if !breakpoint.enabled || breakpoint.access != access || !breakpoint.covers(address) {
+            continue;
+        }
+        if breakpoint.conditions_met(nes, address, data, program_counter) {
+            nes.breakpoints.list[index].hit_count += 1;
+            if nes.breakpoints.triggered.is_none() {
+                nes.breakpoints.triggered = Some(BreakpointHit {
+                    index: index,
+                    access: access,
+                    address: address,
+                    data: data,
+                    program_counter: program_counter,
+                    scanline: nes.ppu.current_scanline,
+                    cycle: nes.ppu.current_scanline_cycle,
+                });
+            }
+        }
+    }
+}
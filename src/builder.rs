@@ -0,0 +1,414 @@
+// Configuration for a new console. NesState::new(mapper) gives the defaults; the
+// builder is for everything else, so that options can be added here later without
+// breaking existing frontends.
+//
+//   let mut nes = NesStateBuilder::new()
+//       .ram_init(RamInit::Random(seed))
+//       .sample_rate(48000)
+//       .sprite_limit(false)
+//       .build(mapper);
+//   nes.power_on();
+
+use crate::apu::FilterType;
+use crate::apu::ResamplerType;
+use crate::game_overrides::GameOverrides;
+use crate::input::ArkanoidPaddle;
+use crate::input::BarcodeBattler;
+use crate::input::ControllerPort;
+use crate::input::Disconnected;
+use crate::input::FamicomFourPlayer;
+use crate::input::FourScore;
+use crate::input::StandardController;
+use crate::mmc::mapper::ExpansionChip;
+use crate::mmc::mapper::Mapper;
+use crate::nes::NesState;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Region {
+    // Only NTSC timing is emulated so far
+    Ntsc,
+}
+
+impl Region {
+    pub fn cpu_clock_rate(&self) -> u64 {
+        return match self {
+            Region::Ntsc => 1_789_773,
+        };
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        return match self {
+            Region::Ntsc => 60.0988,
+        };
+    }
+}
+
+// Which console the cartridge is plugged into. They run games the same way, but
+// differ in what can be plugged into them and what reaches the speaker.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ConsoleModel {
+    // NES-001. Cartridge audio goes out to the expansion port instead of the mixer,
+    // so expansion chips can't be heard.
+    NesFrontloader,
+    // NES-101, which also has no expansion port at all
+    NesToploader,
+    // HVC-001. The controllers are wired in, and the second has a microphone, read
+    // through bit 2 of $4016.
+    Famicom,
+    // HVC-101, with NES style controller ports but otherwise a Famicom
+    AvFamicom,
+}
+
+impl ConsoleModel {
+    pub fn is_famicom(&self) -> bool {
+        return *self == ConsoleModel::Famicom || *self == ConsoleModel::AvFamicom;
+    }
+
+    pub fn hardwired_controllers(&self) -> bool {
+        return *self == ConsoleModel::Famicom;
+    }
+
+    pub fn has_microphone(&self) -> bool {
+        return *self == ConsoleModel::Famicom;
+    }
+
+    // Whether expansion audio chips on the cartridge are mixed in
+    pub fn cartridge_audio(&self) -> bool {
+        return self.is_famicom();
+    }
+
+    // Mutes the cartridge's expansion chips, if they can't be heard
+    pub fn connect_cartridge_audio(&self, mapper: &mut dyn Mapper) {
+        if !self.cartridge_audio() {
+            for chip in mapper.expansion_chips() {
+                mapper.set_expansion_level(chip, 0.0);
+            }
+        }
+    }
+
+    // Whether an ExpansionDevice can be plugged in at all
+    pub fn expansion_port(&self) -> bool {
+        return *self != ConsoleModel::NesToploader;
+    }
+
+    // Whether device can be plugged in, in place of the controllers
+    pub fn supports(&self, device: InputDevice) -> bool {
+        return match device {
+            InputDevice::StandardController => true,
+            // These plug into NES style controller ports
+            InputDevice::Disconnected | InputDevice::FourScore | InputDevice::ArkanoidPaddle => !self.hardwired_controllers(),
+            // and these into the Famicom's expansion port
+            InputDevice::FamicomFourPlayer | InputDevice::ArkanoidPaddleFamicom | InputDevice::BarcodeBattler => self.is_famicom(),
+        };
+    }
+}
+
+// Rows and columns at each edge of the picture that a frontend should crop. Many
+// games leave garbage there, since most TVs never showed it. The core always
+// renders the full 256x240; this is only advice.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Overscan {
+    pub top: u8,
+    pub bottom: u8,
+    pub left: u8,
+    pub right: u8,
+}
+
+// Console RAM contents at power on. Real hardware is somewhere between patterned
+// and random, and a few games (or their RNGs) depend on it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RamInit {
+    Zeros,
+    Ones,
+    // Four bytes of $00 followed by four of $FF, repeating, as FCEUX does
+    Pattern,
+    // Pseudo-random, reproducible from the seed
+    Random(u64),
+}
+
+impl RamInit {
+    pub fn fill(&self, ram: &mut [u8]) {
+        match *self {
+            RamInit::Zeros => {for byte in ram.iter_mut() {*byte = 0x00;}},
+            RamInit::Ones => {for byte in ram.iter_mut() {*byte = 0xFF;}},
+            RamInit::Pattern => {
+                for (i, byte) in ram.iter_mut().enumerate() {
+                    *byte = if (i & 0x4) == 0 {0x00} else {0xFF};
+                }
+            },
+            RamInit::Random(seed) => {
+                // xorshift64; the seed must not be zero
+                let mut state = seed | 1;
+                for byte in ram.iter_mut() {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    *byte = (state >> 32) as u8;
+                }
+            },
+        }
+    }
+}
+
+// What deterministic mode powers on with
+pub const DETERMINISTIC_RAM_INIT: RamInit = RamInit::Zeros;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InputDevice {
+    StandardController,
+    // Nothing plugged in; the data line always reads 0
+    Disconnected,
+    // Four player adapters go in both ports; see NesState::set_player_buttons()
+    FourScore,
+    FamicomFourPlayer,
+    // Driven by ControllerPort::set_analog(). The Famicom version goes in both ports.
+    ArkanoidPaddle,
+    ArkanoidPaddleFamicom,
+    // Port 1 only; see NesState::insert_barcode()
+    BarcodeBattler,
+}
+
+impl InputDevice {
+    // port is 0 or 1
+    pub fn connect(&self, port: usize) -> Box<dyn ControllerPort> {
+        return match self {
+            InputDevice::StandardController => Box::new(StandardController::new()),
+            InputDevice::Disconnected => Box::new(Disconnected::new()),
+            InputDevice::FourScore => Box::new(FourScore::new(port)),
+            InputDevice::FamicomFourPlayer => Box::new(FamicomFourPlayer::new()),
+            InputDevice::ArkanoidPaddle => Box::new(ArkanoidPaddle::new(false, port)),
+            InputDevice::ArkanoidPaddleFamicom => Box::new(ArkanoidPaddle::new(true, port)),
+            InputDevice::BarcodeBattler => Box::new(BarcodeBattler::new()),
+        };
+    }
+
+    // What goes in each port for an NES 2.0 default expansion device code (see
+    // BoardInfo), if it's one this core has. None for 0, which means unknown.
+    pub fn for_expansion_device(code: u8) -> Option<[InputDevice; 2]> {
+        return match code {
+            0x01 => Some([InputDevice::StandardController, InputDevice::StandardController]),
+            0x02 => Some([InputDevice::FourScore, InputDevice::FourScore]),
+            0x03 => Some([InputDevice::FamicomFourPlayer, InputDevice::FamicomFourPlayer]),
+            0x0F => Some([InputDevice::StandardController, InputDevice::ArkanoidPaddle]),
+            0x10 => Some([InputDevice::ArkanoidPaddleFamicom, InputDevice::ArkanoidPaddleFamicom]),
+            _ => None
+        };
+    }
+}
+
+pub struct NesStateBuilder {
+    region: Region,
+    console: ConsoleModel,
+    ram_init: RamInit,
+    sample_rate: u64,
+    audio_filter: FilterType,
+    audio_filter_hq: bool,
+    resampler: ResamplerType,
+    stereo: bool,
+    expansion_levels: Vec<(ExpansionChip, f32)>,
+    sprite_limit: bool,
+    ppu_alignment: u8,
+    input_devices: [InputDevice; 2],
+    // Ports the frontend hasn't picked a device for take the cartridge's default
+    input_devices_chosen: [bool; 2],
+    deterministic: bool,
+    lazy_ppu: bool,
+    overscan: Overscan,
+}
+
+impl NesStateBuilder {
+    pub fn new() -> NesStateBuilder {
+        return NesStateBuilder {
+            region: Region::Ntsc,
+            console: ConsoleModel::AvFamicom,
+            ram_init: RamInit::Zeros,
+            sample_rate: 44100,
+            audio_filter: FilterType::FamiCom,
+            audio_filter_hq: true,
+            resampler: ResamplerType::Nearest,
+            stereo: false,
+            expansion_levels: Vec::new(),
+            sprite_limit: true,
+            ppu_alignment: 0,
+            input_devices: [InputDevice::StandardController, InputDevice::StandardController],
+            input_devices_chosen: [false; 2],
+            deterministic: false,
+            lazy_ppu: false,
+            overscan: Overscan::default(),
+        }
+    }
+
+    pub fn region(mut self, region: Region) -> NesStateBuilder {
+        self.region = region;
+        return self;
+    }
+
+    // Defaults to the AV Famicom, which takes every device and plays every chip
+    pub fn console(mut self, console: ConsoleModel) -> NesStateBuilder {
+        self.console = console;
+        return self;
+    }
+
+    pub fn ram_init(mut self, ram_init: RamInit) -> NesStateBuilder {
+        self.ram_init = ram_init;
+        return self;
+    }
+
+    pub fn sample_rate(mut self, sample_rate: u64) -> NesStateBuilder {
+        self.sample_rate = sample_rate;
+        return self;
+    }
+
+    // The low quality chain is cheaper, for slow machines
+    pub fn audio_filter(mut self, filter_type: FilterType, hq: bool) -> NesStateBuilder {
+        self.audio_filter = filter_type;
+        self.audio_filter_hq = hq;
+        return self;
+    }
+
+    // Sinc resampling is alias free, at some extra CPU cost; band-limited synthesis is
+    // alias free and cheaper still
+    pub fn resampler(mut self, resampler: ResamplerType) -> NesStateBuilder {
+        self.resampler = resampler;
+        return self;
+    }
+
+    // Interleaved left / right output; see ApuState::set_pan()
+    pub fn stereo(mut self, enabled: bool) -> NesStateBuilder {
+        self.stereo = enabled;
+        return self;
+    }
+
+    // Scales a sound chip against its default balance with the 2A03. Ignored if the
+    // cartridge doesn't have the chip.
+    pub fn expansion_level(mut self, chip: ExpansionChip, level: f32) -> NesStateBuilder {
+        self.expansion_levels.push((chip, level));
+        return self;
+    }
+
+    // Turning the limit off draws every sprite on a scanline, which removes most
+    // flicker but isn't accurate
+    pub fn sprite_limit(mut self, enabled: bool) -> NesStateBuilder {
+        self.sprite_limit = enabled;
+        return self;
+    }
+
+    // The CPU and PPU power on in one of several phase alignments, and a handful of
+    // timing sensitive test ROMs behave differently in each. 0 through 2.
+    pub fn ppu_alignment(mut self, alignment: u8) -> NesStateBuilder {
+        self.ppu_alignment = alignment % 3;
+        return self;
+    }
+
+    // port is 0 or 1. Without this, the port gets the device the cartridge's header
+    // asks for, or else a standard controller. Devices the console has no way to
+    // plug in (see ConsoleModel::supports()) get a standard controller instead.
+    pub fn input_device(mut self, port: usize, device: InputDevice) -> NesStateBuilder {
+        if port < self.input_devices.len() {
+            self.input_devices[port] = device;
+            self.input_devices_chosen[port] = true;
+        }
+        return self;
+    }
+
+    // Pins down everything that can differ between power ons or between machines, so
+    // that the same cartridge and inputs always give the same state_hash() sequence,
+    // for TAS and netplay. RAM powers on zeroed and the CPU and PPU in alignment 0,
+    // overriding ram_init() and ppu_alignment(), and anything that would read the
+    // wall clock runs on emulated time instead. See NesState::deterministic.
+    pub fn deterministic(mut self, enabled: bool) -> NesStateBuilder {
+        self.deterministic = enabled;
+        return self;
+    }
+
+    // Runs the PPU in batches; see NesState::lazy_ppu
+    pub fn lazy_ppu(mut self, enabled: bool) -> NesStateBuilder {
+        self.lazy_ppu = enabled;
+        return self;
+    }
+
+    // See NesState::overscan
+    pub fn overscan(mut self, overscan: Overscan) -> NesStateBuilder {
+        self.overscan = overscan;
+        return self;
+    }
+
+    // Replaces whichever settings the game has overrides for. None changes nothing,
+    // so the result of OverrideTable::lookup() can be passed straight in. Call after
+    // the settings it should take precedence over.
+    pub fn game_overrides(mut self, overrides: Option<&GameOverrides>) -> NesStateBuilder {
+        let overrides = match overrides {
+            Some(overrides) => overrides,
+            None => return self
+        };
+        if let Some(region) = overrides.region {
+            self = self.region(region);
+        }
+        for port in 0 .. overrides.input_devices.len() {
+            if let Some(device) = overrides.input_devices[port] {
+                self = self.input_device(port, device);
+            }
+        }
+        for &(chip, level) in overrides.expansion_levels.iter() {
+            self = self.expansion_level(chip, level);
+        }
+        if let Some(sprite_limit) = overrides.sprite_limit {
+            self = self.sprite_limit(sprite_limit);
+        }
+        if let Some(lazy_ppu) = overrides.lazy_ppu {
+            self = self.lazy_ppu(lazy_ppu);
+        }
+        if let Some(alignment) = overrides.ppu_alignment {
+            self = self.ppu_alignment(alignment);
+        }
+        if let Some(overscan) = overrides.overscan {
+            self = self.overscan(overscan);
+        }
+        return self;
+    }
+
+    // The console still needs power_on() before it will run
+    pub fn build(self, mut mapper: Box<dyn Mapper>) -> NesState {
+        let mut input_devices = self.input_devices;
+        if let Some(defaults) = InputDevice::for_expansion_device(mapper.board_info().default_expansion_device) {
+            for port in 0 .. input_devices.len() {
+                if !self.input_devices_chosen[port] {
+                    input_devices[port] = defaults[port];
+                }
+            }
+        }
+        for &(chip, level) in self.expansion_levels.iter() {
+            mapper.set_expansion_level(chip, level);
+        }
+        self.console.connect_cartridge_audio(&mut *mapper);
+        for device in input_devices.iter_mut() {
+            if !self.console.supports(*device) {
+                *device = InputDevice::StandardController;
+            }
+        }
+        let mut nes = NesState::new(mapper);
+        nes.region = self.region;
+        nes.console = self.console;
+        nes.deterministic = self.deterministic;
+        nes.lazy_ppu = self.lazy_ppu;
+        nes.overscan = self.overscan;
+        nes.apu.cpu_clock_rate = self.region.cpu_clock_rate();
+        nes.configure_rtc();
+        let ram_init = if self.deterministic {DETERMINISTIC_RAM_INIT} else {self.ram_init};
+        ram_init.fill(&mut nes.memory.iram_raw);
+        // Nothing is capturing yet, so neither of these can fail
+        let _ = nes.apu.set_sample_rate(self.sample_rate);
+        nes.apu.resampler_type = self.resampler;
+        nes.apu.set_filter(self.audio_filter, self.audio_filter_hq);
+        let _ = nes.apu.set_stereo(self.stereo);
+        nes.ppu.sprite_limit = self.sprite_limit;
+        // No-ops in deterministic mode
+        for _ in 0 .. self.ppu_alignment {
+            nes.nudge_ppu_alignment();
+        }
+        for (port, device) in input_devices.iter().enumerate() {
+            nes.ports[port] = device.connect(port);
+        }
+        return nes;
+    }
+}
@@ -0,0 +1,313 @@
+// Flat C API, for frontends and language bindings that can't use the Rust API
+// directly. Enabled with the "capi" feature, and built as a shared library with
+//   cargo rustc --lib --release --features capi --crate-type cdylib
+// Every function takes the opaque handle returned by rusticnes_create, and none of
+// them are thread safe with respect to the same handle.
+//
+// Functions returning int use 0 for success and -1 for failure. The framebuffer
+// is 256x240 pixels of 0x00RRGGBB, and audio is mono signed 16-bit.
+
+use std::os::raw::c_int;
+use std::panic;
+use std::ptr;
+
+use crate::builder::NesStateBuilder;
+use crate::cartridge;
+use crate::memory::AddressSpace;
+use crate::mmc::none::NoneMapper;
+use crate::nes::NesState;
+use crate::palettes;
+
+pub const RUSTICNES_SCREEN_WIDTH: usize = 256;
+pub const RUSTICNES_SCREEN_HEIGHT: usize = 240;
+
+pub struct RusticNes {
+    nes: NesState,
+    framebuffer: Vec<u32>,
+    // Samples produced by the APU but not yet collected by the caller
+    audio: Vec<i16>,
+}
+
+// Panics must never unwind across the FFI boundary
+fn guard<T, F: FnOnce() -> T>(fallback: T, f: F) -> T {
+    return panic::catch_unwind(panic::AssertUnwindSafe(f)).unwrap_or(fallback);
+}
+
+unsafe fn handle<'a>(console: *mut RusticNes) -> Option<&'a mut RusticNes> {
+    return console.as_mut();
+}
+
+// Creates a console with no cartridge inserted
+#[no_mangle]
+pub extern "C" fn rusticnes_create() -> *mut RusticNes {
+    let console = RusticNes {
+        nes: NesState::new(Box::new(NoneMapper::new())),
+        framebuffer: vec![0u32; RUSTICNES_SCREEN_WIDTH * RUSTICNES_SCREEN_HEIGHT],
+        audio: Vec::new(),
+    };
+    return Box::into_raw(Box::new(console));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_destroy(console: *mut RusticNes) {
+    if !console.is_null() {
+        drop(Box::from_raw(console));
+    }
+}
+
+// Inserts a cartridge (iNES or NSF file contents) and powers the console on.
+// Debugging state such as breakpoints and cheats is reset.
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_load_rom(console: *mut RusticNes, data: *const u8, length: usize) -> c_int {
+    let console = match handle(console) {
+        Some(console) => console,
+        None => return -1
+    };
+    if data.is_null() {
+        return -1;
+    }
+    let rom = std::slice::from_raw_parts(data, length);
+    let mapper = match guard(None, || cartridge::mapper_from_file(rom).ok()) {
+        Some(mapper) => mapper,
+        None => return -1
+    };
+    let sample_rate = console.nes.apu.sample_rate;
+    let rom_crc32 = cartridge::rom_crc32(rom);
+    console.nes = NesStateBuilder::new()
+        .sample_rate(sample_rate)
+        .build(mapper);
+    console.nes.rom_crc32 = rom_crc32;
+    console.audio.clear();
+    return guard(-1, || {
+        console.nes.power_on();
+        return 0;
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_reset(console: *mut RusticNes) {
+    if let Some(console) = handle(console) {
+        guard((), || console.nes.reset());
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_power_on(console: *mut RusticNes) {
+    if let Some(console) = handle(console) {
+        guard((), || console.nes.power_on());
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_power_cycle(console: *mut RusticNes) {
+    if let Some(console) = handle(console) {
+        guard((), || console.nes.power_cycle());
+    }
+}
+
+// Runs until the start of the next vblank, then updates the framebuffer
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_run_frame(console: *mut RusticNes) -> c_int {
+    let console = match handle(console) {
+        Some(console) => console,
+        None => return -1
+    };
+    let framebuffer = &mut console.framebuffer;
+    let audio = &mut console.audio;
+    let nes = &mut console.nes;
+    return guard(-1, || {
+        let frame = nes.run_frame();
+        palettes::render_xrgb(frame.screen, framebuffer);
+        audio.extend(frame.samples);
+        return 0;
+    });
+}
+
+// Pointer to 256x240 pixels, valid until the next call that takes this handle
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_framebuffer(console: *mut RusticNes) -> *const u32 {
+    return match handle(console) {
+        Some(console) => console.framebuffer.as_ptr(),
+        None => ptr::null()
+    };
+}
+
+// Fails while an audio capture is running
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_set_sample_rate(console: *mut RusticNes, sample_rate: u32) -> c_int {
+    return match handle(console) {
+        Some(console) => match console.nes.apu.set_sample_rate(sample_rate as u64) {
+            Ok(()) => 0,
+            Err(_) => -1
+        },
+        None => -1
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_audio_samples_available(console: *mut RusticNes) -> usize {
+    return match handle(console) {
+        Some(console) => console.audio.len(),
+        None => 0
+    };
+}
+
+// Copies up to max_samples pending samples into buffer, returning how many were copied
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_read_audio(console: *mut RusticNes, buffer: *mut i16, max_samples: usize) -> usize {
+    let console = match handle(console) {
+        Some(console) => console,
+        None => return 0
+    };
+    if buffer.is_null() {
+        return 0;
+    }
+    let count = max_samples.min(console.audio.len());
+    ptr::copy_nonoverlapping(console.audio.as_ptr(), buffer, count);
+    console.audio.drain(0 .. count);
+    return count;
+}
+
+// Buttons are a bitmask: A, B, Select, Start, Up, Down, Left, Right from bit 0 to bit 7.
+// Ports 2 and 3 are players 3 and 4, with a four player adapter connected.
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_set_input(console: *mut RusticNes, port: c_int, buttons: u8) {
+    if let Some(console) = handle(console) {
+        match port {
+            0 ..= 3 => console.nes.set_player_buttons(port as usize, buttons),
+            _ => {}
+        }
+    }
+}
+
+// For paddles: 0.0 to 1.0, left to right. Ignored by devices without a dial.
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_set_analog(console: *mut RusticNes, port: c_int, position: f32) {
+    if let Some(console) = handle(console) {
+        match port {
+            0 | 1 => console.nes.set_analog(port as usize, position),
+            _ => {}
+        }
+    }
+}
+
+// Size in bytes of a savestate at this moment, or 0 if the cartridge can't be saved
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_save_state_size(console: *mut RusticNes) -> usize {
+    return match handle(console) {
+        Some(console) => guard(0, || console.nes.max_state_size()),
+        None => 0
+    };
+}
+
+// Returns the number of bytes written, or 0 if the buffer was too small or saving failed
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_save_state(console: *mut RusticNes, buffer: *mut u8, length: usize) -> usize {
+    let console = match handle(console) {
+        Some(console) => console,
+        None => return 0
+    };
+    if buffer.is_null() {
+        return 0;
+    }
+    let out = std::slice::from_raw_parts_mut(buffer, length);
+    return guard(0, || console.nes.save_state_into(out).unwrap_or(0));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_load_state(console: *mut RusticNes, data: *const u8, length: usize) -> c_int {
+    let console = match handle(console) {
+        Some(console) => console,
+        None => return -1
+    };
+    if data.is_null() {
+        return -1;
+    }
+    let state = std::slice::from_raw_parts(data, length);
+    return guard(-1, || {
+        match console.nes.load_state(state) {
+            Ok(()) => return 0,
+            Err(why) => {
+                println!("rusticnes_load_state: {}", why);
+                return -1;
+            }
+        }
+    });
+}
+
+// In-memory slots, numbered from 0; see NesState::save_slots. Return 0 on success, or
+// -1 if the slot doesn't exist (or for loading, is empty or won't load).
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_save_slot(console: *mut RusticNes, slot: usize) -> c_int {
+    return match handle(console) {
+        Some(console) => guard(-1, || if console.nes.save_slot(slot).is_ok() {0} else {-1}),
+        None => -1
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_load_slot(console: *mut RusticNes, slot: usize) -> c_int {
+    let console = match handle(console) {
+        Some(console) => console,
+        None => return -1
+    };
+    return guard(-1, || {
+        match console.nes.load_slot(slot) {
+            Ok(()) => return 0,
+            Err(why) => {
+                println!("rusticnes_load_slot: {}", why);
+                return -1;
+            }
+        }
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_clear_slot(console: *mut RusticNes, slot: usize) {
+    if let Some(console) = handle(console) {
+        console.nes.save_slots.clear(slot);
+    }
+}
+
+// See NesState::state_hash(); 0 if the console can't be hashed
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_state_hash(console: *mut RusticNes) -> u64 {
+    return match handle(console) {
+        Some(console) => guard(0, || console.nes.state_hash()),
+        None => 0
+    };
+}
+
+// Reads CPU memory without side effects
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_peek(console: *mut RusticNes, address: u16) -> u8 {
+    return match handle(console) {
+        Some(console) => crate::memory::debug_read_byte(&console.nes, address),
+        None => 0
+    };
+}
+
+// Reads up to length bytes from an address space without side effects, returning how
+// many were read. space: 0 CPU, 1 PPU, 2 OAM, 3 palette, 4 PRG ROM, 5 CHR.
+#[no_mangle]
+pub unsafe extern "C" fn rusticnes_peek_range(console: *mut RusticNes, space: u32, address: u32, buffer: *mut u8, length: usize) -> usize {
+    let space = match space {
+        0 => AddressSpace::Cpu,
+        1 => AddressSpace::Ppu,
+        2 => AddressSpace::Oam,
+        3 => AddressSpace::Palette,
+        4 => AddressSpace::PrgRom,
+        5 => AddressSpace::ChrRom,
+        _ => return 0
+    };
+    if buffer.is_null() {
+        return 0;
+    }
+    return match handle(console) {
+        Some(console) => {
+            let buffer = std::slice::from_raw_parts_mut(buffer, length);
+            guard(0, || console.nes.debug_peek_range(space, address as usize, buffer))
+        },
+        None => 0
+    };
+}
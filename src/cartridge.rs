@@ -3,6 +3,7 @@ use crate::mmc::action53::Action53;
 use crate::mmc::axrom::AxRom;
 use crate::mmc::bnrom::BnRom;
 use crate::mmc::cnrom::CnRom;
+use crate::mmc::datach::Datach;
 use crate::mmc::fme7::Fme7;
 use crate::mmc::gxrom::GxRom;
 use crate::mmc::ines31::INes31;
@@ -16,12 +17,17 @@ use crate::mmc::pxrom::PxRom;
 use crate::mmc::uxrom::UxRom;
 use crate::mmc::vrc6::Vrc6;
 
+use crate::checksum;
+use crate::error::Error;
 use crate::ines::INesCartridge;
 use crate::nsf::NsfFile;
 
 use std::io::Read;
 
-fn mapper_from_ines(ines: INesCartridge) -> Result<Box<dyn Mapper>, String> {
+const INES_MAGIC: &[u8] = b"NES\x1A";
+const NSF_MAGIC: &[u8] = b"NESM\x1A";
+
+fn mapper_from_ines(ines: INesCartridge) -> Result<Box<dyn Mapper>, Error> {
     let mapper_number = ines.header.mapper_number();
 
     let mapper: Box<dyn Mapper> = match mapper_number {
@@ -41,8 +47,10 @@ fn mapper_from_ines(ines: INesCartridge) -> Result<Box<dyn Mapper>, String> {
         34 => Box::new(BnRom::from_ines(ines)?),
         66 => Box::new(GxRom::from_ines(ines)?),
         69 => Box::new(Fme7::from_ines(ines)?),
+        157 => Box::new(Datach::from_ines(ines)?),
+        185 => Box::new(CnRom::from_ines(ines)?),
         _ => {
-            return Err(format!("Unsupported iNES mapper: {}", ines.header.mapper_number()));
+            return Err(Error::UnsupportedMapper{mapper: ines.header.mapper_number()});
         }
     };
 
@@ -51,30 +59,35 @@ fn mapper_from_ines(ines: INesCartridge) -> Result<Box<dyn Mapper>, String> {
     return Ok(mapper);
 }
 
-pub fn mapper_from_reader(file_reader: &mut dyn Read) -> Result<Box<dyn Mapper>, String> {
+pub fn mapper_from_reader(file_reader: &mut dyn Read) -> Result<Box<dyn Mapper>, Error> {
     let mut entire_file = Vec::new();
-    match file_reader.read_to_end(&mut entire_file) {
-        Ok(_) => {/* proceed normally */},
-        Err(e) => {
-            return Err(format!("Failed to read any data at all, giving up.{}\n", e));
-        }
-    }
+    file_reader.read_to_end(&mut entire_file)?;
 
-    let mut errors = String::new();
-    match INesCartridge::from_reader(&mut entire_file.as_slice()) {
-        Ok(ines) => {return mapper_from_ines(ines);},
-        Err(e) => {errors += format!("ines: {}\n", e).as_str()}
+    // The magic bytes decide the format; anything wrong past that point is
+    // reported as-is rather than trying the next one.
+    if entire_file.starts_with(INES_MAGIC) {
+        let ines = INesCartridge::from_reader(&mut entire_file.as_slice())?;
+        return mapper_from_ines(ines);
     }
 
-    match NsfFile::from_reader(&mut entire_file.as_slice()) {
-        Ok(nsf) => {return Ok(Box::new(NsfMapper::from_nsf(nsf)?));},
-        Err(e) => {errors += format!("nsf: {}\n", e).as_str()}
+    if entire_file.starts_with(NSF_MAGIC) {
+        let nsf = NsfFile::from_reader(&mut entire_file.as_slice())?;
+        return Ok(Box::new(NsfMapper::from_nsf(nsf)?));
     }
 
-    return Err(format!("Unable to open file as any known type, giving up.\n{}", errors));
+    return Err(Error::UnknownFormat);
+}
+
+// Identifies a cartridge: CRC-32 of everything after the iNES header, which matches
+// the CRCs ROM databases list. NSF files are checksummed whole.
+pub fn rom_crc32(file_data: &[u8]) -> u32 {
+    if file_data.starts_with(INES_MAGIC) && file_data.len() >= 16 {
+        return checksum::crc32(&file_data[16 ..]);
+    }
+    return checksum::crc32(file_data);
 }
 
-pub fn mapper_from_file(file_data: &[u8]) -> Result<Box<dyn Mapper>, String> {
+pub fn mapper_from_file(file_data: &[u8]) -> Result<Box<dyn Mapper>, Error> {
     let mut file_reader = file_data;
     return mapper_from_reader(&mut file_reader);
 }
\ No newline at end of file
@@ -0,0 +1,265 @@
+// Cheat engine. Supports raw RAM cheats, applied either once per frame or every
+// time the game writes the address, and read substitution cheats for ROM, which is
+// what Pro Action Rocky (and FCEUX's "S" cheats) boil down to.
+
+use crate::nes::NesState;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CheatType {
+    // Poke the value into memory at the start of every frame
+    PerFrame,
+    // Replace the value whenever the CPU writes this address
+    PerWrite,
+    // Replace the value whenever the CPU reads this address; used for ROM patches
+    ReadSubstitute,
+}
+
+#[derive(Clone, Debug)]
+pub struct Cheat {
+    pub address: u16,
+    pub value: u8,
+    // Only apply when the original value (read, or currently in memory) matches
+    pub compare: Option<u8>,
+    pub cheat_type: CheatType,
+    pub enabled: bool,
+    pub description: String,
+}
+
+impl Cheat {
+    pub fn new(cheat_type: CheatType, address: u16, value: u8, compare: Option<u8>) -> Cheat {
+        return Cheat {
+            address: address,
+            value: value,
+            compare: compare,
+            cheat_type: cheat_type,
+            enabled: true,
+            description: String::new(),
+        }
+    }
+
+    fn applies_to(&self, original: u8) -> bool {
+        return match self.compare {
+            Some(compare) => original == compare,
+            None => true
+        };
+    }
+}
+
+const CHEAT_READ: u8  = 0b0000_0001;
+const CHEAT_WRITE: u8 = 0b0000_0010;
+
+pub struct CheatEngine {
+    cheats: Vec<Cheat>,
+    // Per-address flags, so reads and writes without cheats stay cheap
+    access_mask: Vec<u8>,
+}
+
+impl CheatEngine {
+    pub fn new() -> CheatEngine {
+        return CheatEngine {
+            cheats: Vec::new(),
+            access_mask: vec![0u8; 0x10000],
+        }
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        return &self.cheats;
+    }
+
+    pub fn add(&mut self, cheat: Cheat) -> usize {
+        self.cheats.push(cheat);
+        self.rebuild_mask();
+        return self.cheats.len() - 1;
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.cheats.len() {
+            self.cheats.remove(index);
+            self.rebuild_mask();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+        self.rebuild_mask();
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if index < self.cheats.len() {
+            self.cheats[index].enabled = enabled;
+            self.rebuild_mask();
+        }
+    }
+
+    pub fn add_par_code(&mut self, code: &str) -> Result<usize, String> {
+        let cheat = decode_pro_action_rocky(code)?;
+        return Ok(self.add(cheat));
+    }
+
+    // FCEUX .cht files, one cheat per line:
+    //   :0075:09:Name          RAM write, every frame
+    //   C:0075:09:03:Name      ... with a compare value
+    //   S:8123:EA:Name         read substitution
+    //   SC:8123:EA:A9:Name     read substitution with a compare value
+    // A leading "-" marks a cheat as disabled.
+    pub fn load_fceux_cht(&mut self, contents: &str) -> Result<usize, String> {
+        let mut count = 0;
+        for (line_number, line) in contents.lines().enumerate() {
+            let mut line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut enabled = true;
+            if line.starts_with('-') {
+                enabled = false;
+                line = &line[1..];
+            }
+            let error = || format!("Malformed cheat on line {}: {}", line_number + 1, line);
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 3 {
+                return Err(error());
+            }
+            let flags = fields[0];
+            let substitute = flags.contains('S');
+            let has_compare = flags.contains('C');
+            let address = u16::from_str_radix(fields[1], 16).map_err(|_| error())?;
+            let value = u8::from_str_radix(fields[2], 16).map_err(|_| error())?;
+            let mut next_field = 3;
+            let compare = if has_compare {
+                let compare_text = fields.get(3).ok_or_else(error)?;
+                next_field = 4;
+                Some(u8::from_str_radix(compare_text, 16).map_err(|_| error())?)
+            } else {
+                None
+            };
+            let cheat_type = if substitute {CheatType::ReadSubstitute} else {CheatType::PerFrame};
+            let mut cheat = Cheat::new(cheat_type, address, value, compare);
+            cheat.enabled = enabled;
+            if fields.len() > next_field {
+                cheat.description = fields[next_field ..].join(":");
+            }
+            self.add(cheat);
+            count += 1;
+        }
+        return Ok(count);
+    }
+
+    fn rebuild_mask(&mut self) {
+        for entry in self.access_mask.iter_mut() {
+            *entry = 0;
+        }
+        for cheat in self.cheats.iter().filter(|c| c.enabled) {
+            match cheat.cheat_type {
+                CheatType::ReadSubstitute => self.access_mask[cheat.address as usize] |= CHEAT_READ,
+                CheatType::PerWrite => self.access_mask[cheat.address as usize] |= CHEAT_WRITE,
+                CheatType::PerFrame => {}
+            }
+        }
+    }
+
+    pub fn substitute_read(&self, address: u16, data: u8) -> u8 {
+        if (self.access_mask[address as usize] & CHEAT_READ) == 0 {
+            return data;
+        }
+        for cheat in self.cheats.iter() {
+            if cheat.enabled && cheat.cheat_type == CheatType::ReadSubstitute &&
+            cheat.address == address && cheat.applies_to(data) {
+                return cheat.value;
+            }
+        }
+        return data;
+    }
+
+    pub fn substitute_write(&self, address: u16, data: u8, current: u8) -> u8 {
+        if (self.access_mask[address as usize] & CHEAT_WRITE) == 0 {
+            return data;
+        }
+        for cheat in self.cheats.iter() {
+            if cheat.enabled && cheat.cheat_type == CheatType::PerWrite &&
+            cheat.address == address && cheat.applies_to(current) {
+                return cheat.value;
+            }
+        }
+        return data;
+    }
+}
+
+// Directly pokes memory without going through the bus, so cheats can't trigger
+// register side effects. Only RAM and cartridge RAM are reachable this way.
+fn poke(nes: &mut NesState, address: u16, data: u8) {
+    match address {
+        0x0000 ..= 0x1FFF => nes.memory.iram_raw[(address & 0x7FF) as usize] = data,
+        0x6000 ..= 0x7FFF => {
+            nes.mapper.write_cpu(address, data);
+            nes.ppu.fetch_cache.invalidate();
+        },
+        _ => {}
+    }
+}
+
+fn peek(nes: &NesState, address: u16) -> u8 {
+    return match address {
+        0x0000 ..= 0x1FFF => nes.memory.iram_raw[(address & 0x7FF) as usize],
+        _ => nes.mapper.debug_read_cpu(address).unwrap_or(nes.memory.open_bus)
+    };
+}
+
+// Called by the memory bus for every CPU write
+pub fn filter_write(nes: &NesState, address: u16, data: u8) -> u8 {
+    if (nes.cheats.access_mask[address as usize] & CHEAT_WRITE) == 0 {
+        return data;
+    }
+    return nes.cheats.substitute_write(address, data, peek(nes, address));
+}
+
+// Called once at the start of every frame
+pub fn apply_frame_cheats(nes: &mut NesState) {
+    for index in 0 .. nes.cheats.cheats.len() {
+        let cheat = &nes.cheats.cheats[index];
+        if !cheat.enabled || cheat.cheat_type == CheatType::ReadSubstitute {
+            continue;
+        }
+        // PerWrite cheats are applied here too, so the value holds even if the
+        // game never writes to the address itself
+        let (address, value) = (cheat.address, cheat.value);
+        if cheat.applies_to(peek(nes, address)) {
+            poke(nes, address, value);
+        }
+    }
+}
+
+// Pro Action Rocky codes are 8 hex digits, obfuscated with a simple shift/xor scheme.
+// The decoded result is always a read substitution somewhere in $8000-$FFFF.
+pub fn decode_pro_action_rocky(code: &str) -> Result<Cheat, String> {
+    let trimmed = code.trim();
+    if trimmed.len() != 8 {
+        return Err(format!("Pro Action Rocky codes are 8 hex digits, got: {}", code));
+    }
+    let mut encoded = u32::from_str_radix(trimmed, 16)
+        .map_err(|_| format!("Pro Action Rocky codes are 8 hex digits, got: {}", code))?;
+
+    const SHIFTS: [u32; 31] = [
+        3, 13, 14, 1, 6, 9, 5, 0, 12, 7, 2, 8, 10, 11, 4,
+        19, 21, 23, 22, 20, 17, 16, 18, 29, 31, 24, 26, 25, 30, 27, 28];
+    let mut key: u32 = 0x7E5EE93A;
+    let xor_value: u32 = 0x5C184B91;
+    let mut result: u32 = 0;
+
+    // Bit 0 is unused
+    encoded >>= 1;
+    for i in (0 ..= 30).rev() {
+        if ((key ^ encoded) >> 30) & 0x1 != 0 {
+            result |= 1 << SHIFTS[i];
+            key ^= xor_value;
+        }
+        encoded <<= 1;
+        key <<= 1;
+    }
+
+    let address = ((result & 0x7FFF) + 0x8000) as u16;
+    let value = ((result >> 24) & 0xFF) as u8;
+    let compare = ((result >> 16) & 0xFF) as u8;
+    let mut cheat = Cheat::new(CheatType::ReadSubstitute, address, value, Some(compare));
+    cheat.description = trimmed.to_uppercase();
+    return Ok(cheat);
+}
@@ -0,0 +1,38 @@
+// Checksums for identifying cartridges and comparing console state.
+
+// CRC-32 (IEEE), as used by zip files and ROM databases
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0 .. 8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    return !crc;
+}
+
+// 64-bit FNV-1a. Fast, and unlike std's hashers, stable across versions and platforms.
+pub struct Fnv1a64 {
+    hash: u64,
+}
+
+impl Fnv1a64 {
+    pub fn new() -> Fnv1a64 {
+        return Fnv1a64 {
+            hash: 0xCBF2_9CE4_8422_2325,
+        };
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.hash ^= byte as u64;
+            self.hash = self.hash.wrapping_mul(0x0000_0100_0000_01B3);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        return self.hash;
+    }
+}
@@ -0,0 +1,80 @@
+// Pictures of the PPU's memory, for debugging graphics a game builds at runtime in
+// CHR RAM: every tile as a sheet, and the four nametables as the PPU would draw them.
+// Both come out as palette indices like PpuState::screen; NesState::chr_tile_sheet_png()
+// and nametables_png() encode them with the "screenshot" feature.
+
+use crate::nes::NesState;
+
+pub const TILE_SHEET_WIDTH: usize = 128;
+pub const NAMETABLES_WIDTH: usize = 512;
+pub const NAMETABLES_HEIGHT: usize = 480;
+
+// The four colors of palette (0 - 3 for the background, 4 - 7 for sprites), with the
+// first always the backdrop, as the PPU draws transparent pixels
+pub fn palette_colors(nes: &NesState, palette: u8) -> [u8; 4] {
+    let base = 0x3F00 + ((palette & 0b111) as u16) * 4;
+    return [
+        nes.ppu.debug_read_byte(&*nes.mapper, 0x3F00),
+        nes.ppu.debug_read_byte(&*nes.mapper, base + 1),
+        nes.ppu.debug_read_byte(&*nes.mapper, base + 2),
+        nes.ppu.debug_read_byte(&*nes.mapper, base + 3),
+    ];
+}
+
+fn tile_pixel(tile: &[u8], x: usize, y: usize) -> usize {
+    let low = (tile[y] >> (7 - x)) & 0b1;
+    let high = (tile[y + 8] >> (7 - x)) & 0b1;
+    return (low | (high << 1)) as usize;
+}
+
+// chr as 8x8 tiles, 16 to a row, in the given colors. The sheet is TILE_SHEET_WIDTH
+// wide and as tall as it needs to be; a trailing partial tile is left out.
+pub fn tile_sheet(chr: &[u8], colors: [u8; 4]) -> Vec<u16> {
+    let tile_count = chr.len() / 16;
+    let rows = (tile_count + 15) / 16;
+    let mut pixels = vec![colors[0] as u16; TILE_SHEET_WIDTH * rows * 8];
+    for (index, tile) in chr.chunks_exact(16).enumerate() {
+        let left = (index % 16) * 8;
+        let top = (index / 16) * 8;
+        for y in 0 .. 8 {
+            for x in 0 .. 8 {
+                pixels[(top + y) * TILE_SHEET_WIDTH + left + x] = colors[tile_pixel(tile, x, y)] as u16;
+            }
+        }
+    }
+    return pixels;
+}
+
+// All four nametables, two by two as they're addressed, with the background pattern
+// table and palettes the PPU is using now. Scrolling, sprites and mid-frame changes
+// aren't shown.
+pub fn nametables(nes: &NesState) -> Vec<u16> {
+    let mut pixels = vec![0u16; NAMETABLES_WIDTH * NAMETABLES_HEIGHT];
+    let pattern_table: u16 = if (nes.ppu.control & 0x10) != 0 {0x1000} else {0x0000};
+    let palettes: Vec<[u8; 4]> = (0 .. 4).map(|palette| palette_colors(nes, palette)).collect();
+    let mut tile = [0u8; 16];
+    for nametable in 0 .. 4u16 {
+        let base = 0x2000 + nametable * 0x400;
+        let left = (nametable as usize % 2) * 256;
+        let top = (nametable as usize / 2) * 240;
+        for row in 0 .. 30u16 {
+            for column in 0 .. 32u16 {
+                let tile_index = nes.ppu.debug_read_byte(&*nes.mapper, base + row * 32 + column) as u16;
+                let attribute = nes.ppu.debug_read_byte(&*nes.mapper, base + 0x3C0 + (row / 4) * 8 + column / 4);
+                let shift = ((row & 0b10) << 1) | (column & 0b10);
+                let colors = palettes[((attribute >> shift) & 0b11) as usize];
+                for i in 0 .. 16 {
+                    tile[i] = nes.ppu.debug_read_byte(&*nes.mapper, pattern_table + tile_index * 16 + i as u16);
+                }
+                for y in 0 .. 8 {
+                    for x in 0 .. 8 {
+                        let pixel_x = left + column as usize * 8 + x;
+                        let pixel_y = top + row as usize * 8 + y;
+                        pixels[pixel_y * NAMETABLES_WIDTH + pixel_x] = colors[tile_pixel(&tile, x, y)] as u16;
+                    }
+                }
+            }
+        }
+    }
+    return pixels;
+}
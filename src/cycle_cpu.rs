@@ -4,11 +4,13 @@
 // http://nesdev.com/6502_cpu.txt - for information on cycle timings for each addressing mode
 
 use crate::addressing;
+use crate::memory::fetch_opcode;
 use crate::memory::read_byte;
 use crate::memory::write_byte;
 use crate::nes::NesState;
 use crate::opcodes;
 use crate::save_load::*;
+use crate::trace;
 use crate::unofficial_opcodes;
 
 #[derive(Copy, Clone)]
@@ -195,7 +197,7 @@ pub fn irq_signal(nes: &NesState) -> bool {
   if nes.registers.flags.interrupts_disabled {
     return false;
   } else {
-    return nes.apu.irq_signal() || nes.mapper.irq_flag();
+    return nes.irq_lines().any();
   }
 }
 
@@ -205,6 +207,7 @@ pub fn poll_for_interrupts(nes: &mut NesState) {
   nes.registers.flags.last_nmi = current_nmi;
   if current_nmi && !last_nmi {
     nes.cpu.nmi_requested = true;
+    nes.event_tracker.snoop_nmi();
   }
   nes.cpu.irq_requested = irq_signal(&nes);
 }
@@ -476,8 +479,11 @@ pub fn run_one_clock(nes: &mut NesState) {
   // Universal behavior for every opcode
   if nes.cpu.tick == 1 {
     // Fetch opcode from memory
+    if nes.tracer.is_some() {
+      trace::trace_instruction(nes);
+    }
     let pc = nes.registers.pc;
-    nes.cpu.opcode = read_byte(nes, pc);
+    nes.cpu.opcode = fetch_opcode(nes, pc);
     nes.registers.pc = nes.registers.pc.wrapping_add(1);
     return; // all done
   }
@@ -0,0 +1,286 @@
+// Decodes 6502 instructions straight out of the live CPU memory map, following
+// whatever banking the mapper currently has selected. Reads go through
+// debug_read_byte, so disassembling never disturbs the running system.
+
+use std::fmt;
+
+use crate::memory::debug_read_byte;
+use crate::nes::NesState;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Relative,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndexedIndirectX,
+    IndirectIndexedY,
+}
+
+impl AddressingMode {
+    // Number of operand bytes following the opcode
+    pub fn operand_bytes(&self) -> u8 {
+        match self {
+            AddressingMode::Implied | AddressingMode::Accumulator => 0,
+            AddressingMode::Absolute | AddressingMode::AbsoluteX |
+            AddressingMode::AbsoluteY | AddressingMode::Indirect => 2,
+            _ => 1
+        }
+    }
+}
+
+use AddressingMode::*;
+
+// Short aliases, purely so the table below stays readable
+const IMP: AddressingMode = Implied;
+const ACC: AddressingMode = Accumulator;
+const IMM: AddressingMode = Immediate;
+const ZPG: AddressingMode = ZeroPage;
+const ZPX: AddressingMode = ZeroPageX;
+const ZPY: AddressingMode = ZeroPageY;
+const REL: AddressingMode = Relative;
+const ABS: AddressingMode = Absolute;
+const ABX: AddressingMode = AbsoluteX;
+const ABY: AddressingMode = AbsoluteY;
+const IND: AddressingMode = Indirect;
+const IZX: AddressingMode = IndexedIndirectX;
+const IZY: AddressingMode = IndirectIndexedY;
+
+// Every opcode, including the unofficial ones this core executes
+const OPCODE_TABLE: [(&str, AddressingMode); 256] = [
+    // 0x00
+    ("BRK", IMP), ("ORA", IZX), ("STP", IMP), ("SLO", IZX), ("NOP", ZPG), ("ORA", ZPG), ("ASL", ZPG), ("SLO", ZPG),
+    ("PHP", IMP), ("ORA", IMM), ("ASL", ACC), ("ANC", IMM), ("NOP", ABS), ("ORA", ABS), ("ASL", ABS), ("SLO", ABS),
+    // 0x10
+    ("BPL", REL), ("ORA", IZY), ("STP", IMP), ("SLO", IZY), ("NOP", ZPX), ("ORA", ZPX), ("ASL", ZPX), ("SLO", ZPX),
+    ("CLC", IMP), ("ORA", ABY), ("NOP", IMP), ("SLO", ABY), ("NOP", ABX), ("ORA", ABX), ("ASL", ABX), ("SLO", ABX),
+    // 0x20
+    ("JSR", ABS), ("AND", IZX), ("STP", IMP), ("RLA", IZX), ("BIT", ZPG), ("AND", ZPG), ("ROL", ZPG), ("RLA", ZPG),
+    ("PLP", IMP), ("AND", IMM), ("ROL", ACC), ("ANC", IMM), ("BIT", ABS), ("AND", ABS), ("ROL", ABS), ("RLA", ABS),
+    // 0x30
+    ("BMI", REL), ("AND", IZY), ("STP", IMP), ("RLA", IZY), ("NOP", ZPX), ("AND", ZPX), ("ROL", ZPX), ("RLA", ZPX),
+    ("SEC", IMP), ("AND", ABY), ("NOP", IMP), ("RLA", ABY), ("NOP", ABX), ("AND", ABX), ("ROL", ABX), ("RLA", ABX),
+    // 0x40
+    ("RTI", IMP), ("EOR", IZX), ("STP", IMP), ("SRE", IZX), ("NOP", ZPG), ("EOR", ZPG), ("LSR", ZPG), ("SRE", ZPG),
+    ("PHA", IMP), ("EOR", IMM), ("LSR", ACC), ("ALR", IMM), ("JMP", ABS), ("EOR", ABS), ("LSR", ABS), ("SRE", ABS),
+    // 0x50
+    ("BVC", REL), ("EOR", IZY), ("STP", IMP), ("SRE", IZY), ("NOP", ZPX), ("EOR", ZPX), ("LSR", ZPX), ("SRE", ZPX),
+    ("CLI", IMP), ("EOR", ABY), ("NOP", IMP), ("SRE", ABY), ("NOP", ABX), ("EOR", ABX), ("LSR", ABX), ("SRE", ABX),
+    // 0x60
+    ("RTS", IMP), ("ADC", IZX), ("STP", IMP), ("RRA", IZX), ("NOP", ZPG), ("ADC", ZPG), ("ROR", ZPG), ("RRA", ZPG),
+    ("PLA", IMP), ("ADC", IMM), ("ROR", ACC), ("ARR", IMM), ("JMP", IND), ("ADC", ABS), ("ROR", ABS), ("RRA", ABS),
+    // 0x70
+    ("BVS", REL), ("ADC", IZY), ("STP", IMP), ("RRA", IZY), ("NOP", ZPX), ("ADC", ZPX), ("ROR", ZPX), ("RRA", ZPX),
+    ("SEI", IMP), ("ADC", ABY), ("NOP", IMP), ("RRA", ABY), ("NOP", ABX), ("ADC", ABX), ("ROR", ABX), ("RRA", ABX),
+    // 0x80
+    ("NOP", IMM), ("STA", IZX), ("NOP", IMM), ("SAX", IZX), ("STY", ZPG), ("STA", ZPG), ("STX", ZPG), ("SAX", ZPG),
+    ("DEY", IMP), ("NOP", IMM), ("TXA", IMP), ("XAA", IMM), ("STY", ABS), ("STA", ABS), ("STX", ABS), ("SAX", ABS),
+    // 0x90
+    ("BCC", REL), ("STA", IZY), ("STP", IMP), ("AHX", IZY), ("STY", ZPX), ("STA", ZPX), ("STX", ZPY), ("SAX", ZPY),
+    ("TYA", IMP), ("STA", ABY), ("TXS", IMP), ("TAS", ABY), ("SHY", ABX), ("STA", ABX), ("SHX", ABY), ("AHX", ABY),
+    // 0xA0
+    ("LDY", IMM), ("LDA", IZX), ("LDX", IMM), ("LAX", IZX), ("LDY", ZPG), ("LDA", ZPG), ("LDX", ZPG), ("LAX", ZPG),
+    ("TAY", IMP), ("LDA", IMM), ("TAX", IMP), ("LAX", IMM), ("LDY", ABS), ("LDA", ABS), ("LDX", ABS), ("LAX", ABS),
+    // 0xB0
+    ("BCS", REL), ("LDA", IZY), ("STP", IMP), ("LAX", IZY), ("LDY", ZPX), ("LDA", ZPX), ("LDX", ZPY), ("LAX", ZPY),
+    ("CLV", IMP), ("LDA", ABY), ("TSX", IMP), ("LAS", ABY), ("LDY", ABX), ("LDA", ABX), ("LDX", ABY), ("LAX", ABY),
+    // 0xC0
+    ("CPY", IMM), ("CMP", IZX), ("NOP", IMM), ("DCP", IZX), ("CPY", ZPG), ("CMP", ZPG), ("DEC", ZPG), ("DCP", ZPG),
+    ("INY", IMP), ("CMP", IMM), ("DEX", IMP), ("AXS", IMM), ("CPY", ABS), ("CMP", ABS), ("DEC", ABS), ("DCP", ABS),
+    // 0xD0
+    ("BNE", REL), ("CMP", IZY), ("STP", IMP), ("DCP", IZY), ("NOP", ZPX), ("CMP", ZPX), ("DEC", ZPX), ("DCP", ZPX),
+    ("CLD", IMP), ("CMP", ABY), ("NOP", IMP), ("DCP", ABY), ("NOP", ABX), ("CMP", ABX), ("DEC", ABX), ("DCP", ABX),
+    // 0xE0
+    ("CPX", IMM), ("SBC", IZX), ("NOP", IMM), ("ISC", IZX), ("CPX", ZPG), ("SBC", ZPG), ("INC", ZPG), ("ISC", ZPG),
+    ("INX", IMP), ("SBC", IMM), ("NOP", IMP), ("SBC", IMM), ("CPX", ABS), ("SBC", ABS), ("INC", ABS), ("ISC", ABS),
+    // 0xF0
+    ("BEQ", REL), ("SBC", IZY), ("STP", IMP), ("ISC", IZY), ("NOP", ZPX), ("SBC", ZPX), ("INC", ZPX), ("ISC", ZPX),
+    ("SED", IMP), ("SBC", ABY), ("NOP", IMP), ("ISC", ABY), ("NOP", ABX), ("SBC", ABX), ("INC", ABX), ("ISC", ABX),
+];
+
+const UNOFFICIAL_MNEMONICS: [&str; 17] = [
+    "STP", "SLO", "RLA", "SRE", "RRA", "SAX", "LAX", "DCP", "ISC",
+    "ANC", "ALR", "ARR", "XAA", "AXS", "AHX", "TAS", "LAS"];
+
+pub fn mnemonic(opcode: u8) -> &'static str {
+    return OPCODE_TABLE[opcode as usize].0;
+}
+
+pub fn addressing_mode(opcode: u8) -> AddressingMode {
+    return OPCODE_TABLE[opcode as usize].1;
+}
+
+pub fn is_official(opcode: u8) -> bool {
+    let name = mnemonic(opcode);
+    return match opcode {
+        0xEA => true,
+        0xEB | 0x9C | 0x9E => false,
+        _ => name != "NOP" && !UNOFFICIAL_MNEMONICS.contains(&name)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Instruction {
+    pub address: u16,
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    // Raw operand: one byte for zero page / immediate / relative, two for absolute modes
+    pub operand: u16,
+    pub length: u8,
+    pub official: bool,
+}
+
+impl Instruction {
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.opcode];
+        if self.length >= 2 {
+            bytes.push((self.operand & 0xFF) as u8);
+        }
+        if self.length >= 3 {
+            bytes.push((self.operand >> 8) as u8);
+        }
+        return bytes;
+    }
+
+    // For branches, the destination taken when the branch succeeds
+    pub fn branch_target(&self) -> Option<u16> {
+        if self.mode != Relative {
+            return None;
+        }
+        let offset = self.operand as u8 as i8;
+        return Some(self.address.wrapping_add(2).wrapping_add(offset as u16));
+    }
+
+    // The address an instruction refers to before any indexing is applied, if it
+    // names one directly. Useful for symbol lookups.
+    pub fn target_address(&self) -> Option<u16> {
+        match self.mode {
+            ZeroPage | ZeroPageX | ZeroPageY | IndexedIndirectX | IndirectIndexedY => Some(self.operand & 0xFF),
+            Absolute | AbsoluteX | AbsoluteY | Indirect => Some(self.operand),
+            Relative => self.branch_target(),
+            _ => None
+        }
+    }
+
+    // The operand in conventional assembler syntax, ie "$0200,X" or "#$10"
+    pub fn operand_string(&self) -> String {
+        return self.format_operand(&format!("${:04X}", self.target_address().unwrap_or(0)));
+    }
+
+    // As operand_string, but with the named address replaced by a label
+    pub fn format_operand(&self, label: &str) -> String {
+        let byte = self.operand & 0xFF;
+        match self.mode {
+            Implied => String::new(),
+            Accumulator => "A".to_string(),
+            Immediate => format!("#${:02X}", byte),
+            ZeroPage => label_or(label, format!("${:02X}", byte)),
+            ZeroPageX => format!("{},X", label_or(label, format!("${:02X}", byte))),
+            ZeroPageY => format!("{},Y", label_or(label, format!("${:02X}", byte))),
+            Relative | Absolute => label.to_string(),
+            AbsoluteX => format!("{},X", label),
+            AbsoluteY => format!("{},Y", label),
+            Indirect => format!("({})", label),
+            IndexedIndirectX => format!("({},X)", label_or(label, format!("${:02X}", byte))),
+            IndirectIndexedY => format!("({}),Y", label_or(label, format!("${:02X}", byte))),
+        }
+    }
+}
+
+// Zero page operands read better as two digits; only substitute real labels
+fn label_or(label: &str, fallback: String) -> String {
+    if label.starts_with("$") {
+        return fallback;
+    }
+    return label.to_string();
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operand = self.operand_string();
+        if operand.is_empty() {
+            return write!(f, "{}", self.mnemonic);
+        }
+        return write!(f, "{} {}", self.mnemonic, operand);
+    }
+}
+
+pub fn decode(address: u16, opcode: u8, operand_low: u8, operand_high: u8) -> Instruction {
+    let mode = addressing_mode(opcode);
+    let operand = match mode.operand_bytes() {
+        0 => 0,
+        1 => operand_low as u16,
+        _ => operand_low as u16 | ((operand_high as u16) << 8),
+    };
+    return Instruction {
+        address: address,
+        opcode: opcode,
+        mnemonic: mnemonic(opcode),
+        mode: mode,
+        operand: operand,
+        length: 1 + mode.operand_bytes(),
+        official: is_official(opcode),
+    }
+}
+
+pub fn disassemble(nes: &NesState, address: u16) -> Instruction {
+    let opcode = debug_read_byte(nes, address);
+    let operand_low = debug_read_byte(nes, address.wrapping_add(1));
+    let operand_high = debug_read_byte(nes, address.wrapping_add(2));
+    return decode(address, opcode, operand_low, operand_high);
+}
+
+// Linear sweep starting at address. Data mixed in with code will decode as
+// garbage, same as any other linear disassembler.
+pub fn disassemble_range(nes: &NesState, address: u16, count: usize) -> Vec<Instruction> {
+    let mut instructions = Vec::with_capacity(count);
+    let mut current_address = address;
+    for _ in 0 .. count {
+        let instruction = disassemble(nes, current_address);
+        current_address = current_address.wrapping_add(instruction.length as u16);
+        instructions.push(instruction);
+    }
+    return instructions;
+}
+
+// Resolves the final address an instruction will access, given the current
+// register state. Intended to be called right before the instruction executes.
+pub fn effective_address(nes: &NesState, instruction: &Instruction) -> Option<u16> {
+    let x = nes.registers.x as u16;
+    let y = nes.registers.y as u16;
+    let zp = instruction.operand & 0xFF;
+    let read_pointer = |low_address: u16, high_address: u16| -> u16 {
+        return debug_read_byte(nes, low_address) as u16 | ((debug_read_byte(nes, high_address) as u16) << 8);
+    };
+    match instruction.mode {
+        ZeroPage => Some(zp),
+        ZeroPageX => Some((zp + x) & 0xFF),
+        ZeroPageY => Some((zp + y) & 0xFF),
+        Absolute => Some(instruction.operand),
+        AbsoluteX => Some(instruction.operand.wrapping_add(x)),
+        AbsoluteY => Some(instruction.operand.wrapping_add(y)),
+        Indirect => {
+            // The high byte fetch does not carry into the next page
+            let low = instruction.operand;
+            let high = (low & 0xFF00) | (low.wrapping_add(1) & 0x00FF);
+            Some(read_pointer(low, high))
+        },
+        IndexedIndirectX => {
+            let pointer = (zp + x) & 0xFF;
+            Some(read_pointer(pointer, (pointer + 1) & 0xFF))
+        },
+        IndirectIndexedY => {
+            Some(read_pointer(zp, (zp + 1) & 0xFF).wrapping_add(y))
+        },
+        Relative => instruction.branch_target(),
+        _ => None
+    }
+}
@@ -0,0 +1,112 @@
+// DPCM samples, the DMC's one-bit delta encoded sound: pulled out of the cartridge for
+// sample rippers, and decoded to PCM for listening to, or for checking a sample that
+// plays wrong against what's actually in the ROM.
+//
+// Nothing marks sample data as such in a ROM, and it can't be reliably told apart from
+// code or graphics, so rather than guess, DpcmRipper collects samples as the game
+// plays them; NesState::start_dpcm_rip() runs one.
+
+use std::io;
+
+use crate::apu::AudioSink;
+use crate::apu::WavFileSink;
+use crate::apu::DMC_PERIODS;
+use crate::nes::NesState;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct DpcmSample {
+    // Where the DMC read it from, $C000 - $FFC0
+    pub address: u16,
+    // The same, as an offset into PRG ROM, for mappers that can tell
+    pub rom_offset: Option<usize>,
+    pub data: Vec<u8>,
+    // As written to $4010, 0 - 15
+    pub rate_index: u8,
+}
+
+// The output level after every bit of data, starting from initial_level as written to
+// $4011. Each bit steps the 7-bit level up or down by 2, low bit first, and steps that
+// would leave 0 - 127 are skipped, as on the DMC.
+pub fn decode(data: &[u8], initial_level: u8) -> Vec<u8> {
+    let mut level = initial_level & 0x7F;
+    let mut levels = Vec::with_capacity(data.len() * 8);
+    for byte in data.iter() {
+        for bit in 0 .. 8 {
+            if (byte >> bit) & 0b1 != 0 {
+                if level <= 125 {
+                    level += 2;
+                }
+            } else if level >= 2 {
+                level -= 2;
+            }
+            levels.push(level);
+        }
+    }
+    return levels;
+}
+
+impl DpcmSample {
+    // length bytes from address on as the DMC would read them, wrapping from $FFFF
+    // back to $8000
+    pub fn read(nes: &NesState, address: u16, length: usize, rate_index: u8) -> DpcmSample {
+        let data = (0 .. length).map(|i| {
+            let byte_address = 0x8000 | (address.wrapping_add(i as u16) & 0x7FFF);
+            nes.mapper.debug_read_cpu(byte_address).unwrap_or(0)
+        }).collect();
+        return DpcmSample {
+            address: address,
+            rom_offset: nes.mapper.debug_prg_rom_address(address),
+            data: data,
+            rate_index: rate_index & 0xF,
+        };
+    }
+
+    // The sample $4010, $4012 and $4013 point to now
+    pub fn current(nes: &NesState) -> DpcmSample {
+        let dmc = &nes.apu.dmc;
+        let rate_index = DMC_PERIODS.iter().position(|&period| period / 2 == dmc.period_initial).unwrap_or(0);
+        return DpcmSample::read(nes, dmc.starting_address, dmc.sample_length as usize, rate_index as u8);
+    }
+
+    // The rate the DMC plays it at, in Hz
+    pub fn sample_rate(&self, cpu_clock_rate: u64) -> u32 {
+        return (cpu_clock_rate / DMC_PERIODS[self.rate_index as usize] as u64) as u32;
+    }
+
+    // decode(), centered and scaled to 16 bits
+    pub fn to_pcm(&self, initial_level: u8) -> Vec<i16> {
+        return decode(&self.data, initial_level).iter().map(|&level| (level as i16 - 64) * 512).collect();
+    }
+
+    // A mono .wav at sample_rate(), starting from the middle level
+    pub fn write_wav(&self, filename: &str, cpu_clock_rate: u64) -> io::Result<()> {
+        let mut sink = WavFileSink::new(filename, self.sample_rate(cpu_clock_rate))?;
+        sink.push_samples(&self.to_pcm(64));
+        return sink.finish();
+    }
+}
+
+// Every distinct sample the DMC has started, in the order they were first played.
+// The same data played at another rate counts as the same sample.
+pub struct DpcmRipper {
+    pub samples: Vec<DpcmSample>,
+}
+
+impl DpcmRipper {
+    pub fn new() -> DpcmRipper {
+        return DpcmRipper {
+            samples: Vec::new(),
+        };
+    }
+
+    // Called as the DMC fetches the first byte of a sample
+    pub(crate) fn sample_started(&mut self, nes: &NesState) {
+        let sample = DpcmSample::current(nes);
+        let seen = self.samples.iter().any(|seen| {
+            seen.address == sample.address && seen.rom_offset == sample.rom_offset && seen.data == sample.data
+        });
+        if !seen {
+            self.samples.push(sample);
+        }
+    }
+}
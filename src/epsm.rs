@@ -0,0 +1,573 @@
+// EPSM: a Yamaha YMF288 (OPN3-L) on the expansion port, written through $401C - $401F,
+// which the 2A03 otherwise ignores. $401C / $401D are the address and data registers
+// for the SSG, the rhythm unit and FM channels 1 - 3; $401E / $401F are the same for
+// FM channels 4 - 6.
+// Reference: https://www.nesdev.org/wiki/Expansion_Port_Sound_Module
+//
+// The SSG is the same AY-3-8910 family core as the Sunsoft 5B, so it's shared with
+// fme7.rs, just clocked faster. The FM channels are modelled per sample at the chip's
+// own rate, with the envelope, detune, key scaling, feedback, algorithm and LFO
+// behaviour of the OPN family, but using floating point rather than the chip's log
+// tables, so they won't be bit exact. Not emulated: the rhythm unit (its samples are in
+// the chip's internal ROM), channel 3's special mode, SSG-EG, and the timers, whose
+// IRQ the EPSM doesn't connect.
+
+use std::f32::consts::TAU;
+
+use crate::expansion_port::ExpansionDevice;
+use crate::mmc::fme7::YM2149F;
+use crate::save_load::*;
+
+const MASTER_CLOCK: f64 = 8_000_000.0;
+const CPU_CLOCK: f64 = 1_789_773.0;
+// The FM channels produce one sample per 144 master clocks
+const FM_SAMPLE_RATE: f64 = MASTER_CLOCK / 144.0;
+// YM2149F::clock() expects to be called at the 5B's rate of 16 times per tone step;
+// the YMF288's SSG steps once every 32 master clocks
+const SSG_CLOCK: f64 = MASTER_CLOCK / 2.0;
+
+// How loud one FM channel at full volume is, and the SSG as a whole, on the same scale
+// as the 2A03's mixed output. A single FM channel is about as loud as a pulse channel.
+const FM_CHANNEL_LEVEL: f32 = 0.12;
+const SSG_LEVEL: f32 = 0.5;
+
+// Operators are stored in slot order (S1 - S4, as the algorithms number them), but
+// their registers are laid out S1, S3, S2, S4
+const REGISTER_SLOTS: [usize; 4] = [0, 2, 1, 3];
+
+// Phase added for each detune setting, indexed by key code
+const DETUNE_TABLE: [[u8; 32]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 3, 3, 3, 4, 4, 4, 5, 5, 6, 6, 7, 8, 8, 8, 8],
+    [1, 1, 1, 1, 2, 2, 2, 2, 2, 3, 3, 3, 4, 4, 4, 5, 5, 6, 6, 7, 8, 8, 9, 10, 11, 12, 13, 14, 16, 16, 16, 16],
+    [2, 2, 2, 2, 2, 3, 3, 3, 4, 4, 4, 5, 5, 6, 6, 7, 8, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 20, 22, 22, 22, 22],
+];
+
+const LFO_FREQUENCIES: [f32; 8] = [3.98, 5.56, 6.02, 6.37, 6.88, 9.63, 48.1, 72.2];
+// Tremolo depth in envelope steps (0.09375 dB each) for each AMS setting
+const AM_DEPTHS: [f32; 4] = [0.0, 15.0, 63.0, 126.0];
+// Vibrato depth in cents for each PMS setting
+const PM_DEPTHS: [f32; 8] = [0.0, 3.4, 6.7, 10.0, 14.0, 20.0, 40.0, 80.0];
+
+// An operator's attenuation, like the chip's envelope, runs from 0 (full volume) to
+// 1023 (silent) in steps of 0.09375 dB
+const MAX_ATTENUATION: f32 = 1023.0;
+
+#[derive(Clone, Copy, PartialEq)]
+enum EnvelopePhase {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+#[derive(Clone)]
+struct FmOperator {
+    detune: u8,
+    multiple: u8,
+    total_level: u8,
+    key_scale: u8,
+    attack_rate: u8,
+    am_enabled: bool,
+    decay_rate: u8,
+    sustain_rate: u8,
+    sustain_level: u8,
+    release_rate: u8,
+
+    // 20 bits, one full wave per wrap
+    phase: u32,
+    attenuation: f32,
+    envelope_phase: EnvelopePhase,
+    key_on: bool,
+}
+
+impl FmOperator {
+    fn new() -> FmOperator {
+        return FmOperator {
+            detune: 0,
+            multiple: 0,
+            total_level: 0,
+            key_scale: 0,
+            attack_rate: 0,
+            am_enabled: false,
+            decay_rate: 0,
+            sustain_rate: 0,
+            sustain_level: 0,
+            release_rate: 0,
+            phase: 0,
+            attenuation: MAX_ATTENUATION,
+            envelope_phase: EnvelopePhase::Release,
+            key_on: false,
+        }
+    }
+
+    // Rates are given in the registers' units, half an envelope rate each, and sped
+    // up for higher notes by the key scale setting
+    fn envelope_rate(&self, rate: u8, key_code: u8) -> u8 {
+        if rate == 0 {
+            return 0;
+        }
+        let key_scaling = key_code >> (3 - self.key_scale);
+        return (rate * 2 + key_scaling).min(63);
+    }
+
+    fn key(&mut self, on: bool, key_code: u8) {
+        if on && !self.key_on {
+            self.phase = 0;
+            self.envelope_phase = EnvelopePhase::Attack;
+            if self.envelope_rate(self.attack_rate, key_code) >= 62 {
+                self.attenuation = 0.0;
+                self.envelope_phase = EnvelopePhase::Decay;
+            }
+        }
+        if !on && self.key_on {
+            self.envelope_phase = EnvelopePhase::Release;
+        }
+        self.key_on = on;
+    }
+
+    fn clock_envelope(&mut self, key_code: u8) {
+        let rate = match self.envelope_phase {
+            EnvelopePhase::Attack => self.envelope_rate(self.attack_rate, key_code),
+            EnvelopePhase::Decay => self.envelope_rate(self.decay_rate, key_code),
+            EnvelopePhase::Sustain => self.envelope_rate(self.sustain_rate, key_code),
+            EnvelopePhase::Release => self.envelope_rate(self.release_rate * 2 + 1, key_code),
+        };
+        let step = envelope_step(rate);
+        match self.envelope_phase {
+            EnvelopePhase::Attack => {
+                if rate >= 62 {
+                    self.attenuation = 0.0;
+                } else {
+                    // Attack is exponential, slowing as it approaches full volume
+                    self.attenuation -= (self.attenuation + 1.0) * step / 16.0;
+                }
+                if self.attenuation <= 0.0 {
+                    self.attenuation = 0.0;
+                    self.envelope_phase = EnvelopePhase::Decay;
+                }
+            },
+            EnvelopePhase::Decay => {
+                self.attenuation += step;
+                // The top sustain level is 93 dB, not 45
+                let sustain_level = if self.sustain_level == 15 {31.0} else {self.sustain_level as f32};
+                if self.attenuation >= sustain_level * 32.0 {
+                    self.envelope_phase = EnvelopePhase::Sustain;
+                }
+            },
+            EnvelopePhase::Sustain | EnvelopePhase::Release => {
+                self.attenuation += step;
+            }
+        }
+        self.attenuation = self.attenuation.min(MAX_ATTENUATION);
+    }
+
+    fn advance_phase(&mut self, fnum: u32, block: u8, key_code: u8) {
+        let mut increment = ((fnum << block) >> 1) as i32;
+        let detune = DETUNE_TABLE[(self.detune & 0b11) as usize][key_code as usize] as i32;
+        if (self.detune & 0b100) != 0 {
+            increment -= detune;
+        } else {
+            increment += detune;
+        }
+        let mut increment = (increment as u32) & 0x1FFFF;
+        if self.multiple == 0 {
+            increment /= 2;
+        } else {
+            increment *= self.multiple as u32;
+        }
+        self.phase = (self.phase + increment) & 0xFFFFF;
+    }
+
+    // modulation is in whole waves
+    fn output(&self, modulation: f32, tremolo: f32) -> f32 {
+        let attenuation = self.attenuation + (self.total_level as f32) * 8.0 + if self.am_enabled {tremolo} else {0.0};
+        if attenuation >= MAX_ATTENUATION {
+            return 0.0;
+        }
+        let phase = (self.phase as f32) / 1_048_576.0 + modulation;
+        // 64 steps is 6 dB, or half the amplitude
+        return (phase * TAU).sin() * 2f32.powf(-attenuation / 64.0);
+    }
+}
+
+// Average attenuation change per envelope clock at a given rate. Every 4 rates double
+// the speed, topping out at 8 steps per clock.
+fn envelope_step(rate: u8) -> f32 {
+    if rate < 4 {
+        return 0.0;
+    }
+    let fraction = 1.0 + (rate & 0b11) as f32 / 4.0;
+    return (fraction * 2f32.powi((rate >> 2) as i32 - 11)).min(8.0);
+}
+
+// The block and the top bits of the frequency, which pick the envelope and detune
+// speeds
+fn key_code(fnum: u16, block: u8) -> u8 {
+    let f11 = (fnum >> 10) & 1;
+    let f10_8 = (fnum >> 7) & 0b111;
+    let n3 = if f11 != 0 {f10_8 != 0} else {f10_8 == 0b111};
+    return (block << 2) | ((f11 as u8) << 1) | (n3 as u8);
+}
+
+#[derive(Clone)]
+struct FmChannel {
+    fnum: u16,
+    block: u8,
+    algorithm: u8,
+    feedback: u8,
+    left: bool,
+    right: bool,
+    ams: u8,
+    pms: u8,
+    operators: [FmOperator; 4],
+    // S1's last two outputs, which feed back into it
+    feedback_history: [f32; 2],
+}
+
+impl FmChannel {
+    fn new() -> FmChannel {
+        return FmChannel {
+            fnum: 0,
+            block: 0,
+            algorithm: 0,
+            feedback: 0,
+            // Both outputs are enabled at reset
+            left: true,
+            right: true,
+            ams: 0,
+            pms: 0,
+            operators: [FmOperator::new(), FmOperator::new(), FmOperator::new(), FmOperator::new()],
+            feedback_history: [0.0; 2],
+        }
+    }
+
+    fn key_code(&self) -> u8 {
+        return key_code(self.fnum, self.block);
+    }
+
+    // lfo is the LFO's position as a sine, from -1.0 to 1.0
+    fn clock(&mut self, lfo: Option<f32>, clock_envelopes: bool) -> f32 {
+        let key_code = self.key_code();
+        let (tremolo, vibrato) = match lfo {
+            Some(lfo) => (
+                AM_DEPTHS[self.ams as usize] * (1.0 - lfo) / 2.0,
+                2f32.powf(PM_DEPTHS[self.pms as usize] * lfo / 1200.0)),
+            None => (0.0, 1.0)
+        };
+        let fnum = ((self.fnum as f32) * vibrato) as u32;
+        for operator in self.operators.iter_mut() {
+            operator.advance_phase(fnum, self.block, key_code);
+            if clock_envelopes {
+                operator.clock_envelope(key_code);
+            }
+        }
+
+        // A modulator's full output swings its carrier's phase by 4 waves either way
+        const MODULATION: f32 = 4.0;
+        let feedback = if self.feedback > 0 {
+            (self.feedback_history[0] + self.feedback_history[1]) * 2f32.powi(self.feedback as i32 - 7)
+        } else {
+            0.0
+        };
+        let ops = &self.operators;
+        let s1 = ops[0].output(feedback, tremolo);
+        self.feedback_history = [self.feedback_history[1], s1];
+        let output = match self.algorithm {
+            0 => {
+                let s2 = ops[1].output(s1 * MODULATION, tremolo);
+                let s3 = ops[2].output(s2 * MODULATION, tremolo);
+                ops[3].output(s3 * MODULATION, tremolo)
+            },
+            1 => {
+                let s2 = ops[1].output(0.0, tremolo);
+                let s3 = ops[2].output((s1 + s2) * MODULATION, tremolo);
+                ops[3].output(s3 * MODULATION, tremolo)
+            },
+            2 => {
+                let s2 = ops[1].output(0.0, tremolo);
+                let s3 = ops[2].output(s2 * MODULATION, tremolo);
+                ops[3].output((s1 + s3) * MODULATION, tremolo)
+            },
+            3 => {
+                let s2 = ops[1].output(s1 * MODULATION, tremolo);
+                let s3 = ops[2].output(0.0, tremolo);
+                ops[3].output((s2 + s3) * MODULATION, tremolo)
+            },
+            4 => {
+                let s2 = ops[1].output(s1 * MODULATION, tremolo);
+                let s3 = ops[2].output(0.0, tremolo);
+                s2 + ops[3].output(s3 * MODULATION, tremolo)
+            },
+            5 => {
+                ops[1].output(s1 * MODULATION, tremolo) +
+                ops[2].output(s1 * MODULATION, tremolo) +
+                ops[3].output(s1 * MODULATION, tremolo)
+            },
+            6 => {
+                ops[1].output(s1 * MODULATION, tremolo) +
+                ops[2].output(0.0, tremolo) +
+                ops[3].output(0.0, tremolo)
+            },
+            _ => {
+                s1 + ops[1].output(0.0, tremolo) + ops[2].output(0.0, tremolo) + ops[3].output(0.0, tremolo)
+            }
+        };
+        return output.max(-1.0).min(1.0);
+    }
+}
+
+pub struct Epsm {
+    // The last address written to each bank
+    address: [u8; 2],
+    // Everything written, for savestates
+    registers: [Vec<u8>; 2],
+    // Writes to $A4 - $A6 wait here until the matching $A0 - $A2 write
+    fnum_latch: u8,
+    fm_channels: Vec<FmChannel>,
+    ssg: YM2149F,
+    lfo_enabled: bool,
+    lfo_rate: u8,
+    lfo_phase: f32,
+    envelope_divider: u8,
+    fm_cycles: f64,
+    ssg_cycles: f64,
+    fm_output: f32,
+    // Scales everything the EPSM outputs
+    pub level: f32,
+}
+
+impl Epsm {
+    pub fn new() -> Epsm {
+        let mut epsm = Epsm {
+            address: [0; 2],
+            registers: [vec![0u8; 0x100], vec![0u8; 0x100]],
+            fnum_latch: 0,
+            fm_channels: vec![FmChannel::new(); 6],
+            ssg: YM2149F::new(),
+            lfo_enabled: false,
+            lfo_rate: 0,
+            lfo_phase: 0.0,
+            envelope_divider: 0,
+            fm_cycles: 0.0,
+            ssg_cycles: 0.0,
+            fm_output: 0.0,
+            level: 1.0,
+        };
+        // Channel outputs come out of reset enabled
+        for address in 0xB4 ..= 0xB6 {
+            epsm.registers[0][address] = 0xC0;
+            epsm.registers[1][address] = 0xC0;
+        }
+        return epsm;
+    }
+
+    fn write_data(&mut self, bank: usize, address: u8, data: u8) {
+        self.registers[bank][address as usize] = data;
+        match address {
+            0x00 ..= 0x0F if bank == 0 => self.ssg.execute_command(address, data),
+            0x22 if bank == 0 => {
+                self.lfo_enabled = (data & 0b0000_1000) != 0;
+                self.lfo_rate = data & 0b0000_0111;
+            },
+            0x28 if bank == 0 => {
+                let channel = match data & 0b0000_0111 {
+                    0 ..= 2 => (data & 0b0000_0111) as usize,
+                    4 ..= 6 => (data & 0b0000_0111) as usize - 1,
+                    _ => return
+                };
+                let key_code = self.fm_channels[channel].key_code();
+                for slot in 0 .. 4 {
+                    let on = (data & (0b0001_0000 << slot)) != 0;
+                    self.fm_channels[channel].operators[slot].key(on, key_code);
+                }
+            },
+            0x30 ..= 0x9F => {
+                if (address & 0b11) == 0b11 {
+                    return;
+                }
+                let channel = &mut self.fm_channels[bank * 3 + (address & 0b11) as usize];
+                let operator = &mut channel.operators[REGISTER_SLOTS[((address >> 2) & 0b11) as usize]];
+                match address & 0xF0 {
+                    0x30 => {
+                        operator.detune = (data >> 4) & 0b111;
+                        operator.multiple = data & 0x0F;
+                    },
+                    0x40 => operator.total_level = data & 0x7F,
+                    0x50 => {
+                        operator.key_scale = data >> 6;
+                        operator.attack_rate = data & 0x1F;
+                    },
+                    0x60 => {
+                        operator.am_enabled = (data & 0b1000_0000) != 0;
+                        operator.decay_rate = data & 0x1F;
+                    },
+                    0x70 => operator.sustain_rate = data & 0x1F,
+                    0x80 => {
+                        operator.sustain_level = data >> 4;
+                        operator.release_rate = data & 0x0F;
+                    },
+                    // SSG-EG isn't emulated
+                    _ => {}
+                }
+            },
+            0xA0 ..= 0xA2 => {
+                let channel = &mut self.fm_channels[bank * 3 + (address - 0xA0) as usize];
+                channel.fnum = (((self.fnum_latch & 0b111) as u16) << 8) | data as u16;
+                channel.block = (self.fnum_latch >> 3) & 0b111;
+            },
+            0xA4 ..= 0xA6 => self.fnum_latch = data,
+            0xB0 ..= 0xB2 => {
+                let channel = &mut self.fm_channels[bank * 3 + (address - 0xB0) as usize];
+                channel.feedback = (data >> 3) & 0b111;
+                channel.algorithm = data & 0b111;
+            },
+            0xB4 ..= 0xB6 => {
+                let channel = &mut self.fm_channels[bank * 3 + (address - 0xB4) as usize];
+                channel.left = (data & 0b1000_0000) != 0;
+                channel.right = (data & 0b0100_0000) != 0;
+                channel.ams = (data >> 4) & 0b11;
+                channel.pms = data & 0b111;
+            },
+            _ => {}
+        }
+    }
+
+    fn clock_fm(&mut self) {
+        let lfo = if self.lfo_enabled {
+            self.lfo_phase += LFO_FREQUENCIES[self.lfo_rate as usize] / FM_SAMPLE_RATE as f32;
+            self.lfo_phase = self.lfo_phase.fract();
+            Some((self.lfo_phase * TAU).sin())
+        } else {
+            None
+        };
+        // Envelopes move once every 3 samples
+        self.envelope_divider += 1;
+        let clock_envelopes = self.envelope_divider == 3;
+        if clock_envelopes {
+            self.envelope_divider = 0;
+        }
+        let mut output = 0.0;
+        for channel in self.fm_channels.iter_mut() {
+            let sample = channel.clock(lfo, clock_envelopes);
+            // The rest of the console is mono, so a channel panned to either side
+            // is heard at full volume
+            if channel.left || channel.right {
+                output += sample;
+            }
+        }
+        self.fm_output = output;
+    }
+}
+
+impl ExpansionDevice for Epsm {
+    fn write(&mut self, _data: u8) {}
+
+    fn read(&mut self, _port: usize) -> u8 {
+        return 0;
+    }
+
+    fn peek(&self, _port: usize) -> u8 {
+        return 0;
+    }
+
+    fn write_register(&mut self, address: u16, data: u8) {
+        match address {
+            0x401C => self.address[0] = data,
+            0x401D => self.write_data(0, self.address[0], data),
+            0x401E => self.address[1] = data,
+            0x401F => self.write_data(1, self.address[1], data),
+            _ => {}
+        }
+    }
+
+    fn audio_input(&self) -> f32 {
+        return (self.fm_output * FM_CHANNEL_LEVEL + self.ssg.output() * SSG_LEVEL) * self.level;
+    }
+
+    fn clock_cpu(&mut self) {
+        self.fm_cycles += FM_SAMPLE_RATE / CPU_CLOCK;
+        if self.fm_cycles >= 1.0 {
+            self.fm_cycles -= 1.0;
+            self.clock_fm();
+        }
+        self.ssg_cycles += SSG_CLOCK / CPU_CLOCK;
+        while self.ssg_cycles >= 1.0 {
+            self.ssg_cycles -= 1.0;
+            self.ssg.clock();
+        }
+    }
+
+    // Register contents, plus where each operator's envelope and phase had got to. The
+    // SSG is rebuilt from its registers on load, restarting its envelope.
+    fn save_state(&self, buff: &mut Vec<u8>) {
+        save_vec(buff, &self.registers[0]);
+        save_vec(buff, &self.registers[1]);
+        save_u8(buff, self.address[0]);
+        save_u8(buff, self.address[1]);
+        save_u8(buff, self.fnum_latch);
+        for channel in self.fm_channels.iter() {
+            for operator in channel.operators.iter() {
+                save_u32(buff, operator.phase);
+                save_u16(buff, operator.attenuation as u16);
+                save_u8(buff, operator.envelope_phase as u8);
+                save_bool(buff, operator.key_on);
+            }
+        }
+    }
+
+    fn load_state(&mut self, buff: &mut Vec<u8>) {
+        let mut operators = Vec::new();
+        for _ in 0 .. 6 * 4 {
+            let mut key_on = false;
+            let mut envelope_phase = 0u8;
+            let mut attenuation = 0u16;
+            let mut phase = 0u32;
+            load_bool(buff, &mut key_on);
+            load_u8(buff, &mut envelope_phase);
+            load_u16(buff, &mut attenuation);
+            load_u32(buff, &mut phase);
+            operators.push((key_on, envelope_phase, attenuation, phase));
+        }
+        let mut fnum_latch = 0;
+        let mut address = [0u8; 2];
+        let mut registers = [vec![0u8; 0x100], vec![0u8; 0x100]];
+        load_u8(buff, &mut fnum_latch);
+        load_u8(buff, &mut address[1]);
+        load_u8(buff, &mut address[0]);
+        load_vec(buff, &mut registers[1]);
+        load_vec(buff, &mut registers[0]);
+
+        // Replay every register except key on, which is restored directly below. Each
+        // frequency needs its latch first.
+        let level = self.level;
+        *self = Epsm::new();
+        self.level = level;
+        for bank in 0 .. 2 {
+            for register in 0x00 ..= 0xFF {
+                if register == 0x28 {
+                    continue;
+                }
+                if (0xA0 ..= 0xA2).contains(&register) {
+                    self.fnum_latch = registers[bank][register + 4];
+                }
+                self.write_data(bank, register as u8, registers[bank][register]);
+            }
+        }
+        self.fnum_latch = fnum_latch;
+        self.address = address;
+        // Popped in reverse, so the last operator comes first
+        for (index, (key_on, envelope_phase, attenuation, phase)) in operators.into_iter().rev().enumerate() {
+            let operator = &mut self.fm_channels[index / 4].operators[index % 4];
+            operator.key_on = key_on;
+            operator.envelope_phase = match envelope_phase {
+                0 => EnvelopePhase::Attack,
+                1 => EnvelopePhase::Decay,
+                2 => EnvelopePhase::Sustain,
+                _ => EnvelopePhase::Release,
+            };
+            operator.attenuation = attenuation as f32;
+            operator.phase = phase;
+        }
+    }
+}
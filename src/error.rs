@@ -0,0 +1,91 @@
+// Errors returned when loading cartridges and savestates. Each variant is
+// something a frontend might reasonably want to handle differently, ie telling
+// the user their dump is bad versus telling them the mapper isn't supported yet.
+
+use std::error;
+use std::fmt;
+
+use crate::ines::INesError;
+use crate::nsf::NsfError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    // The underlying reader failed before any data could be examined
+    Io{reason: String},
+    // The data isn't an iNES or NSF file at all
+    UnknownFormat,
+    // Recognized the format, but the header describes something invalid
+    BadHeader{reason: String},
+    // The file ends before all of the data its header promises
+    TruncatedRom{reason: String},
+    UnsupportedMapper{mapper: u16},
+    // A supported mapper, in a configuration this emulator can't handle
+    UnsupportedCartridge{reason: String},
+    // Savestate data that can't be parsed
+    BadSavestate{reason: String},
+    // A savestate written by an incompatible version of this library
+    SavestateVersion{found: u32, expected: u32},
+    // A caller provided buffer can't hold the data
+    BufferTooSmall{needed: usize, available: usize},
+    // A rollback netplay request for a frame outside the session's window
+    Rollback{reason: String},
+    // An input movie that can't be parsed, or needs something this core doesn't have
+    BadMovie{reason: String},
+    // A debugger expression with a syntax error
+    BadExpression{reason: String},
+    // A per-game override table that can't be parsed; see game_overrides.rs
+    BadOverrides{reason: String},
+    // Scripted input that can't be parsed; see input_macro.rs
+    BadMacro{reason: String},
+}
+
+impl error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io{reason} => {write!(f, "Failed to read file: {}", reason)},
+            Error::UnknownFormat => {write!(f, "Unable to open file as any known type")},
+            Error::BadHeader{reason} => {write!(f, "Bad header: {}", reason)},
+            Error::TruncatedRom{reason} => {write!(f, "File is truncated: {}", reason)},
+            Error::UnsupportedMapper{mapper} => {write!(f, "Unsupported iNES mapper: {}", mapper)},
+            Error::UnsupportedCartridge{reason} => {write!(f, "Unsupported cartridge: {}", reason)},
+            Error::BadSavestate{reason} => {write!(f, "Bad savestate: {}", reason)},
+            Error::SavestateVersion{found, expected} => {
+                write!(f, "Savestate version {} is not supported, expected {}", found, expected)
+            },
+            Error::BufferTooSmall{needed, available} => {
+                write!(f, "Buffer is too small: {} bytes needed, {} available", needed, available)
+            },
+            Error::Rollback{reason} => {write!(f, "Rollback: {}", reason)},
+            Error::BadMovie{reason} => {write!(f, "Bad movie: {}", reason)},
+            Error::BadExpression{reason} => {write!(f, "Bad expression: {}", reason)},
+            Error::BadOverrides{reason} => {write!(f, "Bad override table: {}", reason)},
+            Error::BadMacro{reason} => {write!(f, "Bad input macro: {}", reason)},
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        return Error::Io{reason: error.to_string()};
+    }
+}
+
+impl From<INesError> for Error {
+    fn from(error: INesError) -> Self {
+        return match error {
+            INesError::Truncated{reason} => Error::TruncatedRom{reason: reason},
+            other => Error::BadHeader{reason: other.to_string()},
+        };
+    }
+}
+
+impl From<NsfError> for Error {
+    fn from(error: NsfError) -> Self {
+        return match error {
+            NsfError::Truncated{reason} => Error::TruncatedRom{reason: reason},
+            other => Error::BadHeader{reason: other.to_string()},
+        };
+    }
+}
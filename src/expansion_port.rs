@@ -0,0 +1,31 @@
+// The Famicom's expansion port, and the NES's, which carries the same signals: the
+// three output bits latched by writes to $4016, extra data lines read back through
+// $4016 and $4017 alongside the controllers', and an audio input mixed in with the
+// cartridge's. Peripherals that don't fit behind a controller port (keyboards,
+// tablets, modems) implement ExpansionDevice and are plugged in with
+// NesState::connect_expansion_device().
+
+// The data lines the expansion port can drive, for $4016 and $4017. The rest of each
+// read comes from the controller ports.
+pub const EXPANSION_DATA_LINES: [u8; 2] = [0b0000_0010, 0b0001_1110];
+
+pub trait ExpansionDevice: Send {
+    // OUT0 - OUT2, the low three bits of every write to $4016. OUT0 is the same strobe
+    // the controller ports see.
+    fn write(&mut self, data: u8);
+    // port is 0 for $4016 and 1 for $4017. Bits outside EXPANSION_DATA_LINES are
+    // ignored.
+    fn read(&mut self, port: usize) -> u8;
+    // read() without side effects, for debuggers
+    fn peek(&self, port: usize) -> u8;
+    // Writes to $4018 - $401F, which the 2A03 ignores. They aren't on the port's own
+    // pins, but devices that also sit on the cartridge bus (the EPSM, see epsm.rs)
+    // decode them.
+    fn write_register(&mut self, _address: u16, _data: u8) {}
+    // The level on the audio input, on the same scale as the 2A03's mixed output
+    fn audio_input(&self) -> f32 {return 0.0;}
+    fn clock_cpu(&mut self) {}
+    fn end_frame(&mut self) {}
+    fn save_state(&self, _buff: &mut Vec<u8>) {}
+    fn load_state(&mut self, _buff: &mut Vec<u8>) {}
+}
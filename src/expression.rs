@@ -0,0 +1,351 @@
+// A small expression language for debugger conditions, shared by breakpoints
+// (Condition::Expression) and trace filtering (NesState::set_trace_filter), so that
+// logs stay short and breaks land exactly where the interesting effect happens:
+//
+//   A == #$20 && scanline > 200
+//   write to $2001 && (value & $18) == 0
+//   [$0300] != 0 || pc >= $C000
+//
+// Numbers are decimal, or hex with $ or 0x, or binary with %; a leading # is
+// allowed and ignored. Names are case insensitive:
+//   a x y s p pc        CPU registers
+//   scanline dot frame  PPU position
+//   rendering           1 while the PPU is drawing
+//   address value       The access being checked, and the byte read or written
+//   [expr]              The byte at a CPU address, read without side effects
+//   read / write / exec 1 for that kind of access, optionally "to", "from" or "at"
+//                       an address
+// Operators, loosest first: || && == != < <= > >= | ^ & + - and unary ! -.
+// Comparisons and logic give 1 or 0; anything nonzero counts as true.
+
+use crate::breakpoints::AccessType;
+use crate::error::Error;
+use crate::memory::debug_read_byte;
+use crate::nes::NesState;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Variable {
+    A,
+    X,
+    Y,
+    S,
+    P,
+    Pc,
+    Scanline,
+    Dot,
+    Frame,
+    Rendering,
+    Address,
+    Value,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Subtract,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Expression {
+    Constant(u32),
+    Variable(Variable),
+    Memory(Box<Expression>),
+    // True during an access of this type, to this address if given
+    Access{access: AccessType, address: Option<Box<Expression>>},
+    Not(Box<Expression>),
+    Negate(Box<Expression>),
+    Binary{op: BinaryOp, left: Box<Expression>, right: Box<Expression>},
+}
+
+// The memory access an expression is checked against
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Access {
+    pub access: AccessType,
+    pub address: u16,
+    pub data: u8,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Number(u32),
+    Name(String),
+    Operator(&'static str),
+}
+
+// Longest first, so that "<=" isn't read as "<"
+const OPERATORS: [&str; 19] = ["||", "&&", "==", "!=", "<=", ">=", "<", ">", "|", "^", "&", "+", "-", "!", "(", ")", "[", "]", "="];
+
+fn bad_expression(reason: String) -> Error {
+    return Error::BadExpression{reason: reason};
+}
+
+fn parse_number(text: &str) -> Result<u32, Error> {
+    let text = text.trim_start_matches('#');
+    let result = if let Some(hex) = text.strip_prefix('$') {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(hex) = text.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(binary) = text.strip_prefix('%') {
+        u32::from_str_radix(binary, 2)
+    } else {
+        text.parse::<u32>()
+    };
+    return result.map_err(|_| bad_expression(format!("\"{}\" isn't a number", text)));
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c.is_ascii_alphanumeric() || c == '#' || c == '$' || c == '%' || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || "#$%_".contains(chars[i])) {
+                i += 1;
+            }
+            let word: String = chars[start .. i].iter().collect();
+            let first = word.chars().next().unwrap();
+            if first.is_ascii_digit() || first == '#' || first == '$' || first == '%' {
+                tokens.push(Token::Number(parse_number(&word)?));
+            } else {
+                tokens.push(Token::Name(word.to_ascii_lowercase()));
+            }
+            continue;
+        }
+        let rest: String = chars[i ..].iter().take(2).collect();
+        let operator = match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            Some(op) => *op,
+            None => return Err(bad_expression(format!("unexpected \"{}\"", c)))
+        };
+        // A lone "=" is taken to mean "=="
+        tokens.push(Token::Operator(if operator == "=" {"=="} else {operator}));
+        i += operator.len();
+    }
+    return Ok(tokens);
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        return self.tokens.get(self.position);
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        return token;
+    }
+
+    fn take_operator(&mut self, operators: &[(&str, BinaryOp)]) -> Option<BinaryOp> {
+        if let Some(Token::Operator(found)) = self.peek() {
+            for &(operator, op) in operators.iter() {
+                if *found == operator {
+                    self.position += 1;
+                    return Some(op);
+                }
+            }
+        }
+        return None;
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), Error> {
+        return match self.next() {
+            Some(Token::Operator(found)) if found == operator => Ok(()),
+            _ => Err(bad_expression(format!("expected \"{}\"", operator)))
+        };
+    }
+
+    // Each level of precedence, loosest first, then unary operators and primaries
+    fn binary(&mut self, level: usize) -> Result<Expression, Error> {
+        const LEVELS: [&[(&str, BinaryOp)]; 7] = [
+            &[("||", BinaryOp::Or)],
+            &[("&&", BinaryOp::And)],
+            &[("==", BinaryOp::Equal), ("!=", BinaryOp::NotEqual), ("<=", BinaryOp::LessOrEqual),
+              (">=", BinaryOp::GreaterOrEqual), ("<", BinaryOp::Less), (">", BinaryOp::Greater)],
+            &[("|", BinaryOp::BitOr)],
+            &[("^", BinaryOp::BitXor)],
+            &[("&", BinaryOp::BitAnd)],
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)],
+        ];
+        if level >= LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(op) = self.take_operator(LEVELS[level]) {
+            let right = self.binary(level + 1)?;
+            left = Expression::Binary{op: op, left: Box::new(left), right: Box::new(right)};
+        }
+        return Ok(left);
+    }
+
+    fn unary(&mut self) -> Result<Expression, Error> {
+        return match self.peek() {
+            Some(Token::Operator("!")) => {self.position += 1; Ok(Expression::Not(Box::new(self.unary()?)))},
+            Some(Token::Operator("-")) => {self.position += 1; Ok(Expression::Negate(Box::new(self.unary()?)))},
+            _ => self.primary()
+        };
+    }
+
+    fn primary(&mut self) -> Result<Expression, Error> {
+        match self.next() {
+            Some(Token::Number(value)) => return Ok(Expression::Constant(value)),
+            Some(Token::Operator("(")) => {
+                let inner = self.binary(0)?;
+                self.expect(")")?;
+                return Ok(inner);
+            },
+            Some(Token::Operator("[")) => {
+                let address = self.binary(0)?;
+                self.expect("]")?;
+                return Ok(Expression::Memory(Box::new(address)));
+            },
+            Some(Token::Name(name)) => return self.name(&name),
+            Some(Token::Operator(op)) => return Err(bad_expression(format!("unexpected \"{}\"", op))),
+            None => return Err(bad_expression("unexpected end of expression".to_string()))
+        }
+    }
+
+    fn name(&mut self, name: &str) -> Result<Expression, Error> {
+        let variable = match name {
+            "a" => Variable::A,
+            "x" => Variable::X,
+            "y" => Variable::Y,
+            "s" | "sp" => Variable::S,
+            "p" => Variable::P,
+            "pc" => Variable::Pc,
+            "scanline" => Variable::Scanline,
+            "dot" | "cycle" => Variable::Dot,
+            "frame" => Variable::Frame,
+            "rendering" => Variable::Rendering,
+            "address" => Variable::Address,
+            "value" => Variable::Value,
+            "read" | "write" | "exec" | "execute" => {
+                let access = match name {
+                    "read" => AccessType::Read,
+                    "write" => AccessType::Write,
+                    _ => AccessType::Execute,
+                };
+                return Ok(Expression::Access{access: access, address: self.access_address()?});
+            },
+            _ => return Err(bad_expression(format!("unknown name \"{}\"", name)))
+        };
+        return Ok(Expression::Variable(variable));
+    }
+
+    // The optional "to $2001" after read, write or exec
+    fn access_address(&mut self) -> Result<Option<Box<Expression>>, Error> {
+        if let Some(Token::Name(word)) = self.peek() {
+            if word == "to" || word == "from" || word == "at" {
+                self.position += 1;
+                return Ok(Some(Box::new(self.unary()?)));
+            }
+        }
+        return match self.peek() {
+            Some(Token::Number(_)) | Some(Token::Operator("[")) | Some(Token::Operator("(")) => Ok(Some(Box::new(self.unary()?))),
+            _ => Ok(None)
+        };
+    }
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Expression, Error> {
+        let mut parser = Parser{tokens: tokenize(text)?, position: 0};
+        let expression = parser.binary(0)?;
+        if parser.position < parser.tokens.len() {
+            return Err(bad_expression(format!("unexpected {:?} after the expression", parser.tokens[parser.position])));
+        }
+        return Ok(expression);
+    }
+
+    // access is None outside of a memory access, where address and value read as 0
+    // and read, write and exec are false
+    pub fn evaluate(&self, nes: &NesState, access: Option<&Access>) -> u32 {
+        match self {
+            Expression::Constant(value) => return *value,
+            Expression::Variable(variable) => return variable_value(nes, *variable, access),
+            Expression::Memory(address) => {
+                return debug_read_byte(nes, address.evaluate(nes, access) as u16) as u32;
+            },
+            Expression::Access{access: wanted, address} => {
+                let matched = match access {
+                    Some(current) => current.access == *wanted && match address {
+                        Some(address) => address.evaluate(nes, Some(current)) as u16 == current.address,
+                        None => true
+                    },
+                    None => false
+                };
+                return matched as u32;
+            },
+            Expression::Not(inner) => return (inner.evaluate(nes, access) == 0) as u32,
+            Expression::Negate(inner) => return inner.evaluate(nes, access).wrapping_neg(),
+            Expression::Binary{op, left, right} => {
+                let left = left.evaluate(nes, access);
+                // Short circuit, so a false guard skips the memory reads after it
+                match op {
+                    BinaryOp::And if left == 0 => return 0,
+                    BinaryOp::Or if left != 0 => return 1,
+                    _ => {}
+                }
+                let right = right.evaluate(nes, access);
+                return match op {
+                    BinaryOp::Or | BinaryOp::And => (right != 0) as u32,
+                    BinaryOp::Equal => (left == right) as u32,
+                    BinaryOp::NotEqual => (left != right) as u32,
+                    BinaryOp::Less => (left < right) as u32,
+                    BinaryOp::LessOrEqual => (left <= right) as u32,
+                    BinaryOp::Greater => (left > right) as u32,
+                    BinaryOp::GreaterOrEqual => (left >= right) as u32,
+                    BinaryOp::BitOr => left | right,
+                    BinaryOp::BitXor => left ^ right,
+                    BinaryOp::BitAnd => left & right,
+                    BinaryOp::Add => left.wrapping_add(right),
+                    BinaryOp::Subtract => left.wrapping_sub(right),
+                };
+            },
+        }
+    }
+
+    pub fn is_true(&self, nes: &NesState, access: Option<&Access>) -> bool {
+        return self.evaluate(nes, access) != 0;
+    }
+}
+
+fn variable_value(nes: &NesState, variable: Variable, access: Option<&Access>) -> u32 {
+    return match variable {
+        Variable::A => nes.registers.a as u32,
+        Variable::X => nes.registers.x as u32,
+        Variable::Y => nes.registers.y as u32,
+        Variable::S => nes.registers.s as u32,
+        Variable::P => nes.registers.status_as_byte(false) as u32,
+        Variable::Pc => nes.registers.pc as u32,
+        Variable::Scanline => nes.ppu.current_scanline as u32,
+        Variable::Dot => nes.ppu.current_scanline_cycle as u32,
+        Variable::Frame => nes.ppu.current_frame,
+        Variable::Rendering => {
+            let active_line = nes.ppu.current_scanline <= 239 || nes.ppu.current_scanline == 261;
+            (nes.ppu.rendering_enabled() && active_line) as u32
+        },
+        Variable::Address => access.map_or(0, |access| access.address as u32),
+        Variable::Value => access.map_or(0, |access| access.data as u32),
+    };
+}
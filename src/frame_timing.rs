@@ -0,0 +1,154 @@
+// Wall clock time spent in each part of the console, one frame at a time, for
+// choosing which accuracy / speed trade-offs are worth making on a given machine.
+// Disabled by default: measuring takes several clock reads per CPU cycle, which can
+// make emulation several times slower, so compare the shares rather than the totals.
+
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct SubsystemTimes {
+    // Instruction execution, including bus accesses and DMA, plus controllers
+    pub cpu: Duration,
+    // Everything the PPU does apart from producing pixels: memory fetches, sprite
+    // evaluation and the scroll counters
+    pub ppu_fetch: Duration,
+    // Working out and plotting the color of each visible dot, estimated from one dot
+    // in every 16
+    pub ppu_pixel: Duration,
+    // The 2A03's channels, mixing, filtering and DMC sample fetches
+    pub apu: Duration,
+    // The cartridge's own per-cycle work: IRQ counters, and on most boards with
+    // expansion audio, its synthesis
+    pub mapper: Duration,
+}
+
+impl SubsystemTimes {
+    pub fn total(&self) -> Duration {
+        return self.cpu + self.ppu_fetch + self.ppu_pixel + self.apu + self.mapper;
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Subsystem {
+    Cpu,
+    Ppu,
+    Apu,
+    Mapper,
+}
+
+// A point in time, along with how much PPU time had been charged by then; see lap()
+pub(crate) struct Lap {
+    at: Instant,
+    ppu: Duration,
+}
+
+pub struct FrameTiming {
+    enabled: bool,
+    // The frame in progress. The PPU's pixel time is kept by the PPU itself, and
+    // ppu_fetch holds the PPU's total until end_frame() splits it.
+    current: SubsystemTimes,
+    last_frame: SubsystemTimes,
+    totals: SubsystemTimes,
+    frames_measured: u64,
+}
+
+impl FrameTiming {
+    pub fn new() -> FrameTiming {
+        return FrameTiming {
+            enabled: false,
+            current: SubsystemTimes::default(),
+            last_frame: SubsystemTimes::default(),
+            totals: SubsystemTimes::default(),
+            frames_measured: 0,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        return self.enabled;
+    }
+
+    // See NesState::set_frame_timing(), which also tells the PPU
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.current = SubsystemTimes::default();
+    }
+
+    // Forgets every frame measured so far
+    pub fn reset(&mut self) {
+        self.current = SubsystemTimes::default();
+        self.last_frame = SubsystemTimes::default();
+        self.totals = SubsystemTimes::default();
+        self.frames_measured = 0;
+    }
+
+    // The last completed frame
+    pub fn last_frame(&self) -> SubsystemTimes {
+        return self.last_frame;
+    }
+
+    // Every frame since measuring started, or since reset()
+    pub fn totals(&self) -> SubsystemTimes {
+        return self.totals;
+    }
+
+    pub fn frames_measured(&self) -> u64 {
+        return self.frames_measured;
+    }
+
+    pub fn average(&self) -> SubsystemTimes {
+        if self.frames_measured == 0 {
+            return SubsystemTimes::default();
+        }
+        let frames = self.frames_measured as u32;
+        return SubsystemTimes {
+            cpu: self.totals.cpu / frames,
+            ppu_fetch: self.totals.ppu_fetch / frames,
+            ppu_pixel: self.totals.ppu_pixel / frames,
+            apu: self.totals.apu / frames,
+            mapper: self.totals.mapper / frames,
+        };
+    }
+
+    pub(crate) fn start_lap(&self) -> Lap {
+        return Lap {at: Instant::now(), ppu: self.current.ppu_fetch};
+    }
+
+    // Charges the time since the lap began to subsystem, then starts the next lap.
+    // PPU catch-up that ran in the meantime (see NesState::lazy_ppu) has already been
+    // charged to the PPU by add_ppu(), so it's taken out.
+    pub(crate) fn lap(&mut self, lap: &mut Lap, subsystem: Subsystem) {
+        let now = Instant::now();
+        let caught_up = self.current.ppu_fetch - lap.ppu;
+        let elapsed = (now - lap.at).saturating_sub(caught_up);
+        match subsystem {
+            Subsystem::Cpu => self.current.cpu += elapsed,
+            Subsystem::Ppu => self.current.ppu_fetch += elapsed,
+            Subsystem::Apu => self.current.apu += elapsed,
+            Subsystem::Mapper => self.current.mapper += elapsed,
+        }
+        lap.at = now;
+        lap.ppu = self.current.ppu_fetch;
+    }
+
+    pub(crate) fn add_ppu(&mut self, elapsed: Duration) {
+        self.current.ppu_fetch += elapsed;
+    }
+
+    // pixel_time is the part of the frame's PPU time the PPU spent on pixels
+    pub(crate) fn end_frame(&mut self, pixel_time: Duration) {
+        if !self.enabled {
+            return;
+        }
+        let mut frame = self.current;
+        frame.ppu_pixel = pixel_time.min(frame.ppu_fetch);
+        frame.ppu_fetch -= frame.ppu_pixel;
+        self.last_frame = frame;
+        self.totals.cpu += frame.cpu;
+        self.totals.ppu_fetch += frame.ppu_fetch;
+        self.totals.ppu_pixel += frame.ppu_pixel;
+        self.totals.apu += frame.apu;
+        self.totals.mapper += frame.mapper;
+        self.frames_measured += 1;
+        self.current = SubsystemTimes::default();
+    }
+}
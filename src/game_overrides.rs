@@ -0,0 +1,161 @@
+// Per-game settings, looked up by cartridge::rom_crc32() and applied when the
+// console is built, so games known to need something unusual (a paddle instead of
+// a controller, a quieter expansion chip, garbage at the edge of the picture) can
+// be set up without the user having to know. The library doesn't ship a table of
+// its own, so the entries come from the frontend, in code or from a text file in
+// this format:
+//
+//   # Comments and blank lines are ignored
+//   0123ABCD port2=arkanoid overscan=8,8,0,0
+//   89ABCDEF vrc6=0.8 sprite_limit=off
+//
+// Each line is a CRC-32 in hex followed by any number of settings:
+//   region=ntsc
+//   port1=<device>, port2=<device>: standard, disconnected, four_score,
+//     famicom_four_player, arkanoid, arkanoid_famicom, barcode_battler
+//   vrc6=<level>, mmc5=<level>, n163=<level>, sunsoft5b=<level>, fds=<level>: see
+//     NesStateBuilder::expansion_level()
+//   sprite_limit=on|off, lazy_ppu=on|off, ppu_alignment=0|1|2
+//   overscan=<top>,<bottom>,<left>,<right>: see NesState::overscan
+
+use std::collections::HashMap;
+
+use crate::builder::InputDevice;
+use crate::builder::Overscan;
+use crate::builder::Region;
+use crate::error::Error;
+use crate::mmc::mapper::ExpansionChip;
+
+// Anything left as None keeps whatever the frontend asked for
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct GameOverrides {
+    pub region: Option<Region>,
+    pub input_devices: [Option<InputDevice>; 2],
+    pub expansion_levels: Vec<(ExpansionChip, f32)>,
+    pub sprite_limit: Option<bool>,
+    pub lazy_ppu: Option<bool>,
+    pub ppu_alignment: Option<u8>,
+    pub overscan: Option<Overscan>,
+}
+
+pub struct OverrideTable {
+    entries: HashMap<u32, GameOverrides>,
+}
+
+fn bad_overrides(line_number: usize, reason: &str) -> Error {
+    return Error::BadOverrides{reason: format!("line {}: {}", line_number, reason)};
+}
+
+fn parse_switch(value: &str) -> Option<bool> {
+    return match value {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None
+    };
+}
+
+fn parse_device(value: &str) -> Option<InputDevice> {
+    return match value {
+        "standard" => Some(InputDevice::StandardController),
+        "disconnected" => Some(InputDevice::Disconnected),
+        "four_score" => Some(InputDevice::FourScore),
+        "famicom_four_player" => Some(InputDevice::FamicomFourPlayer),
+        "arkanoid" => Some(InputDevice::ArkanoidPaddle),
+        "arkanoid_famicom" => Some(InputDevice::ArkanoidPaddleFamicom),
+        "barcode_battler" => Some(InputDevice::BarcodeBattler),
+        _ => None
+    };
+}
+
+fn parse_overscan(value: &str) -> Option<Overscan> {
+    let margins: Vec<u8> = value.split(',').map(|margin| margin.trim().parse::<u8>()).collect::<Result<_, _>>().ok()?;
+    if margins.len() != 4 {
+        return None;
+    }
+    return Some(Overscan{top: margins[0], bottom: margins[1], left: margins[2], right: margins[3]});
+}
+
+fn parse_setting(overrides: &mut GameOverrides, key: &str, value: &str) -> Option<()> {
+    match key {
+        "region" => overrides.region = Some(match value {"ntsc" => Region::Ntsc, _ => return None}),
+        "port1" => overrides.input_devices[0] = Some(parse_device(value)?),
+        "port2" => overrides.input_devices[1] = Some(parse_device(value)?),
+        "vrc6" | "mmc5" | "n163" | "sunsoft5b" | "fds" => {
+            let chip = match key {
+                "vrc6" => ExpansionChip::Vrc6,
+                "mmc5" => ExpansionChip::Mmc5,
+                "n163" => ExpansionChip::N163,
+                "fds" => ExpansionChip::Fds,
+                _ => ExpansionChip::Sunsoft5B,
+            };
+            overrides.expansion_levels.push((chip, value.parse::<f32>().ok()?));
+        },
+        "sprite_limit" => overrides.sprite_limit = Some(parse_switch(value)?),
+        "lazy_ppu" => overrides.lazy_ppu = Some(parse_switch(value)?),
+        "ppu_alignment" => {
+            let alignment = value.parse::<u8>().ok()?;
+            if alignment > 2 {
+                return None;
+            }
+            overrides.ppu_alignment = Some(alignment);
+        },
+        "overscan" => overrides.overscan = Some(parse_overscan(value)?),
+        _ => return None
+    }
+    return Some(());
+}
+
+impl OverrideTable {
+    pub fn new() -> OverrideTable {
+        return OverrideTable {
+            entries: HashMap::new(),
+        }
+    }
+
+    // Replaces any existing entry for the same cartridge
+    pub fn add(&mut self, rom_crc32: u32, overrides: GameOverrides) {
+        self.entries.insert(rom_crc32, overrides);
+    }
+
+    pub fn remove(&mut self, rom_crc32: u32) {
+        self.entries.remove(&rom_crc32);
+    }
+
+    pub fn lookup(&self, rom_crc32: u32) -> Option<&GameOverrides> {
+        return self.entries.get(&rom_crc32);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    // Adds every entry in text, in the format at the top of this file. On error,
+    // nothing is added.
+    pub fn parse(&mut self, text: &str) -> Result<(), Error> {
+        let mut parsed = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let crc_field = fields.next().unwrap_or("");
+            let rom_crc32 = u32::from_str_radix(crc_field, 16)
+                .map_err(|_| bad_overrides(line_number, &format!("bad CRC-32 \"{}\"", crc_field)))?;
+            let mut overrides = GameOverrides::default();
+            for field in fields {
+                let (key, value) = field.split_once('=')
+                    .ok_or_else(|| bad_overrides(line_number, &format!("expected key=value, found \"{}\"", field)))?;
+                if parse_setting(&mut overrides, key, value).is_none() {
+                    return Err(bad_overrides(line_number, &format!("bad setting \"{}\"", field)));
+                }
+            }
+            parsed.push((rom_crc32, overrides));
+        }
+        for (rom_crc32, overrides) in parsed {
+            self.add(rom_crc32, overrides);
+        }
+        return Ok(());
+    }
+}
@@ -7,6 +7,7 @@ use std::io::Read;
 use std::error::Error;
 use std::fmt;
 
+use crate::mmc::mapper::BoardInfo;
 use crate::mmc::mapper::Mirroring;
 use crate::memoryblock::MemoryBlock;
 use crate::memoryblock::MemoryType;
@@ -15,7 +16,9 @@ use crate::memoryblock::MemoryType;
 pub enum INesError {
     InvalidHeader,
     Unimplemented,
-    ReadError{reason: String}
+    ReadError{reason: String},
+    // The file ended before all of the data the header describes
+    Truncated{reason: String}
 }
 
 impl Error for INesError {}
@@ -25,13 +28,17 @@ impl fmt::Display for INesError  {
         match self {
             INesError::InvalidHeader => {write!(f, "Invalid iNES Header")},
             INesError::Unimplemented => {write!(f, "Unimplemented (Lazy programmers!!1)")},
-            INesError::ReadError{reason} => {write!(f, "Error reading cartridge: {}", reason)}
+            INesError::ReadError{reason} => {write!(f, "Error reading cartridge: {}", reason)},
+            INesError::Truncated{reason} => {write!(f, "Cartridge data is truncated: {}", reason)}
         }
     }
 }
 
 impl From<std::io::Error> for INesError {
     fn from(error: std::io::Error) -> Self {
+        if error.kind() == std::io::ErrorKind::UnexpectedEof {
+            return INesError::Truncated{reason: error.to_string()};
+        }
         return INesError::ReadError{reason: error.to_string()};
     }
 }
@@ -63,7 +70,7 @@ const INES2_CHR_RAM: usize = 11;
 //const INES2_CPU_PPU_TIMING: usize = 12;
 //const INES2_SYSTEM_TYPE: usize = 13;
 //const INES2_MISC_ROM_COUNT: usize = 14;
-//const INES2_DEFAULT_EXPANSION: usize = 15;
+const INES2_DEFAULT_EXPANSION: usize = 15;
 
 impl INesHeader {
     pub fn from(raw_bytes: &[u8]) -> INesHeader {
@@ -327,6 +334,23 @@ impl INesHeader {
             _ => 0
         }
     }
+
+    // NES 2.0's code for what the game expects in the controller ports, 0 if unknown;
+    // see InputDevice::for_expansion_device()
+    pub fn default_expansion_device(&self) -> u8 {
+        match self.version() {
+            2 => self.raw_bytes[INES2_DEFAULT_EXPANSION] & 0b0011_1111,
+            _ => 0
+        }
+    }
+
+    pub fn board_info(&self) -> BoardInfo {
+        return BoardInfo {
+            mapper_number: self.mapper_number(),
+            submapper: self.submapper_number(),
+            default_expansion_device: self.default_expansion_device(),
+        };
+    }
 }
 
 #[derive(Clone)]
@@ -430,19 +454,35 @@ impl INesCartridge {
         return blocks;
     }
 
-    pub fn prg_ram_block(&self) -> Result<MemoryBlock, String> {
-        let blocks = self.prg_ram_blocks();
-        if blocks.len() != 1 {
-            return Err(format!("Unsupported mixed PRG RAM types for mapper number {}", self.header.mapper_number()));
-        }
-        return Ok(blocks[0].clone());
+    // All of the cartridge's PRG RAM as one block, sized from the header. Boards with
+    // both plain and battery backed RAM (8 KB of each, say) get a single block of the
+    // combined size, battery backed as a whole: the save is larger than it needs to
+    // be, but nothing the game keeps is lost.
+    pub fn prg_ram_block(&self) -> Result<MemoryBlock, crate::error::Error> {
+        return Ok(combined_ram_block(self.header.prg_ram_size(), self.header.prg_sram_size()));
     }
 
-    pub fn chr_block(&self) -> Result<MemoryBlock, String> {
-        let blocks = self.chr_blocks();
-        if blocks.len() != 1 {
-            return Err(format!("Unsupported mixed CHR types for mapper number {}", self.header.mapper_number()));
+    // CHR ROM, or else all of the CHR RAM as one block, as for prg_ram_block()
+    pub fn chr_block(&self) -> Result<MemoryBlock, crate::error::Error> {
+        let ram_size = self.header.chr_ram_size() + self.header.chr_sram_size();
+        if self.chr.len() > 0 && ram_size > 0 {
+            return Err(crate::error::Error::UnsupportedCartridge{reason: format!("Unsupported mixed CHR types for mapper number {}", self.header.mapper_number())});
         }
-        return Ok(blocks[0].clone());
+        if self.chr.len() > 0 {
+            return Ok(MemoryBlock::new(&self.chr, MemoryType::Rom));
+        }
+        return Ok(combined_ram_block(self.header.chr_ram_size(), self.header.chr_sram_size()));
     }
 }
+
+fn combined_ram_block(ram_size: usize, sram_size: usize) -> MemoryBlock {
+    let memory_type = if sram_size > 0 {
+        MemoryType::NvRam
+    } else if ram_size > 0 {
+        MemoryType::Ram
+    } else {
+        // As for prg_ram_blocks(), an empty block stands in for missing RAM
+        MemoryType::Rom
+    };
+    return MemoryBlock::new(&vec![0u8; ram_size + sram_size], memory_type);
+}
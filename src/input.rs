@@ -0,0 +1,536 @@
+// Devices plugged into the controller ports. The console talks to them through three
+// signals: the strobe (bit 0 of writes to $4016, shared by both ports), and a clock
+// and data lines per port, which reading $4016 or $4017 pulses and samples. Every
+// device speaks that protocol through ControllerPort, so memory.rs doesn't need to
+// know what's plugged in.
+
+use crate::save_load::*;
+
+pub trait ControllerPort: Send {
+    // Called on every write to $4016. Bit 0 is the strobe; bits 1 and 2 are only
+    // wired to the Famicom's expansion port.
+    fn strobe(&mut self, data: u8);
+    // Called on every read of this port's register. Returns the data lines the
+    // device drives, in bits 0 - 4; the console fills in the rest.
+    fn read(&mut self) -> u8;
+    // What read() would return, without clocking the device. For debuggers.
+    fn peek(&self) -> u8;
+    // Standard controller buttons, for devices that have them. Bit 0 first:
+    // A, B, Select, Start, Up, Down, Left, Right
+    fn set_buttons(&mut self, _buttons: u8) {}
+    fn buttons(&self) -> u8 {return 0;}
+    // Multitaps carry a second pad on each port, for players 3 and 4
+    fn set_tap_buttons(&mut self, _buttons: u8) {}
+    fn tap_buttons(&self) -> u8 {return 0;}
+    // Analog position, for paddles and other dial controllers. 0.0 to 1.0, left to right.
+    fn set_analog(&mut self, _position: f32) {}
+    // Auto-fire for a button (bit number, as in set_buttons()), or None to turn it off
+    fn set_turbo(&mut self, _button: usize, _turbo: Option<Turbo>) {}
+    // For barcode readers; see NesState::insert_barcode()
+    fn insert_barcode(&mut self, _barcode: &str) -> bool {return false;}
+    // Called every CPU cycle, for devices with timing of their own
+    fn clock_cpu(&mut self) {}
+    // Called once per frame, after the frame finishes
+    fn end_frame(&mut self) {}
+    fn save_state(&self, _buff: &mut Vec<u8>) {}
+    fn load_state(&mut self, _buff: &mut Vec<u8>) {}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Turbo {
+    // Frames per press and release
+    pub period: u32,
+    // Fraction of the period spent pressed, 0.0 to 1.0
+    pub duty: f32,
+}
+
+impl Turbo {
+    fn pressed(&self, frame: u32) -> bool {
+        let period = self.period.max(1);
+        let frames_pressed = (period as f32 * self.duty.max(0.0).min(1.0)).round() as u32;
+        return frame % period < frames_pressed;
+    }
+}
+
+// Nothing plugged in; the data lines float low
+pub struct Disconnected {}
+
+impl Disconnected {
+    pub fn new() -> Disconnected {
+        return Disconnected {};
+    }
+}
+
+impl ControllerPort for Disconnected {
+    fn strobe(&mut self, _data: u8) {}
+    fn read(&mut self) -> u8 {return 0;}
+    fn peek(&self) -> u8 {return 0;}
+}
+
+// The standard pad: an 8-bit shift register, reloaded from the buttons while the
+// strobe is high
+pub struct StandardController {
+    pub buttons: u8,
+    pub turbo: [Option<Turbo>; 8],
+    // Frames since power on, for timing turbo presses
+    frame: u32,
+    shift_register: u8,
+    strobe: bool,
+}
+
+impl StandardController {
+    pub fn new() -> StandardController {
+        return StandardController {
+            buttons: 0,
+            turbo: [None; 8],
+            frame: 0,
+            shift_register: 0,
+            strobe: false,
+        };
+    }
+
+    // Held buttons, minus turbo buttons in the released part of their cycle
+    fn pressed_buttons(&self) -> u8 {
+        let mut pressed = self.buttons;
+        for (bit, turbo) in self.turbo.iter().enumerate() {
+            if let Some(turbo) = turbo {
+                if !turbo.pressed(self.frame) {
+                    pressed &= !(1 << bit);
+                }
+            }
+        }
+        return pressed;
+    }
+}
+
+impl ControllerPort for StandardController {
+    fn strobe(&mut self, data: u8) {
+        self.strobe = data & 0x1 != 0;
+        if self.strobe {
+            self.shift_register = self.pressed_buttons();
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        if self.strobe {
+            // strobe is high, so copy input data to latch (probably bad if this
+            // actually occurs here, but it matches what real hardware would do)
+            self.shift_register = self.pressed_buttons();
+        }
+        let result = self.shift_register & 0x1;
+        // Standard Controllers set extra bits to 1, which affects controller detection routines
+        self.shift_register = (self.shift_register >> 1) | 0x80;
+        return result;
+    }
+
+    fn peek(&self) -> u8 {
+        if self.strobe {
+            return self.pressed_buttons() & 0x1;
+        }
+        return self.shift_register & 0x1;
+    }
+
+    fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = buttons;
+    }
+
+    fn buttons(&self) -> u8 {
+        return self.buttons;
+    }
+
+    fn set_turbo(&mut self, button: usize, turbo: Option<Turbo>) {
+        if button < self.turbo.len() {
+            self.turbo[button] = turbo;
+        }
+    }
+
+    fn end_frame(&mut self) {
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    fn save_state(&self, buff: &mut Vec<u8>) {
+        save_u8(buff, self.buttons);
+        save_u32(buff, self.frame);
+        save_u8(buff, self.shift_register);
+        save_bool(buff, self.strobe);
+    }
+
+    fn load_state(&mut self, buff: &mut Vec<u8>) {
+        load_bool(buff, &mut self.strobe);
+        load_u8(buff, &mut self.shift_register);
+        load_u32(buff, &mut self.frame);
+        load_u8(buff, &mut self.buttons);
+    }
+}
+
+// The NES Four Score, in four player mode. It takes up both ports, and each half
+// reports two pads and then a signature: players 1 and 3 on $4016, 2 and 4 on $4017.
+pub struct FourScore {
+    pub buttons: u8,
+    // Player 3 or 4
+    pub tap_buttons: u8,
+    signature: u8,
+    // 24 bits: pad, tap pad, signature
+    shift_register: u32,
+    strobe: bool,
+}
+
+impl FourScore {
+    // port is 0 or 1
+    pub fn new(port: usize) -> FourScore {
+        return FourScore {
+            buttons: 0,
+            tap_buttons: 0,
+            // $10 and $20 when read into a byte high bit first, as games do
+            signature: if port == 0 {0x08} else {0x04},
+            shift_register: 0,
+            strobe: false,
+        };
+    }
+
+    fn reload(&mut self) {
+        self.shift_register = (self.buttons as u32) | ((self.tap_buttons as u32) << 8) | ((self.signature as u32) << 16);
+    }
+}
+
+impl ControllerPort for FourScore {
+    fn strobe(&mut self, data: u8) {
+        self.strobe = data & 0x1 != 0;
+        if self.strobe {
+            self.reload();
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        if self.strobe {
+            self.reload();
+        }
+        let result = (self.shift_register & 0x1) as u8;
+        // 1s after the signature, like a standard controller after its 8 buttons
+        self.shift_register = (self.shift_register >> 1) | 0x80_0000;
+        return result;
+    }
+
+    fn peek(&self) -> u8 {
+        if self.strobe {
+            return self.buttons & 0x1;
+        }
+        return (self.shift_register & 0x1) as u8;
+    }
+
+    fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = buttons;
+    }
+
+    fn buttons(&self) -> u8 {
+        return self.buttons;
+    }
+
+    fn set_tap_buttons(&mut self, buttons: u8) {
+        self.tap_buttons = buttons;
+    }
+
+    fn tap_buttons(&self) -> u8 {
+        return self.tap_buttons;
+    }
+
+    fn save_state(&self, buff: &mut Vec<u8>) {
+        save_u8(buff, self.buttons);
+        save_u8(buff, self.tap_buttons);
+        save_u32(buff, self.shift_register);
+        save_bool(buff, self.strobe);
+    }
+
+    fn load_state(&mut self, buff: &mut Vec<u8>) {
+        load_bool(buff, &mut self.strobe);
+        load_u32(buff, &mut self.shift_register);
+        load_u8(buff, &mut self.tap_buttons);
+        load_u8(buff, &mut self.buttons);
+    }
+}
+
+// Famicom four player adapters (Hori and similar) in their simple mode, which most
+// games use: each port's built-in pad on D0, and a pad plugged into the expansion
+// port on D1. No signature.
+pub struct FamicomFourPlayer {
+    pub buttons: u8,
+    // Player 3 or 4
+    pub tap_buttons: u8,
+    shift_register: u8,
+    tap_shift_register: u8,
+    strobe: bool,
+}
+
+impl FamicomFourPlayer {
+    pub fn new() -> FamicomFourPlayer {
+        return FamicomFourPlayer {
+            buttons: 0,
+            tap_buttons: 0,
+            shift_register: 0,
+            tap_shift_register: 0,
+            strobe: false,
+        };
+    }
+
+    fn reload(&mut self) {
+        self.shift_register = self.buttons;
+        self.tap_shift_register = self.tap_buttons;
+    }
+}
+
+impl ControllerPort for FamicomFourPlayer {
+    fn strobe(&mut self, data: u8) {
+        self.strobe = data & 0x1 != 0;
+        if self.strobe {
+            self.reload();
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        if self.strobe {
+            self.reload();
+        }
+        let result = (self.shift_register & 0x1) | ((self.tap_shift_register & 0x1) << 1);
+        self.shift_register = (self.shift_register >> 1) | 0x80;
+        self.tap_shift_register = (self.tap_shift_register >> 1) | 0x80;
+        return result;
+    }
+
+    fn peek(&self) -> u8 {
+        if self.strobe {
+            return (self.buttons & 0x1) | ((self.tap_buttons & 0x1) << 1);
+        }
+        return (self.shift_register & 0x1) | ((self.tap_shift_register & 0x1) << 1);
+    }
+
+    fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = buttons;
+    }
+
+    fn buttons(&self) -> u8 {
+        return self.buttons;
+    }
+
+    fn set_tap_buttons(&mut self, buttons: u8) {
+        self.tap_buttons = buttons;
+    }
+
+    fn tap_buttons(&self) -> u8 {
+        return self.tap_buttons;
+    }
+
+    fn save_state(&self, buff: &mut Vec<u8>) {
+        save_u8(buff, self.buttons);
+        save_u8(buff, self.tap_buttons);
+        save_u8(buff, self.shift_register);
+        save_u8(buff, self.tap_shift_register);
+        save_bool(buff, self.strobe);
+    }
+
+    fn load_state(&mut self, buff: &mut Vec<u8>) {
+        load_bool(buff, &mut self.strobe);
+        load_u8(buff, &mut self.tap_shift_register);
+        load_u8(buff, &mut self.shift_register);
+        load_u8(buff, &mut self.tap_buttons);
+        load_u8(buff, &mut self.buttons);
+    }
+}
+
+// Roughly the range the Vaus's potentiometer covers, after the factory trim
+const PADDLE_MIN: u8 = 0x62;
+const PADDLE_MAX: u8 = 0xF2;
+
+// Taito's Arkanoid "Vaus" controller: a dial and a fire button (A in set_buttons()).
+// The strobe samples the dial into a shift register, which reads back inverted, high
+// bit first. The NES version puts the dial on D3 and fire on D4 of its port, usually
+// port 2. The Famicom version plugs into the expansion port, with fire on $4016 D1
+// and the dial on $4017 D1; connect it to both ports and give both the same input.
+pub struct ArkanoidPaddle {
+    pub famicom: bool,
+    // 0 or 1; only matters for the Famicom version
+    pub port: usize,
+    pub position: u8,
+    pub fire: bool,
+    shift_register: u8,
+    strobe: bool,
+}
+
+impl ArkanoidPaddle {
+    pub fn new(famicom: bool, port: usize) -> ArkanoidPaddle {
+        return ArkanoidPaddle {
+            famicom: famicom,
+            port: port,
+            position: PADDLE_MIN,
+            fire: false,
+            shift_register: 0,
+            strobe: false,
+        };
+    }
+
+    fn output(&self, shift_register: u8) -> u8 {
+        let data = (!shift_register & 0x80) >> 7;
+        let fire = self.fire as u8;
+        if self.famicom {
+            return if self.port == 0 {fire << 1} else {data << 1};
+        }
+        return (fire << 4) | (data << 3);
+    }
+}
+
+impl ControllerPort for ArkanoidPaddle {
+    fn strobe(&mut self, data: u8) {
+        self.strobe = data & 0x1 != 0;
+        if self.strobe {
+            self.shift_register = self.position;
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        if self.strobe {
+            self.shift_register = self.position;
+        }
+        let result = self.output(self.shift_register);
+        self.shift_register <<= 1;
+        return result;
+    }
+
+    fn peek(&self) -> u8 {
+        if self.strobe {
+            return self.output(self.position);
+        }
+        return self.output(self.shift_register);
+    }
+
+    fn set_buttons(&mut self, buttons: u8) {
+        self.fire = buttons & 0x1 != 0;
+    }
+
+    fn buttons(&self) -> u8 {
+        return self.fire as u8;
+    }
+
+    fn set_analog(&mut self, position: f32) {
+        let range = (PADDLE_MAX - PADDLE_MIN) as f32;
+        self.position = PADDLE_MIN + (position.max(0.0).min(1.0) * range).round() as u8;
+    }
+
+    fn save_state(&self, buff: &mut Vec<u8>) {
+        save_u8(buff, self.position);
+        save_bool(buff, self.fire);
+        save_u8(buff, self.shift_register);
+        save_bool(buff, self.strobe);
+    }
+
+    fn load_state(&mut self, buff: &mut Vec<u8>) {
+        load_bool(buff, &mut self.strobe);
+        load_u8(buff, &mut self.shift_register);
+        load_bool(buff, &mut self.fire);
+        load_u8(buff, &mut self.position);
+    }
+}
+
+// Digits of an EAN-13 or EAN-8 barcode. A missing check digit (12 or 7 digits) is
+// filled in; None if the barcode isn't valid.
+pub fn barcode_digits(barcode: &str) -> Option<Vec<u8>> {
+    let mut digits = Vec::new();
+    for c in barcode.chars() {
+        digits.push(c.to_digit(10)? as u8);
+    }
+    let length = match digits.len() {
+        7 | 12 => digits.len() + 1,
+        8 | 13 => digits.len(),
+        _ => return None
+    };
+    // Weights alternate 3, 1, ... from the digit just before the check digit
+    let mut sum = 0;
+    for (i, &digit) in digits[0 .. length - 1].iter().rev().enumerate() {
+        sum += digit as u32 * if i % 2 == 0 {3} else {1};
+    }
+    let check_digit = ((10 - sum % 10) % 10) as u8;
+    if digits.len() < length {
+        digits.push(check_digit);
+    } else if digits[length - 1] != check_digit {
+        return None;
+    }
+    return Some(digits);
+}
+
+// Sent after the digits, and needed for the game to accept them
+const BARCODE_BATTLER_SIGNATURE: &str = "EPOCH\r\n";
+const BARCODE_BATTLER_MESSAGE_LENGTH: usize = 20;
+// Start, data and stop bits
+const BARCODE_BATTLER_STREAM_LENGTH: usize = BARCODE_BATTLER_MESSAGE_LENGTH * 10;
+const BARCODE_BATTLER_CYCLES_PER_BIT: u32 = 1000;
+
+// Epoch's Barcode Battler II, linked through the Famicom expansion port for Barcode
+// World. Scanned codes go out as 20 bytes of text, serially on $4017 D2: a start bit,
+// the 8 data bits (low bit first, inverted), and a stop bit. Connect it to port 1.
+pub struct BarcodeBattler {
+    // One entry per bit of the message being sent
+    stream: Vec<bool>,
+    stream_cycles: u32,
+}
+
+impl BarcodeBattler {
+    pub fn new() -> BarcodeBattler {
+        return BarcodeBattler {
+            stream: Vec::new(),
+            stream_cycles: 0,
+        };
+    }
+}
+
+impl ControllerPort for BarcodeBattler {
+    fn strobe(&mut self, _data: u8) {}
+
+    fn read(&mut self) -> u8 {
+        return self.peek();
+    }
+
+    fn peek(&self) -> u8 {
+        let bit = (self.stream_cycles / BARCODE_BATTLER_CYCLES_PER_BIT) as usize;
+        if bit < self.stream.len() && self.stream[bit] {
+            return 0x04;
+        }
+        return 0;
+    }
+
+    fn insert_barcode(&mut self, barcode: &str) -> bool {
+        let digits = match barcode_digits(barcode) {
+            Some(digits) => digits,
+            None => return false
+        };
+        let mut message: String = digits.iter().map(|digit| (b'0' + digit) as char).collect();
+        message.push_str(BARCODE_BATTLER_SIGNATURE);
+        let message = format!("{:>width$}", message, width = BARCODE_BATTLER_MESSAGE_LENGTH);
+        self.stream.clear();
+        for byte in message.bytes() {
+            self.stream.push(true);
+            for bit in 0 .. 8 {
+                self.stream.push((byte >> bit) & 0x1 == 0);
+            }
+            self.stream.push(false);
+        }
+        self.stream_cycles = 0;
+        return true;
+    }
+
+    fn clock_cpu(&mut self) {
+        if !self.stream.is_empty() {
+            self.stream_cycles += 1;
+            if self.stream_cycles / BARCODE_BATTLER_CYCLES_PER_BIT >= self.stream.len() as u32 {
+                self.stream.clear();
+            }
+        }
+    }
+
+    fn save_state(&self, buff: &mut Vec<u8>) {
+        save_bits(buff, &self.stream, BARCODE_BATTLER_STREAM_LENGTH);
+        save_u32(buff, self.stream_cycles);
+    }
+
+    fn load_state(&mut self, buff: &mut Vec<u8>) {
+        load_u32(buff, &mut self.stream_cycles);
+        load_bits(buff, &mut self.stream, BARCODE_BATTLER_STREAM_LENGTH);
+    }
+}
@@ -0,0 +1,101 @@
+// A record of everything fed to the controller ports, for regression tests and bug
+// reports: which buttons, dial positions and barcodes arrived, and the master clock
+// tick each arrived on. Replay feeds them back on the same ticks, so it reproduces
+// a session exactly however the frontend stepped it. Lighter than a movie: nothing
+// is recorded on frames where nothing changes, and there's no file format beyond
+// what the caller does with the entries. Resets and power cycles asked for with
+// NesState::request_reset() and request_power_cycle() are logged too.
+
+use crate::nes::NesState;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum InputEvent {
+    // As NesState::set_player_buttons() takes them
+    Buttons{player: usize, buttons: u8},
+    Analog{port: usize, position: f32},
+    Barcode(String),
+    SoftReset,
+    PowerCycle,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct InputLogEntry {
+    pub master_clock: u64,
+    pub event: InputEvent,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct InputLog {
+    // The console as logging began, which replay starts from
+    pub start_state: Vec<u8>,
+    pub entries: Vec<InputLogEntry>,
+}
+
+impl InputLog {
+    pub fn new(start_state: Vec<u8>) -> InputLog {
+        return InputLog {
+            start_state: start_state,
+            entries: Vec::new(),
+        };
+    }
+
+    pub fn record(&mut self, master_clock: u64, event: InputEvent) {
+        self.entries.push(InputLogEntry{master_clock: master_clock, event: event});
+    }
+}
+
+pub struct InputReplay {
+    pub log: InputLog,
+    // The next entry to feed
+    position: usize,
+    // Master clock tick of that entry, or u64::MAX when there are none left
+    next_clock: u64,
+}
+
+impl InputReplay {
+    pub fn new(log: InputLog) -> InputReplay {
+        let mut replay = InputReplay {
+            log: log,
+            position: 0,
+            next_clock: 0,
+        };
+        replay.update_next_clock();
+        return replay;
+    }
+
+    fn update_next_clock(&mut self) {
+        self.next_clock = match self.log.entries.get(self.position) {
+            Some(entry) => entry.master_clock,
+            None => u64::MAX,
+        };
+    }
+
+    pub fn finished(&self) -> bool {
+        return self.position >= self.log.entries.len();
+    }
+
+    // Whether the next entry is due by master_clock. Checked every cycle.
+    pub(crate) fn due(&self, master_clock: u64) -> bool {
+        return master_clock >= self.next_clock;
+    }
+
+    // Feeds every entry that's due by the console's master clock
+    pub(crate) fn feed(&mut self, nes: &mut NesState) {
+        while nes.master_clock >= self.next_clock {
+            let event = self.log.entries[self.position].event.clone();
+            apply_event(nes, &event);
+            self.position += 1;
+            self.update_next_clock();
+        }
+    }
+}
+
+pub fn apply_event(nes: &mut NesState, event: &InputEvent) {
+    match event {
+        InputEvent::Buttons{player, buttons} => nes.set_player_buttons(*player, *buttons),
+        InputEvent::Analog{port, position} => nes.set_analog(*port, *position),
+        InputEvent::Barcode(barcode) => {nes.insert_barcode(barcode);},
+        InputEvent::SoftReset => nes.reset(),
+        InputEvent::PowerCycle => nes.power_cycle(),
+    }
+}
@@ -0,0 +1,127 @@
+// Scripted controller sequences, for automated testing and frontends' "combo"
+// buttons: hold these buttons for so many frames, then those, and so on. Played into
+// a player's buttons with NesState::play_macro(), on top of whatever the frontend
+// holds. Macros can be built in code, or parsed from text:
+//
+//   Down+B*2 _*10 A Start*3
+//
+// Each step is a set of buttons joined by + (A, B, Select, Start, Up, Down, Left,
+// Right, in any case) or _ for none, optionally followed by *frames; the default is
+// one frame.
+
+use crate::error::Error;
+
+const BUTTON_NAMES: [&str; 8] = ["a", "b", "select", "start", "up", "down", "left", "right"];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MacroStep {
+    // As NesState::set_player_buttons() takes them
+    pub buttons: u8,
+    pub frames: u32,
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct InputMacro {
+    pub steps: Vec<MacroStep>,
+}
+
+fn bad_macro(reason: String) -> Error {
+    return Error::BadMacro{reason: reason};
+}
+
+impl InputMacro {
+    pub fn new() -> InputMacro {
+        return InputMacro::default();
+    }
+
+    pub fn press(mut self, buttons: u8, frames: u32) -> InputMacro {
+        self.steps.push(MacroStep{buttons: buttons, frames: frames});
+        return self;
+    }
+
+    pub fn wait(self, frames: u32) -> InputMacro {
+        return self.press(0, frames);
+    }
+
+    pub fn total_frames(&self) -> u32 {
+        return self.steps.iter().map(|step| step.frames).sum();
+    }
+
+    // The format at the top of this file
+    pub fn parse(text: &str) -> Result<InputMacro, Error> {
+        let mut input_macro = InputMacro::new();
+        for field in text.split_whitespace() {
+            let (buttons_field, frames) = match field.split_once('*') {
+                Some((buttons_field, frames_field)) => {
+                    let frames = frames_field.parse::<u32>()
+                        .map_err(|_| bad_macro(format!("bad frame count in \"{}\"", field)))?;
+                    (buttons_field, frames)
+                },
+                None => (field, 1)
+            };
+            let mut buttons = 0u8;
+            if buttons_field != "_" {
+                for name in buttons_field.split('+') {
+                    let bit = BUTTON_NAMES.iter().position(|&button| button.eq_ignore_ascii_case(name))
+                        .ok_or_else(|| bad_macro(format!("unknown button \"{}\"", name)))?;
+                    buttons |= 1 << bit;
+                }
+            }
+            input_macro = input_macro.press(buttons, frames);
+        }
+        return Ok(input_macro);
+    }
+}
+
+// A macro partway through playing into one player's buttons
+pub struct MacroPlayback {
+    input_macro: InputMacro,
+    step: usize,
+    frames_into_step: u32,
+    // What the frontend is holding, which the macro's buttons are added to
+    pub held: u8,
+}
+
+impl MacroPlayback {
+    pub fn new(input_macro: InputMacro, held: u8) -> MacroPlayback {
+        let mut playback = MacroPlayback {
+            input_macro: input_macro,
+            step: 0,
+            frames_into_step: 0,
+            held: held,
+        };
+        playback.skip_empty_steps();
+        return playback;
+    }
+
+    fn skip_empty_steps(&mut self) {
+        while self.step < self.input_macro.steps.len() && self.input_macro.steps[self.step].frames == 0 {
+            self.step += 1;
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        return self.step >= self.input_macro.steps.len();
+    }
+
+    // The buttons to hold for the current frame, including the frontend's
+    pub fn buttons(&self) -> u8 {
+        return match self.input_macro.steps.get(self.step) {
+            Some(step) => step.buttons | self.held,
+            None => self.held
+        };
+    }
+
+    // Moves on a frame
+    pub fn end_frame(&mut self) {
+        if self.finished() {
+            return;
+        }
+        self.frames_into_step += 1;
+        if self.frames_into_step >= self.input_macro.steps[self.step].frames {
+            self.step += 1;
+            self.frames_into_step = 0;
+            self.skip_empty_steps();
+        }
+    }
+}
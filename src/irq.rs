@@ -0,0 +1,66 @@
+// The CPU's IRQ input is a single wire, pulled low by any number of sources at
+// once. Each source holds its own line until the program acknowledges it, in that
+// source's own way, so the CPU keeps seeing an IRQ for as long as any one of them is
+// still asserted. NesState::irq_lines() collects them; the CPU polls the result.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IrqSource {
+    // The cartridge, which may have more than one source of its own (scanline counter,
+    // cycle counter, expansion audio) already combined. Acknowledged through the
+    // mapper's registers, ie writing $E000 on MMC3 or reading $5204 on MMC5.
+    Mapper,
+    // Raised at the end of the 4-step sequence unless inhibited. Acknowledged by
+    // reading $4015, or by writing $4017 with the inhibit bit set.
+    ApuFrameCounter,
+    // Raised when a non-looping sample ends with IRQs enabled. Acknowledged by
+    // writing $4015, or by writing $4010 with the IRQ enable bit clear.
+    Dmc,
+}
+
+pub const ALL_IRQ_SOURCES: [IrqSource; 3] = [IrqSource::Mapper, IrqSource::ApuFrameCounter, IrqSource::Dmc];
+
+fn source_bit(source: IrqSource) -> u8 {
+    return match source {
+        IrqSource::Mapper          => 0b0000_0001,
+        IrqSource::ApuFrameCounter => 0b0000_0010,
+        IrqSource::Dmc             => 0b0000_0100,
+    };
+}
+
+// The level of every IRQ line at one point in time
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct IrqLines {
+    asserted: u8,
+}
+
+impl IrqLines {
+    pub fn new() -> IrqLines {
+        return IrqLines::default();
+    }
+
+    pub fn set(&mut self, source: IrqSource, asserted: bool) {
+        if asserted {
+            self.asserted |= source_bit(source);
+        } else {
+            self.asserted &= !source_bit(source);
+        }
+    }
+
+    pub fn asserted(&self, source: IrqSource) -> bool {
+        return (self.asserted & source_bit(source)) != 0;
+    }
+
+    // What the CPU sees: the wire is low if any source is holding it
+    pub fn any(&self) -> bool {
+        return self.asserted != 0;
+    }
+
+    pub fn sources(&self) -> Vec<IrqSource> {
+        return ALL_IRQ_SOURCES.iter().copied().filter(|&source| self.asserted(source)).collect();
+    }
+
+    // Lines asserted here that weren't in previous
+    pub fn rising_since(&self, previous: IrqLines) -> IrqLines {
+        return IrqLines{asserted: self.asserted & !previous.asserted};
+    }
+}
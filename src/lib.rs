@@ -1,18 +1,64 @@
+pub mod achievements;
 pub mod addressing;
 pub mod apu;
+pub mod apu_log;
+pub mod av_capture;
 pub mod asm;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod breakpoints;
+pub mod builder;
 pub mod cartridge;
+pub mod checksum;
+pub mod chr_export;
+pub mod cheats;
 pub mod cycle_cpu;
+pub mod disassembler;
+pub mod dpcm;
+#[cfg(feature = "epsm")]
+pub mod epsm;
+pub mod error;
+pub mod expansion_port;
+pub mod expression;
+pub mod frame_timing;
+pub mod game_overrides;
 pub mod tracked_events;
 pub mod ines;
+pub mod input;
+pub mod input_log;
+pub mod input_macro;
+pub mod irq;
+#[cfg(feature = "libretro")]
+pub mod libretro;
+#[cfg(feature = "lua")]
+pub mod lua;
 pub mod memory;
 pub mod memoryblock;
+pub mod midi;
 pub mod mmc;
+pub mod movie;
 pub mod nes;
 pub mod nsf;
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod ntsc_simd;
 pub mod opcodes;
 pub mod opcode_info;
+pub mod overlay;
 pub mod palettes;
 pub mod ppu;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod profiler;
+pub mod ram_search;
+pub mod rollback;
+pub mod savestate;
+#[cfg(feature = "screenshot")]
+pub mod screenshot;
+pub mod symbols;
+pub mod test_rom;
+pub mod trace;
 pub mod unofficial_opcodes;
+pub mod vgm;
+pub mod video_diff;
+pub mod watch;
 mod save_load;
\ No newline at end of file
@@ -0,0 +1,476 @@
+// libretro core, built on top of NesState. Enabled with the "libretro" feature.
+// RetroArch loads cores as shared libraries; build with
+//   cargo rustc --lib --release --features libretro --crate-type cdylib
+// and load the resulting cdylib.
+//
+// The libretro API is a set of global C functions, so the console lives in a
+// single global slot. Only the pieces of the API a cartridge based core needs
+// are implemented; everything else reports "unsupported" to the frontend.
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::panic;
+use std::sync::Mutex;
+
+use crate::builder::NesStateBuilder;
+use crate::cartridge;
+use crate::cheats::Cheat;
+use crate::cheats::CheatType;
+use crate::nes::NesState;
+use crate::palettes;
+
+pub const RETRO_API_VERSION: c_uint = 1;
+
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_DEVICE_ID_JOYPAD_B: c_uint = 0;
+const RETRO_DEVICE_ID_JOYPAD_SELECT: c_uint = 2;
+const RETRO_DEVICE_ID_JOYPAD_START: c_uint = 3;
+const RETRO_DEVICE_ID_JOYPAD_UP: c_uint = 4;
+const RETRO_DEVICE_ID_JOYPAD_DOWN: c_uint = 5;
+const RETRO_DEVICE_ID_JOYPAD_LEFT: c_uint = 6;
+const RETRO_DEVICE_ID_JOYPAD_RIGHT: c_uint = 7;
+const RETRO_DEVICE_ID_JOYPAD_A: c_uint = 8;
+
+const RETRO_MEMORY_SAVE_RAM: c_uint = 0;
+const RETRO_MEMORY_SYSTEM_RAM: c_uint = 2;
+
+const RETRO_REGION_NTSC: c_uint = 0;
+
+const SCREEN_WIDTH: usize = 256;
+const SCREEN_HEIGHT: usize = 240;
+const SAMPLE_RATE: u64 = 44100;
+const NTSC_FPS: f64 = 60.0988;
+
+// NES controller bits, in the order the shift register reports them
+const JOYPAD_MAPPING: [c_uint; 8] = [
+    RETRO_DEVICE_ID_JOYPAD_A,
+    RETRO_DEVICE_ID_JOYPAD_B,
+    RETRO_DEVICE_ID_JOYPAD_SELECT,
+    RETRO_DEVICE_ID_JOYPAD_START,
+    RETRO_DEVICE_ID_JOYPAD_UP,
+    RETRO_DEVICE_ID_JOYPAD_DOWN,
+    RETRO_DEVICE_ID_JOYPAD_LEFT,
+    RETRO_DEVICE_ID_JOYPAD_RIGHT,
+];
+
+pub type RetroEnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type RetroVideoRefreshFn = unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type RetroAudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+pub type RetroAudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type RetroInputPollFn = unsafe extern "C" fn();
+pub type RetroInputStateFn = unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    pub geometry: RetroGameGeometry,
+    pub timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+struct Callbacks {
+    environment: Option<RetroEnvironmentFn>,
+    video_refresh: Option<RetroVideoRefreshFn>,
+    audio_sample_batch: Option<RetroAudioSampleBatchFn>,
+    input_poll: Option<RetroInputPollFn>,
+    input_state: Option<RetroInputStateFn>,
+}
+
+struct Core {
+    nes: NesState,
+    framebuffer: Vec<u32>,
+    audio_buffer: Vec<i16>,
+    // The frontend fills this after retro_load_game; it's handed to the mapper
+    // before the first frame and kept in sync after every frame from then on.
+    save_ram: Vec<u8>,
+    save_ram_loaded: bool,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+fn callbacks() -> std::sync::MutexGuard<'static, Callbacks> {
+    return CALLBACKS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+}
+
+fn core() -> std::sync::MutexGuard<'static, Option<Core>> {
+    return CORE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+}
+
+// Panics must never unwind into the frontend. Mappers without savestate support
+// panic when asked to serialize, for instance.
+fn guard<T, F: FnOnce() -> T>(fallback: T, f: F) -> T {
+    return panic::catch_unwind(panic::AssertUnwindSafe(f)).unwrap_or(fallback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    return RETRO_API_VERSION;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *core() = None;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    if info.is_null() {
+        return;
+    }
+    *info = RetroSystemInfo {
+        library_name: b"RusticNES\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"nes|nsf\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    if info.is_null() {
+        return;
+    }
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: SCREEN_WIDTH as c_uint,
+            base_height: SCREEN_HEIGHT as c_uint,
+            max_width: SCREEN_WIDTH as c_uint,
+            max_height: SCREEN_HEIGHT as c_uint,
+            aspect_ratio: 4.0 / 3.0,
+        },
+        timing: RetroSystemTiming {
+            fps: NTSC_FPS,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: RetroEnvironmentFn) {
+    callbacks().environment = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: RetroVideoRefreshFn) {
+    callbacks().video_refresh = Some(callback);
+}
+
+// Audio is always delivered in batches; single samples are unused
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: RetroAudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: RetroAudioSampleBatchFn) {
+    callbacks().audio_sample_batch = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: RetroInputPollFn) {
+    callbacks().input_poll = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: RetroInputStateFn) {
+    callbacks().input_state = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {
+    // Only standard controllers are supported
+}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = core().as_mut() {
+        guard((), || core.nes.reset());
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let rom = std::slice::from_raw_parts((*game).data as *const u8, (*game).size);
+
+    let environment = callbacks().environment;
+    if let Some(environment) = environment {
+        let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+        if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+            return false;
+        }
+    }
+
+    let mapper = match cartridge::mapper_from_file(rom) {
+        Ok(mapper) => mapper,
+        Err(why) => {
+            println!("libretro: failed to load game: {}", why);
+            return false;
+        }
+    };
+    let rom_crc32 = cartridge::rom_crc32(rom);
+    let mut nes = NesStateBuilder::new()
+        .sample_rate(SAMPLE_RATE)
+        .build(mapper);
+    nes.rom_crc32 = rom_crc32;
+    nes.power_on();
+    let save_ram = nes.sram();
+    *core() = Some(Core {
+        nes: nes,
+        framebuffer: vec![0u32; SCREEN_WIDTH * SCREEN_HEIGHT],
+        audio_buffer: Vec::new(),
+        save_ram: save_ram,
+        save_ram_loaded: false,
+    });
+    return true;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const RetroGameInfo, _num_info: usize) -> bool {
+    return false;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *core() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    return RETRO_REGION_NTSC;
+}
+
+fn poll_input(core: &mut Core, callbacks: &Callbacks) {
+    let (input_poll, input_state) = match (callbacks.input_poll, callbacks.input_state) {
+        (Some(poll), Some(state)) => (poll, state),
+        _ => return
+    };
+    unsafe { input_poll(); }
+    for port in 0 .. 2 {
+        let mut buttons = 0u8;
+        for (bit, &id) in JOYPAD_MAPPING.iter().enumerate() {
+            if unsafe { input_state(port, RETRO_DEVICE_JOYPAD, 0, id) } != 0 {
+                buttons |= 1 << bit;
+            }
+        }
+        core.nes.ports[port as usize].set_buttons(buttons);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let mut core_guard = core();
+    let core = match core_guard.as_mut() {
+        Some(core) => core,
+        None => return
+    };
+    let callbacks = callbacks();
+
+    if !core.save_ram_loaded {
+        if !core.save_ram.is_empty() {
+            core.nes.set_sram(core.save_ram.clone());
+        }
+        core.save_ram_loaded = true;
+    }
+
+    poll_input(core, &callbacks);
+    let samples = {
+        let framebuffer = &mut core.framebuffer;
+        let nes = &mut core.nes;
+        guard(Vec::new(), || {
+            let frame = nes.run_frame();
+            palettes::render_xrgb(frame.screen, framebuffer);
+            return frame.samples;
+        })
+    };
+
+    if let Some(video_refresh) = callbacks.video_refresh {
+        unsafe {
+            video_refresh(core.framebuffer.as_ptr() as *const c_void,
+                SCREEN_WIDTH as c_uint, SCREEN_HEIGHT as c_uint, SCREEN_WIDTH * 4);
+        }
+    }
+
+    // The APU is mono; libretro wants interleaved stereo
+    core.audio_buffer.clear();
+    for sample in samples {
+        core.audio_buffer.push(sample);
+        core.audio_buffer.push(sample);
+    }
+    if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+        let mut frames_written = 0;
+        let total_frames = core.audio_buffer.len() / 2;
+        while frames_written < total_frames {
+            let written = unsafe {
+                audio_sample_batch(core.audio_buffer[frames_written * 2 ..].as_ptr(), total_frames - frames_written)
+            };
+            if written == 0 {
+                break;
+            }
+            frames_written += written;
+        }
+    }
+
+    let save_ram = core.nes.sram();
+    if save_ram.len() == core.save_ram.len() {
+        core.save_ram.copy_from_slice(&save_ram);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    return match core().as_ref() {
+        Some(core) => guard(0, || core.nes.max_state_size()),
+        None => 0
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let mut core_guard = core();
+    let core = match core_guard.as_mut() {
+        Some(core) => core,
+        None => return false
+    };
+    if data.is_null() {
+        return false;
+    }
+    // Called every frame for run-ahead, so this mustn't allocate
+    let out = std::slice::from_raw_parts_mut(data as *mut u8, size);
+    return guard(false, || core.nes.save_state_into(out).is_ok());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut core_guard = core();
+    let core = match core_guard.as_mut() {
+        Some(core) => core,
+        None => return false
+    };
+    if data.is_null() {
+        return false;
+    }
+    let state = std::slice::from_raw_parts(data as *const u8, size);
+    return guard(false, || {
+        match core.nes.load_state(state) {
+            Ok(()) => return true,
+            Err(why) => {
+                println!("libretro: failed to load state: {}", why);
+                return false;
+            }
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+    if let Some(core) = core().as_mut() {
+        core.nes.cheats.clear();
+    }
+}
+
+// Accepts Pro Action Rocky codes ("394D7D3F") and raw RAM writes ("0075:09")
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(_index: c_uint, enabled: bool, code: *const c_char) {
+    if code.is_null() {
+        return;
+    }
+    let code = match CStr::from_ptr(code).to_str() {
+        Ok(code) => code.trim(),
+        Err(_) => return
+    };
+    let mut core_guard = core();
+    let core = match core_guard.as_mut() {
+        Some(core) => core,
+        None => return
+    };
+    let cheat = match code.split_once(':') {
+        Some((address, value)) => {
+            match (u16::from_str_radix(address, 16), u8::from_str_radix(value, 16)) {
+                (Ok(address), Ok(value)) => Cheat::new(CheatType::PerFrame, address, value, None),
+                _ => return
+            }
+        },
+        None => match crate::cheats::decode_pro_action_rocky(code) {
+            Ok(cheat) => cheat,
+            Err(_) => return
+        }
+    };
+    let index = core.nes.cheats.add(cheat);
+    core.nes.cheats.set_enabled(index, enabled);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    let mut core_guard = core();
+    let core = match core_guard.as_mut() {
+        Some(core) => core,
+        None => return std::ptr::null_mut()
+    };
+    return match id {
+        RETRO_MEMORY_SAVE_RAM if core.nes.mapper.has_sram() && !core.save_ram.is_empty() => {
+            core.save_ram.as_mut_ptr() as *mut c_void
+        },
+        RETRO_MEMORY_SYSTEM_RAM => core.nes.memory.iram_raw.as_mut_ptr() as *mut c_void,
+        _ => std::ptr::null_mut()
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    let core_guard = core();
+    let core = match core_guard.as_ref() {
+        Some(core) => core,
+        None => return 0
+    };
+    return match id {
+        RETRO_MEMORY_SAVE_RAM if core.nes.mapper.has_sram() => core.save_ram.len(),
+        RETRO_MEMORY_SYSTEM_RAM => core.nes.memory.iram_raw.len(),
+        _ => 0
+    };
+}
@@ -0,0 +1,312 @@
+// Lua scripting, enabled with the "lua" feature. Implements the commonly used
+// parts of FCEUX's scripting API so existing scripts can be adapted with little
+// or no change:
+//
+//   memory.readbyte(addr), memory.readbytesigned(addr), memory.readword(addr),
+//   memory.writebyte(addr, value)
+//   emu.frameadvance(), emu.framecount(), emu.lagcount(), emu.lagged(),
+//   emu.poweron(), emu.softreset(), emu.registerafter(fn), emu.statehash()
+//   joypad.get(port), joypad.set(port, buttons)
+//   gui.pixel(x, y, color), gui.line(x1, y1, x2, y2, color),
+//   gui.box(x1, y1, x2, y2, fill, outline), gui.text(x, y, str, color, backcolor),
+//   gui.clear()
+//   savestate.create(), savestate.save(slot), savestate.load(slot)
+//
+// The script body runs as a coroutine; emu.frameadvance() yields back to the host,
+// which calls run_frame() once per frame. Functions that touch the console are
+// only valid while the script is running, so don't stash them in locals that
+// outlive a frame. gui functions draw on NesState::draw_overlay().
+
+use std::cell::RefCell;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+
+use mlua::{AnyUserData, Lua, RegistryKey, Table, Thread, ThreadStatus, UserData, Value};
+
+use crate::memory;
+use crate::nes::NesState;
+
+pub use crate::overlay::OVERLAY_WIDTH;
+pub use crate::overlay::OVERLAY_HEIGHT;
+
+const JOYPAD_BUTTONS: [&str; 8] = ["A", "B", "select", "start", "up", "down", "left", "right"];
+
+const PRELUDE: &str = r#"
+memory = {}
+joypad = {}
+gui = {}
+savestate = {}
+emu = {}
+emu.frameadvance = coroutine.yield
+function emu.registerafter(callback)
+    __rusticnes_after_frame = callback
+end
+"#;
+
+// Opaque savestate handle returned by savestate.create()
+struct SaveSlot {
+    state: Option<Vec<u8>>,
+}
+
+impl UserData for SaveSlot {}
+
+pub struct LuaScript {
+    lua: Lua,
+    main: Option<RegistryKey>,
+    // What the script drew on the console's overlay this frame, kept for overlay()
+    overlay: Vec<u32>,
+}
+
+impl LuaScript {
+    pub fn new() -> LuaScript {
+        let lua = Lua::new();
+        lua.load(PRELUDE).set_name("prelude").exec().expect("Lua prelude failed to load");
+        return LuaScript {
+            lua: lua,
+            main: None,
+            overlay: vec![0u32; OVERLAY_WIDTH * OVERLAY_HEIGHT],
+        }
+    }
+
+    // Compiles the script and runs it up to its first emu.frameadvance()
+    pub fn load(&mut self, nes: &mut NesState, source: &str, name: &str) -> Result<(), String> {
+        let function = self.lua.load(source).set_name(name).into_function().map_err(|e| e.to_string())?;
+        let thread = self.lua.create_thread(function).map_err(|e| e.to_string())?;
+        self.main = Some(self.lua.create_registry_value(thread).map_err(|e| e.to_string())?);
+        let result = self.resume(nes);
+        self.copy_overlay(nes);
+        return result;
+    }
+
+    // True once the script body has returned. Callbacks registered with
+    // emu.registerafter keep running regardless.
+    pub fn finished(&self) -> bool {
+        return match self.main {
+            Some(ref key) => match self.lua.registry_value::<Thread>(key) {
+                Ok(thread) => thread.status() != ThreadStatus::Resumable,
+                Err(_) => true
+            },
+            None => true
+        };
+    }
+
+    // Emulates one frame, then runs the script's per-frame work
+    pub fn run_frame(&mut self, nes: &mut NesState) -> Result<(), String> {
+        nes.run_until_vblank();
+        let result = self.run_after_frame(nes).and_then(|_| self.resume(nes));
+        self.copy_overlay(nes);
+        return result;
+    }
+
+    // 0xAARRGGBB, where an alpha of 0 is fully transparent. A copy of the console's
+    // overlay as of the end of the last run_frame() or load(), or blank if nothing drew
+    // on the frame the PPU is on. It includes whatever else drew on that frame, ie
+    // debuggers; see NesState::draw_overlay().
+    pub fn overlay(&self) -> &[u32] {
+        return &self.overlay;
+    }
+
+    fn copy_overlay(&mut self, nes: &NesState) {
+        match nes.overlay() {
+            Some(overlay) if overlay.frame == nes.ppu.current_frame => {
+                self.overlay.copy_from_slice(&overlay.pixels);
+            },
+            _ => {
+                for pixel in self.overlay.iter_mut() {
+                    *pixel = 0;
+                }
+            }
+        }
+    }
+
+    fn run_after_frame(&mut self, nes: &mut NesState) -> Result<(), String> {
+        let lua = &self.lua;
+        return with_api(lua, nes, || {
+            let callback: Value = lua.globals().get("__rusticnes_after_frame")?;
+            if let Value::Function(callback) = callback {
+                callback.call::<_, ()>(())?;
+            }
+            return Ok(());
+        });
+    }
+
+    fn resume(&mut self, nes: &mut NesState) -> Result<(), String> {
+        if self.finished() {
+            return Ok(());
+        }
+        let lua = &self.lua;
+        let thread: Thread = match self.main {
+            Some(ref key) => lua.registry_value(key).map_err(|e| e.to_string())?,
+            None => return Ok(())
+        };
+        return with_api(lua, nes, || {
+            thread.resume::<_, ()>(())?;
+            return Ok(());
+        });
+    }
+}
+
+fn color_from_lua(value: Value) -> mlua::Result<u32> {
+    return match value {
+        // FCEUX style 0xRRGGBBAA
+        Value::Integer(rgba) => Ok(((rgba as u32) >> 8) | ((rgba as u32) << 24)),
+        Value::Number(rgba) => Ok(((rgba as u32) >> 8) | ((rgba as u32) << 24)),
+        Value::String(name) => {
+            let name = name.to_str()?.to_lowercase();
+            match name.as_str() {
+                "white" => Ok(0xFFFFFFFF),
+                "black" => Ok(0xFF000000),
+                "red" => Ok(0xFFFF0000),
+                "green" => Ok(0xFF00FF00),
+                "blue" => Ok(0xFF0000FF),
+                "yellow" => Ok(0xFFFFFF00),
+                "clear" => Ok(0x00000000),
+                _ if name.starts_with('#') && name.len() == 7 => {
+                    let rgb = u32::from_str_radix(&name[1..], 16).map_err(mlua::Error::external)?;
+                    Ok(0xFF000000 | rgb)
+                },
+                _ if name.starts_with('#') && name.len() == 9 => {
+                    let rgba = u32::from_str_radix(&name[1..], 16).map_err(mlua::Error::external)?;
+                    Ok((rgba >> 8) | (rgba << 24))
+                },
+                _ => Err(mlua::Error::RuntimeError(format!("Unknown color: {}", name)))
+            }
+        },
+        Value::Nil => Ok(0),
+        _ => Err(mlua::Error::RuntimeError("Colors are 0xRRGGBBAA or a name".to_string()))
+    };
+}
+
+// Installs the console facing half of the API for the duration of f. The
+// functions borrow the console, so they're rebuilt on every call.
+fn with_api<F>(lua: &Lua, nes: &mut NesState, f: F) -> Result<(), String>
+where F: FnOnce() -> mlua::Result<()> {
+    let nes = RefCell::new(nes);
+    let result = lua.scope(|scope| {
+        let globals = lua.globals();
+
+        let memory_table: Table = globals.get("memory")?;
+        memory_table.set("readbyte", scope.create_function(|_, address: u16| {
+            return Ok(memory::debug_read_byte(&nes.borrow(), address));
+        })?)?;
+        memory_table.set("readbytesigned", scope.create_function(|_, address: u16| {
+            return Ok(memory::debug_read_byte(&nes.borrow(), address) as i8);
+        })?)?;
+        memory_table.set("readword", scope.create_function(|_, address: u16| {
+            let nes = nes.borrow();
+            let low = memory::debug_read_byte(&nes, address) as u16;
+            let high = memory::debug_read_byte(&nes, address.wrapping_add(1)) as u16;
+            return Ok((high << 8) | low);
+        })?)?;
+        memory_table.set("writebyte", scope.create_function(|_, (address, data): (u16, u8)| {
+            memory::write_byte(&mut nes.borrow_mut(), address, data);
+            return Ok(());
+        })?)?;
+
+        let emu: Table = globals.get("emu")?;
+        emu.set("framecount", scope.create_function(|_, ()| Ok(nes.borrow().ppu.current_frame))?)?;
+        emu.set("lagcount", scope.create_function(|_, ()| Ok(nes.borrow().lag_counter))?)?;
+        emu.set("lagged", scope.create_function(|_, ()| Ok(nes.borrow().lag_frame))?)?;
+        // As a hex string, since Lua numbers can't hold all 64 bits
+        emu.set("statehash", scope.create_function(|_, ()| {
+            let hash = panic::catch_unwind(AssertUnwindSafe(|| nes.borrow_mut().state_hash()))
+                .map_err(|_| mlua::Error::RuntimeError("This console can't be hashed".to_string()))?;
+            return Ok(format!("{:016X}", hash));
+        })?)?;
+        emu.set("poweron", scope.create_function(|_, ()| {
+            nes.borrow_mut().power_on();
+            return Ok(());
+        })?)?;
+        emu.set("softreset", scope.create_function(|_, ()| {
+            nes.borrow_mut().reset();
+            return Ok(());
+        })?)?;
+
+        let joypad: Table = globals.get("joypad")?;
+        joypad.set("get", scope.create_function(|lua, port: u8| {
+            let nes = nes.borrow();
+            let buttons = match port {
+                1 ..= 4 => nes.player_buttons(port as usize - 1),
+                _ => return Err(mlua::Error::RuntimeError(format!("No controller port {}", port)))
+            };
+            let table = lua.create_table()?;
+            for (bit, name) in JOYPAD_BUTTONS.iter().enumerate() {
+                table.set(*name, (buttons & (1 << bit)) != 0)?;
+            }
+            return Ok(table);
+        })?)?;
+        joypad.set("set", scope.create_function(|_, (port, table): (u8, Table)| {
+            let mut buttons = 0u8;
+            for (bit, name) in JOYPAD_BUTTONS.iter().enumerate() {
+                if table.get::<_, Option<bool>>(*name)?.unwrap_or(false) {
+                    buttons |= 1 << bit;
+                }
+            }
+            let mut nes = nes.borrow_mut();
+            match port {
+                1 ..= 4 => nes.set_player_buttons(port as usize - 1, buttons),
+                _ => return Err(mlua::Error::RuntimeError(format!("No controller port {}", port)))
+            }
+            return Ok(());
+        })?)?;
+
+        let gui: Table = globals.get("gui")?;
+        gui.set("pixel", scope.create_function(|_, (x, y, color): (i64, i64, Value)| {
+            nes.borrow_mut().draw_overlay().pixel(x, y, color_from_lua(color)?);
+            return Ok(());
+        })?)?;
+        gui.set("line", scope.create_function(|_, (x1, y1, x2, y2, color): (i64, i64, i64, i64, Value)| {
+            nes.borrow_mut().draw_overlay().line(x1, y1, x2, y2, color_from_lua(color)?);
+            return Ok(());
+        })?)?;
+        gui.set("box", scope.create_function(|_, (x1, y1, x2, y2, fill, outline): (i64, i64, i64, i64, Value, Value)| {
+            let fill = color_from_lua(fill)?;
+            let outline = match outline {
+                Value::Nil => fill,
+                color => color_from_lua(color)?
+            };
+            nes.borrow_mut().draw_overlay().draw_box(x1, y1, x2, y2, fill, outline);
+            return Ok(());
+        })?)?;
+        // White on black unless told otherwise, as in FCEUX
+        gui.set("text", scope.create_function(|_, (x, y, text, color, background): (i64, i64, String, Value, Value)| {
+            let color = match color {
+                Value::Nil => 0xFFFFFFFF,
+                color => color_from_lua(color)?
+            };
+            let background = match background {
+                Value::Nil => 0xFF000000,
+                color => color_from_lua(color)?
+            };
+            nes.borrow_mut().draw_overlay().text(x, y, &text, color, background);
+            return Ok(());
+        })?)?;
+        gui.set("clear", scope.create_function(|_, ()| {
+            nes.borrow_mut().draw_overlay().clear();
+            return Ok(());
+        })?)?;
+
+        let savestate: Table = globals.get("savestate")?;
+        savestate.set("create", scope.create_function(|_, ()| Ok(SaveSlot{state: None}))?)?;
+        savestate.set("save", scope.create_function(|_, slot: AnyUserData| {
+            if !nes.borrow().mapper.supports_savestates() {
+                return Err(mlua::Error::RuntimeError("This mapper does not support savestates".to_string()));
+            }
+            let state = nes.borrow().save_state();
+            slot.borrow_mut::<SaveSlot>()?.state = Some(state);
+            return Ok(());
+        })?)?;
+        savestate.set("load", scope.create_function(|_, slot: AnyUserData| {
+            let slot = slot.borrow::<SaveSlot>()?;
+            match slot.state {
+                Some(ref state) => nes.borrow_mut().load_state(state)
+                    .map_err(|e| mlua::Error::RuntimeError(e.to_string()))?,
+                None => return Err(mlua::Error::RuntimeError("Savestate slot is empty".to_string()))
+            }
+            return Ok(());
+        })?)?;
+
+        return f();
+    });
+    return result.map_err(|e| e.to_string());
+}
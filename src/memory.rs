@@ -1,11 +1,120 @@
 use crate::{nes::NesState, save_load::{save_vec, load_vec, load_u8, save_u8}};
+use crate::breakpoints;
+use crate::cheats;
+use crate::breakpoints::AccessType;
+use crate::profiler;
+use crate::vgm;
+use crate::expansion_port::EXPANSION_DATA_LINES;
+use crate::mmc::mapper::Mapper;
+
+const CPU_PAGE_SIZE: usize = 0x100;
+const CPU_PAGES: usize = 0x100;
+// PRG ROM at $8000 - $FFFF
+const PRG_PAGE_START: usize = 0x80;
+
+#[derive(Copy, Clone, PartialEq)]
+enum CpuPage {
+    // Through the full memory map
+    Slow,
+    // Internal RAM, $0000 - $1FFF
+    Iram,
+    // From the copy of PRG
+    Cached,
+    // PRG not copied yet
+    Unknown,
+}
+
+// Where each 256 byte page of the CPU's address space reads from, so that the common
+// case, an opcode or operand fetch from PRG ROM or a read from RAM, is one lookup
+// instead of the mapper call and address match in live_read_byte(). Only used with
+// mappers that say their CPU reads have no side effects (see
+// Mapper::cpu_reads_cacheable()). PRG pages are copied on first use and thrown away
+// whenever the cartridge sees a write that could switch banks; the mapper still sees
+// every read that isn't served from here. Registers, cartridge RAM and anything
+// else are always Slow.
+pub struct CpuPageTable {
+    // None until the mapper has been asked
+    cacheable: Option<bool>,
+    pages: [CpuPage; CPU_PAGES],
+    prg: Vec<u8>,
+}
+
+impl CpuPageTable {
+    pub fn new() -> CpuPageTable {
+        return CpuPageTable {
+            cacheable: None,
+            pages: [CpuPage::Slow; CPU_PAGES],
+            prg: vec![0u8; (CPU_PAGES - PRG_PAGE_START) * CPU_PAGE_SIZE],
+        };
+    }
+
+    // Banks may have changed
+    pub fn invalidate(&mut self) {
+        for page in PRG_PAGE_START .. CPU_PAGES {
+            if self.pages[page] == CpuPage::Cached {
+                self.pages[page] = CpuPage::Unknown;
+            }
+        }
+    }
+
+    // A different cartridge, or the same one with a different state loaded
+    pub fn mapper_replaced(&mut self) {
+        self.cacheable = None;
+        self.pages = [CpuPage::Slow; CPU_PAGES];
+    }
+
+    fn configure(&mut self, mapper: &dyn Mapper) {
+        let cacheable = mapper.cpu_reads_cacheable();
+        self.cacheable = Some(cacheable);
+        if cacheable {
+            for page in 0x00 .. 0x20 {
+                self.pages[page] = CpuPage::Iram;
+            }
+            for page in PRG_PAGE_START .. CPU_PAGES {
+                self.pages[page] = CpuPage::Unknown;
+            }
+        }
+    }
+
+    fn fill(&mut self, mapper: &dyn Mapper, page: usize) {
+        let start = page * CPU_PAGE_SIZE;
+        for address in start .. start + CPU_PAGE_SIZE {
+            match mapper.debug_read_cpu(address as u16) {
+                Some(byte) => self.prg[address - PRG_PAGE_START * CPU_PAGE_SIZE] = byte,
+                None => {
+                    self.pages[page] = CpuPage::Slow;
+                    return;
+                }
+            }
+        }
+        self.pages[page] = CpuPage::Cached;
+    }
+
+    // None if the read has to take the slow path
+    pub fn read(&mut self, mapper: &dyn Mapper, iram: &[u8], address: u16) -> Option<u8> {
+        if self.cacheable.is_none() {
+            self.configure(mapper);
+        }
+        let page = address as usize / CPU_PAGE_SIZE;
+        if self.pages[page] == CpuPage::Unknown {
+            self.fill(mapper, page);
+        }
+        return match self.pages[page] {
+            CpuPage::Iram => Some(iram[(address & 0x7FF) as usize]),
+            CpuPage::Cached => Some(self.prg[address as usize - PRG_PAGE_START * CPU_PAGE_SIZE]),
+            _ => None
+        };
+    }
+}
 
 pub struct CpuMemory {
     pub iram_raw: Vec<u8>,
 
     pub recent_reads: Vec<u16>,
     pub recent_writes: Vec<u16>,
-    pub open_bus: u8
+    pub open_bus: u8,
+
+    pub page_table: CpuPageTable,
 }
 
 impl CpuMemory {
@@ -15,6 +124,7 @@ impl CpuMemory {
             recent_reads: Vec::new(),
             recent_writes: Vec::new(),
             open_bus: 0,
+            page_table: CpuPageTable::new(),
         }
     }
 
@@ -29,6 +139,15 @@ impl CpuMemory {
     }
 }
 
+// The Famicom's second controller has a microphone, which reads back through bit 2
+// of $4016
+fn microphone_bit(nes: &NesState, port: usize) -> u8 {
+    if port == 0 && nes.microphone && nes.console.has_microphone() {
+        return 0b0000_0100;
+    }
+    return 0;
+}
+
 pub fn debug_read_byte(nes: &NesState, address: u16) -> u8 {
     // Handle a few special cases for debug reads
     match address {
@@ -44,7 +163,8 @@ pub fn debug_read_byte(nes: &NesState, address: u16) -> u8 {
             }
         },
         0x4015 => {
-            return nes.apu.debug_read_register(address);
+            // Bit 5 isn't driven, and reads back whatever was last on the bus
+            return nes.apu.debug_read_register(address) | (nes.memory.open_bus & 0x20);
         },
         _ => {}
     }
@@ -53,7 +173,95 @@ pub fn debug_read_byte(nes: &NesState, address: u16) -> u8 {
     return _read_byte(nes, address, mapped_byte);
 }
 
+// Every memory a debugger can show. Cpu and Ppu are the two buses as the game sees
+// them, through the current banking; the rest are the memories themselves.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AddressSpace {
+    // $0000 - $FFFF
+    Cpu,
+    // $0000 - $3FFF
+    Ppu,
+    // 256 bytes of sprite attributes
+    Oam,
+    // 32 entries, before the greyscale bit is applied
+    Palette,
+    PrgRom,
+    // CHR ROM, or CHR RAM on boards that have it
+    ChrRom,
+}
+
+impl AddressSpace {
+    pub fn len(&self, nes: &NesState) -> usize {
+        return match self {
+            AddressSpace::Cpu => 0x10000,
+            AddressSpace::Ppu => 0x4000,
+            AddressSpace::Oam => nes.ppu.oam.len(),
+            AddressSpace::Palette => nes.ppu.palette.len(),
+            AddressSpace::PrgRom => nes.mapper.prg_rom().len(),
+            AddressSpace::ChrRom => nes.mapper.chr().len(),
+        };
+    }
+}
+
+// Reads any address space without side effects: open bus, the PPU's read buffer and
+// register latches, mapper IRQ counters and controller shift registers are all left
+// alone. None past the end of the space.
+pub fn debug_peek(nes: &NesState, space: AddressSpace, address: usize) -> Option<u8> {
+    if address >= space.len(nes) {
+        return None;
+    }
+    return match space {
+        AddressSpace::Cpu => Some(debug_read_byte(nes, address as u16)),
+        AddressSpace::Ppu => Some(nes.ppu.debug_read_byte(& *nes.mapper, address as u16)),
+        AddressSpace::Oam => Some(nes.ppu.oam[address]),
+        AddressSpace::Palette => Some(nes.ppu.palette[address]),
+        AddressSpace::PrgRom => Some(nes.mapper.prg_rom()[address]),
+        AddressSpace::ChrRom => Some(nes.mapper.chr()[address]),
+    };
+}
+
+// Fills buff from consecutive addresses starting at address, for hex views. Returns
+// how many bytes were read, which is short if the space ends first.
+pub fn debug_peek_range(nes: &NesState, space: AddressSpace, address: usize, buff: &mut [u8]) -> usize {
+    for (i, byte) in buff.iter_mut().enumerate() {
+        match debug_peek(nes, space, address + i) {
+            Some(data) => *byte = data,
+            None => return i
+        }
+    }
+    return buff.len();
+}
+
 pub fn read_byte(nes: &mut NesState, address: u16) -> u8 {
+    let byte = live_read_byte(nes, address);
+    let byte = nes.cheats.substitute_read(address, byte);
+    breakpoints::snoop(nes, AccessType::Read, address, byte);
+    return byte;
+}
+
+// Opcode fetches behave exactly like reads on the bus, but debugging tools
+// need to tell them apart
+pub fn fetch_opcode(nes: &mut NesState, address: u16) -> u8 {
+    let byte = live_read_byte(nes, address);
+    let byte = nes.cheats.substitute_read(address, byte);
+    nes.event_tracker.snoop_cpu_execute(address, byte);
+    if nes.profiler.enabled {
+        profiler::record_execute(nes, address);
+    }
+    breakpoints::snoop(nes, AccessType::Execute, address, byte);
+    return byte;
+}
+
+fn live_read_byte(nes: &mut NesState, address: u16) -> u8 {
+    if address >= 0x2000 && address <= 0x3FFF {
+        nes.sync_ppu();
+    }
+    if let Some(byte) = nes.memory.page_table.read(&*nes.mapper, &nes.memory.iram_raw, address) {
+        nes.memory.open_bus = byte;
+        nes.event_tracker.snoop_cpu_read(nes.registers.pc, address, byte);
+        return byte;
+    }
+
     let mapped_byte = nes.mapper.read_cpu(address).unwrap_or(nes.memory.open_bus);
 
     // This is a live read, handle any side effects
@@ -103,31 +311,25 @@ pub fn read_byte(nes: &mut NesState, address: u16) -> u8 {
             }
         },
         0x4015 => {
-            let apu_byte = nes.apu.read_register(address);
+            // Bit 5 isn't driven, and reads back whatever was last on the bus. This read
+            // is internal to the CPU, so it doesn't update open bus either.
+            let apu_byte = nes.apu.read_register(address) | (nes.memory.open_bus & 0x20);
             nes.event_tracker.snoop_cpu_read(nes.registers.pc, address, apu_byte);
             return apu_byte;
         },
-        0x4016 => {
-            if nes.input_latch {
-                // strobe register is high, so copy input data to latch (probably bad if this
-                // actually occurs here, but it matches what real hardware would do)
-                nes.p1_data = nes.p1_input;
-            }
-            let result = 0x40 | (nes.p1_data & 0x1);
-            // Standard Controllers set extra bits to 1, which affects controller detection routines
-            nes.p1_data = (nes.p1_data >> 1) | 0x80; 
-            nes.event_tracker.snoop_cpu_read(nes.registers.pc, address, result);
-            return result;
-        },
-        0x4017 => {
-            if nes.input_latch {
-                // strobe register is high, so copy input data to latch (probably bad if this
-                // actually occurs here, but it matches what real hardware would do)
-                nes.p2_data = nes.p2_input;
+        0x4016 | 0x4017 => {
+            nes.input_polled = true;
+            // Only the low bits are driven. The rest hold whatever was last on the bus,
+            // usually the $40 from the instruction's operand, and some games (Paperboy)
+            // check for that.
+            let port = (address - 0x4016) as usize;
+            let mut data = nes.ports[port].read();
+            if let Some(device) = nes.expansion_device.as_mut() {
+                data |= device.read(port) & EXPANSION_DATA_LINES[port];
             }
-            let result = 0x40 | (nes.p2_data & 0x1);
-            // Standard Controllers set extra bits to 1, which affects controller detection routines
-            nes.p2_data = (nes.p2_data >> 1) | 0x80; 
+            data |= microphone_bit(nes, port);
+            let result = (nes.memory.open_bus & 0xE0) | (data & 0x1F);
+            nes.memory.open_bus = result;
             nes.event_tracker.snoop_cpu_read(nes.registers.pc, address, result);
             return result;
         },
@@ -168,13 +370,14 @@ fn _read_byte(nes: &NesState, address: u16, mapped_byte: u8) -> u8 {
                 _ => return 0
             }
         },
-        0x4016 => {
-            let result = 0x40 | (nes.p1_data & 0x1);
-            return result;
-        },
-        0x4017 => {
-            let result = 0x40 | (nes.p2_data & 0x1);
-            return result;
+        0x4016 | 0x4017 => {
+            let port = (address - 0x4016) as usize;
+            let mut data = nes.ports[port].peek();
+            if let Some(device) = nes.expansion_device.as_ref() {
+                data |= device.peek(port) & EXPANSION_DATA_LINES[port];
+            }
+            data |= microphone_bit(nes, port);
+            return (nes.memory.open_bus & 0xE0) | (data & 0x1F);
         },
         0x4020 ..= 0xFFFF => {
             return mapped_byte;
@@ -186,19 +389,40 @@ fn _read_byte(nes: &NesState, address: u16, mapped_byte: u8) -> u8 {
 }
 
 pub fn write_byte(nes: &mut NesState, address: u16, data: u8) {
+    // A lazy PPU has to see everything up to now before its registers or the
+    // cartridge's banks change
+    if address >= 0x2000 && (address <= 0x3FFF || address >= 0x4020) {
+        nes.sync_ppu();
+    }
+    let data = cheats::filter_write(nes, address, data);
+
     // Track every byte written, unconditionally
     // (filtering is done inside the tracker)
     nes.event_tracker.snoop_cpu_write(nes.registers.pc, address, data);
+    breakpoints::snoop(nes, AccessType::Write, address, data);
+    if nes.vgm_logger.is_some() {
+        vgm::snoop_write(nes, address, data);
+    }
+    if let Some(logger) = nes.apu_write_log.as_mut() {
+        logger.log_write(nes.apu.current_cycle, address, data);
+    }
 
     // The mapper *always* sees the write. Even to RAM, and even to internal registers.
     // Most mappers ignore writes to addresses below 0x6000. Some (notably MMC5) do not.
     nes.mapper.write_cpu(address, data);
+    // Neither cache covers PRG RAM, and the mappers that allow caching only bank
+    // through registers elsewhere, so frequent WRAM writes don't refill them
+    if address >= 0x4020 && (address < 0x6000 || address >= 0x8000) {
+        nes.ppu.fetch_cache.invalidate();
+        nes.memory.page_table.invalidate();
+    }
     match address {
         0x0000 ..= 0x1FFF => nes.memory.iram_raw[(address & 0x7FF) as usize] = data,
         0x2000 ..= 0x3FFF => {
             // PPU
             let ppu_reg = address & 0x7;
             nes.ppu.latch = data;
+            nes.ppu.log_register_write(ppu_reg as u8, data);
             match ppu_reg {
                 // PPUCTRL
                 0 => {
@@ -312,16 +536,22 @@ pub fn write_byte(nes: &mut NesState, address: u16, data: u8) {
             nes.apu.write_register(address, data);
         },
         0x4016 => {
-            // Input latch
-            nes.input_latch = data & 0x1 != 0;
-            if nes.input_latch {
-                nes.p1_data = nes.p1_input;
-                nes.p2_data = nes.p2_input;
+            // Strobe, to both ports
+            for port in nes.ports.iter_mut() {
+                port.strobe(data);
+            }
+            if let Some(device) = nes.expansion_device.as_mut() {
+                device.write(data & 0b0000_0111);
             }
         },
         0x4017 => {
             nes.apu.write_register(address, data);
         },
+        0x4018 ..= 0x401F => {
+            if let Some(device) = nes.expansion_device.as_mut() {
+                device.write_register(address, data);
+            }
+        },
         _ => () // Do nothing!
     }
 }
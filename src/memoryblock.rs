@@ -9,7 +9,9 @@ use crate::save_load::{save_vec, load_vec};
 pub struct MemoryBlock {
     bytes: Vec<u8>,
     readonly: bool,
-    volatile: bool
+    volatile: bool,
+    // Battery backed contents changed since the last clear_dirty()
+    dirty: bool,
 }
 
 #[derive(PartialEq)]
@@ -25,6 +27,7 @@ impl MemoryBlock {
             bytes: data.to_vec(),
             readonly: memory_type == MemoryType::Rom,
             volatile: memory_type != MemoryType::NvRam,
+            dirty: false,
         }
     }
 
@@ -51,7 +54,7 @@ impl MemoryBlock {
         if address >= self.len() || self.readonly  {
             return;
         }
-        self.bytes[address] = data;
+        self.store(address, data);
     }
 
     pub fn wrapping_read(&self, address: usize) -> Option<u8> {
@@ -66,7 +69,23 @@ impl MemoryBlock {
             return;
         }
         let len = self.len();
-        self.bytes[address % len] = data;
+        self.store(address % len, data);
+    }
+
+    fn store(&mut self, address: usize, data: u8) {
+        if !self.volatile && self.bytes[address] != data {
+            self.dirty = true;
+        }
+        self.bytes[address] = data;
+    }
+
+    // Only ever set for non-volatile (battery backed) blocks
+    pub fn is_dirty(&self) -> bool {
+        return self.dirty;
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
     }
 
     pub fn banked_read(&self, bank_size: usize, bank_index: usize, offset: usize) -> Option<u8> {
@@ -79,6 +98,20 @@ impl MemoryBlock {
         self.wrapping_write(effective_address, data);
     }
 
+    // Resolves an address the same way wrapping_read would, without reading anything.
+    // Useful for debugging tools which need to know *where* in the block a byte lives.
+    pub fn wrapping_address(&self, address: usize) -> Option<usize> {
+        if self.bytes.len() == 0 {
+            return None;
+        }
+        return Some(address % self.len());
+    }
+
+    pub fn banked_address(&self, bank_size: usize, bank_index: usize, offset: usize) -> Option<usize> {
+        let effective_address = (bank_size * bank_index) + (offset % bank_size);
+        return self.wrapping_address(effective_address);
+    }
+
     pub fn as_vec(&self) -> &Vec<u8> {
         return &self.bytes;
     }
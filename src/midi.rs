@@ -0,0 +1,156 @@
+// Standard MIDI file export of a piano roll, for transcribing soundtracks. Each
+// channel gets a track of its own, named after it. Notes start at the volume they were
+// struck at, and bends, including sweeps and slides, become pitch bends over a range of
+// PITCH_BEND_RANGE semitones either way, set at the start of each track. Noise and
+// sample channels have no pitch, so they go to the General MIDI drum channel instead,
+// with noise rates 0 - 15 as drum notes from DRUM_BASE_NOTE up. Timbre and volume
+// changes partway through a note aren't exported.
+// Reference: https://www.midi.org/specifications/file-format-specifications/standard-midi-files
+
+use crate::apu::piano_roll::NoteEvent;
+use crate::apu::piano_roll::NoteEventKind;
+
+// One tick per frame
+pub const TICKS_PER_QUARTER_NOTE: u16 = 60;
+pub const PITCH_BEND_RANGE: f32 = 12.0;
+pub const DRUM_BASE_NOTE: u8 = 35;
+const DRUM_CHANNEL: u8 = 9;
+
+fn put_variable_length(buff: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push(0x80 | (value & 0x7F) as u8);
+        value >>= 7;
+    }
+    bytes.reverse();
+    buff.extend_from_slice(&bytes);
+}
+
+fn put_chunk(buff: &mut Vec<u8>, id: &[u8], data: &[u8]) {
+    buff.extend_from_slice(id);
+    buff.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buff.extend_from_slice(data);
+}
+
+// A track's events, with delta times worked out as they're added
+struct Track {
+    data: Vec<u8>,
+    last_tick: u32,
+}
+
+impl Track {
+    fn new() -> Track {
+        return Track {
+            data: Vec::new(),
+            last_tick: 0,
+        };
+    }
+
+    fn event(&mut self, tick: u32, bytes: &[u8]) {
+        put_variable_length(&mut self.data, tick.saturating_sub(self.last_tick));
+        self.last_tick = self.last_tick.max(tick);
+        self.data.extend_from_slice(bytes);
+    }
+
+    fn meta(&mut self, tick: u32, kind: u8, payload: &[u8]) {
+        let mut bytes = vec![0xFF, kind];
+        put_variable_length(&mut bytes, payload.len() as u32);
+        bytes.extend_from_slice(payload);
+        self.event(tick, &bytes);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let tick = self.last_tick;
+        self.meta(tick, 0x2F, &[]);
+        return self.data;
+    }
+}
+
+// The MIDI channel for the nth channel with a pitch, skipping the drum channel
+fn tonal_midi_channel(index: usize) -> u8 {
+    let channel = (index % 15) as u8;
+    return if channel >= DRUM_CHANNEL {channel + 1} else {channel};
+}
+
+fn pitch_bend(bend: f32) -> [u8; 2] {
+    let value = (8192.0 + bend / PITCH_BEND_RANGE * 8192.0).round().max(0.0).min(16383.0) as u16;
+    return [(value & 0x7F) as u8, (value >> 7) as u8];
+}
+
+fn velocity(volume: f32) -> u8 {
+    return (1.0 + volume.max(0.0).min(1.0) * 126.0).round() as u8;
+}
+
+// events as PianoRoll collects them, channel_names in ApuState::all_channels() order
+// and tonal saying which of those have a pitch (see ChannelNote::tonal). Time starts
+// at the first event, and notes still held are let go a frame after the last.
+pub fn write(events: &[NoteEvent], channel_names: &[String], tonal: &[bool], frame_rate: f64) -> Vec<u8> {
+    let first_frame = events.iter().map(|event| event.frame).min().unwrap_or(0);
+    let end_tick = events.iter().map(|event| event.frame - first_frame + 1).max().unwrap_or(0);
+    let mut tracks = Vec::new();
+
+    let mut tempo_track = Track::new();
+    let microseconds_per_quarter = (1_000_000.0 * TICKS_PER_QUARTER_NOTE as f64 / frame_rate).round() as u32;
+    tempo_track.meta(0, 0x51, &microseconds_per_quarter.to_be_bytes()[1 ..]);
+    tracks.push(tempo_track.finish());
+
+    let mut tonal_channels = 0;
+    for (channel, name) in channel_names.iter().enumerate() {
+        let mut track = Track::new();
+        track.meta(0, 0x03, name.as_bytes());
+        let is_tonal = tonal.get(channel).copied().unwrap_or(false);
+        let midi_channel = if is_tonal {
+            tonal_channels += 1;
+            tonal_midi_channel(tonal_channels - 1)
+        } else {
+            DRUM_CHANNEL
+        };
+        if is_tonal {
+            // RPN 0, pitch bend sensitivity
+            track.event(0, &[0xB0 | midi_channel, 101, 0]);
+            track.event(0, &[0xB0 | midi_channel, 100, 0]);
+            track.event(0, &[0xB0 | midi_channel, 6, PITCH_BEND_RANGE as u8]);
+            track.event(0, &[0xB0 | midi_channel, 38, 0]);
+        }
+        let key = |note: u8| -> u8 {
+            return if is_tonal {note.min(127)} else {DRUM_BASE_NOTE.saturating_add(note).min(127)};
+        };
+        let mut last_bend = None;
+        let mut held = None;
+        for event in events.iter().filter(|event| event.channel == channel) {
+            let tick = event.frame - first_frame;
+            let bend = pitch_bend(event.bend);
+            if is_tonal && event.kind != NoteEventKind::Off && last_bend != Some(bend) {
+                track.event(tick, &[0xE0 | midi_channel, bend[0], bend[1]]);
+                last_bend = Some(bend);
+            }
+            match event.kind {
+                NoteEventKind::On => {
+                    track.event(tick, &[0x90 | midi_channel, key(event.note), velocity(event.volume)]);
+                    held = Some(key(event.note));
+                },
+                NoteEventKind::Off => {
+                    track.event(tick, &[0x80 | midi_channel, key(event.note), 0]);
+                    held = None;
+                },
+                NoteEventKind::Change => {},
+            }
+        }
+        if let Some(note) = held {
+            track.event(end_tick, &[0x80 | midi_channel, note, 0]);
+        }
+        tracks.push(track.finish());
+    }
+
+    let mut buff = Vec::new();
+    let mut header = Vec::new();
+    header.extend_from_slice(&1u16.to_be_bytes());
+    header.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+    header.extend_from_slice(&TICKS_PER_QUARTER_NOTE.to_be_bytes());
+    put_chunk(&mut buff, b"MThd", &header);
+    for track in tracks.iter() {
+        put_chunk(&mut buff, b"MTrk", track);
+    }
+    return buff;
+}
@@ -1,6 +1,7 @@
 // A very simple Mapper with no esoteric features or bank switching.
 // Reference capabilities: https://wiki.nesdev.com/w/index.php/NROM
 
+use crate::error::Error;
 use crate::ines::INesCartridge;
 use crate::memoryblock::MemoryBlock;
 
@@ -20,10 +21,11 @@ pub struct Action53 {
     prg_outer_bank: usize,
     prg_mode: u8,
     prg_outer_bank_size: usize,
+    pub board: BoardInfo,
 }
 
 impl Action53 {
-    pub fn from_ines(ines: INesCartridge) -> Result<Action53, String> {
+    pub fn from_ines(ines: INesCartridge) -> Result<Action53, Error> {
         let prg_rom_block = ines.prg_rom_block();
         let prg_ram_block = ines.prg_ram_block()?;
         let chr_block = ines.chr_block()?;
@@ -40,6 +42,7 @@ impl Action53 {
             prg_outer_bank: 0xFF,
             prg_mode: 0,
             prg_outer_bank_size: 0,
+            board: ines.header.board_info(),
         });
     }
 
@@ -86,6 +89,18 @@ impl Action53 {
 }
 
 impl Mapper for Action53 {
+    fn board_info(&self) -> BoardInfo {
+        return self.board;
+    }
+
+    fn prg_rom(&self) -> &[u8] {
+        return self.prg_rom.as_vec();
+    }
+
+    fn chr(&self) -> &[u8] {
+        return self.chr.as_vec();
+    }
+
     fn mirroring(&self) -> Mirroring {
         match self.mirroring_mode {
             0 => Mirroring::OneScreenLower,
@@ -95,6 +110,14 @@ impl Mapper for Action53 {
             _ => Mirroring::Horizontal // unreachable
         }
     }
+
+    fn ppu_reads_cacheable(&self) -> bool {
+        return true;
+    }
+
+    fn cpu_reads_cacheable(&self) -> bool {
+        return true;
+    }
     
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         match address {
@@ -106,6 +129,15 @@ impl Mapper for Action53 {
         }
     }
 
+    fn debug_prg_rom_address(&self, address: u16) -> Option<usize> {
+        match address {
+            0x8000 ..= 0xFFFF => {
+                self.prg_rom.wrapping_address(self.prg_address(address))
+            },
+            _ => None
+        }
+    }
+
     fn write_cpu(&mut self, address: u16, data: u8) {
         match address {
             0x5000 ..= 0x5FFF => {self.register_select = data & 0x81;},
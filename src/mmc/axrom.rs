@@ -1,6 +1,7 @@
 // AxROM, bank switchable PRG ROM, 8kb CHR RAM, basic single-screen mirroring.
 // Reference capabilities: https://wiki.nesdev.com/w/index.php/AxROM
 
+use crate::error::Error;
 use crate::ines::INesCartridge;
 use crate::memoryblock::MemoryBlock;
 
@@ -16,10 +17,11 @@ pub struct AxRom {
     pub mirroring: Mirroring,
     pub prg_bank: usize,
     pub vram: Vec<u8>,
+    pub board: BoardInfo,
 }
 
 impl AxRom {
-    pub fn from_ines(ines: INesCartridge) -> Result<AxRom, String> {
+    pub fn from_ines(ines: INesCartridge) -> Result<AxRom, Error> {
         let prg_rom_block = ines.prg_rom_block();
         let chr_block = ines.chr_block()?;
 
@@ -29,15 +31,36 @@ impl AxRom {
             mirroring: Mirroring::OneScreenUpper,
             prg_bank: 0x07,
             vram: vec![0u8; 0x1000],
+            board: ines.header.board_info(),
         });
     }
 }
 
 impl Mapper for AxRom {
+    fn board_info(&self) -> BoardInfo {
+        return self.board;
+    }
+
+    fn prg_rom(&self) -> &[u8] {
+        return self.prg_rom.as_vec();
+    }
+
+    fn chr(&self) -> &[u8] {
+        return self.chr.as_vec();
+    }
+
     fn mirroring(&self) -> Mirroring {
         return self.mirroring;
     }
 
+    fn ppu_reads_cacheable(&self) -> bool {
+        return true;
+    }
+
+    fn cpu_reads_cacheable(&self) -> bool {
+        return true;
+    }
+
     fn print_debug_status(&self) {
         println!("======= AxROM =======");
         println!("PRG Bank: {}, Mirroring Mode: {}", self.prg_bank, mirroring_mode_name(self.mirroring));
@@ -51,6 +74,13 @@ impl Mapper for AxRom {
         }
     }
 
+    fn debug_prg_rom_address(&self, address: u16) -> Option<usize> {
+        match address {
+            0x8000 ..= 0xFFFF => {self.prg_rom.banked_address(0x8000, self.prg_bank, (address - 0x8000) as usize)},
+            _ => None
+        }
+    }
+
     fn write_cpu(&mut self, address: u16, data: u8) {
         match address {
             0x8000 ..= 0xFFFF => {
@@ -89,6 +119,10 @@ impl Mapper for AxRom {
         }
     }
 
+    fn supports_savestates(&self) -> bool {
+        return true;
+    }
+
     fn save_state(&self, buff: &mut Vec<u8>) {    
         self.prg_rom.save_state(buff);
         self.chr.save_state(buff);
@@ -3,6 +3,7 @@
 // dependency free for my own sanity.
 // Reference capabilities: https://wiki.nesdev.com/w/index.php/BNROM
 
+use crate::error::Error;
 use crate::ines::INesCartridge;
 use crate::memoryblock::MemoryBlock;
 
@@ -18,10 +19,11 @@ pub struct BnRom {
     pub mirroring: Mirroring,
     pub prg_bank: usize,
     pub vram: Vec<u8>,
+    pub board: BoardInfo,
 }
 
 impl BnRom {
-    pub fn from_ines(ines: INesCartridge) -> Result<BnRom, String> {
+    pub fn from_ines(ines: INesCartridge) -> Result<BnRom, Error> {
         let prg_rom_block = ines.prg_rom_block();
         let chr_block = ines.chr_block()?;
 
@@ -31,15 +33,36 @@ impl BnRom {
             mirroring: ines.header.mirroring(),
             prg_bank: 0x07,
             vram: vec![0u8; 0x1000],
+            board: ines.header.board_info(),
         });
     }
 }
 
 impl Mapper for BnRom {
+    fn board_info(&self) -> BoardInfo {
+        return self.board;
+    }
+
+    fn prg_rom(&self) -> &[u8] {
+        return self.prg_rom.as_vec();
+    }
+
+    fn chr(&self) -> &[u8] {
+        return self.chr.as_vec();
+    }
+
     fn mirroring(&self) -> Mirroring {
         return self.mirroring;
     }
 
+    fn ppu_reads_cacheable(&self) -> bool {
+        return true;
+    }
+
+    fn cpu_reads_cacheable(&self) -> bool {
+        return true;
+    }
+
     fn print_debug_status(&self) {
         println!("======= BNROM =======");
         println!("PRG Bank: {}, Mirroring Mode: {}", self.prg_bank, mirroring_mode_name(self.mirroring));
@@ -53,6 +76,13 @@ impl Mapper for BnRom {
         }
     }
 
+    fn debug_prg_rom_address(&self, address: u16) -> Option<usize> {
+        match address {
+            0x8000 ..= 0xFFFF => {self.prg_rom.banked_address(0x8000, self.prg_bank, (address - 0x8000) as usize)},
+            _ => None
+        }
+    }
+
     fn write_cpu(&mut self, address: u16, data: u8) {
         match address {
             0x8000 ..= 0xFFFF => {self.prg_bank = data as usize;}
@@ -84,6 +114,10 @@ impl Mapper for BnRom {
         }
     }
 
+    fn supports_savestates(&self) -> bool {
+        return true;
+    }
+
     fn save_state(&self, buff: &mut Vec<u8>) {    
         self.prg_rom.save_state(buff);
         self.chr.save_state(buff);
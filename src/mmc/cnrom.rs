@@ -1,6 +1,9 @@
 // CnROM, 16-32kb PRG ROM, up to 2048k CHR ROM
 // Reference capabilities: https://wiki.nesdev.com/w/index.php/INES_Mapper_003
+// Also mapper 185, CnROM with the bank register repurposed as a copy protection
+// check: https://wiki.nesdev.com/w/index.php/INES_Mapper_185
 
+use crate::error::Error;
 use crate::ines::INesCartridge;
 use crate::memoryblock::MemoryBlock;
 
@@ -16,10 +19,11 @@ pub struct CnRom {
     pub mirroring: Mirroring,
     pub chr_bank: usize,
     pub vram: Vec<u8>,
+    pub board: BoardInfo,
 }
 
 impl CnRom {
-        pub fn from_ines(ines: INesCartridge) -> Result<CnRom, String> {
+        pub fn from_ines(ines: INesCartridge) -> Result<CnRom, Error> {
         let prg_rom_block = ines.prg_rom_block();
         let chr_block = ines.chr_block()?;
 
@@ -29,21 +33,58 @@ impl CnRom {
             mirroring: ines.header.mirroring(),
             chr_bank: 0x00,
             vram: vec![0u8; 0x1000],
+            board: ines.header.board_info(),
         });
     }
 }
 
+impl CnRom {
+    // Mapper 185 boards only connect CHR ROM while the register holds the right value,
+    // which NES 2.0 submappers 4 - 7 give in the low two bits. Without one, guess as
+    // FCEUX does. Every other CnROM board is always connected.
+    fn chr_enabled(&self) -> bool {
+        if self.board.mapper_number != 185 {
+            return true;
+        }
+        let value = self.chr_bank as u8;
+        return match self.board.submapper {
+            4 ..= 7 => (value & 0b11) == self.board.submapper - 4,
+            _ => (value & 0b11) != 0 && value != 0x13,
+        };
+    }
+}
+
 impl Mapper for CnRom {
+    fn board_info(&self) -> BoardInfo {
+        return self.board;
+    }
+
     fn print_debug_status(&self) {
         println!("======= CnROM =======");
         println!("CHR Bank: {}, Mirroring Mode: {}", self.chr_bank, mirroring_mode_name(self.mirroring));
         println!("====================");
     }
 
+    fn prg_rom(&self) -> &[u8] {
+        return self.prg_rom.as_vec();
+    }
+
+    fn chr(&self) -> &[u8] {
+        return self.chr.as_vec();
+    }
+
     fn mirroring(&self) -> Mirroring {
         return self.mirroring;
     }
 
+    fn ppu_reads_cacheable(&self) -> bool {
+        return true;
+    }
+
+    fn cpu_reads_cacheable(&self) -> bool {
+        return true;
+    }
+
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         match address {
             0x8000 ..= 0xFFFF => {self.prg_rom.wrapping_read((address - 0x8000) as usize)},
@@ -51,6 +92,13 @@ impl Mapper for CnRom {
         }
     }
 
+    fn debug_prg_rom_address(&self, address: u16) -> Option<usize> {
+        match address {
+            0x8000 ..= 0xFFFF => {self.prg_rom.wrapping_address((address - 0x8000) as usize)},
+            _ => None
+        }
+    }
+
     fn write_cpu(&mut self, address: u16, data: u8) {
         match address {
             0x8000 ..= 0xFFFF => {
@@ -62,6 +110,8 @@ impl Mapper for CnRom {
 
     fn debug_read_ppu(&self, address: u16) -> Option<u8> {
         match address {
+            // Disconnected CHR reads back as all 1s
+            0x0000 ..= 0x1FFF if !self.chr_enabled() => Some(0xFF),
             0x0000 ..= 0x1FFF => {self.chr.banked_read(0x2000, self.chr_bank, address as usize)},
             0x2000 ..= 0x3FFF => match self.mirroring {
                 Mirroring::Horizontal => Some(self.vram[mirroring::horizontal_mirroring(address) as usize]),
@@ -84,6 +134,10 @@ impl Mapper for CnRom {
         }
     }
     
+    fn supports_savestates(&self) -> bool {
+        return true;
+    }
+
     fn save_state(&self, buff: &mut Vec<u8>) {    
         self.prg_rom.save_state(buff);
         self.chr.save_state(buff);
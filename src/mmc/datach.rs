@@ -0,0 +1,524 @@
+// Bandai Datach Joint ROM System (mapper 157): a Bandai FCG board (LZ93D50) with a
+// barcode reader and a 24C02 EEPROM built into the base unit, and CHR RAM.
+// Reference capabilities: https://wiki.nesdev.com/w/index.php/INES_Mapper_157
+// The 24C01 that a few game cartridges add (Battle Rush) is not emulated.
+
+use crate::error::Error;
+use crate::ines::INesCartridge;
+use crate::input::barcode_digits;
+use crate::memoryblock::MemoryBlock;
+
+use crate::mmc::mapper::*;
+use crate::mmc::mirroring;
+
+use crate::save_load::*;
+
+// How long the reader holds each bar or space on its output, in CPU cycles
+const BARCODE_CYCLES_PER_BIT: u32 = 1000;
+
+// EAN encodings of each digit, one bit per module, 1 for a bar. R codes are the
+// bitwise complement of L codes.
+const EAN_L_CODES: [u8; 10] = [0x0D, 0x19, 0x13, 0x3D, 0x23, 0x31, 0x2F, 0x3B, 0x37, 0x0B];
+const EAN_G_CODES: [u8; 10] = [0x27, 0x33, 0x1B, 0x21, 0x1D, 0x39, 0x05, 0x11, 0x09, 0x17];
+// Which of the EAN-13 left hand digits use G codes, for each first digit; bit 5 is
+// the second digit
+const EAN_13_PARITY: [u8; 10] = [0x00, 0x0B, 0x0D, 0x0E, 0x13, 0x19, 0x1C, 0x15, 0x16, 0x1A];
+
+// Quiet zones, guards and 12 digits of an EAN-13 code
+const BARCODE_MAX_MODULES: usize = 160;
+
+// Appends the low count bits, high bit first
+fn push_bits(modules: &mut Vec<bool>, bits: u8, count: usize) {
+    for i in (0 .. count).rev() {
+        modules.push((bits >> i) & 0x1 != 0);
+    }
+}
+
+// The reader's output, one entry per module from the left: true for a bar
+fn barcode_modules(digits: &[u8]) -> Vec<bool> {
+    let mut modules = Vec::new();
+    // Quiet zone, then the start guard
+    for _ in 0 .. 33 {modules.push(false);}
+    push_bits(&mut modules, 0b101, 3);
+    let (left, right, parity) = if digits.len() == 13 {
+        (&digits[1 .. 7], &digits[7 .. 13], EAN_13_PARITY[digits[0] as usize])
+    } else {
+        (&digits[0 .. 4], &digits[4 .. 8], 0)
+    };
+    for (i, &digit) in left.iter().enumerate() {
+        let uses_g_code = (parity >> (left.len() - 1 - i)) & 0x1 != 0;
+        let code = if uses_g_code {EAN_G_CODES[digit as usize]} else {EAN_L_CODES[digit as usize]};
+        push_bits(&mut modules, code, 7);
+    }
+    push_bits(&mut modules, 0b01010, 5);
+    for &digit in right.iter() {
+        push_bits(&mut modules, !EAN_L_CODES[digit as usize] & 0x7F, 7);
+    }
+    push_bits(&mut modules, 0b101, 3);
+    for _ in 0 .. 32 {modules.push(false);}
+    return modules;
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum EepromMode {
+    Idle,
+    ChipAddress,
+    WordAddress,
+    Write,
+    Read,
+}
+
+// A 24C02: 256 bytes of I2C EEPROM, bit-banged by the CPU through $800D
+#[derive(Clone)]
+struct Eeprom24C02 {
+    data: Vec<u8>,
+    mode: EepromMode,
+    // Mode to enter once the current byte is acknowledged
+    next_mode: EepromMode,
+    address: u8,
+    shift_register: u8,
+    // Bits shifted in or out of the current byte; 8 is the acknowledge
+    bit_count: u8,
+    // SDA as driven by the EEPROM (open drain, so 1 releases it)
+    output: bool,
+    scl: bool,
+    sda: bool,
+    // Written since the last Mapper::clear_sram_dirty()
+    dirty: bool,
+}
+
+impl Eeprom24C02 {
+    fn new() -> Eeprom24C02 {
+        return Eeprom24C02 {
+            data: vec![0xFFu8; 256],
+            mode: EepromMode::Idle,
+            next_mode: EepromMode::Idle,
+            address: 0,
+            shift_register: 0,
+            bit_count: 0,
+            output: true,
+            scl: false,
+            sda: false,
+            dirty: false,
+        };
+    }
+
+    fn write_lines(&mut self, scl: bool, sda: bool) {
+        let previous_scl = self.scl;
+        let previous_sda = self.sda;
+        self.scl = scl;
+        self.sda = sda;
+
+        if previous_scl && scl && previous_sda != sda {
+            if !sda {
+                // Start (or repeated start)
+                self.mode = EepromMode::ChipAddress;
+                self.bit_count = 0;
+                self.shift_register = 0;
+            } else {
+                // Stop
+                self.mode = EepromMode::Idle;
+            }
+            self.output = true;
+            return;
+        }
+        if !previous_scl && scl {
+            self.clock_rising();
+        }
+        if previous_scl && !scl {
+            self.clock_falling();
+        }
+    }
+
+    // The EEPROM samples SDA while the clock is high
+    fn clock_rising(&mut self) {
+        match self.mode {
+            EepromMode::ChipAddress | EepromMode::WordAddress | EepromMode::Write => {
+                if self.bit_count < 8 {
+                    self.shift_register = (self.shift_register << 1) | (self.sda as u8);
+                    self.bit_count += 1;
+                }
+            },
+            EepromMode::Read => {
+                if self.bit_count == 9 && self.sda {
+                    // No acknowledge from the CPU; the read is over
+                    self.mode = EepromMode::Idle;
+                    self.output = true;
+                }
+            },
+            EepromMode::Idle => {}
+        }
+    }
+
+    // and changes its own output while the clock is low
+    fn clock_falling(&mut self) {
+        match self.mode {
+            EepromMode::ChipAddress | EepromMode::WordAddress | EepromMode::Write => {
+                if self.bit_count == 8 {
+                    self.receive_byte();
+                } else if self.bit_count == 9 {
+                    self.output = true;
+                    self.bit_count = 0;
+                    self.shift_register = 0;
+                    self.mode = self.next_mode;
+                    if self.mode == EepromMode::Read {
+                        self.begin_read_byte();
+                    }
+                }
+            },
+            EepromMode::Read => {
+                if self.bit_count < 8 {
+                    self.output = (self.shift_register >> (7 - self.bit_count)) & 0x1 != 0;
+                    self.bit_count += 1;
+                } else if self.bit_count == 8 {
+                    // Let go of SDA, so the CPU can acknowledge
+                    self.output = true;
+                    self.bit_count = 9;
+                } else {
+                    self.address = self.address.wrapping_add(1);
+                    self.begin_read_byte();
+                }
+            },
+            EepromMode::Idle => {}
+        }
+    }
+
+    fn receive_byte(&mut self) {
+        let byte = self.shift_register;
+        match self.mode {
+            EepromMode::ChipAddress => {
+                if byte & 0xF0 != 0xA0 {
+                    // Addressed to some other chip; stay off the bus
+                    self.mode = EepromMode::Idle;
+                    return;
+                }
+                self.next_mode = if byte & 0x1 != 0 {EepromMode::Read} else {EepromMode::WordAddress};
+            },
+            EepromMode::WordAddress => {
+                self.address = byte;
+                self.next_mode = EepromMode::Write;
+            },
+            EepromMode::Write => {
+                if self.data[self.address as usize] != byte {
+                    self.dirty = true;
+                }
+                self.data[self.address as usize] = byte;
+                // Writes wrap within an 8 byte page
+                self.address = (self.address & 0xF8) | (self.address.wrapping_add(1) & 0x07);
+                self.next_mode = EepromMode::Write;
+            },
+            _ => {}
+        }
+        // Acknowledge
+        self.output = false;
+        self.bit_count = 9;
+    }
+
+    fn begin_read_byte(&mut self) {
+        self.shift_register = self.data[self.address as usize];
+        self.output = self.shift_register & 0x80 != 0;
+        self.bit_count = 1;
+    }
+
+    fn save_state(&self, buff: &mut Vec<u8>) {
+        save_vec(buff, &self.data);
+        save_u8(buff, self.mode as u8);
+        save_u8(buff, self.next_mode as u8);
+        save_u8(buff, self.address);
+        save_u8(buff, self.shift_register);
+        save_u8(buff, self.bit_count);
+        save_bool(buff, self.output);
+        save_bool(buff, self.scl);
+        save_bool(buff, self.sda);
+    }
+
+    fn load_state(&mut self, buff: &mut Vec<u8>) {
+        load_bool(buff, &mut self.sda);
+        load_bool(buff, &mut self.scl);
+        load_bool(buff, &mut self.output);
+        load_u8(buff, &mut self.bit_count);
+        load_u8(buff, &mut self.shift_register);
+        load_u8(buff, &mut self.address);
+        let mut next_mode = 0;
+        load_u8(buff, &mut next_mode);
+        self.next_mode = eeprom_mode_from_u8(next_mode);
+        let mut mode = 0;
+        load_u8(buff, &mut mode);
+        self.mode = eeprom_mode_from_u8(mode);
+        load_vec(buff, &mut self.data);
+    }
+}
+
+fn eeprom_mode_from_u8(mode: u8) -> EepromMode {
+    return match mode {
+        1 => EepromMode::ChipAddress,
+        2 => EepromMode::WordAddress,
+        3 => EepromMode::Write,
+        4 => EepromMode::Read,
+        _ => EepromMode::Idle,
+    };
+}
+
+#[derive(Clone)]
+pub struct Datach {
+    pub prg_rom: MemoryBlock,
+    pub chr: MemoryBlock,
+    pub mirroring: Mirroring,
+    pub prg_bank: usize,
+    pub vram: Vec<u8>,
+    pub irq_enabled: bool,
+    pub irq_pending: bool,
+    pub irq_counter: u16,
+    pub irq_latch: u16,
+    eeprom: Eeprom24C02,
+    // Modules of the barcode being swiped, and how far along the swipe is
+    barcode: Vec<bool>,
+    barcode_cycles: u32,
+    pub board: BoardInfo,
+}
+
+impl Datach {
+    pub fn from_ines(ines: INesCartridge) -> Result<Datach, Error> {
+        let prg_rom_block = ines.prg_rom_block();
+        let chr_block = ines.chr_block()?;
+
+        return Ok(Datach {
+            prg_rom: prg_rom_block.clone(),
+            chr: chr_block.clone(),
+            mirroring: Mirroring::Vertical,
+            prg_bank: 0,
+            vram: vec![0u8; 0x1000],
+            irq_enabled: false,
+            irq_pending: false,
+            irq_counter: 0,
+            irq_latch: 0,
+            eeprom: Eeprom24C02::new(),
+            barcode: Vec::new(),
+            barcode_cycles: 0,
+            board: ines.header.board_info(),
+        });
+    }
+
+    // Bit 3 of $6000 - $7FFF: the reader's output, high for a space
+    fn barcode_output(&self) -> u8 {
+        let module = (self.barcode_cycles / BARCODE_CYCLES_PER_BIT) as usize;
+        if module < self.barcode.len() && self.barcode[module] {
+            return 0;
+        }
+        return 0x08;
+    }
+}
+
+impl Mapper for Datach {
+    fn board_info(&self) -> BoardInfo {
+        return self.board;
+    }
+
+    fn print_debug_status(&self) {
+        println!("======= Datach =======");
+        println!("PRG Bank: {}, ", self.prg_bank);
+        println!("IRQ: Enabled: {}, Pending: {}, Counter: {}, Latch: {}", self.irq_enabled, self.irq_pending, self.irq_counter, self.irq_latch);
+        println!("Mirroring Mode: {}", mirroring_mode_name(self.mirroring));
+        println!("====================");
+    }
+
+    fn prg_rom(&self) -> &[u8] {
+        return self.prg_rom.as_vec();
+    }
+
+    fn chr(&self) -> &[u8] {
+        return self.chr.as_vec();
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring;
+    }
+
+    fn debug_read_cpu(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000 ..= 0x7FFF => Some(self.barcode_output() | ((self.eeprom.output as u8) << 4)),
+            0x8000 ..= 0xBFFF => self.prg_rom.banked_read(0x4000, self.prg_bank, address as usize - 0x8000),
+            0xC000 ..= 0xFFFF => self.prg_rom.banked_read(0x4000, 0xFF, address as usize - 0xC000),
+            _ => None
+        }
+    }
+
+    fn debug_prg_rom_address(&self, address: u16) -> Option<usize> {
+        match address {
+            0x8000 ..= 0xBFFF => self.prg_rom.banked_address(0x4000, self.prg_bank, address as usize - 0x8000),
+            0xC000 ..= 0xFFFF => self.prg_rom.banked_address(0x4000, 0xFF, address as usize - 0xC000),
+            _ => None
+        }
+    }
+
+    fn write_cpu(&mut self, address: u16, data: u8) {
+        match address {
+            0x8000 ..= 0xFFFF => {
+                match address & 0x000F {
+                    0x8 => {self.prg_bank = (data & 0x0F) as usize;},
+                    0x9 => {
+                        match data & 0b0000_0011 {
+                            0 => self.mirroring = Mirroring::Vertical,
+                            1 => self.mirroring = Mirroring::Horizontal,
+                            2 => self.mirroring = Mirroring::OneScreenLower,
+                            3 => self.mirroring = Mirroring::OneScreenUpper,
+                            _ => {}
+                        }
+                    },
+                    0xA => {
+                        // Acknowledges any pending IRQ, and reloads the counter
+                        self.irq_pending = false;
+                        self.irq_enabled = (data & 0b0000_0001) != 0;
+                        self.irq_counter = self.irq_latch;
+                    },
+                    0xB => {self.irq_latch = (self.irq_latch & 0xFF00) | (data as u16);},
+                    0xC => {self.irq_latch = (self.irq_latch & 0x00FF) | ((data as u16) << 8);},
+                    0xD => {
+                        // Bit 7 set means the CPU is reading, and has let go of SDA
+                        let scl = (data & 0b0010_0000) != 0;
+                        let sda = (data & 0b0100_0000) != 0 || (data & 0b1000_0000) != 0;
+                        self.eeprom.write_lines(scl, sda);
+                    },
+                    _ => {}
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.irq_enabled {
+            // Checked before the decrement, so a counter of 0 fires right away
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+        }
+        if !self.barcode.is_empty() {
+            self.barcode_cycles += 1;
+            if self.barcode_cycles / BARCODE_CYCLES_PER_BIT >= self.barcode.len() as u32 {
+                self.barcode.clear();
+            }
+        }
+    }
+
+    fn irq_flag(&self) -> bool {
+        return self.irq_pending;
+    }
+
+    fn acknowledge_irq(&mut self) {
+        self.irq_pending = false;
+    }
+
+    fn debug_read_ppu(&self, address: u16) -> Option<u8> {
+        match address {
+            0x0000 ..= 0x1FFF => self.chr.wrapping_read(address as usize),
+            0x2000 ..= 0x3FFF => match self.mirroring {
+                Mirroring::Horizontal => Some(self.vram[mirroring::horizontal_mirroring(address) as usize]),
+                Mirroring::Vertical   => Some(self.vram[mirroring::vertical_mirroring(address) as usize]),
+                Mirroring::OneScreenLower => Some(self.vram[mirroring::one_screen_lower(address) as usize]),
+                Mirroring::OneScreenUpper => Some(self.vram[mirroring::one_screen_upper(address) as usize]),
+                _ => None
+            },
+            _ => None
+        }
+    }
+
+    fn write_ppu(&mut self, address: u16, data: u8) {
+        match address {
+            0x0000 ..= 0x1FFF => self.chr.wrapping_write(address as usize, data),
+            0x2000 ..= 0x3FFF => match self.mirroring {
+                Mirroring::Horizontal => self.vram[mirroring::horizontal_mirroring(address) as usize] = data,
+                Mirroring::Vertical   => self.vram[mirroring::vertical_mirroring(address) as usize] = data,
+                Mirroring::OneScreenLower => self.vram[mirroring::one_screen_lower(address) as usize] = data,
+                Mirroring::OneScreenUpper => self.vram[mirroring::one_screen_upper(address) as usize] = data,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    // The base unit's EEPROM keeps its contents without power, like battery backed RAM
+    fn has_sram(&self) -> bool {
+        return true;
+    }
+
+    fn get_sram(&self) -> Vec<u8> {
+        return self.eeprom.data.clone();
+    }
+
+    fn load_sram(&mut self, sram_data: Vec<u8>) {
+        if sram_data.len() == self.eeprom.data.len() {
+            self.eeprom.data = sram_data;
+        }
+    }
+
+    fn sram_dirty(&self) -> bool {
+        return self.eeprom.dirty;
+    }
+
+    fn clear_sram_dirty(&mut self) {
+        self.eeprom.dirty = false;
+    }
+
+    fn insert_barcode(&mut self, barcode: &str) -> bool {
+        return match barcode_digits(barcode) {
+            Some(digits) => {
+                self.barcode = barcode_modules(&digits);
+                self.barcode_cycles = 0;
+                true
+            },
+            None => false
+        };
+    }
+
+    fn supports_savestates(&self) -> bool {
+        return true;
+    }
+
+    fn save_state(&self, buff: &mut Vec<u8>) {
+        self.prg_rom.save_state(buff);
+        self.chr.save_state(buff);
+        save_usize(buff, self.prg_bank);
+        save_vec(buff, &self.vram);
+        save_u8(buff, match self.mirroring {
+            Mirroring::Horizontal => 1,
+            Mirroring::OneScreenLower => 2,
+            Mirroring::OneScreenUpper => 3,
+            _ => 0,
+        });
+        save_bool(buff, self.irq_enabled);
+        save_bool(buff, self.irq_pending);
+        save_u16(buff, self.irq_counter);
+        save_u16(buff, self.irq_latch);
+        self.eeprom.save_state(buff);
+        save_bits(buff, &self.barcode, BARCODE_MAX_MODULES);
+        save_u32(buff, self.barcode_cycles);
+    }
+
+    fn load_state(&mut self, buff: &mut Vec<u8>) {
+        load_u32(buff, &mut self.barcode_cycles);
+        load_bits(buff, &mut self.barcode, BARCODE_MAX_MODULES);
+        self.eeprom.load_state(buff);
+        load_u16(buff, &mut self.irq_latch);
+        load_u16(buff, &mut self.irq_counter);
+        load_bool(buff, &mut self.irq_pending);
+        load_bool(buff, &mut self.irq_enabled);
+        let mut mirroring = 0;
+        load_u8(buff, &mut mirroring);
+        self.mirroring = match mirroring {
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::OneScreenLower,
+            3 => Mirroring::OneScreenUpper,
+            _ => Mirroring::Vertical,
+        };
+        load_vec(buff, &mut self.vram);
+        load_usize(buff, &mut self.prg_bank);
+        self.chr.load_state(buff);
+        self.prg_rom.load_state(buff);
+    }
+
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new((*self).clone())
+    }
+}
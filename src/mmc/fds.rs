@@ -0,0 +1,372 @@
+// Famicom Disk System expansion audio: one wavetable channel with frequency
+// modulation. There's no disk drive here, so this is only used by NSF playback.
+// Reference capabilities: https://wiki.nesdev.com/w/index.php?title=FDS_audio
+
+use crate::apu::AudioChannelState;
+use crate::apu::PlaybackRate;
+use crate::apu::Volume;
+use crate::apu::Timbre;
+use crate::apu::RingBuffer;
+use crate::apu::filters;
+use crate::apu::filters::DspFilter;
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+// A full volume, full amplitude wave is about 2.4 times as loud as an APU pulse
+// at full volume: https://wiki.nesdev.com/w/index.php?title=FDS_audio#Mixing
+pub const FDS_RELATIVE_MIX: f32 = 2.4;
+// The loudest sample at the loudest volume
+pub const FDS_FULL_VOLUME: f32 = 63.0 * 32.0;
+
+// $4089 bits 0-1: 2/2, 2/3, 2/4 and 2/5
+const MASTER_VOLUME: [f32; 4] = [1.0, 2.0 / 3.0, 0.5, 0.4];
+// Mod table entries, as steps of the mod counter; None resets it to 0
+const MOD_STEPS: [Option<i8>; 8] = [Some(0), Some(1), Some(2), Some(4), None, Some(-4), Some(-2), Some(-1)];
+
+// The volume and mod envelopes, $4080 and $4084
+pub struct FdsEnvelope {
+    // When set, gain is whatever was last written, and the envelope doesn't run
+    pub disabled: bool,
+    pub increase: bool,
+    pub speed: u8,
+    pub gain: u8,
+    pub timer: u32,
+}
+
+impl FdsEnvelope {
+    pub fn new() -> FdsEnvelope {
+        return FdsEnvelope {
+            disabled: true,
+            increase: false,
+            speed: 0,
+            gain: 0,
+            timer: 0,
+        };
+    }
+
+    pub fn write(&mut self, data: u8, master_speed: u8) {
+        self.disabled = (data & 0b1000_0000) != 0;
+        self.increase = (data & 0b0100_0000) != 0;
+        self.speed = data & 0x3F;
+        if self.disabled {
+            self.gain = data & 0x3F;
+        }
+        self.reload(master_speed);
+    }
+
+    fn reload(&mut self, master_speed: u8) {
+        self.timer = 8 * (master_speed as u32 + 1) * (self.speed as u32 + 1);
+    }
+
+    pub fn clock(&mut self, master_speed: u8) {
+        if self.disabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.reload(master_speed);
+            if self.increase && self.gain < 32 {
+                self.gain += 1;
+            }
+            if !self.increase && self.gain > 0 {
+                self.gain -= 1;
+            }
+        }
+    }
+}
+
+pub struct FdsAudio {
+    pub debug_disable: bool,
+    // Output volume multiplier, for mixing
+    pub gain: f32,
+
+    pub wave_table: [u8; 64],
+    pub wave_write_enabled: bool,
+    pub wave_frequency: u16,
+    pub wave_halted: bool,
+    pub wave_accumulator: u32,
+    pub wave_position: u8,
+    pub master_volume: u8,
+
+    pub volume_envelope: FdsEnvelope,
+    // The volume envelope's gain only takes effect at the start of each cycle
+    pub output_volume: u8,
+    pub envelopes_halted: bool,
+    pub envelope_speed: u8,
+
+    pub mod_envelope: FdsEnvelope,
+    pub mod_table: [u8; 64],
+    pub mod_frequency: u16,
+    pub mod_halted: bool,
+    pub mod_accumulator: u32,
+    pub mod_position: u8,
+    // 7-bit signed
+    pub mod_counter: i8,
+
+    pub current_output: f32,
+    // The chip's output passes through a lowpass filter on the RAM adapter
+    pub lowpass: filters::LowPassIIR,
+
+    pub output_buffer: RingBuffer,
+    pub edge_buffer: RingBuffer,
+    pub last_edge: bool,
+    pub debug_filter: filters::HighPassIIR,
+}
+
+impl FdsAudio {
+    pub fn new() -> FdsAudio {
+        return FdsAudio {
+            debug_disable: false,
+            gain: 1.0,
+
+            wave_table: [0u8; 64],
+            wave_write_enabled: false,
+            wave_frequency: 0,
+            wave_halted: true,
+            wave_accumulator: 0,
+            wave_position: 0,
+            master_volume: 0,
+
+            volume_envelope: FdsEnvelope::new(),
+            output_volume: 0,
+            envelopes_halted: false,
+            envelope_speed: 0xE8,
+
+            mod_envelope: FdsEnvelope::new(),
+            mod_table: [0u8; 64],
+            mod_frequency: 0,
+            mod_halted: true,
+            mod_accumulator: 0,
+            mod_position: 0,
+            mod_counter: 0,
+
+            current_output: 0.0,
+            lowpass: filters::LowPassIIR::new(1_789_773.0, 2000.0),
+
+            output_buffer: RingBuffer::new(32768),
+            edge_buffer: RingBuffer::new(32768),
+            last_edge: false,
+            debug_filter: filters::HighPassIIR::new(44100.0, 300.0),
+        };
+    }
+
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        match address {
+            0x4040 ..= 0x407F => {
+                if self.wave_write_enabled {
+                    self.wave_table[(address - 0x4040) as usize] = data & 0x3F;
+                }
+            },
+            0x4080 => {self.volume_envelope.write(data, self.envelope_speed);},
+            0x4082 => {self.wave_frequency = (self.wave_frequency & 0x0F00) | data as u16;},
+            0x4083 => {
+                self.wave_frequency = (self.wave_frequency & 0x00FF) | (((data & 0x0F) as u16) << 8);
+                self.wave_halted = (data & 0b1000_0000) != 0;
+                self.envelopes_halted = (data & 0b0100_0000) != 0;
+                if self.wave_halted {
+                    self.wave_accumulator = 0;
+                    self.wave_position = 0;
+                }
+            },
+            0x4084 => {self.mod_envelope.write(data, self.envelope_speed);},
+            0x4085 => {self.mod_counter = ((data << 1) as i8) >> 1;},
+            0x4086 => {self.mod_frequency = (self.mod_frequency & 0x0F00) | data as u16;},
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0x00FF) | (((data & 0x0F) as u16) << 8);
+                self.mod_halted = (data & 0b1000_0000) != 0;
+                if self.mod_halted {
+                    self.mod_accumulator = 0;
+                }
+            },
+            0x4088 => {
+                // Each write fills two entries, and only while the mod unit is halted
+                if self.mod_halted {
+                    self.mod_table[self.mod_position as usize] = data & 0x07;
+                    self.mod_table[(self.mod_position + 1) as usize & 0x3F] = data & 0x07;
+                    self.mod_position = (self.mod_position + 2) & 0x3F;
+                }
+            },
+            0x4089 => {
+                self.wave_write_enabled = (data & 0b1000_0000) != 0;
+                self.master_volume = data & 0b0000_0011;
+            },
+            0x408A => {self.envelope_speed = data;},
+            _ => {}
+        }
+    }
+
+    pub fn read_register(&self, address: u16) -> Option<u8> {
+        match address {
+            0x4040 ..= 0x407F => {
+                let index = if self.wave_write_enabled {(address - 0x4040) as usize} else {self.wave_position as usize};
+                return Some(0x40 | self.wave_table[index]);
+            },
+            0x4090 => return Some(0x40 | self.volume_envelope.gain),
+            0x4092 => return Some(0x40 | self.mod_envelope.gain),
+            _ => return None
+        }
+    }
+
+    // The wave's pitch, after modulation. See the FDS audio reference for where this
+    // strange rounding comes from.
+    pub fn modulated_frequency(&self) -> u32 {
+        let pitch = self.wave_frequency as i32;
+        let counter = self.mod_counter as i32;
+        let mut temp = counter * self.mod_envelope.gain as i32;
+        let remainder = temp & 0xF;
+        temp >>= 4;
+        if remainder > 0 && (temp & 0x80) == 0 {
+            if counter < 0 {
+                temp -= 1;
+            } else {
+                temp += 2;
+            }
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        temp *= pitch;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        return (pitch + temp).max(0) as u32;
+    }
+
+    fn step_mod_counter(&mut self) {
+        match MOD_STEPS[self.mod_table[self.mod_position as usize] as usize] {
+            Some(step) => {
+                // wraps within 7 bits
+                let counter = (self.mod_counter as i16 + step as i16 + 64).rem_euclid(128) - 64;
+                self.mod_counter = counter as i8;
+            },
+            None => {self.mod_counter = 0;}
+        }
+        self.mod_position = (self.mod_position + 1) & 0x3F;
+    }
+
+    pub fn clock(&mut self) {
+        if !self.envelopes_halted && !self.wave_halted && self.envelope_speed != 0 {
+            self.volume_envelope.clock(self.envelope_speed);
+            self.mod_envelope.clock(self.envelope_speed);
+        }
+
+        if !self.mod_halted && self.mod_frequency != 0 {
+            self.mod_accumulator += self.mod_frequency as u32;
+            if self.mod_accumulator >= 0x10000 {
+                self.mod_accumulator &= 0xFFFF;
+                self.step_mod_counter();
+            }
+        }
+
+        if !self.wave_halted && !self.wave_write_enabled {
+            self.wave_accumulator += self.modulated_frequency();
+            while self.wave_accumulator >= 0x10000 {
+                self.wave_accumulator -= 0x10000;
+                self.wave_position = (self.wave_position + 1) & 0x3F;
+                if self.wave_position == 0 {
+                    self.output_volume = self.volume_envelope.gain;
+                    self.last_edge = true;
+                }
+            }
+        }
+
+        // While the wave table is being written, the output holds its last value
+        if !self.wave_write_enabled {
+            let sample = self.wave_table[self.wave_position as usize] as f32;
+            let volume = self.output_volume.min(32) as f32;
+            self.current_output = sample * volume * MASTER_VOLUME[self.master_volume as usize];
+        }
+        self.lowpass.consume(self.current_output);
+    }
+
+    // Filtered, from 0.0 up to FDS_FULL_VOLUME
+    pub fn output(&self) -> f32 {
+        if self.debug_disable {
+            return 0.0;
+        }
+        return self.lowpass.output() * self.gain;
+    }
+}
+
+impl AudioChannelState for FdsAudio {
+    fn name(&self) -> String {
+        return "Wavetable".to_string();
+    }
+
+    fn chip(&self) -> String {
+        return "FDS".to_string();
+    }
+
+    fn sample_buffer(&self) -> &RingBuffer {
+        return &self.output_buffer;
+    }
+
+    fn edge_buffer(&self) -> &RingBuffer {
+        return &self.edge_buffer;
+    }
+
+    fn record_current_output(&mut self) {
+        self.debug_filter.consume(self.current_output);
+        self.output_buffer.push(-self.debug_filter.output() as i16);
+        self.edge_buffer.push(self.last_edge as i16);
+        self.last_edge = false;
+    }
+
+    fn min_sample(&self) -> i16 {
+        return -1024;
+    }
+
+    fn max_sample(&self) -> i16 {
+        return 1024;
+    }
+
+    fn muted(&self) -> bool {
+        return self.debug_disable;
+    }
+
+    fn mute(&mut self) {
+        self.debug_disable = true;
+    }
+
+    fn unmute(&mut self) {
+        self.debug_disable = false;
+    }
+
+    fn gain(&self) -> f32 {
+        return self.gain;
+    }
+
+    fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
+    fn playing(&self) -> bool {
+        return
+            (!self.wave_halted) &&
+            (self.wave_frequency > 0) &&
+            (self.output_volume > 0);
+    }
+
+    fn rate(&self) -> PlaybackRate {
+        let frequency = 1_789_773.0 * (self.wave_frequency as f32) / (65536.0 * 64.0);
+        return PlaybackRate::FundamentalFrequency {frequency: frequency};
+    }
+
+    fn volume(&self) -> Option<Volume> {
+        return Some(Volume::VolumeIndex{ index: self.output_volume.min(32) as usize, max: 32 });
+    }
+
+    fn timbre(&self) -> Option<Timbre> {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&self.wave_table);
+        let truncated_result = (hasher.finish() & 0xFF) as usize;
+        return Some(Timbre::PatchIndex{ index: truncated_result, max: 255 });
+    }
+}
@@ -1,6 +1,7 @@
 // Sunsoft FME-7, 5A, and 5B (notably lacking expansion audio for now)
 // Reference implementation: https://wiki.nesdev.com/w/index.php/Sunsoft_FME-7
 
+use crate::error::Error;
 use crate::ines::INesCartridge;
 use crate::memoryblock::MemoryBlock;
 
@@ -32,10 +33,12 @@ pub struct Fme7 {
     pub irq_pending: bool,
     pub audio_command_select: u8,
     expansion_audio_chip: YM2149F,
+    pub expansion_level: f32,
+    pub board: BoardInfo,
 }
 
 impl Fme7 {
-    pub fn from_ines(ines: INesCartridge) -> Result<Fme7, String> {
+    pub fn from_ines(ines: INesCartridge) -> Result<Fme7, Error> {
         let prg_rom_block = ines.prg_rom_block();
         let prg_ram_block = ines.prg_ram_block()?;
         let chr_block = ines.chr_block()?;
@@ -57,6 +60,8 @@ impl Fme7 {
             irq_pending: false,
             audio_command_select: 0,
             expansion_audio_chip: YM2149F::new(),
+            expansion_level: 1.0,
+            board: ines.header.board_info(),
         });
     }
 
@@ -71,9 +76,29 @@ impl Fme7 {
 }
 
 impl Mapper for Fme7 {
+    fn board_info(&self) -> BoardInfo {
+        return self.board;
+    }
+
+    fn prg_rom(&self) -> &[u8] {
+        return self.prg_rom.as_vec();
+    }
+
+    fn chr(&self) -> &[u8] {
+        return self.chr_rom.as_vec();
+    }
+
     fn mirroring(&self) -> Mirroring {
         return Mirroring::Horizontal;
     }
+
+    fn ppu_reads_cacheable(&self) -> bool {
+        return true;
+    }
+
+    fn cpu_reads_cacheable(&self) -> bool {
+        return true;
+    }
     
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         match address {
@@ -96,6 +121,23 @@ impl Mapper for Fme7 {
         }
     }
 
+    fn debug_prg_rom_address(&self, address: u16) -> Option<usize> {
+        match address {
+            0x6000 ..= 0x7FFF => {
+                if self.prg_ram_selected {
+                    None
+                } else {
+                    self.prg_rom.banked_address(0x2000, self.prg_banks[0], (address - 0x6000) as usize)
+                }
+            },
+            0x8000 ..= 0x9FFF => self.prg_rom.banked_address(0x2000, self.prg_banks[1], (address - 0x8000) as usize),
+            0xA000 ..= 0xBFFF => self.prg_rom.banked_address(0x2000, self.prg_banks[2], (address - 0xA000) as usize),
+            0xC000 ..= 0xDFFF => self.prg_rom.banked_address(0x2000, self.prg_banks[3], (address - 0xC000) as usize),
+            0xE000 ..= 0xFFFF => self.prg_rom.banked_address(0x2000, 0xFF, (address - 0xE000) as usize),
+            _ => None
+        }
+    }
+
     fn clock_cpu(&mut self) {
         self.clock_irq();
         self.expansion_audio_chip.clock();
@@ -200,8 +242,26 @@ impl Mapper for Fme7 {
         return self.irq_enabled && self.irq_pending;
     }
 
+    fn acknowledge_irq(&mut self) {
+        self.irq_pending = false;
+    }
+
     fn mix_expansion_audio(&self, nes_sample: f32) -> f32 {
-        return (self.expansion_audio_chip.output() - 0.5) * 1.06 - nes_sample;
+        return (self.expansion_audio_chip.output() - 0.5) * 1.06 * self.expansion_level - nes_sample;
+    }
+
+    fn expansion_chips(&self) -> Vec<ExpansionChip> {
+        return vec![ExpansionChip::Sunsoft5B];
+    }
+
+    fn expansion_level(&self, chip: ExpansionChip) -> f32 {
+        return if chip == ExpansionChip::Sunsoft5B {self.expansion_level} else {1.0};
+    }
+
+    fn set_expansion_level(&mut self, chip: ExpansionChip, level: f32) {
+        if chip == ExpansionChip::Sunsoft5B {
+            self.expansion_level = level;
+        }
     }
 
     fn channels(&self) ->  Vec<& dyn AudioChannelState> {
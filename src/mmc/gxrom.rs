@@ -1,6 +1,7 @@
 // GxRom, simple bank switchable 32kb PRG ROM and 8k CHR ROM
 // Reference capabilities: https://wiki.nesdev.com/w/index.php/GxROM
 
+use crate::error::Error;
 use crate::ines::INesCartridge;
 use crate::memoryblock::MemoryBlock;
 
@@ -17,10 +18,11 @@ pub struct GxRom {
     pub prg_bank: usize,
     pub chr_bank: usize,
     pub vram: Vec<u8>,
+    pub board: BoardInfo,
 }
 
 impl GxRom {
-    pub fn from_ines(ines: INesCartridge) -> Result<GxRom, String> {
+    pub fn from_ines(ines: INesCartridge) -> Result<GxRom, Error> {
         let prg_rom_block = ines.prg_rom_block();
         let chr_block = ines.chr_block()?;
 
@@ -31,21 +33,42 @@ impl GxRom {
             prg_bank: 0x00,
             chr_bank: 0x00,
             vram: vec![0u8; 0x1000],
+            board: ines.header.board_info(),
         });
     }
 }
 
 impl Mapper for GxRom {
+    fn board_info(&self) -> BoardInfo {
+        return self.board;
+    }
+
     fn print_debug_status(&self) {
         println!("======= GxROM =======");
         println!("PRG Bank: {}, CHR Bank: {}, Mirroring Mode: {}", self.prg_bank, self.chr_bank, mirroring_mode_name(self.mirroring));
         println!("====================");
     }
 
+    fn prg_rom(&self) -> &[u8] {
+        return self.prg_rom.as_vec();
+    }
+
+    fn chr(&self) -> &[u8] {
+        return self.chr.as_vec();
+    }
+
     fn mirroring(&self) -> Mirroring {
         return self.mirroring;
     }
 
+    fn ppu_reads_cacheable(&self) -> bool {
+        return true;
+    }
+
+    fn cpu_reads_cacheable(&self) -> bool {
+        return true;
+    }
+
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         match address {
             0x8000 ..= 0xFFFF => {self.prg_rom.banked_read(0x8000, self.prg_bank, (address - 0x8000) as usize)},
@@ -53,6 +76,13 @@ impl Mapper for GxRom {
         }
     }
 
+    fn debug_prg_rom_address(&self, address: u16) -> Option<usize> {
+        match address {
+            0x8000 ..= 0xFFFF => {self.prg_rom.banked_address(0x8000, self.prg_bank, (address - 0x8000) as usize)},
+            _ => None
+        }
+    }
+
     fn write_cpu(&mut self, address: u16, data: u8) {
         match address {
             0x8000 ..= 0xFFFF => {
@@ -87,6 +117,10 @@ impl Mapper for GxRom {
         }
     }
     
+    fn supports_savestates(&self) -> bool {
+        return true;
+    }
+
     fn save_state(&self, buff: &mut Vec<u8>) {    
         self.prg_rom.save_state(buff);
         self.chr.save_state(buff);
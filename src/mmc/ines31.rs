@@ -2,6 +2,7 @@
 // of NSF music. It implements a common subset of the features used by NSFs. 
 // Reference capabilities: https://wiki.nesdev.com/w/index.php/INES_Mapper_031
 
+use crate::error::Error;
 use crate::ines::INesCartridge;
 use crate::memoryblock::MemoryBlock;
 
@@ -17,10 +18,11 @@ pub struct INes31 {
     pub mirroring: Mirroring,
     pub vram: Vec<u8>,
     pub prg_banks: Vec<usize>,
+    pub board: BoardInfo,
 }
 
 impl INes31 {
-    pub fn from_ines(ines: INesCartridge) -> Result<INes31, String> {
+    pub fn from_ines(ines: INesCartridge) -> Result<INes31, Error> {
         let prg_rom_block = ines.prg_rom_block();
         let chr_block = ines.chr_block()?;
 
@@ -30,20 +32,41 @@ impl INes31 {
             mirroring: ines.header.mirroring(),
             vram: vec![0u8; 0x1000],
             prg_banks: vec![255usize; 8],
+            board: ines.header.board_info(),
         })
     }
 }
 
 impl Mapper for INes31 {
+    fn board_info(&self) -> BoardInfo {
+        return self.board;
+    }
+
     fn print_debug_status(&self) {
         println!("======= iNes 31 =======");
         println!("Mirroring Mode: {}", mirroring_mode_name(self.mirroring));
         println!("====================");
     }
 
+    fn prg_rom(&self) -> &[u8] {
+        return self.prg_rom.as_vec();
+    }
+
+    fn chr(&self) -> &[u8] {
+        return self.chr.as_vec();
+    }
+
     fn mirroring(&self) -> Mirroring {
         return self.mirroring;
     }
+
+    fn ppu_reads_cacheable(&self) -> bool {
+        return true;
+    }
+
+    fn cpu_reads_cacheable(&self) -> bool {
+        return true;
+    }
     
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         match address {
@@ -59,6 +82,20 @@ impl Mapper for INes31 {
         }
     }
 
+    fn debug_prg_rom_address(&self, address: u16) -> Option<usize> {
+        match address {
+            0x8000 ..= 0x8FFF => self.prg_rom.banked_address(0x1000, self.prg_banks[0], (address as usize) - 0x8000),
+            0x9000 ..= 0x9FFF => self.prg_rom.banked_address(0x1000, self.prg_banks[1], (address as usize) - 0x9000),
+            0xA000 ..= 0xAFFF => self.prg_rom.banked_address(0x1000, self.prg_banks[2], (address as usize) - 0xA000),
+            0xB000 ..= 0xBFFF => self.prg_rom.banked_address(0x1000, self.prg_banks[3], (address as usize) - 0xB000),
+            0xC000 ..= 0xCFFF => self.prg_rom.banked_address(0x1000, self.prg_banks[4], (address as usize) - 0xC000),
+            0xD000 ..= 0xDFFF => self.prg_rom.banked_address(0x1000, self.prg_banks[5], (address as usize) - 0xD000),
+            0xE000 ..= 0xEFFF => self.prg_rom.banked_address(0x1000, self.prg_banks[6], (address as usize) - 0xE000),
+            0xF000 ..= 0xFFFF => self.prg_rom.banked_address(0x1000, self.prg_banks[7], (address as usize) - 0xF000),
+            _ => None
+        }
+    }
+
     fn write_cpu(&mut self, address: u16, data: u8) {
         match address {
             0x5FF8 => {self.prg_banks[0] = data as usize},
@@ -97,6 +134,10 @@ impl Mapper for INes31 {
         }
     }
 
+    fn supports_savestates(&self) -> bool {
+        return true;
+    }
+
     fn save_state(&self, buff: &mut Vec<u8>) {    
         self.prg_rom.save_state(buff);
         self.chr.save_state(buff);
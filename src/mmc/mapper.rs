@@ -1,4 +1,5 @@
 use crate::apu::AudioChannelState;
+use crate::mmc::rtc::Rtc;
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum Mirroring {
@@ -19,31 +20,91 @@ pub fn mirroring_mode_name(mode: Mirroring) -> &'static str {
     }
 }
 
+// Sound chips a cartridge can add to the console's own
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ExpansionChip {
+    Vrc6,
+    Mmc5,
+    N163,
+    Sunsoft5B,
+    Fds,
+}
+
+// What the cartridge's header says about the board, beyond what the mapper needed
+// to build itself. iNES 1.0 headers leave the submapper and expansion device at 0.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct BoardInfo {
+    pub mapper_number: u16,
+    // Tells apart boards that share a mapper number but behave differently
+    pub submapper: u8,
+    // What the game expects in the controller ports; see
+    // InputDevice::for_expansion_device()
+    pub default_expansion_device: u8,
+}
+
 pub trait Mapper: Send {
     fn read_cpu(&mut self, address: u16) -> Option<u8> {return self.debug_read_cpu(address);}
     fn write_cpu(&mut self, address: u16, data: u8);
     fn access_ppu(&mut self, _address: u16) {}
     fn read_ppu(&mut self, address: u16) -> Option<u8> {return self.debug_read_ppu(address);}
     fn write_ppu(&mut self, address: u16, data: u8);
+    // True if the mapper doesn't watch the PPU bus (read_ppu() has no side effects and
+    // access_ppu() does nothing), and what read_ppu() returns for $0000 - $2FFF
+    // only changes through write_cpu() at $4020 and up (outside $6000 - $7FFF),
+    // write_ppu() and load_state().
+    // The PPU can then keep its own copy of the banks; see PpuFetchCache.
+    fn ppu_reads_cacheable(&self) -> bool {return false;}
+    // True if read_cpu() has no side effects, and what it returns for $8000 - $FFFF
+    // only changes through write_cpu() outside $6000 - $7FFF, and load_state(). The
+    // CPU can then read PRG from its own copy; see memory::CpuPageTable.
+    fn cpu_reads_cacheable(&self) -> bool {return false;}
     fn debug_read_cpu(&self, address: u16) -> Option<u8>;
     fn debug_read_ppu(&self, address: u16) -> Option<u8>;
+    // Where in PRG ROM the byte at this CPU address currently comes from, if anywhere.
+    fn debug_prg_rom_address(&self, _address: u16) -> Option<usize> {return None;}
     fn print_debug_status(&self) {}
     fn mirroring(&self) -> Mirroring;
+    // All zeroes for anything that isn't loaded from an iNES header, ie NSF
+    fn board_info(&self) -> BoardInfo {return BoardInfo::default();}
+    // All of PRG ROM, and all of CHR whether it's ROM or RAM, for debuggers
+    fn prg_rom(&self) -> &[u8] {return &[];}
+    fn chr(&self) -> &[u8] {return &[];}
     fn has_sram(&self) -> bool {return false;}
     fn get_sram(&self) -> Vec<u8> {return vec![0u8; 0];}
     fn load_sram(&mut self, _: Vec<u8>) {}
+    // Battery backed memory has changed since the last clear_sram_dirty()
+    fn sram_dirty(&self) -> bool {return false;}
+    fn clear_sram_dirty(&mut self) {}
+    // For boards with a real time clock
+    fn rtc(&self) -> Option<&Rtc> {return None;}
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {return None;}
+    // The cartridge's IRQ line, held until acknowledged
     fn irq_flag(&self) -> bool {return false;}
+    // Clears whatever is holding irq_flag() the way the board's acknowledge register
+    // would, without any of that register's other effects
+    fn acknowledge_irq(&mut self) {}
     fn clock_cpu(&mut self) {}
     fn mix_expansion_audio(&self, nes_sample: f32) -> f32 {return nes_sample;}
     fn channels(&self) ->  Vec<& dyn AudioChannelState> {return Vec::new();}
     fn channels_mut(&mut self) ->  Vec<&mut dyn AudioChannelState> {return Vec::new();}
     fn record_expansion_audio_output(&mut self, _nes_sample: f32) {}
+    // Each chip is balanced against the 2A03 by default, roughly as the cartridge's own
+    // mixing resistors would. These scale that, ie for a frontend's mixer panel.
+    fn expansion_chips(&self) -> Vec<ExpansionChip> {return Vec::new();}
+    fn expansion_level(&self, _chip: ExpansionChip) -> f32 {return 1.0;}
+    fn set_expansion_level(&mut self, _chip: ExpansionChip, _level: f32) {}
+    // Mappers that haven't implemented save_state() and load_state() yet panic in
+    // them, so check this first
+    fn supports_savestates(&self) -> bool {return false;}
     fn save_state(&self, _buff: &mut Vec<u8>) { todo!() }
     fn load_state(&mut self, _buff: &mut Vec<u8>) { todo!() }
     fn box_clone(&self) -> Box<dyn Mapper> { todo!() }
     fn nsf_set_track(&mut self, _track_index: u8) {}
     fn nsf_manual_mode(&mut self) {}
     fn audio_multiplexing(&mut self, _emulate: bool) {}
+    // For cartridges with a barcode reader. False if there isn't one, or the
+    // barcode isn't valid.
+    fn insert_barcode(&mut self, _barcode: &str) -> bool {return false;}
 }
 
 impl Clone for Box<dyn Mapper>
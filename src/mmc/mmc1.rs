@@ -1,6 +1,9 @@
 // Common mapper with bank switched PRG_ROM, CHR_ROM/RAM, and optional PRG RAM.
 // Reference capabilities: https://wiki.nesdev.com/w/index.php/MMC1
+// NES 2.0 submappers handled here: 3, the MMC1A, which can't disable PRG RAM; and 5,
+// SEROM / SHROM / SH1ROM, whose 32k of PRG isn't banked at all.
 
+use crate::error::Error;
 use crate::ines::INesCartridge;
 use crate::memoryblock::MemoryBlock;
 
@@ -30,10 +33,14 @@ pub struct Mmc1 {
 
     pub mirroring: Mirroring,
     pub last_write: bool,
+    pub board: BoardInfo,
 }
 
+const SUBMAPPER_MMC1A: u8 = 3;
+const SUBMAPPER_FIXED_PRG: u8 = 5;
+
 impl Mmc1 {
-    pub fn from_ines(ines: INesCartridge) -> Result<Mmc1, String> {
+    pub fn from_ines(ines: INesCartridge) -> Result<Mmc1, Error> {
         let prg_rom_block = ines.prg_rom_block();
         let prg_ram_block = ines.prg_ram_block()?;
         let chr_block = ines.chr_block()?;
@@ -57,11 +64,16 @@ impl Mmc1 {
             control: 0x0C,
             mirroring: Mirroring::Vertical,
             last_write: false,
+            board: ines.header.board_info(),
         })
     }
 }
 
 impl Mapper for Mmc1 {
+    fn board_info(&self) -> BoardInfo {
+        return self.board;
+    }
+
     fn print_debug_status(&self) {
         let prg_mode = (self.control >> 2) & 0x3;
         let chr_mode = (self.control & 0x10) >> 4;
@@ -75,10 +87,22 @@ impl Mapper for Mmc1 {
         println!("====================");
     }
 
+    fn prg_rom(&self) -> &[u8] {
+        return self.prg_rom.as_vec();
+    }
+
+    fn chr(&self) -> &[u8] {
+        return self.chr.as_vec();
+    }
+
     fn mirroring(&self) -> Mirroring {
         return self.mirroring;
     }
 
+    fn ppu_reads_cacheable(&self) -> bool {
+        return true;
+    }
+
     fn read_cpu(&mut self, address: u16) -> Option<u8> {
         self.last_write = false;
         return self.debug_read_cpu(address);
@@ -90,6 +114,9 @@ impl Mapper for Mmc1 {
             0x6000 ..= 0x7FFF => {
                 self.prg_ram.banked_read(0x2000, self.prg_ram_bank, address as usize)
             },
+            0x8000 ..= 0xFFFF if self.board.submapper == SUBMAPPER_FIXED_PRG => {
+                self.prg_rom.wrapping_read((address - 0x8000) as usize)
+            },
             // PRG ROM - First 16k Page
             0x8000 ..= 0xBFFF => {
                 let prg_rom_len = self.prg_rom.len();
@@ -144,6 +171,30 @@ impl Mapper for Mmc1 {
         }
     }
 
+    fn debug_prg_rom_address(&self, address: u16) -> Option<usize> {
+        if self.prg_rom.len() == 0 {
+            return None;
+        }
+        if self.board.submapper == SUBMAPPER_FIXED_PRG {
+            return match address {
+                0x8000 ..= 0xFFFF => self.prg_rom.wrapping_address((address - 0x8000) as usize),
+                _ => None
+            };
+        }
+        let prg_mode = (self.control >> 2) & 0x3;
+        // Mirrors the bank selection logic in debug_read_cpu
+        let bank = match (address, prg_mode) {
+            (0x8000 ..= 0xBFFF, 0 | 1) => self.prg_bank & 0xFFFE,
+            (0x8000 ..= 0xBFFF, 2) => 0,
+            (0x8000 ..= 0xBFFF, _) => self.prg_bank,
+            (0xC000 ..= 0xFFFF, 0 | 1) => self.prg_bank | 0x0001,
+            (0xC000 ..= 0xFFFF, 2) => self.prg_bank,
+            (0xC000 ..= 0xFFFF, _) => 0xFF,
+            _ => return None
+        };
+        return self.prg_rom.banked_address(0x4000, bank, (address - 0x8000) as usize);
+    }
+
     fn write_cpu(&mut self, address: u16, data: u8) {
         match address {
             // PRG RAM
@@ -198,9 +249,8 @@ impl Mapper for Mmc1 {
                             },
                             0xE000 ..= 0xFF00 => {
                                 // The 5th bit disables RAM, so invert it here to decide when
-                                // RAM should be enabled.
-                                // TODO: This is ignored on certain MMC variants!
-                                self.prg_ram_enabled = self.shift_data & 0b1_0000 == 0;
+                                // RAM should be enabled. The MMC1A ignores it.
+                                self.prg_ram_enabled = self.shift_data & 0b1_0000 == 0 || self.board.submapper == SUBMAPPER_MMC1A;
                                 self.prg_bank = (self.shift_data & 0b0_1111) as usize;
                             },
                             _ => ()
@@ -295,6 +345,18 @@ impl Mapper for Mmc1 {
     fn load_sram(&mut self, sram_data: Vec<u8>) {
         *self.prg_ram.as_mut_vec() = sram_data;
     }
+
+    fn sram_dirty(&self) -> bool {
+        return self.prg_ram.is_dirty();
+    }
+
+    fn clear_sram_dirty(&mut self) {
+        self.prg_ram.clear_dirty();
+    }
+    fn supports_savestates(&self) -> bool {
+        return true;
+    }
+
     fn save_state(&self, buff: &mut Vec<u8>) {
         self.prg_rom.save_state(buff);
         self.prg_ram.save_state(buff);
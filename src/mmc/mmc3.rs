@@ -1,6 +1,7 @@
 // Advanced mapper with bank-switched PRG ROM and CHR ROM, and a scanline counter feeding into IRQ
 // Reference capabilities: https://wiki.nesdev.com/w/index.php/MMC3
 
+use crate::error::Error;
 use crate::ines::INesCartridge;
 use crate::memoryblock::MemoryBlock;
 
@@ -45,10 +46,11 @@ pub struct Mmc3 {
     pub last_chr_read: u16,
 
     pub mirroring: Mirroring,
+    pub board: BoardInfo,
 }
 
 impl Mmc3 {
-    pub fn from_ines(ines: INesCartridge) -> Result<Mmc3, String> {
+    pub fn from_ines(ines: INesCartridge) -> Result<Mmc3, Error> {
         let prg_rom_block = ines.prg_rom_block();
         let prg_ram_block = ines.prg_ram_block()?;
         let chr_block = ines.chr_block()?;
@@ -87,6 +89,7 @@ impl Mmc3 {
             low_a12_counter: 0,
 
             mirroring: ines.header.mirroring(),
+            board: ines.header.board_info(),
         })
     }
 
@@ -169,6 +172,10 @@ impl Mmc3 {
 }
 
 impl Mapper for Mmc3 {
+    fn board_info(&self) -> BoardInfo {
+        return self.board;
+    }
+
     fn print_debug_status(&self) {
         println!("======= MMC3 =======");
         println!("IRQ: Current: {}, Reload: {}", self.irq_counter, self.irq_reload);
@@ -177,6 +184,14 @@ impl Mapper for Mmc3 {
         println!("====================");
     }
 
+    fn prg_rom(&self) -> &[u8] {
+        return self.prg_rom.as_vec();
+    }
+
+    fn chr(&self) -> &[u8] {
+        return self.chr.as_vec();
+    }
+
     fn mirroring(&self) -> Mirroring {
         return self.mirroring;
     }
@@ -185,6 +200,10 @@ impl Mapper for Mmc3 {
         return self.irq_flag;
     }
 
+    fn acknowledge_irq(&mut self) {
+        self.irq_flag = false;
+    }
+
     fn clock_cpu(&mut self) {
         self.snoop_cpu_m2();
     }
@@ -219,6 +238,31 @@ impl Mapper for Mmc3 {
         }
     }
 
+    fn debug_prg_rom_address(&self, address: u16) -> Option<usize> {
+        match address {
+            0x8000 ..= 0xFFFF => {
+                if self.switch_prg_banks {
+                    match address {
+                        0x8000 ..= 0x9FFF => self.prg_rom.banked_address(0x2000, 0xFE,            address as usize -  0x8000),
+                        0xA000 ..= 0xBFFF => self.prg_rom.banked_address(0x2000, self.prg_bank_7, address as usize -  0xA000),
+                        0xC000 ..= 0xDFFF => self.prg_rom.banked_address(0x2000, self.prg_bank_6, address as usize -  0xC000),
+                        0xE000 ..= 0xFFFF => self.prg_rom.banked_address(0x2000, 0xFF,            address as usize -  0xE000),
+                        _ => None,
+                    }
+                } else {
+                    match address {
+                        0x8000 ..= 0x9FFF => self.prg_rom.banked_address(0x2000, self.prg_bank_6, address as usize -  0x8000),
+                        0xA000 ..= 0xBFFF => self.prg_rom.banked_address(0x2000, self.prg_bank_7, address as usize -  0xA000),
+                        0xC000 ..= 0xDFFF => self.prg_rom.banked_address(0x2000, 0xFE,            address as usize -  0xC000),
+                        0xE000 ..= 0xFFFF => self.prg_rom.banked_address(0x2000, 0xFF,            address as usize -  0xE000),
+                        _ => None,
+                    }
+                }
+            },
+            _ => None
+        }
+    }
+
     fn write_cpu(&mut self, address: u16, data: u8) {
         match address {
             // PRG RAM
@@ -368,6 +412,18 @@ impl Mapper for Mmc3 {
         *self.prg_ram.as_mut_vec() = sram_data;
     }
 
+    fn sram_dirty(&self) -> bool {
+        return self.prg_ram.is_dirty();
+    }
+
+    fn clear_sram_dirty(&mut self) {
+        self.prg_ram.clear_dirty();
+    }
+
+    fn supports_savestates(&self) -> bool {
+        return true;
+    }
+
     fn save_state(&self, buff: &mut Vec<u8>) {
         self.prg_rom.save_state(buff);
         self.prg_ram.save_state(buff);
@@ -3,6 +3,7 @@
 // it here quite yet.
 // Reference capabilities: https://wiki.nesdev.com/w/index.php/MMC5
 
+use crate::error::Error;
 use crate::ines::INesCartridge;
 use crate::memoryblock::MemoryBlock;
 
@@ -27,6 +28,8 @@ pub struct Mmc5PcmChannel {
     pub irq_enable: bool,
     pub irq_pending: bool,
     pub muted: bool,
+    // Output volume multiplier, for mixing
+    pub gain: f32,
     pub output_buffer: RingBuffer,
     pub edge_buffer: RingBuffer,
     pub debug_filter: filters::HighPassIIR,
@@ -40,6 +43,7 @@ impl Mmc5PcmChannel {
             irq_enable: false,
             irq_pending: false,
             muted: false,
+            gain: 1.0,
             output_buffer: RingBuffer::new(32768),
             edge_buffer: RingBuffer::new(32768),
             debug_filter: filters::HighPassIIR::new(44100.0, 300.0),
@@ -94,6 +98,14 @@ impl AudioChannelState for Mmc5PcmChannel {
         self.muted = false;
     }
 
+    fn gain(&self) -> f32 {
+        return self.gain;
+    }
+
+    fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
 
     fn playing(&self) -> bool {
         return true;
@@ -160,10 +172,12 @@ pub struct Mmc5 {
     pub pulse_2: PulseChannelState,
     pub audio_sequencer_counter: u16,
     pub pcm_channel: Mmc5PcmChannel,
+    pub expansion_level: f32,
+    pub board: BoardInfo,
 }
 
 impl Mmc5 {
-    pub fn from_ines(ines: INesCartridge) -> Result<Mmc5, String> {
+    pub fn from_ines(ines: INesCartridge) -> Result<Mmc5, Error> {
         let prg_rom_block = ines.prg_rom_block();
         let prg_ram_block = ines.prg_ram_block()?;
         let chr_block = ines.chr_block()?;
@@ -219,6 +233,8 @@ impl Mmc5 {
             pulse_2: pulse2,
             audio_sequencer_counter: 0,
             pcm_channel: Mmc5PcmChannel::new(),
+            expansion_level: 1.0,
+            board: ines.header.board_info(),
         })
     }
 
@@ -649,6 +665,10 @@ impl Mmc5 {
 }
 
 impl Mapper for Mmc5 {
+    fn board_info(&self) -> BoardInfo {
+        return self.board;
+    }
+
     fn print_debug_status(&self) {
         println!("======= MMC5 =======");
         println!("PRG ROM: {}k, PRG RAM: {}k, CHR ROM: {}k", self.prg_rom.len() / 1024, self.prg_ram.len() / 1024, self.chr.len() / 1024);
@@ -668,8 +688,23 @@ impl Mapper for Mmc5 {
         println!("====================");
     }
 
+    // The scanline IRQ and the PCM channel's share the cartridge's one line
     fn irq_flag(&self) -> bool {
-        return self.irq_enabled && self.irq_pending;
+        return (self.irq_enabled && self.irq_pending) ||
+            (self.pcm_channel.irq_enable && self.pcm_channel.irq_pending);
+    }
+
+    fn acknowledge_irq(&mut self) {
+        self.irq_pending = false;
+        self.pcm_channel.irq_pending = false;
+    }
+
+    fn prg_rom(&self) -> &[u8] {
+        return self.prg_rom.as_vec();
+    }
+
+    fn chr(&self) -> &[u8] {
+        return self.chr.as_vec();
     }
 
     fn mirroring(&self) -> Mirroring {
@@ -862,16 +897,30 @@ impl Mapper for Mmc5 {
     }
 
     fn mix_expansion_audio(&self, nes_sample: f32) -> f32 {
-        let pulse_1_output = if !self.pulse_1.debug_disable {(self.pulse_1.output() as f32 / 15.0) - 0.5} else {0.0};
-        let pulse_2_output = if !self.pulse_2.debug_disable {(self.pulse_2.output() as f32 / 15.0) - 0.5} else {0.0};
-        let pcm_output = if !self.pcm_channel.muted {(self.pcm_channel.level as f32 / 256.0) - 0.5} else {0.0};
+        let pulse_1_output = if !self.pulse_1.debug_disable {((self.pulse_1.output() as f32 / 15.0) - 0.5) * self.pulse_1.gain} else {0.0};
+        let pulse_2_output = if !self.pulse_2.debug_disable {((self.pulse_2.output() as f32 / 15.0) - 0.5) * self.pulse_2.gain} else {0.0};
+        let pcm_output = if !self.pcm_channel.muted {((self.pcm_channel.level as f32 / 256.0) - 0.5) * self.pcm_channel.gain} else {0.0};
 
         return 
-            (pulse_1_output + pulse_2_output) * 0.12 + 
-            pcm_output * 0.25 + 
+            ((pulse_1_output + pulse_2_output) * 0.12 + 
+            pcm_output * 0.25) * self.expansion_level + 
             nes_sample;
     }
 
+    fn expansion_chips(&self) -> Vec<ExpansionChip> {
+        return vec![ExpansionChip::Mmc5];
+    }
+
+    fn expansion_level(&self, chip: ExpansionChip) -> f32 {
+        return if chip == ExpansionChip::Mmc5 {self.expansion_level} else {1.0};
+    }
+
+    fn set_expansion_level(&mut self, chip: ExpansionChip, level: f32) {
+        if chip == ExpansionChip::Mmc5 {
+            self.expansion_level = level;
+        }
+    }
+
     fn channels(&self) ->  Vec<& dyn AudioChannelState> {
         let mut channels: Vec<& dyn AudioChannelState> = Vec::new();
         channels.push(&self.pulse_1);
@@ -5,6 +5,8 @@ pub mod action53;
 pub mod axrom;
 pub mod bnrom;
 pub mod cnrom;
+pub mod datach;
+pub mod fds;
 pub mod fme7;
 pub mod gxrom;
 pub mod ines31;
@@ -16,5 +18,6 @@ pub mod none;
 pub mod nrom;
 pub mod nsf;
 pub mod pxrom;
+pub mod rtc;
 pub mod uxrom;
 pub mod vrc6;
@@ -1,6 +1,7 @@
 // Namco 163 (and also 129), reference capabilities:
 // https://wiki.nesdev.com/w/index.php?title=INES_Mapper_019
 
+use crate::error::Error;
 use crate::ines::INesCartridge;
 use crate::memoryblock::MemoryBlock;
 use crate::memoryblock::MemoryType;
@@ -367,6 +368,8 @@ pub struct Namco163 {
     pub nt_ram_at_1000: bool,
 
     pub audio_relative_mix: f32,
+    pub expansion_level: f32,
+    pub board: BoardInfo,
 }
 
 pub fn amplitude_from_db(db: f32) -> f32 {
@@ -388,7 +391,7 @@ pub fn n163_mixing_level(submapper: u8) -> f32 {
 }
 
 impl Namco163 {
-    pub fn from_ines(ines: INesCartridge) -> Result<Namco163, String> {
+    pub fn from_ines(ines: INesCartridge) -> Result<Namco163, Error> {
         let prg_rom_block = ines.prg_rom_block();
         let prg_ram_block = ines.prg_ram_block()?;
         let chr_block = ines.chr_block()?;
@@ -415,6 +418,8 @@ impl Namco163 {
             nt_ram_at_1000: false,
 
             audio_relative_mix: n163_mixing_level(ines.header.submapper_number()),
+            expansion_level: 1.0,
+            board: ines.header.board_info(),
         })
     }
 
@@ -452,6 +457,18 @@ impl Namco163 {
 }
 
 impl Mapper for Namco163 {
+    fn board_info(&self) -> BoardInfo {
+        return self.board;
+    }
+
+    fn prg_rom(&self) -> &[u8] {
+        return self.prg_rom.as_vec();
+    }
+
+    fn chr(&self) -> &[u8] {
+        return self.chr.as_vec();
+    }
+
     fn mirroring(&self) -> Mirroring {
         return Mirroring::Horizontal;
     }
@@ -479,6 +496,16 @@ impl Mapper for Namco163 {
         }
     }
 
+    fn debug_prg_rom_address(&self, address: u16) -> Option<usize> {
+        match address {
+            0x8000 ..= 0x9FFF => self.prg_rom.banked_address(0x2000, self.prg_banks[0] as usize, address as usize),
+            0xA000 ..= 0xBFFF => self.prg_rom.banked_address(0x2000, self.prg_banks[1] as usize, address as usize),
+            0xC000 ..= 0xDFFF => self.prg_rom.banked_address(0x2000, self.prg_banks[2] as usize, address as usize),
+            0xE000 ..= 0xFFFF => self.prg_rom.banked_address(0x2000, 0xFF, address as usize),
+            _ => {None}
+        }
+    }
+
     fn read_cpu(&mut self, address: u16) -> Option<u8> {
         let data = self.debug_read_cpu(address);
         match address {
@@ -604,11 +631,25 @@ impl Mapper for Namco163 {
         
         // Normalize the N163 volume against APU pulse, then multiply that by our
         // desired relative mix:
-        let n163_weight = (nes_pulse_full_volume / n163_square_full_volume) * self.audio_relative_mix;
+        let n163_weight = (nes_pulse_full_volume / n163_square_full_volume) * self.audio_relative_mix * self.expansion_level;
 
         return nes_sample + (self.expansion_audio_chip.current_output * n163_weight);
     }
 
+    fn expansion_chips(&self) -> Vec<ExpansionChip> {
+        return vec![ExpansionChip::N163];
+    }
+
+    fn expansion_level(&self, chip: ExpansionChip) -> f32 {
+        return if chip == ExpansionChip::N163 {self.expansion_level} else {1.0};
+    }
+
+    fn set_expansion_level(&mut self, chip: ExpansionChip, level: f32) {
+        if chip == ExpansionChip::N163 {
+            self.expansion_level = level;
+        }
+    }
+
     fn record_expansion_audio_output(&mut self, _nes_sample: f32) {
         self.expansion_audio_chip.record_output();
     }
@@ -647,6 +688,10 @@ impl Mapper for Namco163 {
         return self.irq_pending;
     }
 
+    fn acknowledge_irq(&mut self) {
+        self.irq_pending = false;
+    }
+
     fn has_sram(&self) -> bool {
         return true;
     }
@@ -659,6 +704,14 @@ impl Mapper for Namco163 {
         *self.prg_ram.as_mut_vec() = sram_data;
     }
 
+    fn sram_dirty(&self) -> bool {
+        return self.prg_ram.is_dirty();
+    }
+
+    fn clear_sram_dirty(&mut self) {
+        self.prg_ram.clear_dirty();
+    }
+
     fn audio_multiplexing(&mut self, emulate: bool) {
         self.expansion_audio_chip.emulate_multiplexing = emulate;
     }
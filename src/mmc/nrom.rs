@@ -1,6 +1,7 @@
 // A very simple Mapper with no esoteric features or bank switching.
 // Reference capabilities: https://wiki.nesdev.com/w/index.php/NROM
 
+use crate::error::Error;
 use crate::ines::INesCartridge;
 use crate::memoryblock::MemoryBlock;
 
@@ -17,10 +18,11 @@ pub struct Nrom {
 
     mirroring: Mirroring,
     vram: Vec<u8>,
+    pub board: BoardInfo,
 }
 
 impl Nrom {
-    pub fn from_ines(ines: INesCartridge) -> Result<Nrom, String> {
+    pub fn from_ines(ines: INesCartridge) -> Result<Nrom, Error> {
         let prg_rom_block = ines.prg_rom_block();
         let prg_ram_block = ines.prg_ram_block()?;
         let chr_block = ines.chr_block()?;
@@ -31,20 +33,41 @@ impl Nrom {
             chr: chr_block.clone(),
             mirroring: ines.header.mirroring(),
             vram: vec![0u8; 0x1000],
+            board: ines.header.board_info(),
         });
     }
 }
 
 impl Mapper for Nrom {
+    fn board_info(&self) -> BoardInfo {
+        return self.board;
+    }
+
     fn print_debug_status(&self) {
         println!("======= NROM =======");
         println!("Mirroring Mode: {}", mirroring_mode_name(self.mirroring));
         println!("====================");
     }
 
+    fn prg_rom(&self) -> &[u8] {
+        return self.prg_rom.as_vec();
+    }
+
+    fn chr(&self) -> &[u8] {
+        return self.chr.as_vec();
+    }
+
     fn mirroring(&self) -> Mirroring {
         return self.mirroring;
     }
+
+    fn ppu_reads_cacheable(&self) -> bool {
+        return true;
+    }
+
+    fn cpu_reads_cacheable(&self) -> bool {
+        return true;
+    }
     
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         match address {
@@ -54,6 +77,13 @@ impl Mapper for Nrom {
         }
     }
 
+    fn debug_prg_rom_address(&self, address: u16) -> Option<usize> {
+        match address {
+            0x8000 ..= 0xFFFF => {self.prg_rom.wrapping_address((address - 0x8000) as usize)},
+            _ => None
+        }
+    }
+
     fn write_cpu(&mut self, address: u16, data: u8) {
         match address {
             0x6000 ..= 0x7FFF => {self.prg_ram.wrapping_write((address - 0x6000) as usize, data);},
@@ -85,6 +115,10 @@ impl Mapper for Nrom {
         }
     }
 
+    fn supports_savestates(&self) -> bool {
+        return true;
+    }
+
     fn save_state(&self, buff: &mut Vec<u8>) {
         self.prg_rom.save_state(buff);
         self.prg_ram.save_state(buff);
@@ -6,6 +6,7 @@ use crate::apu::AudioChannelState;
 use crate::asm::*;
 use crate::asm::Opcode::*;
 use crate::asm::AddressingMode::*;
+use crate::error::Error;
 use crate::memoryblock::MemoryBlock;
 use crate::memoryblock::MemoryType;
 use crate::mmc::mapper::*;
@@ -25,6 +26,10 @@ use crate::mmc::fme7::YM2149F;
 use crate::mmc::n163::Namco163Audio;
 use crate::mmc::n163::n163_mixing_level;
 
+use crate::mmc::fds::FdsAudio;
+use crate::mmc::fds::FDS_FULL_VOLUME;
+use crate::mmc::fds::FDS_RELATIVE_MIX;
+
 const PPUCTRL: u16 = 0x2000;
 const PPUMASK: u16 = 0x2001;
 const PPUSTATUS: u16 = 0x2002;
@@ -140,8 +145,9 @@ fn initialize_apu() -> Opcode {
     ]);
 }
 
-fn initialize_memory() -> Opcode {
-    return List(vec![
+// FDS tunes are loaded into PRG RAM, so clearing it would erase them
+fn initialize_memory(clear_prg_ram: bool) -> Opcode {
+    let mut opcodes = vec![
         Label(String::from("initialize_memory")),
 
         // Main memory
@@ -160,24 +166,30 @@ fn initialize_memory() -> Opcode {
         Ldx(ZeroPage(0x01)),
         Cpx(Immediate(0x08)), // Stop when we reach 0x800
         Bne(RelativeLabel(String::from("_main_ram_loop"))),
+    ];
+
+    if clear_prg_ram {
+        opcodes.extend(vec![
+            // PRG RAM
+            Lda(Immediate(0x00)), // Start at 0x6000
+            Sta(ZeroPage(0x00)),
+            Lda(Immediate(0x60)),
+            Sta(ZeroPage(0x01)),
+
+            Lda(Immediate(0x00)),
+            Ldy(Immediate(0x00)),
+            Label(String::from("_prg_ram_loop")),
+            Sta(IndirectIndexedY(0x00)),
+            Inc(ZeroPage(0x00)),
+            Bne(RelativeLabel(String::from("_prg_ram_loop"))),
+            Inc(ZeroPage(0x01)),
+            Ldx(ZeroPage(0x01)),
+            Cpx(Immediate(0x80)), // Stop when we reach 0x8000
+            Bne(RelativeLabel(String::from("_prg_ram_loop"))),
+        ]);
+    }
 
-        // PRG RAM
-        Lda(Immediate(0x00)), // Start at 0x6000
-        Sta(ZeroPage(0x00)),
-        Lda(Immediate(0x60)),
-        Sta(ZeroPage(0x01)),
-
-        Lda(Immediate(0x00)),
-        Ldy(Immediate(0x00)),
-        Label(String::from("_prg_ram_loop")),
-        Sta(IndirectIndexedY(0x00)),
-        Inc(ZeroPage(0x00)),
-        Bne(RelativeLabel(String::from("_prg_ram_loop"))),
-        Inc(ZeroPage(0x01)),
-        Ldx(ZeroPage(0x01)),
-        Cpx(Immediate(0x80)), // Stop when we reach 0x8000
-        Bne(RelativeLabel(String::from("_prg_ram_loop"))),
-
+    opcodes.extend(vec![
         // zero page
         Lda(Immediate(0x00)),
         Ldx(Immediate(0x00)),
@@ -188,6 +200,7 @@ fn initialize_memory() -> Opcode {
 
         Rts,
     ]);
+    return List(opcodes);
 }
 
 fn init_track(init_address: u16) -> Opcode {
@@ -288,7 +301,7 @@ fn playback_loop(play_address: u16) -> Opcode {
     ]);
 }
 
-fn nsf_player(init_address: u16, play_address: u16) -> Vec<Opcode> {
+fn nsf_player(init_address: u16, play_address: u16, clear_prg_ram: bool) -> Vec<Opcode> {
     vec![
         // Disable IRQ-based interrupts (We don't need them; NSF code by spec
         // shouldn't use them, and if it does, shenanigans.)
@@ -309,10 +322,26 @@ fn nsf_player(init_address: u16, play_address: u16) -> Vec<Opcode> {
         poll_input(),
         switch_tracks(init_address),
         initialize_apu(),
-        initialize_memory(),
+        initialize_memory(clear_prg_ram),
     ]
 }
 
+// The banks mapped in at the start of every track: $8000 - $FFFF, and for FDS tunes,
+// the PRG RAM at $6000 - $7FFF. Tunes that aren't bank switched are padded out to
+// start from the lowest of those, and get mapped in order.
+fn initial_banks(header: &NsfHeader) -> (Vec<usize>, Vec<usize>) {
+    if !header.is_bank_switched() {
+        if header.fds() {
+            return (vec![2, 3, 4, 5, 6, 7, 8, 9], vec![0, 1]);
+        }
+        return (vec![0, 1, 2, 3, 4, 5, 6, 7], Vec::new());
+    }
+    let banks = header.initial_banks();
+    // FDS RAM starts out with the same banks as $E000 and $F000
+    let fds_ram_banks = if header.fds() {vec![banks[6], banks[7]]} else {Vec::new()};
+    return (banks, fds_ram_banks);
+}
+
 enum TrackAdvanceMode {
     Timer,
     Silence,
@@ -342,6 +371,9 @@ pub struct NsfMapper {
     p1_pressed: u8,
 
     prg_rom_banks: Vec<usize>,
+    fds_ram_banks: Vec<usize>,
+    // FDS tunes can write over themselves, so each track starts from a fresh copy
+    fds_image: Vec<u8>,
     playback_accumulator: f32,
     playback_period: f32,
     playback_counter: u8,
@@ -372,29 +404,42 @@ pub struct NsfMapper {
     n163_ram_auto_increment: bool,
     n163_expansion_audio_chip: Namco163Audio,
     n163_mix: f32,
+
+    fds_enabled: bool,
+    fds_audio: FdsAudio,
+
+    // Mixer panel adjustments, on top of each chip's usual balance
+    vrc6_level: f32,
+    mmc5_level: f32,
+    s5b_level: f32,
+    n163_level: f32,
+    fds_level: f32,
 }
 
 impl NsfMapper {
-    pub fn from_nsf(nsf: NsfFile) -> Result<NsfMapper, String> {
-        let nsf_player_opcodes = nsf_player(nsf.header.init_address(), nsf.header.play_address());
-        let mut nsf_player = assemble(nsf_player_opcodes, PLAYER_ORIGIN)?;
+    pub fn from_nsf(nsf: NsfFile) -> Result<NsfMapper, Error> {
+        let nsf_player_opcodes = nsf_player(nsf.header.init_address(), nsf.header.play_address(), !nsf.header.fds());
+        let mut nsf_player = assemble(nsf_player_opcodes, PLAYER_ORIGIN)
+            .map_err(|reason| Error::UnsupportedCartridge{reason: format!("Failed to assemble NSF player: {}", reason)})?;
         nsf_player.resize(PLAYER_SIZE as usize, 0);
 
         let mut prg_rom = nsf.prg.clone();
-        let mut prg_rom_banks = nsf.header.initial_banks();
+        let (prg_rom_banks, fds_ram_banks) = initial_banks(&nsf.header);
         if !nsf.header.is_bank_switched() {
-            if nsf.header.load_address() < 0x8000 {
-                return Err(format!("Load address {} is below 0x8000, this conflicts with player implementation. Refusing to load.", nsf.header.load_address()));
+            // FDS tunes may also load into the RAM below 0x8000
+            let lowest_address = if nsf.header.fds() {0x6000} else {0x8000};
+            if (nsf.header.load_address() as usize) < lowest_address {
+                return Err(Error::UnsupportedCartridge{reason: format!("Load address {} is below {:#X}, this conflicts with player implementation. Refusing to load.", nsf.header.load_address(), lowest_address)});
             }
 
             // Coerce this ROM into a bank switched format anyway, so the mapper logic becomes simplified
             let mut padded_rom: Vec<u8> = Vec::new();
-            padded_rom.resize((nsf.header.load_address() as usize) - 0x8000, 0);
+            padded_rom.resize((nsf.header.load_address() as usize) - lowest_address, 0);
             padded_rom.extend(prg_rom);
-            padded_rom.resize(0x8000, 0);
+            padded_rom.resize(0x10000 - lowest_address, 0);
             prg_rom = padded_rom;
-            prg_rom_banks = vec![0, 1, 2, 3, 4, 5, 6, 7];
         }
+        let fds_image = if nsf.header.fds() {prg_rom.clone()} else {Vec::new()};
 
         let ntsc_clockrate = 1786860.0;
         let cycles_per_play = (nsf.header.ntsc_playback_speed() as f32) * ntsc_clockrate / 1000000.0;
@@ -454,7 +499,18 @@ impl NsfMapper {
             n163_expansion_audio_chip: Namco163Audio::new(),
             n163_mix: n163_mixing_level(0),
 
+            fds_enabled: nsf.header.fds(),
+            fds_audio: FdsAudio::new(),
+
+            vrc6_level: 1.0,
+            mmc5_level: 1.0,
+            s5b_level: 1.0,
+            n163_level: 1.0,
+            fds_level: 1.0,
+
             prg_rom_banks: prg_rom_banks,
+            fds_ram_banks: fds_ram_banks,
+            fds_image: fds_image,
 
             mirroring: Mirroring::FourScreen,
             vram: vec![0u8; 0x1000],
@@ -697,9 +753,9 @@ impl NsfMapper {
         if !self.vrc6_enabled {
             return 0.0;
         }
-        let pulse_1_output = if !self.vrc6_pulse1.debug_disable {self.vrc6_pulse1.output() as f32} else {0.0};
-        let pulse_2_output = if !self.vrc6_pulse2.debug_disable {self.vrc6_pulse2.output() as f32} else {0.0};
-        let sawtooth_output = if !self.vrc6_sawtooth.debug_disable {self.vrc6_sawtooth.output() as f32} else {0.0};
+        let pulse_1_output = if !self.vrc6_pulse1.debug_disable {self.vrc6_pulse1.output() as f32 * self.vrc6_pulse1.gain} else {0.0};
+        let pulse_2_output = if !self.vrc6_pulse2.debug_disable {self.vrc6_pulse2.output() as f32 * self.vrc6_pulse2.gain} else {0.0};
+        let sawtooth_output = if !self.vrc6_sawtooth.debug_disable {self.vrc6_sawtooth.output() as f32 * self.vrc6_sawtooth.gain} else {0.0};
         let vrc6_combined_sample = (pulse_1_output + pulse_2_output + sawtooth_output) / 61.0;
 
         let nes_pulse_full_volume = 95.88 / ((8128.0 / 15.0) + 100.0);
@@ -911,9 +967,9 @@ impl NsfMapper {
             return 0.0;
         }
         
-        let pulse_1_output = if !self.mmc5_pulse_1.debug_disable {(self.mmc5_pulse_1.output() as f32 / 15.0) - 0.5} else {0.0};
-        let pulse_2_output = if !self.mmc5_pulse_2.debug_disable {(self.mmc5_pulse_2.output() as f32 / 15.0) - 0.5} else {0.0};
-        let pcm_output = if !self.mmc5_pcm_channel.muted {(self.mmc5_pcm_channel.level as f32 / 256.0) - 0.5} else {0.0};
+        let pulse_1_output = if !self.mmc5_pulse_1.debug_disable {((self.mmc5_pulse_1.output() as f32 / 15.0) - 0.5) * self.mmc5_pulse_1.gain} else {0.0};
+        let pulse_2_output = if !self.mmc5_pulse_2.debug_disable {((self.mmc5_pulse_2.output() as f32 / 15.0) - 0.5) * self.mmc5_pulse_2.gain} else {0.0};
+        let pcm_output = if !self.mmc5_pcm_channel.muted {((self.mmc5_pcm_channel.level as f32 / 256.0) - 0.5) * self.mmc5_pcm_channel.gain} else {0.0};
 
         return 
             (pulse_1_output + pulse_2_output) * 0.12 + 
@@ -1017,6 +1073,58 @@ impl NsfMapper {
         self.n163_expansion_audio_chip.clock();
     }
 
+    fn fds_write(&mut self, address: u16, data: u8) {
+        if !self.fds_enabled {
+            return;
+        }
+        match address {
+            0x4040 ..= 0x408A => {self.fds_audio.write_register(address, data);},
+            0x5FF6 => {self.fds_ram_banks[0] = data as usize},
+            0x5FF7 => {self.fds_ram_banks[1] = data as usize},
+            0x6000 ..= 0x7FFF => {
+                let bank = self.fds_ram_banks[((address - 0x6000) >> 12) as usize];
+                self.prg.banked_write(0x1000, bank, (address & 0x0FFF) as usize, data);
+            },
+            // The whole of the RAM adapter's memory is writable
+            0x8000 ..= 0xDFFF => {
+                let bank = self.prg_rom_banks[((address - 0x8000) >> 12) as usize];
+                self.prg.banked_write(0x1000, bank, (address & 0x0FFF) as usize, data);
+            },
+            _ => {}
+        }
+    }
+
+    fn fds_read(&self, address: u16) -> Option<u8> {
+        if !self.fds_enabled {
+            return None;
+        }
+        match address {
+            0x4040 ..= 0x4092 => self.fds_audio.read_register(address),
+            0x6000 ..= 0x7FFF => {
+                let bank = self.fds_ram_banks[((address - 0x6000) >> 12) as usize];
+                self.prg.banked_read(0x1000, bank, (address & 0x0FFF) as usize)
+            },
+            _ => None
+        }
+    }
+
+    fn fds_output(&self) -> f32 {
+        if !self.fds_enabled {
+            return 0.0;
+        }
+        // APU pulse numbers from https://wiki.nesdev.com/w/index.php?title=APU_Mixer
+        let nes_pulse_full_volume = 95.88 / ((8128.0 / 15.0) + 100.0);
+        let fds_weight = (nes_pulse_full_volume / FDS_FULL_VOLUME) * FDS_RELATIVE_MIX;
+        return self.fds_audio.output() * fds_weight;
+    }
+
+    fn clock_fds(&mut self) {
+        if !self.fds_enabled {
+            return;
+        }
+        self.fds_audio.clock();
+    }
+
     fn fade_weight(&self) -> f32 {
         match self.advance_mode {
             TrackAdvanceMode::Timer => {
@@ -1047,6 +1155,14 @@ impl Mapper for NsfMapper {
         self.advance_mode = TrackAdvanceMode::Manual;
     }
 
+    fn prg_rom(&self) -> &[u8] {
+        return self.prg.as_vec();
+    }
+
+    fn chr(&self) -> &[u8] {
+        return &self.chr;
+    }
+
     fn mirroring(&self) -> Mirroring {
         return self.mirroring;
     }
@@ -1063,6 +1179,7 @@ impl Mapper for NsfMapper {
         self.clock_mmc5();
         self.clock_s5b();
         self.clock_n163();
+        self.clock_fds();
         self.current_cycles += 1;
 
         if self.detect_silence() {
@@ -1074,14 +1191,46 @@ impl Mapper for NsfMapper {
 
     fn mix_expansion_audio(&self, nes_sample: f32) -> f32 {
         let mixed_sample =  
-            self.vrc6_output() +
-            self.mmc5_output() +
-            self.s5b_output() +
-            self.n163_output() + 
+            self.vrc6_output() * self.vrc6_level +
+            self.mmc5_output() * self.mmc5_level +
+            self.s5b_output() * self.s5b_level +
+            self.n163_output() * self.n163_level + 
+            self.fds_output() * self.fds_level +
             nes_sample;
         return mixed_sample * self.fade_weight();
     }
 
+    // VRC7 audio isn't emulated, so tunes that use it play without it
+    fn expansion_chips(&self) -> Vec<ExpansionChip> {
+        let mut chips = Vec::new();
+        if self.vrc6_enabled {chips.push(ExpansionChip::Vrc6);}
+        if self.mmc5_enabled {chips.push(ExpansionChip::Mmc5);}
+        if self.s5b_enabled {chips.push(ExpansionChip::Sunsoft5B);}
+        if self.n163_enabled {chips.push(ExpansionChip::N163);}
+        if self.fds_enabled {chips.push(ExpansionChip::Fds);}
+        return chips;
+    }
+
+    fn expansion_level(&self, chip: ExpansionChip) -> f32 {
+        return match chip {
+            ExpansionChip::Vrc6 => self.vrc6_level,
+            ExpansionChip::Mmc5 => self.mmc5_level,
+            ExpansionChip::Sunsoft5B => self.s5b_level,
+            ExpansionChip::N163 => self.n163_level,
+            ExpansionChip::Fds => self.fds_level,
+        };
+    }
+
+    fn set_expansion_level(&mut self, chip: ExpansionChip, level: f32) {
+        match chip {
+            ExpansionChip::Vrc6 => {self.vrc6_level = level},
+            ExpansionChip::Mmc5 => {self.mmc5_level = level},
+            ExpansionChip::Sunsoft5B => {self.s5b_level = level},
+            ExpansionChip::N163 => {self.n163_level = level},
+            ExpansionChip::Fds => {self.fds_level = level},
+        }
+    }
+
     fn channels(&self) ->  Vec<& dyn AudioChannelState> {
         let mut channels: Vec<& dyn AudioChannelState> = Vec::new();
         if self.vrc6_enabled {
@@ -1113,6 +1262,9 @@ impl Mapper for NsfMapper {
             n163_channels.truncate(enabled_channels);
             channels.append(&mut n163_channels);
         }
+        if self.fds_enabled {
+            channels.push(&self.fds_audio);
+        }
         return channels;
     }
 
@@ -1147,6 +1299,9 @@ impl Mapper for NsfMapper {
             n163_channels.truncate(enabled_channels);
             channels.append(&mut n163_channels);
         }
+        if self.fds_enabled {
+            channels.push(&mut self.fds_audio);
+        }
         return channels;
     }
 
@@ -1167,6 +1322,9 @@ impl Mapper for NsfMapper {
         if self.n163_enabled {
             self.n163_expansion_audio_chip.record_output();
         }
+        if self.fds_enabled {
+            self.fds_audio.record_current_output();
+        }
         self.last_sample = self.current_sample;
         self.current_sample = self.mix_expansion_audio(nes_sample);
     }
@@ -1189,6 +1347,11 @@ impl Mapper for NsfMapper {
             None => {}
         }        
 
+        match self.fds_read(address) {
+            Some(data) => return Some(data),
+            None => {}
+        }
+
         match address {
             PLAYER_PLAYBACK_COUNTER => Some(self.playback_counter),
             PLAYER_TRACK_SELECT => Some(self.current_track - 1),
@@ -1215,9 +1378,11 @@ impl Mapper for NsfMapper {
                 self.p1_held = data;
             },
             PLAYER_RESET_BANKS => {
-                self.prg_rom_banks = self.header.initial_banks();
-                if !self.header.is_bank_switched() {
-                    self.prg_rom_banks = vec![0, 1, 2, 3, 4, 5, 6, 7];
+                let (prg_rom_banks, fds_ram_banks) = initial_banks(&self.header);
+                self.prg_rom_banks = prg_rom_banks;
+                self.fds_ram_banks = fds_ram_banks;
+                if self.fds_enabled {
+                    self.prg.as_mut_vec().copy_from_slice(&self.fds_image);
                 }
             },
             0x5FF8 => {self.prg_rom_banks[0] = data as usize},
@@ -1242,6 +1407,9 @@ impl Mapper for NsfMapper {
         if self.n163_enabled {
             self.n163_write(address, data);
         }
+        if self.fds_enabled {
+            self.fds_write(address, data);
+        }
     }
 
     fn debug_read_ppu(&self, address: u16) -> Option<u8> {
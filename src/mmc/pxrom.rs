@@ -1,6 +1,7 @@
 // MMC2, a somewhat advanced bank switcher with extended CHR memory
 // https://wiki.nesdev.com/w/index.php/MMC2
 
+use crate::error::Error;
 use crate::ines::INesCartridge;
 use crate::memoryblock::MemoryBlock;
 
@@ -20,10 +21,11 @@ pub struct PxRom {
     pub chr_1_fe_bank: usize,
     pub prg_bank: usize,
     pub vram: Vec<u8>,
+    pub board: BoardInfo,
 }
 
 impl PxRom {
-    pub fn from_ines(ines: INesCartridge) -> Result<PxRom, String> {
+    pub fn from_ines(ines: INesCartridge) -> Result<PxRom, Error> {
         let prg_rom_block = ines.prg_rom_block();
         let prg_ram_block = ines.prg_ram_block()?;
         let chr_block = ines.chr_block()?;
@@ -41,11 +43,16 @@ impl PxRom {
             chr_1_fe_bank: 0,
             prg_bank: 0,
             vram: vec![0u8; 0x1000],
+            board: ines.header.board_info(),
         })
     }
 }
 
 impl Mapper for PxRom {
+    fn board_info(&self) -> BoardInfo {
+        return self.board;
+    }
+
     fn print_debug_status(&self) {
         println!("======= PxROM =======");
         println!("PRG Bank: {}, ", self.prg_bank);
@@ -55,6 +62,14 @@ impl Mapper for PxRom {
         println!("====================");
     }
 
+    fn prg_rom(&self) -> &[u8] {
+        return self.prg_rom.as_vec();
+    }
+
+    fn chr(&self) -> &[u8] {
+        return self.chr.as_vec();
+    }
+
     fn mirroring(&self) -> Mirroring {
         return self.mirroring;
     }
@@ -70,6 +85,16 @@ impl Mapper for PxRom {
         }
     }
 
+    fn debug_prg_rom_address(&self, address: u16) -> Option<usize> {
+        match address {
+            0x8000 ..= 0x9FFF => self.prg_rom.banked_address(0x2000, self.prg_bank, address as usize - 0x8000),
+            0xA000 ..= 0xBFFF => self.prg_rom.banked_address(0x2000, 0xFD,          address as usize - 0xA000),
+            0xC000 ..= 0xDFFF => self.prg_rom.banked_address(0x2000, 0xFE,          address as usize - 0xC000),
+            0xE000 ..= 0xFFFF => self.prg_rom.banked_address(0x2000, 0xFF,          address as usize - 0xE000),
+            _ => None
+        }
+    }
+
     fn write_cpu(&mut self, address: u16, data: u8) {
         match address {
             0x6000 ..= 0x7FFF => self.prg_ram.wrapping_write(address as usize, data),
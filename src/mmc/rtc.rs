@@ -0,0 +1,120 @@
+// A battery backed real time clock, for boards that carry one. It keeps time as
+// seconds since the Unix epoch and ticks on emulated CPU cycles, so it stays in step
+// with the game however fast or slow the frontend runs; the frontend only decides
+// where it starts, through NesState::set_rtc_seconds(), and whether to catch up on
+// real time when SRAM is loaded.
+//
+// None of the boards emulated here have one yet. A mapper that does owns an Rtc,
+// clocks it from clock_cpu(), returns it from Mapper::rtc() and rtc_mut(), and
+// appends save_sram()'s bytes to its own SRAM.
+
+use crate::save_load::*;
+
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+// Appended to the cartridge's SRAM: the clock's time, then the wall clock time it was
+// saved at (0 if it wasn't), both little endian seconds
+pub const RTC_SRAM_SIZE: usize = 16;
+
+#[derive(Clone)]
+pub struct Rtc {
+    seconds: u64,
+    // Into the current second
+    cycles: u64,
+    cpu_clock_rate: u64,
+    // Set by games that stop the clock while they adjust it
+    pub halted: bool,
+    // Whether SRAM records the wall clock, so that the clock catches up on the time
+    // the console spent switched off. NesState clears it in deterministic mode.
+    pub wall_clock: bool,
+}
+
+fn wall_clock_seconds() -> u64 {
+    return match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        Err(_) => 0
+    };
+}
+
+impl Rtc {
+    pub fn new(cpu_clock_rate: u64) -> Rtc {
+        return Rtc {
+            seconds: 0,
+            cycles: 0,
+            cpu_clock_rate: cpu_clock_rate,
+            halted: false,
+            wall_clock: true,
+        };
+    }
+
+    pub fn seconds(&self) -> u64 {
+        return self.seconds;
+    }
+
+    // Starts the next second afresh
+    pub fn set_seconds(&mut self, seconds: u64) {
+        self.seconds = seconds;
+        self.cycles = 0;
+    }
+
+    pub fn set_cpu_clock_rate(&mut self, cpu_clock_rate: u64) {
+        self.cpu_clock_rate = cpu_clock_rate;
+    }
+
+    pub fn clock_cpu(&mut self) {
+        if self.halted {
+            return;
+        }
+        self.cycles += 1;
+        if self.cycles >= self.cpu_clock_rate {
+            self.cycles = 0;
+            self.seconds += 1;
+        }
+    }
+
+    // Sets a clock that's never been set to the time now, as a new battery would
+    // have been at the factory
+    pub fn start_from_wall_clock(&mut self) {
+        if self.seconds == 0 {
+            self.set_seconds(wall_clock_seconds());
+        }
+    }
+
+    pub fn save_sram(&self, buff: &mut Vec<u8>) {
+        let saved_at = if self.wall_clock {wall_clock_seconds()} else {0};
+        buff.extend_from_slice(&self.seconds.to_le_bytes());
+        buff.extend_from_slice(&saved_at.to_le_bytes());
+    }
+
+    // Reads the trailer save_sram() wrote
+    pub fn load_sram(&mut self, data: &[u8]) {
+        if data.len() < RTC_SRAM_SIZE {
+            return;
+        }
+        let mut seconds = [0u8; 8];
+        let mut saved_at = [0u8; 8];
+        seconds.copy_from_slice(&data[0 .. 8]);
+        saved_at.copy_from_slice(&data[8 .. 16]);
+        let mut seconds = u64::from_le_bytes(seconds);
+        let saved_at = u64::from_le_bytes(saved_at);
+        if self.wall_clock && saved_at != 0 && !self.halted {
+            seconds += wall_clock_seconds().saturating_sub(saved_at);
+        }
+        self.set_seconds(seconds);
+    }
+
+    pub fn save_state(&self, buff: &mut Vec<u8>) {
+        save_u64(buff, self.seconds);
+        save_u64(buff, self.cycles);
+        save_u64(buff, self.cpu_clock_rate);
+        save_bool(buff, self.halted);
+    }
+
+    pub fn load_state(&mut self, buff: &mut Vec<u8>) {
+        load_bool(buff, &mut self.halted);
+        load_u64(buff, &mut self.cpu_clock_rate);
+        load_u64(buff, &mut self.cycles);
+        load_u64(buff, &mut self.seconds);
+    }
+}
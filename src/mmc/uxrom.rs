@@ -1,6 +1,7 @@
 // UxROM, simple bank switchable PRG ROM with the last page fixed
 // Reference capabilities: https://wiki.nesdev.com/w/index.php/UxROM
 
+use crate::error::Error;
 use crate::ines::INesCartridge;
 use crate::memoryblock::MemoryBlock;
 
@@ -16,10 +17,11 @@ pub struct UxRom {
     pub mirroring: Mirroring,
     pub prg_bank: usize,
     pub vram: Vec<u8>,
+    pub board: BoardInfo,
 }
 
 impl UxRom {
-    pub fn from_ines(ines: INesCartridge) -> Result<UxRom, String> {
+    pub fn from_ines(ines: INesCartridge) -> Result<UxRom, Error> {
         let prg_rom_block = ines.prg_rom_block();
         let chr_block = ines.chr_block()?;
 
@@ -29,11 +31,16 @@ impl UxRom {
             mirroring: ines.header.mirroring(),
             prg_bank: 0x00,
             vram: vec![0u8; 0x1000],
+            board: ines.header.board_info(),
         })
     }
 }
 
 impl Mapper for UxRom {
+    fn board_info(&self) -> BoardInfo {
+        return self.board;
+    }
+
     fn print_debug_status(&self) {
         println!("======= UxROM =======");
         println!("PRG Bank: {}, ", self.prg_bank);
@@ -41,10 +48,26 @@ impl Mapper for UxRom {
         println!("====================");
     }
 
+    fn prg_rom(&self) -> &[u8] {
+        return self.prg_rom.as_vec();
+    }
+
+    fn chr(&self) -> &[u8] {
+        return self.chr.as_vec();
+    }
+
     fn mirroring(&self) -> Mirroring {
         return self.mirroring;
     }
 
+    fn ppu_reads_cacheable(&self) -> bool {
+        return true;
+    }
+
+    fn cpu_reads_cacheable(&self) -> bool {
+        return true;
+    }
+
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         match address {
             0x8000 ..= 0xBFFF => self.prg_rom.banked_read(0x4000, self.prg_bank, address as usize - 0x8000),
@@ -53,6 +76,14 @@ impl Mapper for UxRom {
         }
     }
 
+    fn debug_prg_rom_address(&self, address: u16) -> Option<usize> {
+        match address {
+            0x8000 ..= 0xBFFF => self.prg_rom.banked_address(0x4000, self.prg_bank, address as usize - 0x8000),
+            0xC000 ..= 0xFFFF => self.prg_rom.banked_address(0x4000, 0xFF, address as usize - 0xC000),
+            _ => None
+        }
+    }
+
     fn write_cpu(&mut self, address: u16, data: u8) {
         match address {
             0x8000 ..= 0xFFFF => {
@@ -86,6 +117,10 @@ impl Mapper for UxRom {
         }
     }
 
+    fn supports_savestates(&self) -> bool {
+        return true;
+    }
+
     fn save_state(&self, buff: &mut Vec<u8>) {
         self.prg_rom.save_state(buff);
         self.chr.save_state(buff);
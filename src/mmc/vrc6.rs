@@ -1,6 +1,7 @@
 // Vrc6, 
 // Reference capabilities: https://wiki.nesdev.com/w/index.php/VRC6
 
+use crate::error::Error;
 use crate::ines::INesCartridge;
 use crate::memoryblock::MemoryBlock;
 
@@ -18,6 +19,8 @@ use crate::apu::filters::DspFilter;
 pub struct Vrc6PulseChannel {
     pub name: String,
     pub debug_disable: bool,
+    // Output volume multiplier, for mixing
+    pub gain: f32,
     pub enabled: bool,
     pub duty_compare: u8,
     pub duty_counter: u8,
@@ -38,6 +41,7 @@ impl Vrc6PulseChannel {
         return Vrc6PulseChannel {
             name: String::from(channel_name),
             debug_disable: false,
+            gain: 1.0,
             enabled: false,
             duty_compare: 16,
             duty_counter: 0,
@@ -169,6 +173,14 @@ impl AudioChannelState for Vrc6PulseChannel {
         self.debug_disable = false;
     }
 
+    fn gain(&self) -> f32 {
+        return self.gain;
+    }
+
+    fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
     fn playing(&self) -> bool {
         return 
             (self.enabled) &&
@@ -194,6 +206,8 @@ impl AudioChannelState for Vrc6PulseChannel {
 pub struct Vrc6SawtoothChannel {
     pub enabled: bool,
     pub debug_disable: bool,
+    // Output volume multiplier, for mixing
+    pub gain: f32,
     pub accumulator_rate: u8,
     pub accumulator_step: u8,
     pub accumulator: u8,
@@ -213,6 +227,7 @@ impl Vrc6SawtoothChannel {
         return Vrc6SawtoothChannel {
             enabled: false,
             debug_disable: false,
+            gain: 1.0,
             accumulator_rate: 0,
             accumulator_step: 0,
             accumulator: 0,
@@ -339,6 +354,14 @@ impl AudioChannelState for Vrc6SawtoothChannel {
         self.debug_disable = false;
     }
 
+    fn gain(&self) -> f32 {
+        return self.gain;
+    }
+
+    fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
     fn playing(&self) -> bool {
         return 
             (self.enabled) &&
@@ -401,10 +424,12 @@ pub struct Vrc6 {
     pub pulse1: Vrc6PulseChannel,
     pub pulse2: Vrc6PulseChannel,
     pub sawtooth: Vrc6SawtoothChannel,
+    pub expansion_level: f32,
+    pub board: BoardInfo,
 }
 
 impl Vrc6 {
-    pub fn from_ines(ines: INesCartridge) -> Result<Vrc6, String> {
+    pub fn from_ines(ines: INesCartridge) -> Result<Vrc6, Error> {
         let prg_rom_block = ines.prg_rom_block();
         let prg_ram_block = ines.prg_ram_block()?;
         let chr_block = ines.chr_block()?;
@@ -437,6 +462,8 @@ impl Vrc6 {
             pulse1: Vrc6PulseChannel::new("Pulse 1"),
             pulse2: Vrc6PulseChannel::new("Pulse 2"),
             sawtooth: Vrc6SawtoothChannel::new(),
+            expansion_level: 1.0,
+            board: ines.header.board_info(),
         });
     }
 
@@ -914,10 +941,30 @@ impl Vrc6 {
 }
 
 impl Mapper for Vrc6 {
+    fn board_info(&self) -> BoardInfo {
+        return self.board;
+    }
+
+    fn prg_rom(&self) -> &[u8] {
+        return self.prg_rom.as_vec();
+    }
+
+    fn chr(&self) -> &[u8] {
+        return self.chr.as_vec();
+    }
+
     fn mirroring(&self) -> Mirroring {
         return self.mirroring;
     }
 
+    fn ppu_reads_cacheable(&self) -> bool {
+        return true;
+    }
+
+    fn cpu_reads_cacheable(&self) -> bool {
+        return true;
+    }
+
     fn clock_cpu(&mut self) {
         if self.irq_enable {
             if self.irq_scanline_mode {
@@ -932,9 +979,9 @@ impl Mapper for Vrc6 {
     }
 
     fn mix_expansion_audio(&self, nes_sample: f32) -> f32 {
-        let pulse_1_output = if !self.pulse1.debug_disable {self.pulse1.output() as f32} else {0.0};
-        let pulse_2_output = if !self.pulse2.debug_disable {self.pulse2.output() as f32} else {0.0};
-        let sawtooth_output = if !self.sawtooth.debug_disable {self.sawtooth.output() as f32} else {0.0};
+        let pulse_1_output = if !self.pulse1.debug_disable {self.pulse1.output() as f32 * self.pulse1.gain} else {0.0};
+        let pulse_2_output = if !self.pulse2.debug_disable {self.pulse2.output() as f32 * self.pulse2.gain} else {0.0};
+        let sawtooth_output = if !self.sawtooth.debug_disable {self.sawtooth.output() as f32 * self.sawtooth.gain} else {0.0};
         let vrc6_combined_sample = (pulse_1_output + pulse_2_output + sawtooth_output) / 61.0;
 
         let nes_pulse_full_volume = 95.88 / ((8128.0 / 15.0) + 100.0);
@@ -942,14 +989,32 @@ impl Mapper for Vrc6 {
         let vrc6_weight = nes_pulse_full_volume / vrc6_pulse_full_volume;
 
         return 
-            (vrc6_combined_sample * vrc6_weight) + 
+            (vrc6_combined_sample * vrc6_weight * self.expansion_level) + 
             nes_sample;
     }
 
+    fn expansion_chips(&self) -> Vec<ExpansionChip> {
+        return vec![ExpansionChip::Vrc6];
+    }
+
+    fn expansion_level(&self, chip: ExpansionChip) -> f32 {
+        return if chip == ExpansionChip::Vrc6 {self.expansion_level} else {1.0};
+    }
+
+    fn set_expansion_level(&mut self, chip: ExpansionChip, level: f32) {
+        if chip == ExpansionChip::Vrc6 {
+            self.expansion_level = level;
+        }
+    }
+
     fn irq_flag(&self) -> bool {
         return self.irq_pending;
     }
 
+    fn acknowledge_irq(&mut self) {
+        self.irq_pending = false;
+    }
+
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         match address {
             0x6000 ..= 0x7FFF => self.prg_ram.wrapping_read(address as usize - 0x6000),
@@ -960,6 +1025,15 @@ impl Mapper for Vrc6 {
         }
     }
 
+    fn debug_prg_rom_address(&self, address: u16) -> Option<usize> {
+        match address {
+            0x8000 ..= 0xBFFF => self.prg_rom.banked_address(0x4000, self.prg_bank_16, address as usize -  0x8000),
+            0xC000 ..= 0xDFFF => self.prg_rom.banked_address(0x2000, self.prg_bank_8, address as usize -  0xC000),
+            0xE000 ..= 0xFFFF => self.prg_rom.banked_address(0x2000, 0xFF, address as usize -  0xE000),
+            _ => None
+        }
+    }
+
     fn write_cpu(&mut self, address: u16, data: u8) {
         match address {
             0x6000 ..= 0x7FFF => {
@@ -0,0 +1,117 @@
+// FCEUX's text movie format. Header lines of "key value" come first, then one line
+// per frame:
+//   |commands|RLDUTSBA|RLDUTSBA||
+// commands is a bitfield (1 soft reset, 2 power, the rest are for FDS and VS System
+// games), followed by one field per gamepad and the Famicom expansion port. Buttons
+// are pressed unless they're a space or a dot. With "fourscore 1" there are four
+// gamepad fields. Reference: https://fceux.com/web/FM2.html
+//
+// Only gamepad movies that start from power on are supported; Zapper, binary, PAL
+// and savestate anchored movies are refused.
+
+use crate::builder::InputDevice;
+use crate::error::Error;
+use crate::movie::Movie;
+use crate::movie::MovieFrame;
+
+const COMMAND_SOFT_RESET: u32 = 0x01;
+const COMMAND_POWER: u32 = 0x02;
+
+fn bad_movie(reason: String) -> Error {
+    return Error::BadMovie{reason: reason};
+}
+
+// "RLDUTSBA", high bit first
+fn parse_gamepad(field: &str, line_number: usize) -> Result<u8, Error> {
+    if field.chars().count() != 8 {
+        return Err(bad_movie(format!("line {}: gamepad field \"{}\" isn't 8 buttons", line_number, field)));
+    }
+    let mut buttons = 0;
+    for (i, c) in field.chars().enumerate() {
+        if c != ' ' && c != '.' {
+            buttons |= 0x80 >> i;
+        }
+    }
+    return Ok(buttons);
+}
+
+pub fn parse(text: &str) -> Result<Movie, Error> {
+    let mut movie = Movie::new();
+    let mut four_score = false;
+    let mut ports = [1u32, 1u32];
+    let mut header_done = false;
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim_end_matches('\r');
+        if line.starts_with('|') {
+            if !header_done {
+                header_done = true;
+                for &port in ports.iter() {
+                    if port > 1 {
+                        return Err(bad_movie("only gamepads are supported, not the Zapper".to_string()));
+                    }
+                }
+                movie.devices = if four_score {
+                    [InputDevice::FourScore, InputDevice::FourScore]
+                } else {
+                    let device = |port: u32| if port == 1 {InputDevice::StandardController} else {InputDevice::Disconnected};
+                    [device(ports[0]), device(ports[1])]
+                };
+            }
+            movie.frames.push(parse_frame(line, four_score, ports, line_number)?);
+            continue;
+        }
+        if header_done || line.is_empty() {
+            continue;
+        }
+        let (key, value) = match line.find(' ') {
+            Some(split) => (&line[.. split], line[split + 1 ..].trim()),
+            None => (line, "")
+        };
+        let flag = value == "1";
+        match key {
+            "version" => {
+                if value != "3" {
+                    return Err(bad_movie(format!("version {} isn't supported, only 3", value)));
+                }
+            },
+            "binary" => if flag {return Err(bad_movie("binary movies aren't supported".to_string()))},
+            "palFlag" => if flag {return Err(bad_movie("PAL movies aren't supported".to_string()))},
+            "savestate" => return Err(bad_movie("movies that start from a savestate aren't supported".to_string())),
+            "rerecordCount" => movie.rerecord_count = value.parse().unwrap_or(0),
+            "romFilename" => movie.rom_filename = value.to_string(),
+            "romChecksum" => movie.rom_checksum = value.to_string(),
+            "comment" => movie.comments.push(value.to_string()),
+            "fourscore" => four_score = flag,
+            "port0" => ports[0] = value.parse().unwrap_or(0),
+            "port1" => ports[1] = value.parse().unwrap_or(0),
+            _ => {}
+        }
+    }
+    return Ok(movie);
+}
+
+fn parse_frame(line: &str, four_score: bool, ports: [u32; 2], line_number: usize) -> Result<MovieFrame, Error> {
+    // Leading and trailing bars leave empty fields at each end
+    let fields: Vec<&str> = line.split('|').collect();
+    let gamepads = if four_score {4} else {2};
+    if fields.len() < gamepads + 3 {
+        return Err(bad_movie(format!("line {}: expected {} gamepad fields", line_number, gamepads)));
+    }
+    let commands: u32 = match fields[1].trim().parse() {
+        Ok(commands) => commands,
+        Err(_) => return Err(bad_movie(format!("line {}: bad command field \"{}\"", line_number, fields[1])))
+    };
+    let mut frame = MovieFrame::default();
+    frame.soft_reset = commands & COMMAND_SOFT_RESET != 0;
+    frame.power = commands & COMMAND_POWER != 0;
+    for player in 0 .. gamepads {
+        let field = fields[2 + player];
+        // Without a Four Score, a disconnected port's field is empty
+        if !four_score && ports[player] == 0 {
+            continue;
+        }
+        frame.buttons[player] = parse_gamepad(field, line_number)?;
+    }
+    return Ok(frame);
+}
@@ -0,0 +1,199 @@
+// Input movies, for recording TASes and playing them back. A movie is the input held
+// on every frame since it began, plus any resets along the way; each file format
+// parses into the same Movie, and NesState::play_movie() feeds it to the ports.
+
+pub mod fm2;
+pub mod native;
+
+use crate::builder::InputDevice;
+use crate::nes::NesState;
+
+// Players 3 and 4 need a Four Score
+pub const MOVIE_PLAYERS: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct MovieFrame {
+    // As NesState::set_player_buttons() takes them
+    pub buttons: [u8; MOVIE_PLAYERS],
+    // Pressed before this frame's input is read
+    pub soft_reset: bool,
+    pub power: bool,
+}
+
+// A savestate taken at the start of a frame
+#[derive(Clone, PartialEq, Debug)]
+pub struct MovieAnchor {
+    pub frame: usize,
+    pub state: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Movie {
+    pub frames: Vec<MovieFrame>,
+    pub devices: [InputDevice; 2],
+    // Sorted by frame. One at frame 0 means the movie starts from that state rather
+    // than from power on.
+    pub anchors: Vec<MovieAnchor>,
+    pub rerecord_count: u32,
+    pub rom_filename: String,
+    // See cartridge::rom_crc32(); 0 if unknown
+    pub rom_crc32: u32,
+    // For formats with other checksums, in their own notation, ie "base64:..." for
+    // an FM2's MD5
+    pub rom_checksum: String,
+    pub comments: Vec<String>,
+}
+
+impl Movie {
+    pub fn new() -> Movie {
+        return Movie {
+            frames: Vec::new(),
+            devices: [InputDevice::StandardController, InputDevice::StandardController],
+            anchors: Vec::new(),
+            rerecord_count: 0,
+            rom_filename: String::new(),
+            rom_crc32: 0,
+            rom_checksum: String::new(),
+            comments: Vec::new(),
+        };
+    }
+
+    pub fn start_anchor(&self) -> Option<&MovieAnchor> {
+        return self.anchors.first().filter(|anchor| anchor.frame == 0);
+    }
+
+    // Drops every frame from frame on, and the anchors after it
+    pub fn truncate(&mut self, frame: usize) {
+        self.frames.truncate(frame);
+        self.anchors.retain(|anchor| anchor.frame <= frame);
+    }
+
+    fn add_anchor(&mut self, frame: usize, state: Vec<u8>) {
+        self.anchors.retain(|anchor| anchor.frame < frame);
+        self.anchors.push(MovieAnchor{frame: frame, state: state});
+    }
+}
+
+pub struct MoviePlayer {
+    pub movie: Movie,
+    // The frame running now, counted from the start of the movie
+    frame: usize,
+    recording: bool,
+    // Commands the console ran at the start of the frame being recorded
+    soft_reset: bool,
+    power: bool,
+    // While recording, anchor every this many frames, so that long movies can be
+    // scrubbed through without replaying from the start
+    pub anchor_interval: Option<usize>,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> MoviePlayer {
+        return MoviePlayer {
+            movie: movie,
+            frame: 0,
+            recording: false,
+            soft_reset: false,
+            power: false,
+            anchor_interval: None,
+        };
+    }
+
+    pub fn frame(&self) -> usize {
+        return self.frame;
+    }
+
+    pub fn recording(&self) -> bool {
+        return self.recording;
+    }
+
+    // Past the last frame of a movie that's playing back. Whatever it held last stays
+    // held.
+    pub fn finished(&self) -> bool {
+        return !self.recording && self.frame >= self.movie.frames.len();
+    }
+
+    // Switches from playback to recording at the current frame, dropping the rest of
+    // the movie, which counts as a re-record if there was any
+    pub fn start_recording(&mut self) {
+        if self.frame < self.movie.frames.len() {
+            self.movie.rerecord_count += 1;
+        }
+        self.movie.truncate(self.frame);
+        self.recording = true;
+        self.soft_reset = false;
+        self.power = false;
+    }
+
+    // Back to playback; with nothing past the current frame, the input stays as is
+    pub fn stop_recording(&mut self) {
+        self.recording = false;
+    }
+
+    // Moves back to frame and drops everything from there on, ie after the frontend
+    // loads a savestate taken at the start of that frame. While recording, this
+    // counts as a re-record.
+    pub fn truncate(&mut self, frame: usize) {
+        if self.recording {
+            self.movie.rerecord_count += 1;
+        }
+        self.movie.truncate(frame);
+        self.frame = frame.min(self.movie.frames.len());
+        self.soft_reset = false;
+        self.power = false;
+    }
+
+    // Notes a reset or power cycle the console ran as the current frame began, to go
+    // in with that frame's input when recording
+    pub(crate) fn record_commands(&mut self, soft_reset: bool, power: bool) {
+        if self.recording {
+            self.soft_reset |= soft_reset;
+            self.power |= power;
+        }
+    }
+
+    // Sets up the current frame's commands and input, when playing
+    pub(crate) fn apply_frame(&self, nes: &mut NesState) {
+        if self.recording {
+            return;
+        }
+        let frame = match self.movie.frames.get(self.frame) {
+            Some(frame) => *frame,
+            None => return
+        };
+        if frame.power {
+            nes.power_cycle();
+        } else if frame.soft_reset {
+            nes.reset();
+        }
+        for player in 0 .. MOVIE_PLAYERS {
+            nes.set_player_buttons(player, frame.buttons[player]);
+        }
+    }
+
+    // Called as each frame ends: records the input it ran with, or moves on to the
+    // next frame of playback
+    pub(crate) fn end_frame(&mut self, nes: &mut NesState) {
+        if self.recording {
+            let mut frame = MovieFrame::default();
+            frame.soft_reset = std::mem::take(&mut self.soft_reset);
+            frame.power = std::mem::take(&mut self.power);
+            for player in 0 .. MOVIE_PLAYERS {
+                frame.buttons[player] = nes.player_buttons(player);
+            }
+            self.movie.frames.truncate(self.frame);
+            self.movie.frames.push(frame);
+            self.frame += 1;
+            if let Some(interval) = self.anchor_interval {
+                if interval > 0 && self.frame % interval == 0 {
+                    self.movie.add_anchor(self.frame, nes.save_state());
+                }
+            }
+        } else {
+            if self.frame < self.movie.frames.len() {
+                self.frame += 1;
+            }
+            self.apply_frame(nes);
+        }
+    }
+}
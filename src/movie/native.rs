@@ -0,0 +1,180 @@
+// This core's own movie format, which unlike FM2 keeps savestate anchors and every
+// device this core can plug in:
+//
+//   "RNMV"            magic
+//   u32               format version
+//   u32               CRC-32 of the cartridge (see cartridge::rom_crc32), 0 if unknown
+//   u32               re-record count
+//   [u8; 2]           device in each port, see device_id()
+//   u32 + bytes       ROM filename, UTF-8, length first
+//   u32               comment count, then each as u32 + bytes
+//   u32               anchor count, then per anchor:
+//     u32             frame
+//     u32 + bytes     savestate
+//   u32               frame count, then per frame:
+//     [u8; 4]         buttons for players 1 - 4, as NesState::set_player_buttons()
+//     u8              commands: bit 0 soft reset, bit 1 power
+//   u32               CRC-32 of everything above
+//
+// All integers are little endian. Paddle positions and barcodes aren't recorded.
+
+use crate::builder::InputDevice;
+use crate::checksum::crc32;
+use crate::error::Error;
+use crate::movie::Movie;
+use crate::movie::MovieAnchor;
+use crate::movie::MovieFrame;
+use crate::movie::MOVIE_PLAYERS;
+
+const MAGIC: &[u8] = b"RNMV";
+pub const MOVIE_VERSION: u32 = 1;
+
+const COMMAND_SOFT_RESET: u8 = 0x01;
+const COMMAND_POWER: u8 = 0x02;
+
+fn bad_movie(reason: &str) -> Error {
+    return Error::BadMovie{reason: reason.to_string()};
+}
+
+fn device_id(device: InputDevice) -> u8 {
+    return match device {
+        InputDevice::Disconnected => 0,
+        InputDevice::StandardController => 1,
+        InputDevice::FourScore => 2,
+        InputDevice::FamicomFourPlayer => 3,
+        InputDevice::ArkanoidPaddle => 4,
+        InputDevice::ArkanoidPaddleFamicom => 5,
+        InputDevice::BarcodeBattler => 6,
+    };
+}
+
+fn device_from_id(id: u8) -> Result<InputDevice, Error> {
+    return match id {
+        0 => Ok(InputDevice::Disconnected),
+        1 => Ok(InputDevice::StandardController),
+        2 => Ok(InputDevice::FourScore),
+        3 => Ok(InputDevice::FamicomFourPlayer),
+        4 => Ok(InputDevice::ArkanoidPaddle),
+        5 => Ok(InputDevice::ArkanoidPaddleFamicom),
+        6 => Ok(InputDevice::BarcodeBattler),
+        _ => Err(bad_movie(&format!("unknown device {}", id)))
+    };
+}
+
+fn put_u32(buff: &mut Vec<u8>, value: u32) {
+    buff.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_bytes(buff: &mut Vec<u8>, data: &[u8]) {
+    put_u32(buff, data.len() as u32);
+    buff.extend_from_slice(data);
+}
+
+pub fn write(movie: &Movie) -> Vec<u8> {
+    let mut buff = Vec::new();
+    buff.extend_from_slice(MAGIC);
+    put_u32(&mut buff, MOVIE_VERSION);
+    put_u32(&mut buff, movie.rom_crc32);
+    put_u32(&mut buff, movie.rerecord_count);
+    buff.push(device_id(movie.devices[0]));
+    buff.push(device_id(movie.devices[1]));
+    put_bytes(&mut buff, movie.rom_filename.as_bytes());
+    put_u32(&mut buff, movie.comments.len() as u32);
+    for comment in movie.comments.iter() {
+        put_bytes(&mut buff, comment.as_bytes());
+    }
+    put_u32(&mut buff, movie.anchors.len() as u32);
+    for anchor in movie.anchors.iter() {
+        put_u32(&mut buff, anchor.frame as u32);
+        put_bytes(&mut buff, &anchor.state);
+    }
+    put_u32(&mut buff, movie.frames.len() as u32);
+    for frame in movie.frames.iter() {
+        buff.extend_from_slice(&frame.buttons);
+        let mut commands = 0;
+        if frame.soft_reset {commands |= COMMAND_SOFT_RESET;}
+        if frame.power {commands |= COMMAND_POWER;}
+        buff.push(commands);
+    }
+    let checksum = crc32(&buff);
+    put_u32(&mut buff, checksum);
+    return buff;
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() - self.position < length {
+            return Err(bad_movie("unexpected end of data"));
+        }
+        let bytes = &self.data[self.position .. self.position + length];
+        self.position += length;
+        return Ok(bytes);
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        return Ok(self.bytes(1)?[0]);
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.bytes(4)?;
+        return Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    }
+
+    fn length_prefixed(&mut self) -> Result<&'a [u8], Error> {
+        let length = self.u32()? as usize;
+        return self.bytes(length);
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        return Ok(String::from_utf8_lossy(self.length_prefixed()?).into_owned());
+    }
+}
+
+pub fn parse(data: &[u8]) -> Result<Movie, Error> {
+    if data.len() < MAGIC.len() + 4 || &data[.. MAGIC.len()] != MAGIC {
+        return Err(bad_movie("not a movie"));
+    }
+    let (body, checksum) = data.split_at(data.len() - 4);
+    if crc32(body) != u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) {
+        return Err(bad_movie("checksum mismatch, the movie is truncated or corrupt"));
+    }
+    let mut reader = Reader{data: body, position: MAGIC.len()};
+    let version = reader.u32()?;
+    if version != MOVIE_VERSION {
+        return Err(bad_movie(&format!("version {} isn't supported, expected {}", version, MOVIE_VERSION)));
+    }
+    let mut movie = Movie::new();
+    movie.rom_crc32 = reader.u32()?;
+    movie.rerecord_count = reader.u32()?;
+    movie.devices = [device_from_id(reader.u8()?)?, device_from_id(reader.u8()?)?];
+    movie.rom_filename = reader.string()?;
+    let comment_count = reader.u32()?;
+    for _ in 0 .. comment_count {
+        movie.comments.push(reader.string()?);
+    }
+    let anchor_count = reader.u32()?;
+    for _ in 0 .. anchor_count {
+        let frame = reader.u32()? as usize;
+        let state = reader.length_prefixed()?.to_vec();
+        movie.anchors.push(MovieAnchor{frame: frame, state: state});
+    }
+    movie.anchors.sort_by_key(|anchor| anchor.frame);
+    let frame_count = reader.u32()?;
+    for _ in 0 .. frame_count {
+        let mut frame = MovieFrame::default();
+        frame.buttons.copy_from_slice(reader.bytes(MOVIE_PLAYERS)?);
+        let commands = reader.u8()?;
+        frame.soft_reset = commands & COMMAND_SOFT_RESET != 0;
+        frame.power = commands & COMMAND_POWER != 0;
+        movie.frames.push(frame);
+    }
+    if reader.position != body.len() {
+        return Err(bad_movie("trailing data after the last frame"));
+    }
+    return Ok(movie);
+}
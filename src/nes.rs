@@ -1,14 +1,159 @@
 use crate::apu::ApuState;
+use crate::apu_log::ApuLogFormat;
+use crate::apu_log::ApuWriteLogger;
+use crate::breakpoints::Breakpoints;
 use crate::cartridge;
+use crate::builder::ConsoleModel;
+use crate::builder::InputDevice;
+use crate::builder::Overscan;
+use crate::builder::Region;
+use crate::builder::DETERMINISTIC_RAM_INIT;
+use crate::cheats;
+use crate::cheats::CheatEngine;
+use crate::checksum::Fnv1a64;
 use crate::cycle_cpu;
 use crate::cycle_cpu::CpuState;
 use crate::cycle_cpu::Registers;
+use crate::error::Error;
+use crate::expansion_port::ExpansionDevice;
+use crate::expression::Expression;
+use crate::frame_timing::{FrameTiming, Subsystem};
+use crate::input::ControllerPort;
+use crate::input::StandardController;
+use crate::dpcm::DpcmRipper;
+use crate::input_log::{InputEvent, InputLog, InputReplay};
+use crate::input_macro::{InputMacro, MacroPlayback};
+use crate::overlay::Overlay;
+use crate::irq::{IrqLines, IrqSource};
 use crate::memory;
+use crate::memory::AddressSpace;
 use crate::memory::CpuMemory;
+use crate::apu::piano_roll::PianoRoll;
 use crate::ppu::PpuState;
+use crate::ppu::ScanlineScroll;
+use crate::profiler::Profiler;
 use crate::mmc::mapper::Mapper;
+use crate::movie::Movie;
+use crate::movie::MovieAnchor;
+use crate::movie::MoviePlayer;
 use crate::save_load::*;
+use crate::savestate::{begin_container, begin_section, end_container, end_section, load_section, read_container};
+use crate::savestate::{SaveStateManager, SectionTag, Sections};
+use crate::symbols::SymbolTable;
+use crate::watch::{WatchList, WatchPoint, WatchSnapshot};
+use crate::tracked_events;
 use crate::tracked_events::EventTracker;
+use crate::trace::TraceFormat;
+use crate::trace::TraceLogger;
+use crate::vgm::VgmLogger;
+
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+const JSR_OPCODE: u8 = 0x20;
+const RTI_OPCODE: u8 = 0x40;
+const RTS_OPCODE: u8 = 0x60;
+
+// In every savestate; see NesState::write_state()
+const SECTION_COUNT: u32 = 8;
+
+// Default for NesState::save_slots
+const SAVE_SLOT_COUNT: usize = 10;
+
+// About one second, in master clock ticks
+const DEBUG_RUN_LIMIT: u64 = 21_477_272;
+
+// Runs once at the end of every frame, with the fully updated console state
+pub type FrameHook = Box<dyn FnMut(&NesState) + Send>;
+
+// What a SignalHook waits for
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SignalTrigger {
+    // The NMI line going high: vblank starting with NMI enabled, or NMI being enabled
+    // during vblank
+    Nmi,
+    // Any IRQ source asserting its line, whether or not the CPU has interrupts
+    // disabled. NesState::irq_lines() says which.
+    Irq,
+    // The PPU reaching this dot. Keeps the PPU from running lazily while registered.
+    Beam{scanline: u16, dot: u16},
+    // The DMC fetching the last byte of a sample: when it raises its IRQ, if that's
+    // enabled, or loops. For debugging streamed sample playback; see also
+    // DmcState::cycles_until_last_fetch().
+    DmcSampleEnd,
+}
+
+// Runs at the end of the CPU cycle its trigger happens in, which may be partway through
+// an instruction; see add_signal_hook()
+pub type SignalHook = Box<dyn FnMut(&NesState) + Send>;
+
+// Signal levels as of the last cycle, to spot the ones SignalHooks wait for
+#[derive(Default)]
+struct SignalWatch {
+    nmi: bool,
+    irq_lines: IrqLines,
+    beam_position: u32,
+    dmc_samples_finished: u32,
+}
+
+// Called with the cartridge's battery backed memory once it's been left alone for a
+// while after changing; see set_sram_flush_hook()
+pub type SramFlushHook = Box<dyn FnMut(&[u8]) + Send>;
+
+struct SramFlush {
+    idle_frames: u32,
+    hook: SramFlushHook,
+    // Changed since the hook last ran
+    pending: bool,
+    // Frames since the last change
+    idle: u32,
+}
+
+// From run_frames_and_hash()
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FrameHashes {
+    // Of the last frame's palette indices and emphasis bits
+    pub frame: u64,
+    // Of every sample produced over all the frames, at the APU's sample rate
+    pub audio: u64,
+}
+
+// Everything a frontend needs to present one frame, from run_frame()
+pub struct FrameOutput<'a> {
+    // 256x240 palette indices with emphasis bits; see palettes::render_xrgb
+    pub screen: &'a [u16],
+    // Mono samples at the APU's sample rate
+    pub samples: Vec<i16>,
+    // False if a breakpoint stopped emulation partway through the frame
+    pub complete: bool,
+}
+
+// Where the PPU is, and how long until the points a frontend racing the beam cares
+// about, from beam_position(). Dots are the PPU's clock; the CPU runs one cycle for
+// every 3.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BeamPosition {
+    pub frame: u32,
+    // 0 - 239 are visible, 241 - 260 are vblank and 261 is the pre-render line
+    pub scanline: u16,
+    // 0 - 340
+    pub dot: u16,
+    // CPU cycles until vblank begins, which is when NMI fires if PPUCTRL enables it
+    pub cpu_cycles_until_vblank: u32,
+    // The same, or None if NMI is currently disabled
+    pub cpu_cycles_until_nmi: Option<u32>,
+    // CPU cycles until the PPU starts drawing the next visible scanline
+    pub cpu_cycles_until_visible_scanline: u32,
+}
+
+// Scratch space for save_state_into() and load_state(), kept between calls so that
+// saving and loading every frame doesn't allocate
+#[derive(Default)]
+struct StateBuffers {
+    save: Vec<u8>,
+    backup: Vec<u8>,
+    section: Vec<u8>,
+}
 
 pub struct NesState {
     pub apu: ApuState,
@@ -17,16 +162,90 @@ pub struct NesState {
     pub ppu: PpuState,
     pub registers: Registers,
     pub master_clock: u64,
-    pub p1_input: u8,
-    pub p1_data: u8,
-    pub p2_input: u8,
-    pub p2_data: u8,
-    pub input_latch: bool,
+    // What's plugged into each controller port
+    pub ports: [Box<dyn ControllerPort>; 2],
+    // What's plugged into the expansion port, if anything; see expansion_port.rs
+    pub expansion_device: Option<Box<dyn ExpansionDevice>>,
+    pub region: Region,
+    // Set by NesStateBuilder::console()
+    pub console: ConsoleModel,
+    // Whether anyone is talking into the Famicom's microphone, on consoles that have
+    // one
+    pub microphone: bool,
+    // How much of the picture's edges the frontend should crop; NesStateBuilder sets
+    // this from the game's overrides, if it has any
+    pub overscan: Overscan,
+    // Set by NesStateBuilder::deterministic(). Power on always starts from the same
+    // RAM and PPU alignment, and anything that would read the wall clock (cartridge
+    // real time clocks, say) must run on master_clock instead.
+    pub deterministic: bool,
     pub mapper: Box<dyn Mapper>,
+    // Lets the PPU fall behind the CPU and catch up in batches, only when the CPU
+    // touches its registers or the cartridge, or when it reaches a point the CPU
+    // would notice anyway (vblank starting or ending, the end of the frame). Much
+    // faster for fast forward and run-ahead, and gives the same results; but while a
+    // frame runs, the event viewer's positions and anything else reading ppu
+    // mid-instruction only see it as of the last catch-up. It quietly runs in step
+    // whenever breakpoints or tracing are on, or the mapper watches the PPU bus (see
+    // Mapper::ppu_reads_cacheable()).
+    pub lazy_ppu: bool,
+    ppu_pending_dots: u32,
+    // 0 when the PPU is being clocked along with the CPU
+    ppu_sync_deadline: u32,
+    // See cartridge::rom_crc32(); 0 if unknown. Savestates from other cartridges are
+    // refused when it's set.
+    pub rom_crc32: u32,
     pub last_frame: u32,
+    // Lag detection: a frame which never reads $4016/$4017 is a lag frame
+    pub input_polled: bool,
+    pub lag_frame: bool,
+    pub lag_counter: u32,
     pub event_tracker: EventTracker,
+    pub breakpoints: Breakpoints,
+    pub tracer: Option<TraceLogger>,
+    pub vgm_logger: Option<VgmLogger>,
+    pub apu_write_log: Option<ApuWriteLogger>,
+    // Drives the controller ports while it plays; see play_movie()
+    pub movie: Option<MoviePlayer>,
+    // See start_input_log() and replay_input_log()
+    pub input_log: Option<InputLog>,
+    pub input_replay: Option<InputReplay>,
+    // See request_reset() and request_power_cycle()
+    reset_requested: bool,
+    power_cycle_requested: bool,
+    pub symbols: SymbolTable,
+    pub profiler: Profiler,
+    // Time spent in each subsystem; see set_frame_timing()
+    pub frame_timing: FrameTiming,
+    pub cheats: CheatEngine,
+    pub save_slots: SaveStateManager,
+    state_buffers: StateBuffers,
+    // How big each section came out the last time the state was written, so the next
+    // save can allocate the whole thing up front rather than grow as it goes
+    state_section_sizes: [Cell<usize>; SECTION_COUNT as usize],
+    frame_hooks: Vec<FrameHook>,
+    // Scripted input playing into each player's buttons; see play_macro()
+    macros: [Option<MacroPlayback>; 4],
+    // Nothing until something draws; see draw_overlay()
+    overlay: Option<Overlay>,
+    signal_hooks: Vec<(SignalTrigger, SignalHook)>,
+    // See set_watch_list()
+    watch_list: Option<WatchList>,
+    watch_snapshot: Option<WatchSnapshot>,
+    // See start_piano_roll()
+    pub piano_roll: Option<PianoRoll>,
+    // See start_dpcm_rip()
+    pub dpcm_ripper: Option<DpcmRipper>,
+    signal_watch: SignalWatch,
+    sram_flush: Option<SramFlush>,
 }
 
+// Frontends may run the console on a thread of its own, so everything NesState owns
+// has to be Send: mappers, controller ports, sinks, loggers and hooks are all boxed
+// behind Send traits for this reason. Fails to compile if that stops being true.
+const fn assert_send<T: Send>() {}
+const _: () = assert_send::<NesState>();
+
 impl NesState {
     pub fn new(m: Box<dyn Mapper>) -> NesState {
         return NesState {
@@ -36,57 +255,338 @@ impl NesState {
             ppu: PpuState::new(),
             registers: Registers::new(),
             master_clock: 0,
-            p1_input: 0,
-            p1_data: 0,
-            p2_input: 0,
-            p2_data: 0,
-            input_latch: false,
+            ports: [Box::new(StandardController::new()), Box::new(StandardController::new())],
+            expansion_device: None,
+            region: Region::Ntsc,
+            console: ConsoleModel::AvFamicom,
+            microphone: false,
+            overscan: Overscan::default(),
+            deterministic: false,
             mapper: m,
+            lazy_ppu: false,
+            ppu_pending_dots: 0,
+            ppu_sync_deadline: 0,
+            rom_crc32: 0,
             last_frame: 0,
+            input_polled: false,
+            lag_frame: false,
+            lag_counter: 0,
             event_tracker: EventTracker::new(),
+            breakpoints: Breakpoints::new(),
+            tracer: None,
+            vgm_logger: None,
+            apu_write_log: None,
+            movie: None,
+            input_log: None,
+            input_replay: None,
+            reset_requested: false,
+            power_cycle_requested: false,
+            symbols: SymbolTable::new(),
+            profiler: Profiler::new(),
+            frame_timing: FrameTiming::new(),
+            cheats: CheatEngine::new(),
+            save_slots: SaveStateManager::new(SAVE_SLOT_COUNT),
+            state_buffers: StateBuffers::default(),
+            state_section_sizes: Default::default(),
+            frame_hooks: Vec::new(),
+            macros: [None, None, None, None],
+            overlay: None,
+            signal_hooks: Vec::new(),
+            watch_list: None,
+            watch_snapshot: None,
+            piano_roll: None,
+            dpcm_ripper: None,
+            signal_watch: SignalWatch::default(),
+            sram_flush: None,
         }
     }
 
+    // Replaces buff's contents with the whole state. See savestate.rs for the format.
+    fn write_state(&self, buff: &mut Vec<u8>) {
+        begin_container(buff, self.rom_crc32, SECTION_COUNT);
+        let sizes = &self.state_section_sizes;
+        // The sections, and the checksum after them
+        buff.reserve(sizes.iter().map(|size| size.get()).sum::<usize>() + 4);
+        let mut index = 0;
+        let mut section = |tag: &SectionTag, save: &dyn Fn(&mut Vec<u8>)| {
+            let start = buff.len();
+            let length_position = begin_section(buff, tag);
+            save(buff);
+            end_section(buff, length_position);
+            sizes[index].set(buff.len() - start);
+            index += 1;
+        };
+        section(b"APU ", &|buff| self.apu.save_state(buff));
+        section(b"CPU ", &|buff| self.cpu.save_state(buff));
+        section(b"MEM ", &|buff| self.memory.save_state(buff));
+        section(b"PPU ", &|buff| self.ppu.save_state(buff));
+        section(b"REGS", &|buff| self.registers.save_state(buff));
+        section(b"PORT", &|buff| {
+            self.ports[0].save_state(buff);
+            self.ports[1].save_state(buff);
+            if let Some(device) = self.expansion_device.as_ref() {
+                device.save_state(buff);
+            }
+        });
+        section(b"MAPR", &|buff| self.mapper.save_state(buff));
+        section(b"NES ", &|buff| {
+            save_u64(buff, self.master_clock);
+            save_u32(buff, self.last_frame);
+            save_bool(buff, self.input_polled);
+            save_bool(buff, self.lag_frame);
+            save_u32(buff, self.lag_counter);
+        });
+        end_container(buff);
+    }
+
     pub fn save_state(&self) -> Vec<u8> {
-        let mut buff = vec!();
-        self.apu.save_state(&mut buff);
-        self.cpu.save_state(&mut buff);
-        self.memory.save_state(&mut buff);
-        self.ppu.save_state(&mut buff);
-        self.registers.save_state(&mut buff);
-        save_u64(&mut buff, self.master_clock);
-        save_u8(&mut buff, self.p1_input);
-        save_u8(&mut buff, self.p1_data);
-        save_u8(&mut buff, self.p2_input);
-        save_u8(&mut buff, self.p2_data);
-        save_bool(&mut buff, self.input_latch);
-        self.mapper.save_state(&mut buff);
-        save_u32(&mut buff, self.last_frame);
-        buff
-    }
-
-    pub fn load_state(&mut self, buff: &mut Vec<u8>) {
-        load_u32(buff, &mut self.last_frame);
-        self.mapper.load_state(buff);
-        load_bool(buff, &mut self.input_latch);
-        load_u8(buff, &mut self.p2_data);
-        load_u8(buff, &mut self.p2_input);
-        load_u8(buff, &mut self.p1_data);
-        load_u8(buff, &mut self.p1_input);
-        load_u64(buff, &mut self.master_clock);
-        self.registers.load_state(buff);
-        self.ppu.load_state(buff);
-        self.memory.load_state(buff);
-        self.cpu.load_state(buff);
-        self.apu.load_state(buff);
+        let mut buff = Vec::new();
+        self.write_state(&mut buff);
+        return buff;
+    }
+
+    // The size of every savestate this console makes. It's fixed once the cartridge
+    // is loaded and the controller ports are set up (plugging in a different device
+    // changes it), so size run-ahead and rollback buffers with it once.
+    pub fn max_state_size(&self) -> usize {
+        return self.save_state().len();
+    }
+
+    // See memory::debug_peek()
+    pub fn debug_peek(&self, space: AddressSpace, address: usize) -> Option<u8> {
+        return memory::debug_peek(self, space, address);
+    }
+
+    pub fn debug_peek_range(&self, space: AddressSpace, address: usize, buff: &mut [u8]) -> usize {
+        return memory::debug_peek_range(self, space, address, buff);
+    }
+
+    // The current frame as a PNG file, for bots and headless capture
+    #[cfg(feature = "screenshot")]
+    pub fn screenshot_png(&self) -> Result<Vec<u8>, Error> {
+        return crate::screenshot::encode_png(&self.ppu.screen);
+    }
+
+    // The cartridge's CHR as it is right now: CHR RAM on boards that have it, for
+    // debugging graphics generated at runtime, otherwise the ROM
+    pub fn chr_snapshot(&self) -> Vec<u8> {
+        return self.mapper.chr().to_vec();
+    }
+
+    // $2000 - $2FFF as the PPU sees it, so through the cartridge's mirroring: four
+    // nametables of 960 tiles and 64 attribute bytes each
+    pub fn nametable_snapshot(&self) -> Vec<u8> {
+        return (0x2000 .. 0x3000).map(|address| self.ppu.debug_read_byte(&*self.mapper, address)).collect();
+    }
+
+    // The scroll of each of the 240 visible scanlines of the last frame to finish
+    // drawing, which is this one once the PPU is past scanline 239; see ScrollLog
+    pub fn scanline_scroll(&mut self) -> &[Option<ScanlineScroll>] {
+        self.sync_ppu();
+        if self.ppu.current_scanline >= 240 {
+            return self.ppu.scroll_log.this_frame();
+        }
+        return self.ppu.scroll_log.last_frame();
+    }
+
+    // All of chr_snapshot() as a sheet of tiles in one of the current palettes (0 - 3
+    // for the background, 4 - 7 for sprites); see chr_export::tile_sheet()
+    #[cfg(feature = "screenshot")]
+    pub fn chr_tile_sheet_png(&self, palette: u8) -> Result<Vec<u8>, Error> {
+        let pixels = crate::chr_export::tile_sheet(self.mapper.chr(), crate::chr_export::palette_colors(self, palette));
+        let width = crate::chr_export::TILE_SHEET_WIDTH;
+        return crate::screenshot::encode_indexed_png(&pixels, width as u32, (pixels.len() / width) as u32);
+    }
+
+    // See chr_export::nametables()
+    #[cfg(feature = "screenshot")]
+    pub fn nametables_png(&self) -> Result<Vec<u8>, Error> {
+        let pixels = crate::chr_export::nametables(self);
+        return crate::screenshot::encode_indexed_png(&pixels,
+            crate::chr_export::NAMETABLES_WIDTH as u32, crate::chr_export::NAMETABLES_HEIGHT as u32);
+    }
+
+    // The last complete frame's tracked events, as tracked_events::events_to_json()
+    // describes
+    pub fn frame_events_json(&self) -> String {
+        return tracked_events::events_to_json(self.ppu.current_frame.wrapping_sub(1), self.event_tracker.events_last_frame());
+    }
+
+    // A digest of everything that decides how emulation proceeds: RAM, VRAM, OAM,
+    // registers, mapper and controller state. Consoles with equal hashes run the same
+    // from here on, so netplay peers and TAS tools can compare it every frame to catch
+    // desyncs. Debug buffers, audio output and the sample clock are left out, so
+    // frontend settings don't change it. Doesn't allocate after the first call. For
+    // boards without savestate support, only the mapper state visible on the buses is
+    // covered.
+    pub fn state_hash(&mut self) -> u64 {
+        self.sync_ppu();
+        let mut buff = std::mem::take(&mut self.state_buffers.save);
+        let mut hasher = Fnv1a64::new();
+        let mut hash = |save: &dyn Fn(&mut Vec<u8>)| {
+            buff.clear();
+            save(&mut buff);
+            hasher.write(&buff);
+        };
+        hash(&|buff| self.apu.save_emulated_state(buff));
+        hash(&|buff| self.cpu.save_state(buff));
+        hash(&|buff| self.memory.save_state(buff));
+        hash(&|buff| self.ppu.save_state(buff));
+        hash(&|buff| self.registers.save_state(buff));
+        hash(&|buff| {
+            self.ports[0].save_state(buff);
+            self.ports[1].save_state(buff);
+            if let Some(device) = self.expansion_device.as_ref() {
+                device.save_state(buff);
+            }
+        });
+        hash(&|buff| {
+            if self.mapper.supports_savestates() {
+                self.mapper.save_state(buff);
+            } else {
+                // Without savestate support, settle for what the mapper shows the
+                // buses: its RAM and current banks, though not its expansion audio
+                // or IRQ counters
+                for address in 0x4020 ..= 0xFFFF {
+                    save_u8(buff, self.mapper.debug_read_cpu(address).unwrap_or(0));
+                }
+                for address in 0x0000 .. 0x3F00 {
+                    save_u8(buff, self.mapper.debug_read_ppu(address).unwrap_or(0));
+                }
+                save_bool(buff, self.mapper.irq_flag());
+            }
+        });
+        hash(&|buff| {
+            save_u64(buff, self.master_clock);
+            save_u32(buff, self.last_frame);
+            save_bool(buff, self.input_polled);
+            save_bool(buff, self.lag_frame);
+            save_u32(buff, self.lag_counter);
+        });
+        self.state_buffers.save = buff;
+        return hasher.finish();
+    }
+
+    // Runs count frames and hashes what came out, for golden value regression tests of
+    // mapper and PPU changes: record the hashes once, then check them after every
+    // change. Build the console with NesStateBuilder::deterministic() so the result
+    // doesn't depend on the power on state, and keep the sample rate and audio filter
+    // fixed, as the audio hash depends on both. Samples already queued are discarded.
+    pub fn run_frames_and_hash(&mut self, count: u32) -> FrameHashes {
+        self.apu.consume_samples();
+        let mut audio = Fnv1a64::new();
+        for _ in 0 .. count {
+            self.run_until_vblank();
+            for sample in self.apu.consume_samples() {
+                audio.write(&sample.to_le_bytes());
+            }
+        }
+        let mut frame = Fnv1a64::new();
+        for pixel in self.ppu.screen.iter() {
+            frame.write(&pixel.to_le_bytes());
+        }
+        return FrameHashes{frame: frame.finish(), audio: audio.finish()};
+    }
+
+    // save_state() without allocating, for run-ahead and rollback, which save every
+    // frame. Returns the number of bytes written.
+    pub fn save_state_into(&mut self, out: &mut [u8]) -> Result<usize, Error> {
+        self.sync_ppu();
+        let mut buff = std::mem::take(&mut self.state_buffers.save);
+        self.write_state(&mut buff);
+        let result = match out.get_mut(.. buff.len()) {
+            Some(dest) => {
+                dest.copy_from_slice(&buff);
+                Ok(buff.len())
+            },
+            None => Err(Error::BufferTooSmall{needed: buff.len(), available: out.len()})
+        };
+        self.state_buffers.save = buff;
+        return result;
+    }
+
+    // Fails without touching the console if the state is from another version, a
+    // different cartridge, is missing a section, or doesn't parse. Doesn't allocate,
+    // once the first call has sized the scratch buffers.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), Error> {
+        let sections = read_container(data, self.rom_crc32)?;
+        self.sync_ppu();
+        let mut backup = std::mem::take(&mut self.state_buffers.backup);
+        let mut buff = std::mem::take(&mut self.state_buffers.section);
+        // A section is only known to be the right size once it's been read, so keep
+        // the current state around to roll back to
+        self.write_state(&mut backup);
+        let result = self.load_sections(&sections, &mut buff);
+        if result.is_err() {
+            // A state this console just saved always loads
+            let _ = self.load_sections(&read_container(&backup, self.rom_crc32).unwrap(), &mut buff);
+        }
+        self.state_buffers.backup = backup;
+        self.state_buffers.section = buff;
+        // The PPU is somewhere else now
+        self.ppu_sync_deadline = 0;
+        return result;
+    }
+
+    // load_state(), named to pair with save_state_into()
+    pub fn load_state_from(&mut self, data: &[u8]) -> Result<(), Error> {
+        return self.load_state(data);
+    }
+
+    fn load_sections(&mut self, sections: &Sections, buff: &mut Vec<u8>) -> Result<(), Error> {
+        // Every section has to be there before any of them are loaded
+        let apu = sections.find(b"APU ")?;
+        let cpu = sections.find(b"CPU ")?;
+        let memory = sections.find(b"MEM ")?;
+        let ppu = sections.find(b"PPU ")?;
+        let registers = sections.find(b"REGS")?;
+        let ports = sections.find(b"PORT")?;
+        let mapper = sections.find(b"MAPR")?;
+        let nes = sections.find(b"NES ")?;
+
+        load_section(b"APU ", apu, buff, |buff| self.apu.load_state(buff))?;
+        load_section(b"CPU ", cpu, buff, |buff| self.cpu.load_state(buff))?;
+        load_section(b"MEM ", memory, buff, |buff| self.memory.load_state(buff))?;
+        load_section(b"PPU ", ppu, buff, |buff| self.ppu.load_state(buff))?;
+        load_section(b"REGS", registers, buff, |buff| self.registers.load_state(buff))?;
+        load_section(b"PORT", ports, buff, |buff| {
+            if let Some(device) = self.expansion_device.as_mut() {
+                device.load_state(buff);
+            }
+            self.ports[1].load_state(buff);
+            self.ports[0].load_state(buff);
+        })?;
+        self.ppu.fetch_cache.mapper_replaced();
+        self.memory.page_table.mapper_replaced();
+        load_section(b"MAPR", mapper, buff, |buff| self.mapper.load_state(buff))?;
+        load_section(b"NES ", nes, buff, |buff| {
+            load_u32(buff, &mut self.lag_counter);
+            load_bool(buff, &mut self.lag_frame);
+            load_bool(buff, &mut self.input_polled);
+            load_u32(buff, &mut self.last_frame);
+            load_u64(buff, &mut self.master_clock);
+        })?;
+        return Ok(());
+    }
+
+    pub fn save_slot(&mut self, index: usize) -> Result<(), Error> {
+        let state = self.save_state();
+        return self.save_slots.store(index, state, self.last_frame);
+    }
+
+    // Fails, leaving the console alone, if the slot is empty or its state won't load
+    pub fn load_slot(&mut self, index: usize) -> Result<(), Error> {
+        let state = self.save_slots.state(index)?.to_vec();
+        return self.load_state(&state);
     }
 
     #[deprecated(since="0.2.0", note="please use `::new(mapper)` instead")]
-    pub fn from_rom(cart_data: &[u8]) -> Result<NesState, String> {
+    pub fn from_rom(cart_data: &[u8]) -> Result<NesState, Error> {
         let maybe_mapper = cartridge::mapper_from_file(cart_data);
         match maybe_mapper {
             Ok(mapper) => {
                 let mut nes = NesState::new(mapper);
+                nes.rom_crc32 = cartridge::rom_crc32(cart_data);
                 nes.power_on();
                 return Ok(nes);
             },
@@ -96,7 +596,16 @@ impl NesState {
         }
     }
 
+    // The power up values of the CPU and APU registers: A, X and Y cleared, S at $FD,
+    // every sound register zeroed, then the reset vector. RAM is left alone outside of
+    // deterministic mode, so call this on a fresh console; see reset() for the reset
+    // button and power_cycle() for switching a running console off and on.
     pub fn power_on(&mut self) {
+        if self.deterministic {
+            // Whatever the RAM held before, so that power cycling starts over exactly
+            DETERMINISTIC_RAM_INIT.fill(&mut self.memory.iram_raw);
+        }
+
         // Initialize CPU register state for power-up sequence
         self.registers.a = 0;
         self.registers.y = 0;
@@ -119,67 +628,973 @@ impl NesState {
         // Clock the APU 10 times (this subtly affects the first IRQ's timing and frame counter operation)
         for _ in 0 .. 10 {
             self.apu.clock_apu(&mut *self.mapper);
+            self.service_dmc_fetch();
+        }
+    }
+
+    // Switching off and on again. Unlike power_on(), which only does what the reset
+    // line and the power up values of the registers do, this rebuilds the CPU, PPU
+    // and APU from scratch, so nothing left over from the last run (a DMA halfway
+    // through, the PPU's address latch, a length counter) survives. RAM keeps whatever
+    // it held, as it mostly does on hardware, except in deterministic mode. Mapper
+    // registers aren't touched; for a cartridge that needs them cleared, load the
+    // ROM again.
+    pub fn power_cycle(&mut self) {
+        self.sync_ppu();
+        self.cpu = CpuState::new();
+        self.registers = Registers::new();
+        self.memory.open_bus = 0;
+        let sprite_limit = self.ppu.sprite_limit;
+        let current_frame = self.ppu.current_frame;
+        let old_ppu = std::mem::replace(&mut self.ppu, PpuState::new());
+        self.ppu_sync_deadline = 0;
+        self.ppu.sprite_limit = sprite_limit;
+        self.ppu.register_writes = old_ppu.register_writes;
+        self.ppu.scroll_log = old_ppu.scroll_log;
+        self.ppu.time_pixels = old_ppu.time_pixels;
+        self.ppu.pixel_time = old_ppu.pixel_time;
+        // Frame hooks and movies count frames by this
+        self.ppu.current_frame = current_frame;
+        self.apu.power_cycle();
+        self.power_on();
+    }
+
+    // Pulls the cartridge out and puts another in, with the console still running,
+    // and returns the old one. Nothing else changes, so the CPU carries on from
+    // wherever it was on the new cartridge: follow with power_cycle() for an ordinary
+    // cartridge change, or don't, for disk swaps and hot-swap tricks. rom_crc32 is
+    // cleared, as it described the old cartridge. Unsaved changes to the old
+    // cartridge's SRAM go to the flush hook first, if one is set.
+    pub fn swap_cartridge(&mut self, mapper: Box<dyn Mapper>) -> Box<dyn Mapper> {
+        self.sync_ppu();
+        if let Some(mut flush) = self.sram_flush.take() {
+            if flush.pending || self.mapper.sram_dirty() {
+                (flush.hook)(&self.mapper.get_sram());
+            }
+            flush.pending = mapper.sram_dirty();
+            flush.idle = 0;
+            self.sram_flush = Some(flush);
+        }
+        let old_mapper = std::mem::replace(&mut self.mapper, mapper);
+        self.console.connect_cartridge_audio(&mut *self.mapper);
+        self.ppu.fetch_cache.mapper_replaced();
+        self.memory.page_table.mapper_replaced();
+        self.rom_crc32 = 0;
+        self.configure_rtc();
+        return old_mapper;
+    }
+
+    // For homebrew development: loads a rebuilt ROM over the running one, keeping RAM,
+    // the cartridge's RAM (CHR RAM included) and its mapper registers, so the game
+    // carries on with the new code and graphics. With keep_cpu_state, the CPU carries
+    // on from exactly where it was, which only works if the code it's in hasn't moved;
+    // otherwise the console is reset as by reset(). A ROM for a different board, or
+    // with different memory sizes, can't take over the old one's state, so it starts
+    // from power_cycle() with only the battery backed RAM carried over. So do boards
+    // without savestate support, whose state can't be carried over at all. On error,
+    // nothing changes.
+    pub fn reload_rom(&mut self, data: &[u8], keep_cpu_state: bool) -> Result<(), Error> {
+        let mut mapper = cartridge::mapper_from_file(data)?;
+        let mut old_state = Vec::new();
+        let mut compatible = mapper.supports_savestates() && self.mapper.supports_savestates() &&
+            mapper.board_info() == self.mapper.board_info();
+        if compatible {
+            self.mapper.save_state(&mut old_state);
+            let mut new_state = Vec::new();
+            mapper.save_state(&mut new_state);
+            compatible = old_state.len() == new_state.len();
+        }
+        if compatible {
+            mapper.load_state(&mut old_state);
+        } else if mapper.has_sram() && self.mapper.has_sram() {
+            mapper.load_sram(self.mapper.get_sram());
+        }
+        self.swap_cartridge(mapper);
+        self.rom_crc32 = cartridge::rom_crc32(data);
+        if !compatible {
+            self.power_cycle();
+        } else if !keep_cpu_state {
+            self.reset();
         }
+        return Ok(());
     }
 
+    // The reset button, which some games tell apart from power on by checking for
+    // signatures they left in RAM. Compared to power_on():
+    //   - RAM, cartridge memory and mapper registers are untouched
+    //   - A, X and Y keep their values; S drops by 3 (the reset sequence's suppressed
+    //     pushes) and interrupts are disabled
+    //   - any instruction or OAM DMA in progress is abandoned
+    //   - $4015 is cleared, silencing every channel, but the other sound registers
+    //     keep their values
+    //   - the frame counter keeps its mode and IRQ inhibit but restarts, as if $4017
+    //     had been written again with its last value, and any frame IRQ is dropped
+    //   - the triangle restarts its waveform and the DMC output keeps only its low bit
+    //   - the PPU is reset as PpuState::reset() describes
     pub fn reset(&mut self) {
+        self.sync_ppu();
         self.registers.s = self.registers.s.wrapping_sub(3);
         self.registers.flags.interrupts_disabled = true;
 
+        self.cpu.tick = 0;
+        self.cpu.service_routine_active = false;
+        self.cpu.upcoming_write = false;
+        self.cpu.oam_dma_active = false;
+
+        self.ppu.reset();
+        self.apu.reset();
+
         // Silence the APU
         memory::write_byte(self, 0x4015, 0);
+        let frame_counter = self.apu.frame_counter_byte();
+        memory::write_byte(self, 0x4017, frame_counter);
 
         let pc_low = memory::read_byte(self, 0xFFFC);
         let pc_high = memory::read_byte(self, 0xFFFD);
         self.registers.pc = pc_low as u16 + ((pc_high as u16) << 8);
+
+        // As at power on, the frame counter has run a few cycles by the time the
+        // first instruction does
+        for _ in 0 .. 10 {
+            self.apu.clock_apu(&mut *self.mapper);
+            self.service_dmc_fetch();
+        }
     }
 
+    // One CPU cycle, with the PPU caught up afterwards
     pub fn cycle(&mut self) {
+        self.clock_cycle();
+        self.sync_ppu();
+    }
+
+    fn clock_cycle(&mut self) {
+        if self.frame_timing.enabled() {
+            self.clock_cycle_timed();
+            return;
+        }
+        self.clock_cpu();
+        self.clock_ppu();
+        self.apu.clock_apu(&mut *self.mapper);
+        self.service_dmc_fetch();
+        self.mapper.clock_cpu();
+        self.clock_peripherals();
+    }
+
+    // clock_cycle(), charging each part to frame_timing as it goes
+    fn clock_cycle_timed(&mut self) {
+        let mut lap = self.frame_timing.start_lap();
+        self.clock_cpu();
+        self.frame_timing.lap(&mut lap, Subsystem::Cpu);
+        self.clock_ppu();
+        self.frame_timing.lap(&mut lap, Subsystem::Ppu);
+        self.apu.clock_apu(&mut *self.mapper);
+        self.service_dmc_fetch();
+        self.frame_timing.lap(&mut lap, Subsystem::Apu);
+        self.mapper.clock_cpu();
+        self.frame_timing.lap(&mut lap, Subsystem::Mapper);
+        self.clock_peripherals();
+        self.frame_timing.lap(&mut lap, Subsystem::Cpu);
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.input_replay.as_ref().map_or(false, |replay| replay.due(self.master_clock)) {
+            let mut replay = self.input_replay.take().unwrap();
+            replay.feed(self);
+            self.input_replay = Some(replay);
+        }
         cycle_cpu::run_one_clock(self);
         self.master_clock = self.master_clock + 12;
+    }
+
+    fn clock_ppu(&mut self) {
         // Three PPU clocks per every 1 CPU clock
-        self.ppu.clock(&mut *self.mapper);
-        self.ppu.clock(&mut *self.mapper);
-        self.ppu.clock(&mut *self.mapper);
+        if self.ppu_sync_deadline == 0 {
+            self.ppu.clock(&mut *self.mapper);
+            self.ppu.clock(&mut *self.mapper);
+            self.ppu.clock(&mut *self.mapper);
+            self.event_tracker.current_scanline = self.ppu.current_scanline;
+            self.event_tracker.current_cycle = self.ppu.current_scanline_cycle;
+        } else {
+            self.ppu_pending_dots += 3;
+            if self.ppu_pending_dots >= self.ppu_sync_deadline {
+                self.sync_ppu();
+            }
+        }
+    }
+
+    fn clock_peripherals(&mut self) {
+        for port in self.ports.iter_mut() {
+            port.clock_cpu();
+        }
+        if let Some(device) = self.expansion_device.as_mut() {
+            device.clock_cpu();
+            // Mixed in from the next cycle on
+            self.apu.expansion_port_input = device.audio_input();
+        }
+        let irq_lines = self.irq_lines();
+        self.event_tracker.snoop_signals(irq_lines, (self.ppu.status & 0x40) != 0);
+        if !self.signal_hooks.is_empty() || self.watch_sample_point().is_some() {
+            self.run_signal_hooks();
+        }
+    }
+
+    // Every source currently holding the CPU's IRQ line, whether or not the CPU has
+    // interrupts disabled; see irq.rs
+    pub fn irq_lines(&self) -> IrqLines {
+        let mut lines = IrqLines::new();
+        lines.set(IrqSource::Mapper, self.mapper.irq_flag());
+        lines.set(IrqSource::ApuFrameCounter, self.apu.frame_interrupt);
+        lines.set(IrqSource::Dmc, self.apu.dmc.interrupt_flag);
+        return lines;
+    }
+
+    // Releases one source's line as if the program had acknowledged it, without the
+    // rest of what the acknowledging access would do. The others are left alone, so
+    // the CPU still sees an IRQ if any of them is asserted.
+    pub fn acknowledge_irq(&mut self, source: IrqSource) {
+        match source {
+            IrqSource::Mapper => self.mapper.acknowledge_irq(),
+            IrqSource::ApuFrameCounter => self.apu.frame_interrupt = false,
+            IrqSource::Dmc => self.apu.dmc.interrupt_flag = false,
+        }
+    }
+
+    // Measures how long each subsystem takes per frame; see frame_timing.rs. Results
+    // appear in frame_timing as each frame completes.
+    pub fn set_frame_timing(&mut self, enabled: bool) {
+        self.frame_timing.set_enabled(enabled);
+        self.ppu.time_pixels = enabled;
+        self.ppu.pixel_time = Duration::ZERO;
+    }
+
+    // DMC sample fetches are ordinary reads on the CPU bus, so they go through the full
+    // memory map: mapper side effects, open bus, cheats and read breakpoints all apply
+    fn service_dmc_fetch(&mut self) {
+        if self.apu.dmc.fetch_requested {
+            if self.dpcm_ripper.is_some() && self.apu.dmc.bytes_remaining == self.apu.dmc.sample_length {
+                let mut ripper = self.dpcm_ripper.take().unwrap();
+                ripper.sample_started(self);
+                self.dpcm_ripper = Some(ripper);
+            }
+            let address = self.apu.dmc.fetch_address();
+            self.event_tracker.snoop_dmc_dma(address);
+            let byte = memory::read_byte(self, address);
+            self.apu.dmc.receive_sample(byte);
+        }
+    }
+
+    // Runs the PPU up to where the CPU is, if it's been allowed to fall behind (see
+    // lazy_ppu), and works out how far it can fall behind next
+    pub fn sync_ppu(&mut self) {
+        let start = if self.frame_timing.enabled() {Some(Instant::now())} else {None};
+        self.ppu.clock_dots(&mut *self.mapper, self.ppu_pending_dots);
+        if let Some(start) = start {
+            self.frame_timing.add_ppu(start.elapsed());
+        }
+        self.ppu_pending_dots = 0;
         self.event_tracker.current_scanline = self.ppu.current_scanline;
         self.event_tracker.current_cycle = self.ppu.current_scanline_cycle;
-        self.apu.clock_apu(&mut *self.mapper);
-        self.mapper.clock_cpu();
+        let lazy = self.lazy_ppu &&
+            self.breakpoints.list().is_empty() &&
+            self.tracer.is_none() &&
+            !self.beam_hooks_registered() &&
+            self.mapper.ppu_reads_cacheable();
+        self.ppu_sync_deadline = if lazy {self.ppu.dots_until_visible_change()} else {0};
     }
 
     pub fn step(&mut self) {
+        self.run_instruction();
+        self.sync_ppu();
+    }
+
+    // step(), leaving the PPU behind if it's allowed to be
+    fn run_instruction(&mut self) {
         // Always run at least one cycle
-        self.cycle();
+        self.clock_cycle();
         let mut i = 0;
         // Continue until either we loop back around to cycle 0 (a new instruction)
         // or this instruction has failed to reset (encountered a STP or an opcode bug)
         while self.cpu.tick >= 1 && i < 10 {
-            self.cycle();
+            self.clock_cycle();
             i += 1;
         }
+        self.check_for_new_frame();
+    }
+
+    fn check_for_new_frame(&mut self) {
         if self.ppu.current_frame != self.last_frame {
+            // Frame hooks may save state
+            self.sync_ppu();
             self.event_tracker.swap_buffers();
+            self.ppu.register_writes.end_frame();
+            self.profiler.end_frame();
+            self.frame_timing.end_frame(std::mem::take(&mut self.ppu.pixel_time));
+            self.lag_frame = !self.input_polled;
+            if self.lag_frame {
+                self.lag_counter += 1;
+            }
+            self.input_polled = false;
+            for port in self.ports.iter_mut() {
+                port.end_frame();
+            }
+            if let Some(device) = self.expansion_device.as_mut() {
+                device.end_frame();
+            }
+            self.advance_macros();
+            cheats::apply_frame_cheats(self);
+            if matches!(self.watch_list, Some(WatchList{sample_point: WatchPoint::EndOfFrame, ..})) {
+                self.sample_watch_list();
+            }
+            if let Some(piano_roll) = self.piano_roll.as_mut() {
+                piano_roll.end_frame(self.ppu.current_frame, &self.apu.all_channels(&*self.mapper));
+            }
+            self.run_frame_hooks();
+            self.check_sram_flush();
+            self.end_movie_frame();
+            self.run_requested_commands();
             self.last_frame = self.ppu.current_frame;
         }
     }
 
+    // Both of these return early if a breakpoint is hit; check self.breakpoints.triggered
     pub fn run_until_hblank(&mut self) {
         let old_scanline = self.ppu.current_scanline;
-        while old_scanline == self.ppu.current_scanline {
+        while old_scanline == self.ppu.current_scanline && self.breakpoints.triggered.is_none() {
             self.step();
         }
     }
 
     pub fn run_until_vblank(&mut self) {
-        while self.ppu.current_scanline == 242 {
+        // Reaching scanline 242 always catches up a lazy PPU, so these can run ahead
+        while self.ppu.current_scanline == 242 && self.breakpoints.triggered.is_none() {
+            self.run_instruction();
+        }
+        while self.ppu.current_scanline != 242 && self.breakpoints.triggered.is_none() {
+            self.run_instruction();
+        }
+        self.sync_ppu();
+    }
+
+    // For frontends driven by the sound card's callback rather than by vsync: runs
+    // until at least count samples are waiting in the APU, then stops. Collect them
+    // with apu.consume_samples() or apu.drain_samples(). Requests larger than the
+    // APU will queue are clamped, since anything beyond that would be dropped.
+    pub fn run_until_audio_samples(&mut self, count: usize) {
+        let count = count.min(self.apu.max_queued_samples());
+        while self.apu.samples_queued() < count && self.breakpoints.triggered.is_none() {
+            self.run_instruction();
+        }
+        self.sync_ppu();
+    }
+
+    // Runs frames frames as run_until_vblank() would, drawing only the last, for fast
+    // forward and headless runs that only look at the odd frame. The game runs exactly
+    // as it would otherwise; see PpuState::skip_video.
+    pub fn fast_forward(&mut self, frames: u32) {
+        let skip_video = self.ppu.skip_video;
+        for frame in 0 .. frames {
+            if self.breakpoints.triggered.is_some() {
+                break;
+            }
+            self.ppu.skip_video = frame + 1 < frames;
+            self.run_until_vblank();
+        }
+        self.ppu.skip_video = skip_video;
+    }
+
+    // Runs up to the start of the next vblank, when the picture is complete. Any
+    // samples left over from before the call are included, so none are lost when
+    // mixing this with other ways of running the console.
+    pub fn run_frame(&mut self) -> FrameOutput<'_> {
+        self.run_until_vblank();
+        return FrameOutput {
+            screen: &self.ppu.screen,
+            samples: self.apu.consume_samples(),
+            complete: self.breakpoints.triggered.is_none(),
+        };
+    }
+
+    pub fn add_frame_hook(&mut self, hook: FrameHook) {
+        self.frame_hooks.push(hook);
+    }
+
+    pub fn clear_frame_hooks(&mut self) {
+        self.frame_hooks.clear();
+    }
+
+    // Tooling that needs to react to interrupts or a point on the screen (auto-splitters,
+    // overlays) can use these rather than checking the state after every cycle
+    pub fn add_signal_hook(&mut self, trigger: SignalTrigger, hook: SignalHook) {
+        self.signal_hooks.push((trigger, hook));
+        // Catches up a lazy PPU, which beam hooks don't allow
+        self.sync_ppu();
+        self.signal_watch = self.current_signals();
+    }
+
+    pub fn clear_signal_hooks(&mut self) {
+        self.signal_hooks.clear();
+    }
+
+    fn beam_hooks_registered(&self) -> bool {
+        return self.signal_hooks.iter().any(|(trigger, _)| matches!(trigger, SignalTrigger::Beam{..})) ||
+            matches!(self.watch_sample_point(), Some(SignalTrigger::Beam{..}));
+    }
+
+    // Samples watch_list at its sample point every frame from now on, replacing any
+    // list there was, or stops with None. Collect the results with watch_snapshot().
+    pub fn set_watch_list(&mut self, watch_list: Option<WatchList>) {
+        self.watch_list = watch_list;
+        self.watch_snapshot = None;
+        if self.watch_sample_point().is_some() {
+            self.sync_ppu();
+            self.signal_watch = self.current_signals();
+        }
+    }
+
+    pub fn watch_list(&self) -> Option<&WatchList> {
+        return self.watch_list.as_ref();
+    }
+
+    // The last sample taken, with the frame it was taken on
+    pub fn watch_snapshot(&self) -> Option<&WatchSnapshot> {
+        return self.watch_snapshot.as_ref();
+    }
+
+    // The signal the watch list is sampled on, if it isn't the end of the frame
+    fn watch_sample_point(&self) -> Option<SignalTrigger> {
+        return match self.watch_list {
+            Some(WatchList{sample_point: WatchPoint::Signal(trigger), ..}) => Some(trigger),
+            _ => None
+        };
+    }
+
+    fn sample_watch_list(&mut self) {
+        if let Some(watch_list) = self.watch_list.as_ref() {
+            self.watch_snapshot = Some(watch_list.sample(self));
+        }
+    }
+
+    fn current_signals(&self) -> SignalWatch {
+        return SignalWatch {
+            nmi: cycle_cpu::nmi_signal(self),
+            irq_lines: self.irq_lines(),
+            beam_position: self.ppu.current_scanline as u32 * 341 + self.ppu.current_scanline_cycle as u32,
+            dmc_samples_finished: self.apu.dmc.samples_finished,
+        };
+    }
+
+    fn run_signal_hooks(&mut self) {
+        let previous = std::mem::take(&mut self.signal_watch);
+        let current = self.current_signals();
+        let nmi_rose = current.nmi && !previous.nmi;
+        let irq_rose = current.irq_lines.rising_since(previous.irq_lines).any();
+        let dmc_sample_ended = current.dmc_samples_finished != previous.dmc_samples_finished;
+        let (from, to) = (previous.beam_position, current.beam_position);
+        self.signal_watch = current;
+        let beam_passed = |scanline: u16, dot: u16| -> bool {
+            let target = scanline as u32 * 341 + dot as u32;
+            if to >= from {
+                return from < target && target <= to;
+            }
+            // Wrapped around into the next frame
+            return target > from || target <= to;
+        };
+        let triggered = |trigger: SignalTrigger| -> bool {
+            return match trigger {
+                SignalTrigger::Nmi => nmi_rose,
+                SignalTrigger::Irq => irq_rose,
+                SignalTrigger::Beam{scanline, dot} => beam_passed(scanline, dot),
+                SignalTrigger::DmcSampleEnd => dmc_sample_ended,
+            };
+        };
+        if self.watch_sample_point().map_or(false, triggered) {
+            self.sample_watch_list();
+        }
+        let mut hooks = std::mem::take(&mut self.signal_hooks);
+        for (trigger, hook) in hooks.iter_mut() {
+            if triggered(*trigger) {
+                hook(self);
+            }
+        }
+        // Keep any hooks that were added while these were running
+        hooks.append(&mut self.signal_hooks);
+        self.signal_hooks = hooks;
+    }
+
+    fn run_frame_hooks(&mut self) {
+        if self.frame_hooks.is_empty() {
+            return;
+        }
+        let mut hooks = std::mem::take(&mut self.frame_hooks);
+        for hook in hooks.iter_mut() {
+            hook(self);
+        }
+        // Keep any hooks that were added while these were running
+        hooks.append(&mut self.frame_hooks);
+        self.frame_hooks = hooks;
+    }
+
+    // Instruction-aware stepping for debuggers. Each of these returns true if it reached
+    // its destination, or false if it gave up early, either because a breakpoint was hit
+    // or because roughly one second of emulated time passed without getting there.
+
+    // Like step, but runs JSR, BRK and any interrupt that fires along the way to
+    // completion, returning control back at the current stack depth.
+    pub fn step_over(&mut self) -> bool {
+        let opcode = memory::debug_read_byte(self, self.registers.pc);
+        let starting_stack = self.registers.s;
+        self.step();
+        let entered_subroutine =
+            (opcode == JSR_OPCODE && self.registers.s == starting_stack.wrapping_sub(2)) ||
+            // BRK, NMI and IRQ all push three bytes
+            self.registers.s == starting_stack.wrapping_sub(3);
+        if !entered_subroutine {
+            return true;
+        }
+        let start_clock = self.master_clock;
+        // The stack wraps within page 1, so compare distances rather than addresses:
+        // still inside while S is below where it started, modulo 256
+        while (starting_stack.wrapping_sub(self.registers.s) as i8) > 0 {
+            if self.debug_run_should_stop(start_clock) {
+                return false;
+            }
             self.step();
         }
-        while self.ppu.current_scanline != 242 {
+        return true;
+    }
+
+    // Runs until the current subroutine (or interrupt handler) returns to its caller
+    pub fn step_out(&mut self) -> bool {
+        let starting_stack = self.registers.s;
+        let start_clock = self.master_clock;
+        loop {
+            if self.debug_run_should_stop(start_clock) {
+                return false;
+            }
+            let opcode = memory::debug_read_byte(self, self.registers.pc);
             self.step();
+            // As in step_over(), S may have wrapped past $FF
+            let returned = (self.registers.s.wrapping_sub(starting_stack) as i8) > 0;
+            if (opcode == RTS_OPCODE || opcode == RTI_OPCODE) && returned {
+                return true;
+            }
         }
     }
 
+    // Runs whole instructions until the next one to execute begins at address
+    pub fn run_to_address(&mut self, address: u16) -> bool {
+        let start_clock = self.master_clock;
+        self.step();
+        while self.registers.pc != address || self.cpu.service_routine_active {
+            if self.debug_run_should_stop(start_clock) {
+                return false;
+            }
+            self.step();
+        }
+        return true;
+    }
+
+    // Runs individual CPU cycles until the PPU next reaches (or passes) the given dot.
+    // This may stop in the middle of an instruction.
+    pub fn run_to_scanline(&mut self, scanline: u16, dot: u16) -> bool {
+        let start_clock = self.master_clock;
+        let reached = |nes: &NesState| -> bool {
+            return nes.ppu.current_scanline == scanline && nes.ppu.current_scanline_cycle >= dot;
+        };
+        // If we're already there, wait for the next time around
+        while reached(self) {
+            if self.debug_run_should_stop(start_clock) {
+                return false;
+            }
+            self.cycle();
+            self.check_for_new_frame();
+        }
+        while !reached(self) {
+            if self.debug_run_should_stop(start_clock) {
+                return false;
+            }
+            self.cycle();
+            self.check_for_new_frame();
+        }
+        return true;
+    }
+
+    // The PPU's position, caught up to the CPU first. Predictions assume rendering stays
+    // as it is now, since turning it on or off changes whether the pre-render line
+    // skips its last dot on odd frames.
+    pub fn beam_position(&mut self) -> BeamPosition {
+        self.sync_ppu();
+        let position = self.ppu.current_scanline as u32 * 341 + self.ppu.current_scanline_cycle as u32;
+        let dots_until_next_frame = 262 * 341 - position;
+        // On odd frames, the next frame starts on dot 1 instead
+        let first_dot = if self.ppu.rendering_enabled() && (self.ppu.current_frame & 0x1) != 0 {1} else {0};
+        let cpu_cycles_until = |dots: u32| (dots + 2) / 3;
+        // The flag is set while the PPU works on dot 1 of scanline 241
+        let vblank = 241 * 341 + 2;
+        let dots_until_vblank = if vblank > position {
+            vblank - position
+        } else {
+            dots_until_next_frame + vblank - first_dot
+        };
+        let dots_until_visible_scanline = if self.ppu.current_scanline < 239 {
+            (self.ppu.current_scanline as u32 + 1) * 341 - position
+        } else {
+            dots_until_next_frame
+        };
+        let cpu_cycles_until_vblank = cpu_cycles_until(dots_until_vblank);
+        return BeamPosition {
+            frame: self.ppu.current_frame,
+            scanline: self.ppu.current_scanline,
+            dot: self.ppu.current_scanline_cycle,
+            cpu_cycles_until_vblank: cpu_cycles_until_vblank,
+            cpu_cycles_until_nmi: if (self.ppu.control & 0x80) != 0 {Some(cpu_cycles_until_vblank)} else {None},
+            cpu_cycles_until_visible_scanline: cpu_cycles_until(dots_until_visible_scanline),
+        };
+    }
+
+    // Runs exactly count CPU cycles, which may stop in the middle of an instruction, to
+    // land on a point predicted by beam_position(). Returns early if a breakpoint is
+    // hit.
+    pub fn run_cpu_cycles(&mut self, count: u32) {
+        for _ in 0 .. count {
+            if self.breakpoints.triggered.is_some() {
+                break;
+            }
+            self.clock_cycle();
+            self.check_for_new_frame();
+        }
+        self.sync_ppu();
+    }
+
+    fn debug_run_should_stop(&self, start_clock: u64) -> bool {
+        return self.breakpoints.triggered.is_some() ||
+            self.master_clock - start_clock > DEBUG_RUN_LIMIT;
+    }
+
+    // Logs every instruction executed from now on to writer. Tracing is slow and
+    // verbose; wrap files in a BufWriter.
+    pub fn start_trace(&mut self, writer: Box<dyn std::io::Write + Send>, format: TraceFormat) {
+        self.stop_trace();
+        self.tracer = Some(TraceLogger::new(writer, format));
+    }
+
+    // Narrows the running trace to instructions matching filter; see TraceLogger.
+    // False if there's no trace running.
+    pub fn set_trace_filter(&mut self, filter: Option<Expression>) -> bool {
+        return match self.tracer.as_mut() {
+            Some(tracer) => {tracer.filter = filter; true},
+            None => false
+        };
+    }
+
+    pub fn stop_trace(&mut self) {
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.flush();
+        }
+    }
+
+    // Logs sound chip writes from now until stop_vgm_log() to a .vgm file
+    pub fn start_vgm_log(&mut self, filename: &str) -> std::io::Result<()> {
+        self.stop_vgm_log()?;
+        let chips = self.mapper.expansion_chips();
+        self.vgm_logger = Some(VgmLogger::new(filename, self.apu.cpu_clock_rate, self.apu.current_cycle, &chips));
+        return Ok(());
+    }
+
+    // Writes out the log, if one was running
+    pub fn stop_vgm_log(&mut self) -> std::io::Result<()> {
+        if let Some(mut logger) = self.vgm_logger.take() {
+            return logger.finish(self.apu.current_cycle);
+        }
+        return Ok(());
+    }
+
+    // Logs every sound register write from now on to writer, timestamped in CPU
+    // cycles. Wrap files in a BufWriter.
+    pub fn start_apu_write_log(&mut self, writer: Box<dyn std::io::Write + Send>, format: ApuLogFormat) {
+        self.stop_apu_write_log();
+        let chips = self.mapper.expansion_chips();
+        self.apu_write_log = Some(ApuWriteLogger::new(writer, format, chips));
+    }
+
+    pub fn stop_apu_write_log(&mut self) {
+        if let Some(mut logger) = self.apu_write_log.take() {
+            logger.finish();
+        }
+    }
+
+    // player is 0 - 3. Players 1 and 2 are the pads in each port; 3 and 4 need a four
+    // player adapter plugged into both, and are ignored otherwise.
+    // Plays a movie from its first frame, connecting the devices it was recorded with.
+    // A movie that starts from power on should be played on a freshly built console;
+    // playback powers it on (or loads the movie's starting savestate) and sets the
+    // first frame's input. Each frame after that gets its input as the one before
+    // ends. The movie stays attached after its last frame, with that frame's input
+    // still held, until stop_movie(). Fails if the movie is for another cartridge.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), Error> {
+        if movie.rom_crc32 != 0 && self.rom_crc32 != 0 && movie.rom_crc32 != self.rom_crc32 {
+            return Err(Error::BadMovie{reason: format!("made with a different cartridge (CRC {:08X}, expected {:08X})",
+                movie.rom_crc32, self.rom_crc32)});
+        }
+        for port in 0 .. 2 {
+            self.ports[port] = movie.devices[port].connect(port);
+        }
+        match movie.start_anchor() {
+            Some(anchor) => self.load_state(&anchor.state)?,
+            None => self.power_on(),
+        }
+        let player = MoviePlayer::new(movie);
+        player.apply_frame(self);
+        self.movie = Some(player);
+        return Ok(());
+    }
+
+    // The overlay to draw on for the frame the PPU is drawing now, blank if nothing
+    // has drawn on this frame yet. It's created the first time this is called.
+    pub fn draw_overlay(&mut self) -> &mut Overlay {
+        self.sync_ppu();
+        let frame = self.ppu.current_frame;
+        let overlay = self.overlay.get_or_insert_with(|| Overlay::new(frame));
+        if overlay.frame != frame {
+            overlay.clear();
+            overlay.frame = frame;
+        }
+        return overlay;
+    }
+
+    // The last overlay drawn; check its frame against the picture it's going over
+    pub fn overlay(&self) -> Option<&Overlay> {
+        return self.overlay.as_ref();
+    }
+
+    pub fn remove_overlay(&mut self) {
+        self.overlay = None;
+    }
+
+    // Follows what every channel plays from here on, as notes; collect them each frame
+    // with piano_roll.take_events(), or all at once from stop_piano_roll()
+    pub fn start_piano_roll(&mut self) {
+        self.piano_roll = Some(PianoRoll::new());
+    }
+
+    pub fn stop_piano_roll(&mut self) -> Option<PianoRoll> {
+        return self.piano_roll.take();
+    }
+
+    // The piano roll's events so far, less any already taken, as a MIDI file; see
+    // midi.rs
+    pub fn piano_roll_midi(&self) -> Option<Vec<u8>> {
+        let piano_roll = self.piano_roll.as_ref()?;
+        let channel_names: Vec<String> = self.apu.all_channels(&*self.mapper).iter()
+            .map(|channel| format!("{} {}", channel.chip(), channel.name())).collect();
+        let tonal: Vec<bool> = piano_roll.channels().iter().map(|channel| channel.tonal).collect();
+        return Some(crate::midi::write(piano_roll.events(), &channel_names, &tonal, self.region.frame_rate()));
+    }
+
+    // Collects every DPCM sample the game plays from here on; see dpcm.rs
+    pub fn start_dpcm_rip(&mut self) {
+        self.dpcm_ripper = Some(DpcmRipper::new());
+    }
+
+    pub fn stop_dpcm_rip(&mut self) -> Option<DpcmRipper> {
+        return self.dpcm_ripper.take();
+    }
+
+    // Plugs device into the expansion port, replacing whatever was there, or unplugs
+    // it with None. Like changing controllers, this changes the savestate size.
+    // Consoles without an expansion port are left with nothing plugged in.
+    pub fn connect_expansion_device(&mut self, device: Option<Box<dyn ExpansionDevice>>) {
+        self.expansion_device = if self.console.expansion_port() {device} else {None};
+        self.apu.expansion_port_input = 0.0;
+    }
+
+    // Starts recording a new movie with these devices plugged in, replacing any movie
+    // attached. From power on, this powers on the console, which should be freshly
+    // built; otherwise the movie starts from a savestate of the console as it is.
+    // Input is recorded as each frame ends. See MoviePlayer for switching between
+    // playback and recording, and truncating, mid movie.
+    pub fn record_movie(&mut self, devices: [InputDevice; 2], from_power_on: bool) {
+        for port in 0 .. 2 {
+            self.ports[port] = devices[port].connect(port);
+        }
+        let mut movie = Movie::new();
+        movie.devices = devices;
+        movie.rom_crc32 = self.rom_crc32;
+        if from_power_on {
+            self.power_on();
+        } else {
+            movie.anchors.push(MovieAnchor{frame: 0, state: self.save_state()});
+        }
+        let mut player = MoviePlayer::new(movie);
+        player.start_recording();
+        self.movie = Some(player);
+    }
+
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        return self.movie.take().map(|player| player.movie);
+    }
+
+    // Resets the console as the next frame begins, rather than wherever it happens to
+    // be now, and records the reset in the movie and input log, so that playback
+    // resets on the same frame
+    pub fn request_reset(&mut self) {
+        self.reset_requested = true;
+    }
+
+    // As request_reset(), for power_cycle(). Takes the place of a reset requested
+    // for the same frame.
+    pub fn request_power_cycle(&mut self) {
+        self.power_cycle_requested = true;
+    }
+
+    fn run_requested_commands(&mut self) {
+        let power = std::mem::take(&mut self.power_cycle_requested);
+        let soft_reset = std::mem::take(&mut self.reset_requested) && !power;
+        if power {
+            self.log_input(InputEvent::PowerCycle);
+            self.power_cycle();
+        } else if soft_reset {
+            self.log_input(InputEvent::SoftReset);
+            self.reset();
+        } else {
+            return;
+        }
+        if let Some(player) = self.movie.as_mut() {
+            player.record_commands(soft_reset, power);
+        }
+    }
+
+    fn end_movie_frame(&mut self) {
+        if let Some(mut player) = self.movie.take() {
+            player.end_frame(self);
+            self.movie = Some(player);
+        }
+    }
+
+    // Logs everything fed to the ports from here on, until stop_input_log()
+    pub fn start_input_log(&mut self) {
+        self.input_log = Some(InputLog::new(self.save_state()));
+    }
+
+    pub fn stop_input_log(&mut self) -> Option<InputLog> {
+        return self.input_log.take();
+    }
+
+    // Loads the log's starting state, then feeds its events back on the cycles they
+    // first arrived on. Input from the frontend still gets through, so stop feeding
+    // it for a faithful replay.
+    pub fn replay_input_log(&mut self, log: InputLog) -> Result<(), Error> {
+        self.load_state(&log.start_state)?;
+        let mut replay = InputReplay::new(log);
+        replay.feed(self);
+        self.input_replay = Some(replay);
+        return Ok(());
+    }
+
+    fn log_input(&mut self, event: InputEvent) {
+        if let Some(log) = self.input_log.as_mut() {
+            log.record(self.master_clock, event);
+        }
+    }
+
+    pub fn set_player_buttons(&mut self, player: usize, buttons: u8) {
+        let buttons = match self.macros.get_mut(player).and_then(|playback| playback.as_mut()) {
+            Some(playback) => {
+                playback.held = buttons;
+                playback.buttons()
+            },
+            None => buttons
+        };
+        self.apply_player_buttons(player, buttons);
+    }
+
+    fn apply_player_buttons(&mut self, player: usize, buttons: u8) {
+        if self.input_log.is_some() && self.player_buttons(player) != buttons {
+            self.log_input(InputEvent::Buttons{player: player, buttons: buttons});
+        }
+        match player {
+            0 | 1 => self.ports[player].set_buttons(buttons),
+            2 | 3 => self.ports[player - 2].set_tap_buttons(buttons),
+            _ => {}
+        }
+    }
+
+    // Plays input_macro into player's buttons starting with the current frame,
+    // replacing any macro already playing there. Buttons the frontend holds are added
+    // to the macro's, and are all that's left once it finishes.
+    pub fn play_macro(&mut self, player: usize, input_macro: InputMacro) {
+        if player >= self.macros.len() {
+            return;
+        }
+        let held = match self.macros[player].take() {
+            Some(playback) => playback.held,
+            None => self.player_buttons(player)
+        };
+        let playback = MacroPlayback::new(input_macro, held);
+        self.apply_player_buttons(player, playback.buttons());
+        self.macros[player] = Some(playback);
+    }
+
+    // Stops player's macro early, leaving just what the frontend holds
+    pub fn stop_macro(&mut self, player: usize) {
+        if let Some(playback) = self.macros.get_mut(player).and_then(|playback| playback.take()) {
+            self.apply_player_buttons(player, playback.held);
+        }
+    }
+
+    pub fn macro_playing(&self, player: usize) -> bool {
+        return self.macros.get(player).map_or(false, |playback| playback.is_some());
+    }
+
+    fn advance_macros(&mut self) {
+        for player in 0 .. self.macros.len() {
+            if let Some(mut playback) = self.macros[player].take() {
+                playback.end_frame();
+                self.apply_player_buttons(player, playback.buttons());
+                if !playback.finished() {
+                    self.macros[player] = Some(playback);
+                }
+            }
+        }
+    }
+
+    pub fn player_buttons(&self, player: usize) -> u8 {
+        return match player {
+            0 | 1 => self.ports[player].buttons(),
+            2 | 3 => self.ports[player - 2].tap_buttons(),
+            _ => 0
+        };
+    }
+
+    // For paddles: 0.0 to 1.0, left to right. Ignored by devices without a dial.
+    pub fn set_analog(&mut self, port: usize, position: f32) {
+        if port < self.ports.len() {
+            self.log_input(InputEvent::Analog{port: port, position: position});
+            self.ports[port].set_analog(position);
+        }
+    }
+
+    // Swipes a barcode (EAN-13 or EAN-8 digits) through whichever reader is connected:
+    // the Datach's, or a Barcode Battler. False if there's no reader, or the barcode
+    // isn't valid.
+    pub fn insert_barcode(&mut self, barcode: &str) -> bool {
+        self.log_input(InputEvent::Barcode(barcode.to_string()));
+        if self.mapper.insert_barcode(barcode) {
+            return true;
+        }
+        for port in self.ports.iter_mut() {
+            if port.insert_barcode(barcode) {
+                return true;
+            }
+        }
+        return false;
+    }
+
+    // Does nothing in deterministic mode, where the alignment is fixed
     pub fn nudge_ppu_alignment(&mut self) {
+        if self.deterministic {
+            return;
+        }
         // Give the PPU a swift kick:
         self.ppu.clock(&mut *self.mapper);
         self.event_tracker.current_scanline = self.ppu.current_scanline;
@@ -190,6 +1605,91 @@ impl NesState {
         return self.mapper.get_sram();
     }
 
+    // Whether battery backed memory has changed since clear_sram_dirty(). Frontends
+    // that save on their own schedule can poll this rather than writing SRAM out
+    // every frame.
+    pub fn sram_dirty(&self) -> bool {
+        let pending = match &self.sram_flush {
+            Some(flush) => flush.pending,
+            None => false,
+        };
+        return self.mapper.sram_dirty() || pending;
+    }
+
+    // Seconds since the Unix epoch on the cartridge's real time clock, if it has one
+    pub fn rtc_seconds(&self) -> Option<u64> {
+        return self.mapper.rtc().map(|rtc| rtc.seconds());
+    }
+
+    // Sets the cartridge's real time clock, returning false if there isn't one. The
+    // clock then runs on emulated time, so this is also how a frontend applies its own
+    // notion of the time, ie for netplay or a movie.
+    pub fn set_rtc_seconds(&mut self, seconds: u64) -> bool {
+        return match self.mapper.rtc_mut() {
+            Some(rtc) => {rtc.set_seconds(seconds); true},
+            None => false
+        };
+    }
+
+    // Brings the clock's settings in line with the console's: its rate with the region,
+    // and the wall clock left alone in deterministic mode, where the clock starts at 0.
+    // The builder calls this; call it again after changing region or deterministic.
+    pub fn configure_rtc(&mut self) {
+        let cpu_clock_rate = self.region.cpu_clock_rate();
+        let wall_clock = !self.deterministic;
+        if let Some(rtc) = self.mapper.rtc_mut() {
+            rtc.set_cpu_clock_rate(cpu_clock_rate);
+            rtc.wall_clock = wall_clock;
+            if wall_clock {
+                rtc.start_from_wall_clock();
+            }
+        }
+    }
+
+    // Call after writing sram() out
+    pub fn clear_sram_dirty(&mut self) {
+        self.mapper.clear_sram_dirty();
+        if let Some(flush) = &mut self.sram_flush {
+            flush.pending = false;
+            flush.idle = 0;
+        }
+    }
+
+    // Runs hook with sram() once battery backed memory has gone idle_frames frames
+    // without changing since it last changed, so a save lands on disk shortly after
+    // the game finishes writing it rather than on every frame of a long write.
+    pub fn set_sram_flush_hook(&mut self, idle_frames: u32, hook: SramFlushHook) {
+        self.sram_flush = Some(SramFlush {
+            idle_frames: idle_frames,
+            hook: hook,
+            pending: self.mapper.sram_dirty(),
+            idle: 0,
+        });
+    }
+
+    pub fn clear_sram_flush_hook(&mut self) {
+        self.sram_flush = None;
+    }
+
+    fn check_sram_flush(&mut self) {
+        let mut flush = match self.sram_flush.take() {
+            Some(flush) => flush,
+            None => return
+        };
+        if self.mapper.sram_dirty() {
+            self.mapper.clear_sram_dirty();
+            flush.pending = true;
+            flush.idle = 0;
+        } else if flush.pending {
+            flush.idle += 1;
+            if flush.idle >= flush.idle_frames {
+                (flush.hook)(&self.mapper.get_sram());
+                flush.pending = false;
+            }
+        }
+        self.sram_flush = Some(flush);
+    }
+
     pub fn set_sram(&mut self, sram_data: Vec<u8>) {
         if sram_data.len() != self.mapper.get_sram().len() {
             println!("SRAM size mismatch, expected {} bytes but file is {} bytes!", self.mapper.get_sram().len(), sram_data.len());
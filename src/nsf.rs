@@ -174,7 +174,9 @@ impl NsfHeader {
 pub enum NsfError {
     InvalidHeader,
     Unimplemented,
-    ReadError{reason: String}
+    ReadError{reason: String},
+    // The file ended before all of the data the header describes
+    Truncated{reason: String}
 }
 
 impl Error for NsfError {}
@@ -184,13 +186,17 @@ impl fmt::Display for NsfError  {
         match self {
             NsfError::InvalidHeader => {write!(f, "Invalid NSF Header")},
             NsfError::Unimplemented => {write!(f, "Unimplemented (Lazy programmers!!1)")},
-            NsfError::ReadError{reason} => {write!(f, "Error reading cartridge: {}", reason)}
+            NsfError::ReadError{reason} => {write!(f, "Error reading cartridge: {}", reason)},
+            NsfError::Truncated{reason} => {write!(f, "Cartridge data is truncated: {}", reason)}
         }
     }
 }
 
 impl From<std::io::Error> for NsfError {
     fn from(error: std::io::Error) -> Self {
+        if error.kind() == std::io::ErrorKind::UnexpectedEof {
+            return NsfError::Truncated{reason: error.to_string()};
+        }
         return NsfError::ReadError{reason: error.to_string()};
     }
 }
@@ -0,0 +1,56 @@
+// SSE2 version of the inner loop of PpuState::render_ntsc(), used when the "simd"
+// feature is enabled on x86_64. SSE2 is part of the x86_64 baseline, so no runtime
+// detection is needed. Sums are added in a different order than the scalar path, so
+// the odd channel can come out one step different where a sum lands right on a
+// boundary.
+
+use std::arch::x86_64::*;
+
+// Each window's 12 terms as three vectors of partial sums
+#[inline(always)]
+unsafe fn window_partials(terms: &[f32], center: usize) -> __m128 {
+    let window = &terms[center - 6 .. center + 6];
+    let a = _mm_loadu_ps(window.as_ptr());
+    let b = _mm_loadu_ps(window.as_ptr().add(4));
+    let c = _mm_loadu_ps(window.as_ptr().add(8));
+    return _mm_add_ps(_mm_add_ps(a, b), c);
+}
+
+// Finishes four windows at once by transposing their partial sums, so no horizontal
+// adds are needed: lane n of the result is the whole of window n
+#[inline(always)]
+unsafe fn window_sums(terms: &[f32], centers: [usize; 4]) -> __m128 {
+    let v0 = window_partials(terms, centers[0]);
+    let v1 = window_partials(terms, centers[1]);
+    let v2 = window_partials(terms, centers[2]);
+    let v3 = window_partials(terms, centers[3]);
+    let sum01 = _mm_add_ps(_mm_unpacklo_ps(v0, v1), _mm_unpackhi_ps(v0, v1));
+    let sum23 = _mm_add_ps(_mm_unpacklo_ps(v2, v3), _mm_unpackhi_ps(v2, v3));
+    return _mm_add_ps(_mm_movelh_ps(sum01, sum23), _mm_movehl_ps(sum23, sum01));
+}
+
+// ppu::clamp() of 255.95 * v: negative values and NaN come out as 0, like the cast
+#[inline(always)]
+unsafe fn channel(v: __m128) -> __m128i {
+    let scaled = _mm_mul_ps(_mm_set1_ps(255.95), v);
+    let clamped = _mm_min_ps(_mm_max_ps(scaled, _mm_setzero_ps()), _mm_set1_ps(255.0));
+    return _mm_cvttps_epi32(clamped);
+}
+
+// Decodes four output pixels whose windows lie entirely within the scanline; see
+// ppu::yiq_to_argb() for the color conversion
+pub fn decode_four(y_terms: &[f32], i_terms: &[f32], q_terms: &[f32], centers: [usize; 4], output: &mut [u32]) {
+    let output = &mut output[.. 4];
+    unsafe {
+        let y = window_sums(y_terms, centers);
+        let i = window_sums(i_terms, centers);
+        let q = window_sums(q_terms, centers);
+        let r = _mm_add_ps(_mm_add_ps(y, _mm_mul_ps(_mm_set1_ps(0.946882), i)), _mm_mul_ps(_mm_set1_ps(0.623557), q));
+        let g = _mm_sub_ps(_mm_add_ps(y, _mm_mul_ps(_mm_set1_ps(-0.274788), i)), _mm_mul_ps(_mm_set1_ps(0.635691), q));
+        let b = _mm_add_ps(_mm_add_ps(y, _mm_mul_ps(_mm_set1_ps(-1.108545), i)), _mm_mul_ps(_mm_set1_ps(1.709007), q));
+        let argb = _mm_or_si128(
+            _mm_or_si128(_mm_set1_epi32(0xFF000000u32 as i32), _mm_slli_epi32(channel(r), 16)),
+            _mm_or_si128(_mm_slli_epi32(channel(g), 8), channel(b)));
+        _mm_storeu_si128(output.as_mut_ptr() as *mut __m128i, argb);
+    }
+}
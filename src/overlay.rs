@@ -0,0 +1,133 @@
+// A layer of pixels, boxes and text drawn over the picture, for Lua scripts and
+// debuggers to annotate the game with. Frontends composite it over the frame it was
+// drawn for, which is `frame`: NesState::draw_overlay() starts a blank one whenever
+// drawing moves on to a new frame, so what was drawn for the last frame stays
+// readable until the next one draws something.
+
+pub const OVERLAY_WIDTH: usize = 256;
+pub const OVERLAY_HEIGHT: usize = 240;
+
+// Text is drawn in 3x5 glyphs on a 4x6 grid
+pub const GLYPH_WIDTH: i64 = 4;
+pub const GLYPH_HEIGHT: i64 = 6;
+
+// ' ' through '_', one row per byte, top first, with bit 2 the leftmost pixel.
+// Lowercase letters are drawn as uppercase, and anything else as '?'.
+const FONT: [[u8; 5]; 64] = [
+    [0, 0, 0, 0, 0], [2, 2, 2, 0, 2], [5, 5, 0, 0, 0], [5, 7, 5, 7, 5], // space ! " #
+    [3, 6, 7, 3, 6], [5, 1, 2, 4, 5], [2, 5, 2, 5, 3], [2, 2, 0, 0, 0], // $ % & '
+    [1, 2, 2, 2, 1], [4, 2, 2, 2, 4], [0, 5, 2, 5, 0], [0, 2, 7, 2, 0], // ( ) * +
+    [0, 0, 0, 2, 4], [0, 0, 7, 0, 0], [0, 0, 0, 0, 2], [1, 1, 2, 4, 4], // , - . /
+    [7, 5, 5, 5, 7], [2, 6, 2, 2, 7], [7, 1, 7, 4, 7], [7, 1, 3, 1, 7], // 0 1 2 3
+    [5, 5, 7, 1, 1], [7, 4, 7, 1, 7], [7, 4, 7, 5, 7], [7, 1, 1, 2, 2], // 4 5 6 7
+    [7, 5, 7, 5, 7], [7, 5, 7, 1, 7], [0, 2, 0, 2, 0], [0, 2, 0, 2, 4], // 8 9 : ;
+    [1, 2, 4, 2, 1], [0, 7, 0, 7, 0], [4, 2, 1, 2, 4], [7, 1, 3, 0, 2], // < = > ?
+    [2, 5, 7, 4, 3], [2, 5, 7, 5, 5], [6, 5, 6, 5, 6], [3, 4, 4, 4, 3], // @ A B C
+    [6, 5, 5, 5, 6], [7, 4, 6, 4, 7], [7, 4, 6, 4, 4], [3, 4, 5, 5, 3], // D E F G
+    [5, 5, 7, 5, 5], [7, 2, 2, 2, 7], [1, 1, 1, 5, 2], [5, 5, 6, 5, 5], // H I J K
+    [4, 4, 4, 4, 7], [5, 7, 7, 5, 5], [6, 5, 5, 5, 5], [2, 5, 5, 5, 2], // L M N O
+    [6, 5, 6, 4, 4], [2, 5, 5, 6, 3], [6, 5, 6, 5, 5], [3, 4, 2, 1, 6], // P Q R S
+    [7, 2, 2, 2, 2], [5, 5, 5, 5, 7], [5, 5, 5, 5, 2], [5, 5, 7, 7, 5], // T U V W
+    [5, 5, 2, 5, 5], [5, 5, 2, 2, 2], [7, 1, 2, 4, 7], [6, 4, 4, 4, 6], // X Y Z [
+    [4, 4, 2, 1, 1], [3, 1, 1, 1, 3], [2, 5, 0, 0, 0], [0, 0, 0, 0, 7], // \ ] ^ _
+];
+
+fn glyph(c: char) -> &'static [u8; 5] {
+    let c = c.to_ascii_uppercase();
+    return match c {
+        ' ' ..= '_' => &FONT[c as usize - 0x20],
+        _ => &FONT['?' as usize - 0x20],
+    };
+}
+
+pub struct Overlay {
+    // 0xAARRGGBB, where an alpha of 0 is fully transparent
+    pub pixels: Vec<u32>,
+    // PpuState::current_frame of the picture this goes over
+    pub frame: u32,
+}
+
+impl Overlay {
+    pub fn new(frame: u32) -> Overlay {
+        return Overlay {
+            pixels: vec![0u32; OVERLAY_WIDTH * OVERLAY_HEIGHT],
+            frame: frame,
+        };
+    }
+
+    pub fn clear(&mut self) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = 0;
+        }
+    }
+
+    // Anything off screen, or fully transparent, is left out
+    pub fn pixel(&mut self, x: i64, y: i64, color: u32) {
+        if color >> 24 == 0 || x < 0 || y < 0 || x >= OVERLAY_WIDTH as i64 || y >= OVERLAY_HEIGHT as i64 {
+            return;
+        }
+        self.pixels[y as usize * OVERLAY_WIDTH + x as usize] = color;
+    }
+
+    pub fn line(&mut self, x1: i64, y1: i64, x2: i64, y2: i64, color: u32) {
+        let dx = (x2 - x1).abs();
+        let dy = -(y2 - y1).abs();
+        let sx = if x1 < x2 {1} else {-1};
+        let sy = if y1 < y2 {1} else {-1};
+        let (mut x, mut y) = (x1, y1);
+        let mut error = dx + dy;
+        loop {
+            self.pixel(x, y, color);
+            if x == x2 && y == y2 {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += sx;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    // Corners are inclusive, in either order
+    pub fn draw_box(&mut self, x1: i64, y1: i64, x2: i64, y2: i64, fill: u32, outline: u32) {
+        let (left, right) = (x1.min(x2), x1.max(x2));
+        let (top, bottom) = (y1.min(y2), y1.max(y2));
+        for y in top + 1 .. bottom {
+            for x in left + 1 .. right {
+                self.pixel(x, y, fill);
+            }
+        }
+        self.line(left, top, right, top, outline);
+        self.line(left, bottom, right, bottom, outline);
+        self.line(left, top, left, bottom, outline);
+        self.line(right, top, right, bottom, outline);
+    }
+
+    // Draws text with its top left corner at x, y, on a background of background
+    // (which may be transparent) one pixel wider all around. Newlines start another
+    // line under the first.
+    pub fn text(&mut self, x: i64, y: i64, text: &str, color: u32, background: u32) {
+        for (row, line) in text.lines().enumerate() {
+            let top = y + row as i64 * GLYPH_HEIGHT;
+            let width = line.chars().count() as i64 * GLYPH_WIDTH;
+            if width > 0 {
+                self.draw_box(x - 1, top - 1, x + width - 1, top + GLYPH_HEIGHT - 1, background, background);
+            }
+            for (column, c) in line.chars().enumerate() {
+                let left = x + column as i64 * GLYPH_WIDTH;
+                for (glyph_y, bits) in glyph(c).iter().enumerate() {
+                    for glyph_x in 0 .. 3 {
+                        if bits & (0b100 >> glyph_x) != 0 {
+                            self.pixel(left + glyph_x, top + glyph_y as i64, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -513,3 +513,37 @@ pub const NTSC_PAL: [u8; 64 * 8 * 3] = [
 0x5e, 0x5e, 0x5e,
 0x00, 0x00, 0x00,
 0x00, 0x00, 0x00];
+
+const fn xrgb_palette() -> [u32; 512] {
+    let mut palette = [0u32; 512];
+    let mut i = 0;
+    while i < 512 {
+        palette[i] = ((NTSC_PAL[i * 3] as u32) << 16) | ((NTSC_PAL[i * 3 + 1] as u32) << 8) | (NTSC_PAL[i * 3 + 2] as u32);
+        i += 1;
+    }
+    return palette;
+}
+
+// NTSC_PAL as 0x00RRGGBB, one entry per color
+pub const NTSC_PAL_XRGB: [u32; 512] = xrgb_palette();
+
+// Converts one entry of PpuState::screen (palette index plus emphasis bits) to 0x00RRGGBB
+pub fn nes_color_to_xrgb(color: u16) -> u32 {
+    return NTSC_PAL_XRGB[(color & 0x1FF) as usize];
+}
+
+pub fn render_xrgb(screen: &[u16], output: &mut [u32]) {
+    for (pixel, &color) in output.iter_mut().zip(screen.iter()) {
+        *pixel = nes_color_to_xrgb(color);
+    }
+}
+
+// Three bytes per pixel, red first, as PNG encoders and ffmpeg's rgb24 expect
+pub fn render_rgb24(screen: &[u16], output: &mut [u8]) {
+    for (pixel, &color) in output.chunks_exact_mut(3).zip(screen.iter()) {
+        let xrgb = nes_color_to_xrgb(color);
+        pixel[0] = (xrgb >> 16) as u8;
+        pixel[1] = (xrgb >> 8) as u8;
+        pixel[2] = xrgb as u8;
+    }
+}
@@ -4,6 +4,9 @@
 
 use crate::{mmc::mapper::*, save_load::*};
 
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
 #[derive(Copy, Clone)]
 pub struct SpriteLatch {
     tile_index: u8,
@@ -95,12 +98,233 @@ impl SpriteLatch {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PpuRegisterWrite {
+    // 0 - 7, for $2000 - $2007
+    pub register: u8,
+    pub value: u8,
+    pub scanline: u16,
+    pub dot: u16,
+}
+
+// Every write to the PPU's registers over the last two frames, in the order they
+// happened, for the debug viewer: a write to $2005 or $2006 partway down the screen
+// is a scroll split. Writes past the capacity are counted but not kept. The buffers
+// are sized up front, so recording doesn't allocate.
+pub struct PpuWriteLog {
+    this_frame: Vec<PpuRegisterWrite>,
+    last_frame: Vec<PpuRegisterWrite>,
+    capacity: usize,
+    pub dropped_this_frame: usize,
+    pub dropped_last_frame: usize,
+}
+
+impl PpuWriteLog {
+    pub fn new(capacity: usize) -> PpuWriteLog {
+        return PpuWriteLog {
+            this_frame: Vec::with_capacity(capacity),
+            last_frame: Vec::with_capacity(capacity),
+            capacity: capacity,
+            dropped_this_frame: 0,
+            dropped_last_frame: 0,
+        };
+    }
+
+    pub fn capacity(&self) -> usize {
+        return self.capacity;
+    }
+
+    // 0 turns logging off. Clears both frames.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        *self = PpuWriteLog::new(capacity);
+    }
+
+    pub fn record(&mut self, write: PpuRegisterWrite) {
+        if self.this_frame.len() < self.capacity {
+            self.this_frame.push(write);
+        } else {
+            self.dropped_this_frame += 1;
+        }
+    }
+
+    pub fn end_frame(&mut self) {
+        std::mem::swap(&mut self.this_frame, &mut self.last_frame);
+        self.this_frame.clear();
+        self.dropped_last_frame = self.dropped_this_frame;
+        self.dropped_this_frame = 0;
+    }
+
+    pub fn writes_this_frame(&self) -> &[PpuRegisterWrite] {
+        return &self.this_frame;
+    }
+
+    pub fn writes_last_frame(&self) -> &[PpuRegisterWrite] {
+        return &self.last_frame;
+    }
+}
+
+// Where the background was scrolled to as a visible scanline began: the pixel of
+// nametable (0 - 3, as in $2000) drawn at the left edge. A y of 240 - 255 means the
+// game scrolled into the attribute table, which the PPU draws as tiles.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ScanlineScroll {
+    pub nametable: u8,
+    pub x: u8,
+    pub y: u8,
+}
+
+impl ScanlineScroll {
+    // The same point within all four nametables laid out two by two, as
+    // NesState::nametable_snapshot() and chr_export::nametables() have them: 0 - 511
+    // and 0 - 479, when y is in the nametable proper
+    pub fn map_x(&self) -> u16 {
+        return (self.nametable as u16 & 0b01) * 256 + self.x as u16;
+    }
+
+    pub fn map_y(&self) -> u16 {
+        return (self.nametable as u16 >> 1) * 240 + self.y as u16;
+    }
+}
+
+// The scroll of every visible scanline over the last two frames, however the game
+// set it: $2005 and $2006 writes, splits partway down, or neither, for games that just
+// let the PPU carry on. Map rippers stitch nametable_snapshot()s together with these.
+// Scanlines drawn with rendering off are None.
+pub struct ScrollLog {
+    this_frame: Vec<Option<ScanlineScroll>>,
+    last_frame: Vec<Option<ScanlineScroll>>,
+}
+
+impl ScrollLog {
+    pub fn new() -> ScrollLog {
+        return ScrollLog {
+            this_frame: vec![None; 240],
+            last_frame: vec![None; 240],
+        };
+    }
+
+    pub fn record(&mut self, scanline: u16, scroll: ScanlineScroll) {
+        if let Some(entry) = self.this_frame.get_mut(scanline as usize) {
+            *entry = Some(scroll);
+        }
+    }
+
+    pub fn end_frame(&mut self) {
+        std::mem::swap(&mut self.this_frame, &mut self.last_frame);
+        for entry in self.this_frame.iter_mut() {
+            *entry = None;
+        }
+    }
+
+    // Filled in up to the scanline being drawn
+    pub fn this_frame(&self) -> &[Option<ScanlineScroll>] {
+        return &self.this_frame;
+    }
+
+    pub fn last_frame(&self) -> &[Option<ScanlineScroll>] {
+        return &self.last_frame;
+    }
+}
+
+// Enough for heavy raster effects; see PpuWriteLog::set_capacity()
+pub const DEFAULT_PPU_WRITE_LOG_CAPACITY: usize = 1024;
+
+const FETCH_PAGE_SIZE: usize = 0x400;
+// Pattern tables and nametables, $0000 - $2FFF
+const FETCH_PAGES: usize = 12;
+
+// Reading the clock around every dot costs more than drawing it, so frame timing only
+// times one dot in this many and scales the result up
+const PIXEL_TIMING_INTERVAL: u16 = 16;
+
+#[derive(Copy, Clone, PartialEq)]
+enum FetchPage {
+    Unknown,
+    Cached,
+    // Has open bus in it somewhere, so it's left to the mapper
+    Uncached,
+}
+
+// A copy of what the PPU currently sees at $0000 - $2FFF, so that the fetches made
+// on almost every dot of a visible scanline are array reads rather than calls into
+// the mapper. Only used with mappers that say their PPU reads have no side effects
+// (see Mapper::ppu_reads_cacheable()); pages are filled on first use and thrown
+// away whenever the cartridge sees a CPU write, which is the only way those mappers
+// switch banks. PPU writes go through to the mapper and throw the copy away too.
+// Anything that writes to the mapper directly rather than over the bus should call
+// invalidate() afterwards.
+pub struct PpuFetchCache {
+    // None until the mapper has been asked
+    cacheable: Option<bool>,
+    pages: [FetchPage; FETCH_PAGES],
+    data: Vec<u8>,
+}
+
+impl PpuFetchCache {
+    pub fn new() -> PpuFetchCache {
+        return PpuFetchCache {
+            cacheable: None,
+            pages: [FetchPage::Unknown; FETCH_PAGES],
+            data: vec![0u8; FETCH_PAGE_SIZE * FETCH_PAGES],
+        };
+    }
+
+    // Banks may have changed
+    pub fn invalidate(&mut self) {
+        if self.cacheable != Some(false) {
+            self.pages = [FetchPage::Unknown; FETCH_PAGES];
+        }
+    }
+
+    // A different cartridge, or the same one with a different state loaded
+    pub fn mapper_replaced(&mut self) {
+        self.cacheable = None;
+        self.pages = [FetchPage::Unknown; FETCH_PAGES];
+    }
+
+    fn fill(&mut self, mapper: &dyn Mapper, page: usize) {
+        let start = page * FETCH_PAGE_SIZE;
+        for address in start .. start + FETCH_PAGE_SIZE {
+            match mapper.debug_read_ppu(address as u16) {
+                Some(byte) => self.data[address] = byte,
+                None => {
+                    self.pages[page] = FetchPage::Uncached;
+                    return;
+                }
+            }
+        }
+        self.pages[page] = FetchPage::Cached;
+    }
+
+    pub fn read(&mut self, mapper: &mut dyn Mapper, address: u16) -> Option<u8> {
+        let page = address as usize / FETCH_PAGE_SIZE;
+        if page >= FETCH_PAGES {
+            return mapper.read_ppu(address);
+        }
+        if self.cacheable.is_none() {
+            self.cacheable = Some(mapper.ppu_reads_cacheable());
+        }
+        if self.cacheable == Some(false) {
+            return mapper.read_ppu(address);
+        }
+        if self.pages[page] == FetchPage::Unknown {
+            self.fill(mapper, page);
+        }
+        return match self.pages[page] {
+            FetchPage::Cached => Some(self.data[address as usize]),
+            _ => mapper.read_ppu(address)
+        };
+    }
+}
+
 pub struct PpuState {
     // PPU Memory (incl. cart CHR ROM for now)
     pub internal_vram: Vec<u8>,
     pub oam: Vec<u8>,
     pub secondary_oam: Vec<SpriteLatch>,
     pub secondary_oam_index: usize,
+    // Sprites past the 8th on a scanline, only collected when the sprite limit is off
+    pub extra_sprites: Vec<SpriteLatch>,
     pub palette: Vec<u8>,
 
     // Memory Mapped Registers
@@ -154,9 +378,26 @@ pub struct PpuState {
 
     pub sprite_zero_on_scanline: bool,
 
+    // Enhancement, not accuracy: when false, every sprite on a scanline is drawn
+    // instead of only the first 8, which removes most flicker. Sprite overflow is
+    // still reported as normal.
+    pub sprite_limit: bool,
+
+    // Frame skip: screen isn't drawn while set. Everything the CPU or the cartridge can
+    // see (fetches, sprite evaluation, sprite zero hits, overflow) carries on as
+    // normal, so the game runs exactly the same, just faster.
+    pub skip_video: bool,
+
+    // For frame timing (see NesState::set_frame_timing()): while set, an estimate of
+    // the time spent on pixels accumulates in pixel_time
+    pub time_pixels: bool,
+    pub pixel_time: Duration,
+
     // Debug Viewer
-    pub recent_reads: Vec<u16>,
-    pub recent_writes: Vec<u16>,
+    pub register_writes: PpuWriteLog,
+    pub scroll_log: ScrollLog,
+
+    pub fetch_cache: PpuFetchCache,
 }
 
 fn debug_default_palette() -> Vec<u8> {
@@ -185,6 +426,7 @@ impl PpuState {
             oam: vec!(0u8; 0x100),
             secondary_oam: vec!(SpriteLatch::new(); 8),
             secondary_oam_index: 0,
+            extra_sprites: Vec::new(),
             palette: debug_default_palette(),
             current_frame: 0,
             current_scanline: 0,
@@ -224,10 +466,16 @@ impl PpuState {
             palette_latch: 0,
             attribute_byte: 0,
             sprite_zero_on_scanline: false,
+            sprite_limit: true,
+            skip_video: false,
+            time_pixels: false,
+            pixel_time: Duration::ZERO,
 
             // Debug
-            recent_reads: Vec::new(),
-            recent_writes: Vec::new(),
+            register_writes: PpuWriteLog::new(DEFAULT_PPU_WRITE_LOG_CAPACITY),
+            scroll_log: ScrollLog::new(),
+
+            fetch_cache: PpuFetchCache::new(),
        };
     }
 
@@ -280,7 +528,7 @@ impl PpuState {
         match masked_address {
             0x0000 ..= 0x3EFF => {
                 //println!("PPU: Read from 0x{:04X}, dot {} of scanline {}", masked_address, self.current_scanline_cycle, self.current_scanline);
-                self.open_bus = match mapper.read_ppu(masked_address) {
+                self.open_bus = match self.fetch_cache.read(mapper, masked_address) {
                     Some(byte) => byte,
                     None => self.open_bus
                 };
@@ -300,10 +548,12 @@ impl PpuState {
 
     pub fn write_byte(&mut self, mapper: &mut dyn Mapper, address: u16, data: u8) {
         let masked_address = address & 0x3FFF;
-        self.recent_writes.insert(0, masked_address);
-        self.recent_writes.truncate(20);
         match masked_address {
-            0x0000 ..= 0x3EFF => mapper.write_ppu(masked_address, data),
+            0x0000 ..= 0x3EFF => {
+                mapper.write_ppu(masked_address, data);
+                // Mirroring means a write can show up at more than one address
+                self.fetch_cache.invalidate();
+            },
             0x3F00 ..= 0x3FFF => {
                 // palette data is 6-bits, so mask off the upper two:
                 let palette_entry = data & 0b0011_1111;
@@ -324,6 +574,7 @@ impl PpuState {
             self.secondary_oam[i].active = false;
         }
         self.secondary_oam_index = 0;
+        self.extra_sprites.clear();
     }
 
     fn evaluate_sprites(&mut self) {
@@ -354,11 +605,43 @@ impl PpuState {
                     }
                 } else {
                     self.status = self.status | 0x20; // bit 5 = sprite overflow this frame
+                    if !self.sprite_limit {
+                        let mut sprite = SpriteLatch::new();
+                        sprite.y_pos =      self.oam[i * 4 + 0];
+                        sprite.tile_index = self.oam[i * 4 + 1];
+                        sprite.attributes = self.oam[i * 4 + 2];
+                        sprite.x_counter  = self.oam[i * 4 + 3];
+                        self.extra_sprites.push(sprite);
+                    }
                 }
             }
         }
     }
 
+    // What the reset button does to the PPU on a front loader NES: PPUCTRL, PPUMASK,
+    // the scroll, the write toggle and the read buffer are cleared, while PPUSTATUS,
+    // OAMADDR, the VRAM address and all of memory are left as they were. (The
+    // Famicom's PPU isn't wired to the reset button at all, and the few thousand
+    // cycles after reset during which the PPU ignores register writes aren't modelled.)
+    pub fn reset(&mut self) {
+        self.control = 0;
+        self.mask = 0;
+        self.temporary_vram_address = 0;
+        self.fine_x = 0;
+        self.write_toggle = false;
+        self.read_buffer = 0;
+    }
+
+    // Called by the CPU as it writes $2000 - $2007
+    pub fn log_register_write(&mut self, register: u8, value: u8) {
+        self.register_writes.record(PpuRegisterWrite {
+            register: register,
+            value: value,
+            scanline: self.current_scanline,
+            dot: self.current_scanline_cycle,
+        });
+    }
+
     pub fn rendering_enabled(&self) -> bool {
         return (self.mask & 0b0001_1000) != 0;
     }
@@ -393,13 +676,37 @@ impl PpuState {
         self.screen[index] = pixel_color;
     }
 
-    fn draw_pixel(&mut self, mapper: &mut dyn Mapper) {
-        // Output a pixel based on the current background shifters
+    // The background's color within its palette at the current dot, from the shifters
+    fn background_palette_index(&self) -> u16 {
         let bg_x_bit = 0b1000_0000_0000_0000 >> self.fine_x;
         let bg_x_shift = 15 - self.fine_x;
-        let mut bg_palette_index = 
+        return
             ((self.tile_shift_high & bg_x_bit) >> (bg_x_shift - 1)) | 
             ((self.tile_shift_low & bg_x_bit) >> bg_x_shift);
+    }
+
+    // All of draw_pixel() that matters when nothing is being drawn
+    fn detect_sprite_zero_hit(&mut self) {
+        if !self.sprite_zero_on_scanline || self.secondary_oam_index == 0 {
+            return;
+        }
+        let px = self.current_scanline_cycle - 1;
+        let background_visible = self.mask & 0b0000_1000 != 0 && ((self.mask & 0b0000_0010 != 0) || px >= 8);
+        let sprites_visible = self.mask & 0b0001_0000 != 0 && ((self.mask & 0b0000_0100 != 0) || px >= 8);
+        let sprite_zero = &self.secondary_oam[0];
+        if background_visible && sprites_visible && sprite_zero.active && sprite_zero.palette_index() != 0 &&
+            self.background_palette_index() != 0 {
+            self.status = self.status | 0x40;
+        }
+    }
+
+    fn draw_pixel(&mut self, mapper: &mut dyn Mapper) {
+        if self.skip_video {
+            self.detect_sprite_zero_hit();
+            return;
+        }
+        // Output a pixel based on the current background shifters
+        let mut bg_palette_index = self.background_palette_index();
 
         let attr_x_bit = 0b1000_0000 >> self.fine_x;
         let attr_x_shift = 7 - self.fine_x;
@@ -425,20 +732,29 @@ impl PpuState {
         // If sprites are enabled
         if self.mask & 0b0001_0000 != 0 && ((self.mask & 0b0000_0100 != 0) || px >= 8) {
             // Find the lowest active sprite with an opaque pixel
+            let mut opaque_sprite: Option<SpriteLatch> = None;
             for sprite_index in 0 .. self.secondary_oam_index {
                 if self.secondary_oam[sprite_index].active && self.secondary_oam[sprite_index].palette_index() != 0 {
                     if self.sprite_zero_on_scanline && sprite_index == 0 && bg_palette_index != 0 {
                         // Sprite zero hit!
                         self.status = self.status | 0x40;
                     }
-                    if bg_palette_index == 0 || !self.secondary_oam[sprite_index].bg_priority() {
-                        let sprite_palette_number = self.secondary_oam[sprite_index].palette() as u16;
-                        let sprite_palette_index = self.secondary_oam[sprite_index].palette_index() as u16;
-                        pixel_color = self.read_byte(mapper, (sprite_palette_number << 2) + sprite_palette_index + 0x3F10);
-                    }
+                    opaque_sprite = Some(self.secondary_oam[sprite_index]);
                     break;
                 }
             }
+            if opaque_sprite.is_none() {
+                opaque_sprite = self.extra_sprites.iter()
+                    .find(|sprite| sprite.active && sprite.palette_index() != 0)
+                    .map(|sprite| *sprite);
+            }
+            if let Some(sprite) = opaque_sprite {
+                if bg_palette_index == 0 || !sprite.bg_priority() {
+                    let sprite_palette_number = sprite.palette() as u16;
+                    let sprite_palette_index = sprite.palette_index() as u16;
+                    pixel_color = self.read_byte(mapper, (sprite_palette_number << 2) + sprite_palette_index + 0x3F10);
+                }
+            }
         }
 
         self.plot_pixel(px, py, pixel_color);
@@ -480,6 +796,20 @@ impl PpuState {
         self.current_vram_address |= (fine_y & 0b111) << 12;
     }
 
+    // Called on dot 1, before anything moves v along. It has already been moved on
+    // two tiles for the prefetch at the end of the scanline before.
+    fn record_scroll(&mut self) {
+        let v = self.current_vram_address;
+        let column = (((v >> 10) & 0b1) << 5) | (v & 0b11111);
+        let column = column.wrapping_sub(2) & 0b11_1111;
+        let coarse_y = (v >> 5) & 0b11111;
+        self.scroll_log.record(self.current_scanline, ScanlineScroll {
+            nametable: ((v >> 10) & 0b10) as u8 | (column >> 5) as u8,
+            x: ((column & 0b11111) * 8) as u8 + self.fine_x,
+            y: (coarse_y * 8 + self.fine_y()) as u8,
+        });
+    }
+
     fn access_bg_tile_early(&mut self, mapper: &mut dyn Mapper) {
         // "fetch" the first byte of CHR tile 0 early, and throw it away
         // This simulates an oddity with the address bus
@@ -555,45 +885,60 @@ impl PpuState {
         }
         if sub_cycle == 4 || sub_cycle == 6 {
             let sprite_index: usize = ((self.current_scanline_cycle - 257) / 8) as usize;
-            let mut tile_index = self.secondary_oam[sprite_index].tile_index;
+            let tile_address = self.sprite_tile_address(&self.secondary_oam[sprite_index]);
 
-            let mut sprite_size: u16 = 8;
-            if (self.control & 0b0010_0000) != 0 {
-                sprite_size = 16;
+            match sub_cycle {
+                4 => self.secondary_oam[sprite_index].bitmap_low  = self.read_byte(mapper, tile_address),
+                6 => self.secondary_oam[sprite_index].bitmap_high = self.read_byte(mapper, tile_address + 8),
+                _ => ()
             }
+        }
+    }
 
-            let mut pattern_address: u16 = 0x0000;
-            // If we're using 8x16 sprites, set the pattern based on the sprite's tile index
-            if sprite_size == 16 {
-                if (tile_index & 0b1) != 0 {
-                    pattern_address = 0x1000;
-                }
-                tile_index &= 0b1111_1110;
-            } else {
-                // Otherwise, the pattern is selected by PPUCTL
-                if (self.control & 0b0000_1000) != 0 {
-                    pattern_address = 0x1000;
-                }
-            }
+    // Address of the low bitplane for this sprite's row on the current scanline
+    fn sprite_tile_address(&self, sprite: &SpriteLatch) -> u16 {
+        let mut tile_index = sprite.tile_index;
 
-            let mut y_offset = self.current_scanline.wrapping_sub(self.secondary_oam[sprite_index].y_pos as u16);
-            if self.secondary_oam[sprite_index].y_flip() {
-                y_offset = sprite_size.wrapping_sub(1).wrapping_sub(y_offset);
-            }
+        let mut sprite_size: u16 = 8;
+        if (self.control & 0b0010_0000) != 0 {
+            sprite_size = 16;
+        }
 
-            if y_offset >= 8 {
-                y_offset = y_offset.wrapping_sub(8);
-                tile_index = tile_index.wrapping_add(1);
+        let mut pattern_address: u16 = 0x0000;
+        // If we're using 8x16 sprites, set the pattern based on the sprite's tile index
+        if sprite_size == 16 {
+            if (tile_index & 0b1) != 0 {
+                pattern_address = 0x1000;
+            }
+            tile_index &= 0b1111_1110;
+        } else {
+            // Otherwise, the pattern is selected by PPUCTL
+            if (self.control & 0b0000_1000) != 0 {
+                pattern_address = 0x1000;
             }
-            y_offset = y_offset % 8;
+        }
+
+        let mut y_offset = self.current_scanline.wrapping_sub(sprite.y_pos as u16);
+        if sprite.y_flip() {
+            y_offset = sprite_size.wrapping_sub(1).wrapping_sub(y_offset);
+        }
 
-            let tile_address = (((tile_index as u16 * 16) + y_offset) & 0xFFF) | pattern_address;
+        if y_offset >= 8 {
+            y_offset = y_offset.wrapping_sub(8);
+            tile_index = tile_index.wrapping_add(1);
+        }
+        y_offset = y_offset % 8;
 
-            match sub_cycle {
-                4 => self.secondary_oam[sprite_index].bitmap_low  = self.read_byte(mapper, tile_address),
-                6 => self.secondary_oam[sprite_index].bitmap_high = self.read_byte(mapper, tile_address + 8),
-                _ => ()
-            }
+        return (((tile_index as u16 * 16) + y_offset) & 0xFFF) | pattern_address;
+    }
+
+    // Real hardware has no time to fetch these, so they're read without side effects,
+    // all at once, to keep mappers which watch the PPU bus behaving normally
+    fn fetch_extra_sprite_tiles(&mut self, mapper: &mut dyn Mapper) {
+        for i in 0 .. self.extra_sprites.len() {
+            let tile_address = self.sprite_tile_address(&self.extra_sprites[i]);
+            self.extra_sprites[i].bitmap_low = mapper.debug_read_ppu(tile_address).unwrap_or(0);
+            self.extra_sprites[i].bitmap_high = mapper.debug_read_ppu(tile_address + 8).unwrap_or(0);
         }
     }
 
@@ -601,6 +946,9 @@ impl PpuState {
         for i in 0 .. self.secondary_oam_index {
             self.secondary_oam[i].shift();
         }
+        for sprite in self.extra_sprites.iter_mut() {
+            sprite.shift();
+        }
     }
 
     fn prerender_scanline(&mut self, mapper: &mut dyn Mapper) {
@@ -681,6 +1029,7 @@ impl PpuState {
                         self.current_scanline_cycle = 0;
                         self.current_scanline = 0;
                         self.current_frame += 1;
+                        self.scroll_log.end_frame();
                     }
                 }
             }
@@ -688,6 +1037,20 @@ impl PpuState {
         }
     }
 
+    // Only some dots are timed, see PIXEL_TIMING_INTERVAL
+    fn start_pixel_timing(&self) -> Option<Instant> {
+        if self.time_pixels && (self.current_scanline_cycle - 1) % PIXEL_TIMING_INTERVAL == 0 {
+            return Some(Instant::now());
+        }
+        return None;
+    }
+
+    fn end_pixel_timing(&mut self, pixel_start: Option<Instant>) {
+        if let Some(pixel_start) = pixel_start {
+            self.pixel_time += pixel_start.elapsed() * PIXEL_TIMING_INTERVAL as u32;
+        }
+    }
+
     fn render_scanline(&mut self, mapper: &mut dyn Mapper) {
         if self.rendering_enabled() {
             match self.current_scanline_cycle {
@@ -696,7 +1059,12 @@ impl PpuState {
                     self.access_bg_tile_early(mapper);
                 },
                 1 ..= 256 => {
+                    if self.current_scanline_cycle == 1 {
+                        self.record_scroll();
+                    }
+                    let pixel_start = self.start_pixel_timing();
                     self.draw_pixel(mapper);
+                    self.end_pixel_timing(pixel_start);
                     self.shift_bg_registers();
                     self.shift_sprites();
                     let sub_cycle = (self.current_scanline_cycle - 1) % 8;
@@ -718,6 +1086,11 @@ impl PpuState {
                         self.evaluate_sprites();
                     }
                     self.fetch_sprite_tiles(mapper);
+                    // Done while skipping video too, so a frame drawn after skipped ones starts
+                    // from the same sprite state it would have without the skip
+                    if self.current_scanline_cycle == 320 && !self.extra_sprites.is_empty() {
+                        self.fetch_extra_sprite_tiles(mapper);
+                    }
                 },
                 321 ..= 336 => {
                     self.shift_bg_registers();
@@ -735,7 +1108,8 @@ impl PpuState {
             }
         } else {
             match self.current_scanline_cycle {
-                1 ..= 256 => {
+                1 ..= 256 if !self.skip_video => {
+                    let pixel_start = self.start_pixel_timing();
                     // The PPU is disabled. Usually, we should show the backdrop color:
                     let mut pixel_color = self.read_byte(mapper, 0x3F00);
                     // However, if the current VRAM address is within palette memory, instead
@@ -748,6 +1122,7 @@ impl PpuState {
                     let px = self.current_scanline_cycle - 1;
                     let py = self.current_scanline;
                     self.plot_pixel(px, py, pixel_color);
+                    self.end_pixel_timing(pixel_start);
                 },
                 _ => ()
             }
@@ -761,6 +1136,48 @@ impl PpuState {
         }
     }
 
+    // How many dots the PPU can run before the CPU, or a loop watching for the end of
+    // the frame, could tell without reading its registers: vblank starting or ending
+    // (the NMI line), scanline 242 and the end of the frame. Errs a dot early for the
+    // dot skipped on odd frames.
+    pub fn dots_until_visible_change(&self) -> u32 {
+        let position = self.current_scanline as u32 * 341 + self.current_scanline_cycle as u32;
+        let points = [241 * 341 + 2, 242 * 341, 261 * 341 + 2, 262 * 341 - 1];
+        for &point in points.iter() {
+            if point > position {
+                return point - position;
+            }
+        }
+        return 1;
+    }
+
+    // count clock()s, passing over the idle part of vblank in one step
+    pub fn clock_dots(&mut self, mapper: &mut dyn Mapper, count: u32) {
+        let mut remaining = count;
+        while remaining > 0 {
+            // Nothing happens between these dots but counting; the first stops at the
+            // start of vblank
+            let idle_until = match (self.current_scanline, self.current_scanline_cycle) {
+                (240, 2 ..= 340) => Some(241 * 341 + 1),
+                (241, 2 ..= 340) => Some(261 * 341),
+                (242 ..= 260, _) => Some(261 * 341),
+                _ => None
+            };
+            if let Some(idle_until) = idle_until {
+                let position = self.current_scanline as u32 * 341 + self.current_scanline_cycle as u32;
+                let skipped = remaining.min(idle_until - position);
+                let position = position + skipped;
+                self.current_scanline = (position / 341) as u16;
+                self.current_scanline_cycle = (position % 341) as u16;
+                self.overall_cycle += skipped as usize;
+                remaining -= skipped;
+            } else {
+                self.clock(mapper);
+                remaining -= 1;
+            }
+        }
+    }
+
     pub fn clock(&mut self, mapper: &mut dyn Mapper) {
         match self.current_scanline {
             0 => {
@@ -791,6 +1208,7 @@ impl PpuState {
             if self.current_scanline > 261 {
                 self.current_scanline = 0;
                 self.current_frame += 1;
+                self.scroll_log.end_frame();
             }
         }
     }
@@ -894,38 +1312,94 @@ impl PpuState {
     }
 
     pub fn render_ntsc(&mut self, width: usize) {
-        // One scanline logic, needs wrapping for Y yet.
+        let levels = ntsc_levels();
+        // The decoder's three sums, term by term, so that each sample is worked out once
+        // rather than once for every output pixel whose window covers it. Kept flat
+        // and separate so the loops below vectorize.
+        let mut y_terms = [0f32; 256 * 8];
+        let mut i_terms = [0f32; 256 * 8];
+        let mut q_terms = [0f32; 256 * 8];
         for scanline in 0 .. 240 {
+            let phase = (self.frame_starting_cycle + (scanline * 341)) * 8;
             // Compute ntsc signal from raw palette+emphasis values
+            let mut color_phase = phase % 12;
             for dot in 0 .. 256 {
-                let dot_phase = (self.frame_starting_cycle + (scanline*341) + dot) *8;
-                for sample_phase in  0 .. 8 {
-                    let pixel = self.screen[scanline*256+dot];
-                    self.scanline_ntsc_samples[dot*8+sample_phase] = render_ntsc_sample(pixel, dot_phase + sample_phase);
+                let pixel = (self.screen[scanline*256+dot] & 0x1FF) as usize;
+                let pixel_levels = &levels[pixel * 12 .. pixel * 12 + 12];
+                for sample_phase in 0 .. 8 {
+                    let p = dot * 8 + sample_phase;
+                    let sample = pixel_levels[color_phase];
+                    self.scanline_ntsc_samples[p] = sample;
+                    let level = sample / 12.0;
+                    y_terms[p] = level;
+                    i_terms[p] = level * PHASED_COS[color_phase];
+                    q_terms[p] = level * PHASED_SIN[color_phase];
+                    color_phase = if color_phase == 11 {0} else {color_phase + 1};
                 }
             }
 
             // Decode scanline into framebuffer
-            let phase = (self.frame_starting_cycle + (scanline * 341)) * 8;
-            for x in 0 .. width {
-                let center = x * (256 * 8) / width + 0;
-                let begin = if center >= 6 {center - 6} else {0};
-                let end = if (center + 6) < (256 * 8) {center + 6} else {256*8};
-                let mut y = 0.0;
-                let mut i = 0.0;
-                let mut q = 0.0;
-                for p in begin .. end {
-                    let level = self.scanline_ntsc_samples[p] / 12.0;
-                    y = y + level;
-                    i = i + level * PHASED_COS[(phase + p) % 12];
-                    q = q + level * PHASED_SIN[(phase + p) % 12];
+            let output = &mut self.filtered_screen[scanline * width .. (scanline + 1) * width];
+            let mut x = 0;
+            while x < width {
+                let centers = [0, 1, 2, 3].map(|lane| (x + lane) * (256 * 8) / width);
+                if x + 4 <= width && centers[0] >= 6 && centers[3] + 6 < 256 * 8 {
+                    // Four whole windows at once
+                    decode_four(&y_terms, &i_terms, &q_terms, centers, &mut output[x .. x + 4]);
+                    x += 4;
+                } else {
+                    let center = centers[0];
+                    let begin = if center >= 6 {center - 6} else {0};
+                    let end = if (center + 6) < (256 * 8) {center + 6} else {256*8};
+                    let y = y_terms[begin .. end].iter().fold(0.0, |sum, term| sum + term);
+                    let i = i_terms[begin .. end].iter().fold(0.0, |sum, term| sum + term);
+                    let q = q_terms[begin .. end].iter().fold(0.0, |sum, term| sum + term);
+                    output[x] = yiq_to_argb(y, i, q);
+                    x += 1;
                 }
-                self.filtered_screen[scanline * width + x] = yiq_to_argb(y, i, q);
             }
         }
     }
 }
 
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+use crate::ntsc_simd::decode_four;
+
+// Scalar version of ntsc_simd::decode_four(), each window summed in the same order as
+// the edges of the scanline are
+#[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+fn decode_four(y_terms: &[f32], i_terms: &[f32], q_terms: &[f32], centers: [usize; 4], output: &mut [u32]) {
+    let mut y = [0f32; 4];
+    let mut i = [0f32; 4];
+    let mut q = [0f32; 4];
+    for tap in 0 .. 12 {
+        for lane in 0 .. 4 {
+            let p = centers[lane] - 6 + tap;
+            y[lane] += y_terms[p];
+            i[lane] += i_terms[p];
+            q[lane] += q_terms[p];
+        }
+    }
+    for lane in 0 .. 4 {
+        output[lane] = yiq_to_argb(y[lane], i[lane], q[lane]);
+    }
+}
+
+// render_ntsc_sample() for every color (palette index and emphasis bits) at each of the
+// 12 phases of the color subcarrier, 12 entries per color
+fn ntsc_levels() -> &'static [f32] {
+    static LEVELS: OnceLock<Vec<f32>> = OnceLock::new();
+    return LEVELS.get_or_init(|| {
+        let mut levels = Vec::with_capacity(512 * 12);
+        for pixel in 0 .. 512 {
+            for phase in 0 .. 12 {
+                levels.push(render_ntsc_sample(pixel, phase));
+            }
+        }
+        return levels;
+    });
+}
+
 const PHASED_SIN: [f32; 12] = [
     // =SIN(PI() * (PHASE+3.9) / 6)
     0.89100652418836800000,
@@ -0,0 +1,131 @@
+// Execution heatmap and cycle profiler. Counts how often every byte of PRG ROM is
+// executed, and attributes CPU cycles to PRG banks and (when symbols are loaded)
+// to functions, one frame at a time. Disabled by default, since the bookkeeping
+// runs on every instruction.
+
+use std::collections::HashMap;
+
+use crate::nes::NesState;
+
+pub struct Profiler {
+    pub enabled: bool,
+    // Granularity of the per-bank cycle counts
+    pub bank_size: usize,
+    // Indexed by PRG ROM offset; grows as needed
+    prg_execution_counts: Vec<u32>,
+    // Indexed by CPU address, for code that doesn't run from PRG ROM (RAM, mapper registers)
+    cpu_execution_counts: Vec<u32>,
+    last_fetch_clock: u64,
+    last_bank: Option<usize>,
+    last_function: Option<String>,
+    current_bank_cycles: HashMap<usize, u64>,
+    current_function_cycles: HashMap<String, u64>,
+    frame_bank_cycles: HashMap<usize, u64>,
+    frame_function_cycles: HashMap<String, u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        return Profiler {
+            enabled: false,
+            bank_size: 0x2000,
+            prg_execution_counts: Vec::new(),
+            cpu_execution_counts: vec![0u32; 0x10000],
+            last_fetch_clock: 0,
+            last_bank: None,
+            last_function: None,
+            current_bank_cycles: HashMap::new(),
+            current_function_cycles: HashMap::new(),
+            frame_bank_cycles: HashMap::new(),
+            frame_function_cycles: HashMap::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        let enabled = self.enabled;
+        let bank_size = self.bank_size;
+        *self = Profiler::new();
+        self.enabled = enabled;
+        self.bank_size = bank_size;
+    }
+
+    // How many times the instruction at this PRG ROM offset has begun executing
+    pub fn prg_execution_count(&self, prg_rom_offset: usize) -> u32 {
+        return *self.prg_execution_counts.get(prg_rom_offset).unwrap_or(&0);
+    }
+
+    // Execution counts for code outside of PRG ROM, by CPU address
+    pub fn cpu_execution_count(&self, address: u16) -> u32 {
+        return self.cpu_execution_counts[address as usize];
+    }
+
+    // The full heatmap, indexed by PRG ROM offset. May be shorter than PRG ROM
+    // if the end of the ROM has never run.
+    pub fn prg_heatmap(&self) -> &[u32] {
+        return &self.prg_execution_counts;
+    }
+
+    // Cycles spent in each PRG bank (in units of bank_size) during the last completed frame.
+    // Code running outside of PRG ROM is not counted here.
+    pub fn last_frame_bank_cycles(&self) -> &HashMap<usize, u64> {
+        return &self.frame_bank_cycles;
+    }
+
+    // Cycles spent in each function during the last completed frame. Cycles are
+    // attributed to the nearest preceding label; empty if no symbols are loaded.
+    pub fn last_frame_function_cycles(&self) -> &HashMap<String, u64> {
+        return &self.frame_function_cycles;
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frame_bank_cycles = std::mem::take(&mut self.current_bank_cycles);
+        self.frame_function_cycles = std::mem::take(&mut self.current_function_cycles);
+    }
+}
+
+// Called at every opcode fetch while the profiler is enabled. Cycles that elapsed
+// since the previous fetch (including any DMA or interrupt overhead) are charged
+// to the previous instruction.
+pub fn record_execute(nes: &mut NesState, address: u16) {
+    let profiler = &mut nes.profiler;
+    let elapsed = (nes.master_clock - profiler.last_fetch_clock) / 12;
+    profiler.last_fetch_clock = nes.master_clock;
+    if elapsed > 0 {
+        if let Some(bank) = profiler.last_bank {
+            *profiler.current_bank_cycles.entry(bank).or_insert(0) += elapsed;
+        }
+        if let Some(function) = &profiler.last_function {
+            match profiler.current_function_cycles.get_mut(function) {
+                Some(cycles) => *cycles += elapsed,
+                None => {profiler.current_function_cycles.insert(function.clone(), elapsed);}
+            }
+        }
+    }
+
+    let prg_rom_offset = nes.mapper.debug_prg_rom_address(address);
+    match prg_rom_offset {
+        Some(offset) => {
+            let counts = &mut nes.profiler.prg_execution_counts;
+            if offset >= counts.len() {
+                counts.resize(offset + 1, 0);
+            }
+            counts[offset] = counts[offset].saturating_add(1);
+            nes.profiler.last_bank = Some(offset / nes.profiler.bank_size.max(1));
+        },
+        None => {
+            let count = &mut nes.profiler.cpu_execution_counts[address as usize];
+            *count = count.saturating_add(1);
+            nes.profiler.last_bank = None;
+        }
+    }
+
+    if nes.symbols.is_empty() {
+        nes.profiler.last_function = None;
+    } else {
+        let function = nes.symbols.nearest(nes, address).map(|symbol| &symbol.name);
+        // Avoid reallocating the name for every instruction in the same function
+        if function != nes.profiler.last_function.as_ref() {
+            nes.profiler.last_function = function.cloned();
+        }
+    }
+}
@@ -0,0 +1,195 @@
+// Python bindings, enabled with the "python" feature. Build with maturin, then:
+//
+//   import rusticnes_core
+//   nes = rusticnes_core.Nes(open("game.nes", "rb").read())
+//   nes.set_input(0, 0b0000_1000)   # hold Start
+//   nes.step_frame()
+//   pixels = nes.framebuffer()      # numpy array, shape (240, 256, 3), dtype uint8
+//
+// Aimed at scripted play and reinforcement learning, so everything is synchronous
+// and there's no audio output unless asked for.
+
+use numpy::ndarray::Array3;
+use numpy::{IntoPyArray, PyArray1, PyArray3};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use crate::builder::NesStateBuilder;
+use crate::cartridge;
+use crate::memory;
+use crate::nes::NesState;
+use crate::palettes::NTSC_PAL;
+
+const SCREEN_WIDTH: usize = 256;
+const SCREEN_HEIGHT: usize = 240;
+
+// Python may hand the object to any thread, so the console sits behind a lock. PyO3
+// already stops two calls from borrowing it mutably at once, so it's never contended.
+#[pyclass(name = "Nes")]
+pub struct PyNes {
+    nes: Mutex<NesState>,
+}
+
+impl PyNes {
+    fn nes(&self) -> MutexGuard<'_, NesState> {
+        // A panic partway through a call (see save_state) leaves the console usable
+        return self.nes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    }
+}
+
+#[pymethods]
+impl PyNes {
+    // Loads an iNES or NSF image and powers the console on
+    #[new]
+    fn new(rom: &[u8]) -> PyResult<PyNes> {
+        let mapper = cartridge::mapper_from_file(rom).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let rom_crc32 = cartridge::rom_crc32(rom);
+        let mut nes = NesStateBuilder::new()
+            .build(mapper);
+        nes.rom_crc32 = rom_crc32;
+        nes.power_on();
+        return Ok(PyNes { nes: Mutex::new(nes) });
+    }
+
+    fn reset(&mut self) {
+        self.nes().reset();
+    }
+
+    fn power_on(&mut self) {
+        self.nes().power_on();
+    }
+
+    // Runs until the start of the next vblank. Returns true if the game didn't
+    // read the controllers during the frame (a lag frame).
+    fn step_frame(&mut self) -> bool {
+        let mut nes = self.nes();
+        nes.run_until_vblank();
+        return nes.lag_frame;
+    }
+
+    // Runs several frames with the same input, returning how many of them lagged
+    fn step_frames(&mut self, count: u32) -> u32 {
+        let mut nes = self.nes();
+        let mut lag_frames = 0;
+        for _ in 0 .. count {
+            nes.run_until_vblank();
+            if nes.lag_frame {
+                lag_frames += 1;
+            }
+        }
+        return lag_frames;
+    }
+
+    // Executes a single CPU instruction
+    fn step(&mut self) {
+        self.nes().step();
+    }
+
+    // Buttons are a bitmask: A, B, Select, Start, Up, Down, Left, Right from bit 0 to bit 7.
+    // Ports 2 and 3 are players 3 and 4, with a four player adapter connected.
+    fn set_input(&mut self, port: u8, buttons: u8) -> PyResult<()> {
+        match port {
+            0 ..= 3 => self.nes().set_player_buttons(port as usize, buttons),
+            _ => return Err(PyValueError::new_err(format!("No controller port {}", port)))
+        }
+        return Ok(());
+    }
+
+    // Reads CPU memory without side effects
+    fn read_memory(&self, address: u16) -> u8 {
+        return memory::debug_read_byte(&self.nes(), address);
+    }
+
+    fn read_memory_range<'py>(&self, py: Python<'py>, address: u16, length: usize) -> Bound<'py, PyBytes> {
+        let nes = self.nes();
+        let data: Vec<u8> = (0 .. length)
+            .map(|i| memory::debug_read_byte(&nes, address.wrapping_add(i as u16)))
+            .collect();
+        return PyBytes::new(py, &data);
+    }
+
+    // Writes through the CPU bus, exactly as the game would
+    fn write_memory(&mut self, address: u16, data: u8) {
+        memory::write_byte(&mut self.nes(), address, data);
+    }
+
+    // The 2k of console RAM as a numpy array, which makes a convenient observation
+    fn ram<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<u8>> {
+        return self.nes().memory.iram_raw.clone().into_pyarray(py);
+    }
+
+    // RGB pixels, shape (240, 256, 3)
+    fn framebuffer<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray3<u8>> {
+        let nes = self.nes();
+        let mut pixels = Array3::<u8>::zeros((SCREEN_HEIGHT, SCREEN_WIDTH, 3));
+        for y in 0 .. SCREEN_HEIGHT {
+            for x in 0 .. SCREEN_WIDTH {
+                let color = nes.ppu.screen[y * SCREEN_WIDTH + x];
+                let index = (color as usize % 512) * 3;
+                for channel in 0 .. 3 {
+                    pixels[[y, x, channel]] = NTSC_PAL[index + channel];
+                }
+            }
+        }
+        return pixels.into_pyarray(py);
+    }
+
+    // Fails while an audio capture is running
+    fn set_sample_rate(&mut self, sample_rate: u64) -> PyResult<()> {
+        return self.nes().apu.set_sample_rate(sample_rate).map_err(|e| PyRuntimeError::new_err(e.to_string()));
+    }
+
+    // Mono samples generated since the last call
+    fn audio_samples<'py>(&mut self, py: Python<'py>) -> Bound<'py, PyArray1<i16>> {
+        return self.nes().apu.consume_samples().into_pyarray(py);
+    }
+
+    fn save_state<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let nes = self.nes();
+        let state = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| nes.save_state()))
+            .map_err(|_| PyRuntimeError::new_err("This mapper does not support savestates"))?;
+        return Ok(PyBytes::new(py, &state));
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> PyResult<()> {
+        return self.nes().load_state(state).map_err(|e| PyValueError::new_err(e.to_string()));
+    }
+
+    // See NesState::state_hash()
+    fn state_hash(&mut self) -> PyResult<u64> {
+        let mut nes = self.nes();
+        return std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| nes.state_hash()))
+            .map_err(|_| PyRuntimeError::new_err("This console can't be hashed"));
+    }
+
+    // See NesState::run_frames_and_hash(); returns (frame, audio)
+    fn run_frames_and_hash(&mut self, count: u32) -> (u64, u64) {
+        let hashes = self.nes().run_frames_and_hash(count);
+        return (hashes.frame, hashes.audio);
+    }
+
+    // See NesState::frame_events_json()
+    fn frame_events_json(&self) -> String {
+        return self.nes().frame_events_json();
+    }
+
+    #[getter]
+    fn frame(&self) -> u32 {
+        return self.nes().ppu.current_frame;
+    }
+
+    #[getter]
+    fn lag_counter(&self) -> u32 {
+        return self.nes().lag_counter;
+    }
+}
+
+#[pymodule]
+fn rusticnes_core(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyNes>()?;
+    return Ok(());
+}
@@ -0,0 +1,104 @@
+// RAM search, for cheat discovery. Take a snapshot, let the game run, then narrow
+// the list of candidate addresses by comparing what's in memory now against either
+// a constant or the previous snapshot. Repeat until only a handful remain.
+
+use crate::memory::debug_read_byte;
+use crate::nes::NesState;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SearchComparison {
+    // Against a constant
+    Equal(u8),
+    NotEqual(u8),
+    Greater(u8),
+    Less(u8),
+    // Against the previous snapshot
+    EqualToPrevious,
+    NotEqualToPrevious,
+    GreaterThanPrevious,
+    LessThanPrevious,
+    // Current value minus previous value, with 8-bit wrapping. A life lost is ChangedBy(-1).
+    ChangedBy(i16),
+}
+
+impl SearchComparison {
+    pub fn test(&self, current: u8, previous: u8) -> bool {
+        match *self {
+            SearchComparison::Equal(value) => current == value,
+            SearchComparison::NotEqual(value) => current != value,
+            SearchComparison::Greater(value) => current > value,
+            SearchComparison::Less(value) => current < value,
+            SearchComparison::EqualToPrevious => current == previous,
+            SearchComparison::NotEqualToPrevious => current != previous,
+            SearchComparison::GreaterThanPrevious => current > previous,
+            SearchComparison::LessThanPrevious => current < previous,
+            SearchComparison::ChangedBy(delta) => current == previous.wrapping_add(delta as u8),
+        }
+    }
+}
+
+pub struct RamSearch {
+    // Every address this search covers, and its value in the last snapshot
+    addresses: Vec<u16>,
+    previous: Vec<u8>,
+    // Indices into addresses which have survived every filter so far
+    candidates: Vec<usize>,
+}
+
+impl RamSearch {
+    // Searches the 2k of console RAM, plus cartridge RAM at $6000-$7FFF if asked
+    pub fn new(nes: &NesState, include_cartridge_ram: bool) -> RamSearch {
+        let mut addresses: Vec<u16> = (0x0000 .. 0x0800).collect();
+        if include_cartridge_ram {
+            addresses.extend(0x6000u16 ..= 0x7FFF);
+        }
+        let mut search = RamSearch {
+            previous: vec![0u8; addresses.len()],
+            candidates: (0 .. addresses.len()).collect(),
+            addresses: addresses,
+        };
+        search.snapshot(nes);
+        return search;
+    }
+
+    // Starts over, with every address a candidate again
+    pub fn reset(&mut self, nes: &NesState) {
+        self.candidates = (0 .. self.addresses.len()).collect();
+        self.snapshot(nes);
+    }
+
+    // Records current values as the new "previous" without filtering anything
+    pub fn snapshot(&mut self, nes: &NesState) {
+        for i in 0 .. self.addresses.len() {
+            self.previous[i] = debug_read_byte(nes, self.addresses[i]);
+        }
+    }
+
+    // Drops every candidate that fails the comparison, then takes a fresh snapshot.
+    // Returns the number of candidates remaining.
+    pub fn filter(&mut self, nes: &NesState, comparison: SearchComparison) -> usize {
+        let addresses = &self.addresses;
+        let previous = &self.previous;
+        self.candidates.retain(|&i| {
+            let current = debug_read_byte(nes, addresses[i]);
+            return comparison.test(current, previous[i]);
+        });
+        self.snapshot(nes);
+        return self.candidates.len();
+    }
+
+    pub fn candidate_count(&self) -> usize {
+        return self.candidates.len();
+    }
+
+    // (address, value at the last snapshot) for every remaining candidate
+    pub fn candidates(&self) -> Vec<(u16, u8)> {
+        return self.candidates.iter().map(|&i| (self.addresses[i], self.previous[i])).collect();
+    }
+
+    // Removes a single address from consideration, ie one the user knows is wrong
+    pub fn exclude(&mut self, address: u16) {
+        let addresses = &self.addresses;
+        self.candidates.retain(|&i| addresses[i] != address);
+    }
+}
@@ -0,0 +1,235 @@
+// Primitives for GGPO style rollback netplay. Each frame runs as soon as the local
+// input for it is in, with any remote input that hasn't arrived yet predicted by
+// repeating that player's last one. When a remote input turns up that differs from
+// its prediction, the console is rewound to the start of that frame and the frames
+// since are run again with the corrected inputs. States live in buffers sized once
+// up front, so nothing here allocates per frame.
+//
+// Frames are numbered from 0 at the start of the session. Once per frame:
+//   session.set_input(frame + delay, local_player, buttons)
+//   session.set_input(frame, remote_player, buttons), for each input received
+//   session.advance_frame(&mut nes), then present nes.ppu.screen and the audio
+//   session.confirm_frame(&mut nes, frame), once every input up to frame is known
+//
+// Resimulated frames run with nes.apu.output_muted set, so their audio (which was
+// heard the first time around) isn't queued or captured again. Frame hooks, the
+// tracer and the loggers do see them, so leave those off during netplay.
+
+use crate::error::Error;
+use crate::nes::NesState;
+
+// Players 3 and 4 go through a Four Score or Famicom expansion controllers
+pub const PLAYERS: usize = 4;
+
+#[derive(Clone, Copy)]
+struct FrameRecord {
+    frame: u64,
+    inputs: [u8; PLAYERS],
+    // Which inputs were set, rather than predicted
+    known: [bool; PLAYERS],
+    // Of the state saved at the start of the frame
+    state_length: usize,
+}
+
+impl FrameRecord {
+    fn new(frame: u64) -> FrameRecord {
+        return FrameRecord {
+            frame: frame,
+            inputs: [0; PLAYERS],
+            known: [false; PLAYERS],
+            state_length: 0,
+        };
+    }
+}
+
+pub struct RollbackSession {
+    max_rollback: usize,
+    // The next frame to run
+    frame: u64,
+    // Indexed by frame number modulo their length, which covers max_rollback frames
+    // either side of the current one
+    records: Vec<FrameRecord>,
+    states: Vec<Vec<u8>>,
+    // Earliest frame whose inputs changed after it ran
+    rollback_from: Option<u64>,
+    confirmed_frame: Option<u64>,
+    // State after the confirmed frame, for resyncing a peer
+    confirmed_state: Vec<u8>,
+    confirmed_state_length: usize,
+    pub rollbacks: u64,
+    pub frames_resimulated: u64,
+}
+
+fn rollback_error(reason: String) -> Error {
+    return Error::Rollback{reason: reason};
+}
+
+impl RollbackSession {
+    // Starts at frame 0, from the console's current state. Inputs can be set for up
+    // to max_rollback frames before or after the next frame to run.
+    pub fn new(nes: &NesState, max_rollback: usize) -> RollbackSession {
+        let ring_length = max_rollback * 2 + 1;
+        let state_size = nes.max_state_size();
+        let mut records = Vec::with_capacity(ring_length);
+        let mut states = Vec::with_capacity(ring_length);
+        for i in 0 .. ring_length {
+            // Never a frame number in the window, so every slot starts out unused
+            records.push(FrameRecord::new(u64::MAX - i as u64));
+            states.push(vec![0u8; state_size]);
+        }
+        return RollbackSession {
+            max_rollback: max_rollback,
+            frame: 0,
+            records: records,
+            states: states,
+            rollback_from: None,
+            confirmed_frame: None,
+            confirmed_state: vec![0u8; state_size],
+            confirmed_state_length: 0,
+            rollbacks: 0,
+            frames_resimulated: 0,
+        };
+    }
+
+    // The next frame advance_frame() will run
+    pub fn frame(&self) -> u64 {
+        return self.frame;
+    }
+
+    pub fn confirmed_frame(&self) -> Option<u64> {
+        return self.confirmed_frame;
+    }
+
+    // The state at the end of the confirmed frame, which both peers should agree on
+    pub fn confirmed_state(&self) -> Option<&[u8]> {
+        return match self.confirmed_frame {
+            Some(_) => Some(&self.confirmed_state[.. self.confirmed_state_length]),
+            None => None
+        };
+    }
+
+    fn slot(&self, frame: u64) -> usize {
+        return (frame % self.records.len() as u64) as usize;
+    }
+
+    fn record(&mut self, frame: u64) -> &mut FrameRecord {
+        let slot = self.slot(frame);
+        if self.records[slot].frame != frame {
+            self.records[slot] = FrameRecord::new(frame);
+        }
+        return &mut self.records[slot];
+    }
+
+    // Injects one player's buttons for a frame. If the frame already ran with a
+    // different prediction, the next advance_frame() rolls back to it first.
+    pub fn set_input(&mut self, frame: u64, player: usize, buttons: u8) -> Result<(), Error> {
+        if player >= PLAYERS {
+            return Err(rollback_error(format!("no player {}", player)));
+        }
+        let window_start = self.frame.saturating_sub(self.max_rollback as u64);
+        let earliest = match self.confirmed_frame {
+            Some(confirmed) => window_start.max(confirmed + 1),
+            None => window_start,
+        };
+        if frame < earliest {
+            return Err(rollback_error(format!("frame {} can no longer change, the earliest is {}", frame, earliest)));
+        }
+        if frame > self.frame + self.max_rollback as u64 {
+            return Err(rollback_error(format!("frame {} is too far ahead of frame {}", frame, self.frame)));
+        }
+        let already_run = frame < self.frame;
+        let record = self.record(frame);
+        let changed = record.inputs[player] != buttons;
+        record.inputs[player] = buttons;
+        record.known[player] = true;
+        if already_run && changed {
+            self.rollback_from = Some(self.rollback_from.map_or(frame, |from| from.min(frame)));
+        }
+        return Ok(());
+    }
+
+    // The inputs a frame ran (or will run) with
+    pub fn inputs(&self, frame: u64) -> Option<[u8; PLAYERS]> {
+        let record = &self.records[self.slot(frame)];
+        return if record.frame == frame {Some(record.inputs)} else {None};
+    }
+
+    // Runs one frame, after first rolling back and resimulating if any input it
+    // predicted turned out wrong. Its audio is queued in nes.apu as usual.
+    pub fn advance_frame(&mut self, nes: &mut NesState) -> Result<(), Error> {
+        self.catch_up(nes)?;
+        let frame = self.frame;
+        self.run_frame(nes, frame)?;
+        self.frame += 1;
+        return Ok(());
+    }
+
+    // Resimulates from the earliest corrected frame, if there is one
+    fn catch_up(&mut self, nes: &mut NesState) -> Result<(), Error> {
+        let from = match self.rollback_from.take() {
+            Some(from) => from,
+            None => return Ok(())
+        };
+        let slot = self.slot(from);
+        nes.load_state_from(&self.states[slot][.. self.records[slot].state_length])?;
+        nes.apu.output_muted = true;
+        let mut result = Ok(());
+        for frame in from .. self.frame {
+            result = self.run_frame(nes, frame);
+            if result.is_err() {
+                break;
+            }
+        }
+        nes.apu.output_muted = false;
+        self.rollbacks += 1;
+        self.frames_resimulated += self.frame - from;
+        return result;
+    }
+
+    fn run_frame(&mut self, nes: &mut NesState, frame: u64) -> Result<(), Error> {
+        // Predict whatever hasn't arrived from the frame before
+        let previous = match frame {
+            0 => [0; PLAYERS],
+            _ => self.inputs(frame - 1).unwrap_or([0; PLAYERS]),
+        };
+        let slot = self.slot(frame);
+        let record = self.record(frame);
+        for player in 0 .. PLAYERS {
+            if !record.known[player] {
+                record.inputs[player] = previous[player];
+            }
+        }
+        let inputs = record.inputs;
+        let state_length = nes.save_state_into(&mut self.states[slot])?;
+        self.records[slot].state_length = state_length;
+        for player in 0 .. PLAYERS {
+            nes.set_player_buttons(player, inputs[player]);
+        }
+        nes.run_until_vblank();
+        return Ok(());
+    }
+
+    // Marks every input up to and including frame as final, and keeps the state at
+    // the end of it. Only frames that have run can be confirmed, and a correction
+    // still pending for them is resimulated first.
+    pub fn confirm_frame(&mut self, nes: &mut NesState, frame: u64) -> Result<(), Error> {
+        if frame >= self.frame {
+            return Err(rollback_error(format!("frame {} hasn't run yet", frame)));
+        }
+        if frame + 1 + (self.max_rollback as u64) < self.frame {
+            return Err(rollback_error(format!("frame {} is too old to confirm", frame)));
+        }
+        self.catch_up(nes)?;
+        let next = frame + 1;
+        if next == self.frame {
+            self.confirmed_state_length = nes.save_state_into(&mut self.confirmed_state)?;
+        } else {
+            let slot = self.slot(next);
+            let length = self.records[slot].state_length;
+            self.confirmed_state[.. length].copy_from_slice(&self.states[slot][.. length]);
+            self.confirmed_state_length = length;
+        }
+        self.confirmed_frame = Some(frame);
+        return Ok(());
+    }
+}
@@ -1,51 +1,70 @@
-use std::{convert::TryInto};
+// Fills dest from the last dest.len() bytes, and removes them. A buffer that runs
+// short reads as zeros rather than panicking; NesState::load_state checks that each
+// section was consumed exactly. Nothing here allocates.
+fn pop_into(buff: &mut Vec<u8>, dest: &mut [u8]) {
+    let start = buff.len().saturating_sub(dest.len());
+    let available = buff.len() - start;
+    dest[.. available].copy_from_slice(&buff[start ..]);
+    for byte in dest[available ..].iter_mut() {
+        *byte = 0;
+    }
+    buff.truncate(start);
+}
 
 pub(crate) fn save_usize(buff: &mut Vec<u8>, data: usize) {
     buff.extend(&data.to_le_bytes());
 }
 pub(crate) fn load_usize(buff: &mut Vec<u8>, data: &mut usize) {
-    *data = usize::from_le_bytes(buff.split_off(buff.len() - std::mem::size_of::<usize>()).try_into().unwrap())
+    let mut bytes = [0u8; std::mem::size_of::<usize>()];
+    pop_into(buff, &mut bytes);
+    *data = usize::from_le_bytes(bytes);
 }
 
 pub(crate) fn save_u8(buff: &mut Vec<u8>, data: u8) {
     buff.push(data);
 }
 pub(crate) fn load_u8(buff: &mut Vec<u8>, data: &mut u8) {
-    *data = buff.pop().unwrap()
+    *data = buff.pop().unwrap_or(0)
 }
 
 pub(crate) fn save_u16(buff: &mut Vec<u8>, data: u16) {
     buff.extend(data.to_le_bytes());
 }
 pub(crate) fn load_u16(buff: &mut Vec<u8>, data: &mut u16) {
-    *data = u16::from_le_bytes(buff.split_off(buff.len() - std::mem::size_of::<u16>()).try_into().unwrap())
+    let mut bytes = [0u8; std::mem::size_of::<u16>()];
+    pop_into(buff, &mut bytes);
+    *data = u16::from_le_bytes(bytes);
 }
 
 pub(crate) fn save_u32(buff: &mut Vec<u8>, data: u32) {
     buff.extend(data.to_le_bytes());
 }
 pub(crate) fn load_u32(buff: &mut Vec<u8>, data: &mut u32) {
-    *data = u32::from_le_bytes(buff.split_off(buff.len() - std::mem::size_of::<u32>()).try_into().unwrap())
+    let mut bytes = [0u8; std::mem::size_of::<u32>()];
+    pop_into(buff, &mut bytes);
+    *data = u32::from_le_bytes(bytes);
 }
 
 pub(crate) fn save_u64(buff: &mut Vec<u8>, data: u64) {
     buff.extend(data.to_le_bytes());
 }
 pub(crate) fn load_u64(buff: &mut Vec<u8>, data: &mut u64) {
-    *data = u64::from_le_bytes(buff.split_off(buff.len() - std::mem::size_of::<u64>()).try_into().unwrap())
+    let mut bytes = [0u8; std::mem::size_of::<u64>()];
+    pop_into(buff, &mut bytes);
+    *data = u64::from_le_bytes(bytes);
 }
 pub(crate) fn save_bool(buff: &mut Vec<u8>, data: bool) {
     save_u8(buff, data as u8);
 }
 pub(crate) fn load_bool(buff: &mut Vec<u8>, data: &mut bool) {
-    *data = buff.pop().unwrap() != 0
+    *data = buff.pop().unwrap_or(0) != 0
 }
 
 pub(crate) fn save_vec(buff: &mut Vec<u8>, data: &Vec<u8>) {
     buff.extend(data);
 }
 pub(crate) fn load_vec(buff: &mut Vec<u8>, data: &mut Vec<u8>) {
-    *data = buff.split_off(buff.len() - data.len())
+    pop_into(buff, data)
 }
 
 pub(crate) fn save_vec_usize(buff: &mut Vec<u8>, data: &Vec<usize>) {
@@ -57,4 +76,25 @@ pub(crate) fn load_vec_usize(buff: &mut Vec<u8>, data: &mut Vec<usize>) {
     for d in &mut data.iter_mut().rev() {
         load_usize(buff, d);
     }
-}
\ No newline at end of file
+}
+// Always capacity bytes plus the length, so that the state's size doesn't depend on
+// what's stored. Bits past capacity are dropped.
+pub(crate) fn save_bits(buff: &mut Vec<u8>, data: &Vec<bool>, capacity: usize) {
+    for i in 0 .. capacity {
+        save_bool(buff, i < data.len() && data[i]);
+    }
+    save_usize(buff, data.len().min(capacity));
+}
+pub(crate) fn load_bits(buff: &mut Vec<u8>, data: &mut Vec<bool>, capacity: usize) {
+    let mut length = 0;
+    load_usize(buff, &mut length);
+    data.clear();
+    data.resize(length.min(capacity), false);
+    for i in (0 .. capacity).rev() {
+        let mut bit = false;
+        load_bool(buff, &mut bit);
+        if i < data.len() {
+            data[i] = bit;
+        }
+    }
+}
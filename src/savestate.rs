@@ -0,0 +1,251 @@
+// Savestate container. Each subsystem still writes its own positional byte stream;
+// this wraps them in tagged, length-prefixed sections behind a header, so that a
+// state from an incompatible version or a different game is refused up front
+// instead of being misread field by field.
+//
+//   "RNSS"            magic
+//   u32               format version
+//   u32               CRC-32 of the cartridge (see cartridge::rom_crc32), 0 if unknown
+//   u32               section count
+//   per section:
+//     [u8; 4]         tag
+//     u32             length
+//     [u8; length]    data
+//   u32               CRC-32 of everything above
+//
+// All integers are little endian.
+
+use std::time::SystemTime;
+
+use crate::checksum::crc32;
+use crate::error::Error;
+
+const MAGIC: &[u8] = b"RNSS";
+// Bump whenever any subsystem's layout changes
+pub const SAVESTATE_VERSION: u32 = 3;
+
+pub type SectionTag = [u8; 4];
+
+// The container is written straight into one buffer, so that a reused buffer never
+// needs to allocate: begin_container(), then begin_section() / end_section() around
+// each subsystem's save, then end_container().
+pub fn begin_container(buff: &mut Vec<u8>, rom_crc32: u32, section_count: u32) {
+    buff.clear();
+    buff.extend_from_slice(MAGIC);
+    buff.extend_from_slice(&SAVESTATE_VERSION.to_le_bytes());
+    buff.extend_from_slice(&rom_crc32.to_le_bytes());
+    buff.extend_from_slice(&section_count.to_le_bytes());
+}
+
+// Returns where the section's length goes, for end_section()
+pub fn begin_section(buff: &mut Vec<u8>, tag: &SectionTag) -> usize {
+    buff.extend_from_slice(tag);
+    let length_position = buff.len();
+    buff.extend_from_slice(&[0u8; 4]);
+    return length_position;
+}
+
+pub fn end_section(buff: &mut Vec<u8>, length_position: usize) {
+    let length = (buff.len() - length_position - 4) as u32;
+    buff[length_position .. length_position + 4].copy_from_slice(&length.to_le_bytes());
+}
+
+pub fn end_container(buff: &mut Vec<u8>) {
+    let checksum = crc32(buff);
+    buff.extend_from_slice(&checksum.to_le_bytes());
+}
+
+fn bad_savestate(reason: &str) -> Error {
+    return Error::BadSavestate{reason: reason.to_string()};
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() - self.position < length {
+            return Err(bad_savestate("unexpected end of data"));
+        }
+        let bytes = &self.data[self.position .. self.position + length];
+        self.position += length;
+        return Ok(bytes);
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.bytes(4)?;
+        return Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    }
+
+    // Tag and data of the next section
+    fn section(&mut self) -> Result<(&'a [u8], &'a [u8]), Error> {
+        let tag = self.bytes(4)?;
+        let length = self.u32()? as usize;
+        return Ok((tag, self.bytes(length)?));
+    }
+}
+
+// The sections of a container, checked by read_container()
+pub struct Sections<'a> {
+    data: &'a [u8],
+    count: u32,
+}
+
+// Checks the header, checksum and section bounds. rom_crc32 is the running
+// cartridge's; a mismatch is only an error when both are known.
+pub fn read_container(data: &[u8], rom_crc32: u32) -> Result<Sections<'_>, Error> {
+    let mut reader = Reader{data: data, position: 0};
+    if reader.bytes(MAGIC.len()).ok() != Some(MAGIC) {
+        return Err(bad_savestate("not a savestate"));
+    }
+    let version = reader.u32()?;
+    if version != SAVESTATE_VERSION {
+        return Err(Error::SavestateVersion{found: version, expected: SAVESTATE_VERSION});
+    }
+    // Catches truncation and corruption anywhere past the version
+    if data.len() < reader.position + 4 {
+        return Err(bad_savestate("unexpected end of data"));
+    }
+    let (body, checksum) = data.split_at(data.len() - 4);
+    if crc32(body) != u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) {
+        return Err(bad_savestate("checksum mismatch, the state is truncated or corrupt"));
+    }
+    reader.data = body;
+    let state_crc32 = reader.u32()?;
+    if state_crc32 != 0 && rom_crc32 != 0 && state_crc32 != rom_crc32 {
+        return Err(bad_savestate(&format!("made with a different cartridge (CRC {:08X}, expected {:08X})", state_crc32, rom_crc32)));
+    }
+    let section_count = reader.u32()?;
+    let sections = Sections{data: &body[reader.position ..], count: section_count};
+    for _ in 0 .. section_count {
+        reader.section()?;
+    }
+    if reader.position != body.len() {
+        return Err(bad_savestate("trailing data after the last section"));
+    }
+    return Ok(sections);
+}
+
+impl<'a> Sections<'a> {
+    // The data of the section with this tag, or fails if the state doesn't have it
+    pub fn find(&self, tag: &SectionTag) -> Result<&'a [u8], Error> {
+        let mut reader = Reader{data: self.data, position: 0};
+        for _ in 0 .. self.count {
+            let (section_tag, data) = reader.section()?;
+            if section_tag == tag {
+                return Ok(data);
+            }
+        }
+        return Err(bad_savestate(&format!("missing section {}", String::from_utf8_lossy(tag))));
+    }
+}
+
+// Runs a subsystem's loader over a section, and fails if it read more or less than
+// the section holds. Loaders pop fields off the end and read zeros once they run
+// out, so a marker is put in front: a short section eats into it, a long one is
+// left over past it. buff is scratch space, reused to avoid allocating.
+pub fn load_section<F: FnOnce(&mut Vec<u8>)>(tag: &SectionTag, data: &[u8], buff: &mut Vec<u8>, load: F) -> Result<(), Error> {
+    const MARKER: &[u8] = b"RNSS-END";
+    buff.clear();
+    buff.extend_from_slice(MARKER);
+    buff.extend_from_slice(data);
+    load(buff);
+    if buff.is_empty() {
+        return Err(bad_savestate(&format!("section {} is too short ({} bytes)",
+            String::from_utf8_lossy(tag), data.len())));
+    }
+    if buff.as_slice() != MARKER {
+        let expected = data.len() + MARKER.len() - buff.len();
+        return Err(bad_savestate(&format!("section {} is {} bytes, expected {}",
+            String::from_utf8_lossy(tag), data.len(), expected)));
+    }
+    return Ok(());
+}
+
+pub struct SaveSlot {
+    pub data: Vec<u8>,
+    // Wall clock time it was saved at
+    pub timestamp: SystemTime,
+    // Console frame it was saved on
+    pub frame: u32,
+}
+
+// A fixed number of in-memory savestate slots, numbered from 0. NesState keeps one;
+// see NesState::save_slot() and load_slot().
+pub struct SaveStateManager {
+    slots: Vec<Option<SaveSlot>>,
+}
+
+impl SaveStateManager {
+    pub fn new(slot_count: usize) -> SaveStateManager {
+        let mut slots = Vec::new();
+        slots.resize_with(slot_count, || None);
+        return SaveStateManager {
+            slots: slots,
+        };
+    }
+
+    pub fn slot_count(&self) -> usize {
+        return self.slots.len();
+    }
+
+    // Growing keeps every slot; shrinking drops the ones past the end
+    pub fn set_slot_count(&mut self, slot_count: usize) {
+        self.slots.resize_with(slot_count, || None);
+    }
+
+    // None if the slot is empty or doesn't exist
+    pub fn slot(&self, index: usize) -> Option<&SaveSlot> {
+        return self.slots.get(index).and_then(|slot| slot.as_ref());
+    }
+
+    pub fn is_empty(&self, index: usize) -> bool {
+        return self.slot(index).is_none();
+    }
+
+    // Replaces whatever the slot held
+    pub fn store(&mut self, index: usize, data: Vec<u8>, frame: u32) -> Result<(), Error> {
+        match self.slots.get_mut(index) {
+            Some(slot) => {
+                *slot = Some(SaveSlot{data: data, timestamp: SystemTime::now(), frame: frame});
+                return Ok(());
+            },
+            None => return Err(no_such_slot(index))
+        }
+    }
+
+    pub fn state(&self, index: usize) -> Result<&[u8], Error> {
+        if index >= self.slots.len() {
+            return Err(no_such_slot(index));
+        }
+        match self.slot(index) {
+            Some(slot) => return Ok(&slot.data),
+            None => return Err(bad_savestate(&format!("slot {} is empty", index)))
+        }
+    }
+
+    pub fn clear(&mut self, index: usize) {
+        if let Some(slot) = self.slots.get_mut(index) {
+            *slot = None;
+        }
+    }
+
+    pub fn clear_all(&mut self) {
+        for slot in self.slots.iter_mut() {
+            *slot = None;
+        }
+    }
+
+    // The most recently saved slot, for quick load
+    pub fn newest(&self) -> Option<usize> {
+        return (0 .. self.slots.len())
+            .filter(|&index| !self.is_empty(index))
+            .max_by_key(|&index| self.slot(index).unwrap().timestamp);
+    }
+}
+
+fn no_such_slot(index: usize) -> Error {
+    return bad_savestate(&format!("no slot {}", index));
+}
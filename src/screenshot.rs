@@ -0,0 +1,32 @@
+// PNG screenshots of the current frame, through the standard NTSC palette. Only
+// built with the "screenshot" feature, which brings in the png crate.
+
+use crate::error::Error;
+use crate::palettes::render_rgb24;
+
+pub const SCREEN_WIDTH: u32 = 256;
+pub const SCREEN_HEIGHT: u32 = 240;
+
+fn encoding_error(error: png::EncodingError) -> Error {
+    return Error::Io{reason: error.to_string()};
+}
+
+// screen is PpuState::screen: 256x240 palette indices with emphasis bits
+pub fn encode_png(screen: &[u16]) -> Result<Vec<u8>, Error> {
+    return encode_indexed_png(screen, SCREEN_WIDTH, SCREEN_HEIGHT);
+}
+
+// Any other picture made of palette indices, such as those from chr_export.rs
+pub fn encode_indexed_png(pixels: &[u16], width: u32, height: u32) -> Result<Vec<u8>, Error> {
+    let mut rgb = vec![0u8; pixels.len() * 3];
+    render_rgb24(pixels, &mut rgb);
+    let mut png_data = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png_data, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(encoding_error)?;
+        writer.write_image_data(&rgb).map_err(encoding_error)?;
+    }
+    return Ok(png_data);
+}
@@ -0,0 +1,271 @@
+// Label files for homebrew debugging. Supports FCEUX .nl files and the debug info
+// files ca65/ld65 produce with --dbgfile. Symbols that live in PRG ROM are keyed
+// by their offset into the ROM, so lookups follow the mapper's current banking:
+// the same CPU address resolves to different labels as banks are swapped.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+use crate::breakpoints::AccessType;
+use crate::breakpoints::Breakpoint;
+use crate::breakpoints::Condition;
+use crate::disassembler::Instruction;
+use crate::nes::NesState;
+
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u16,
+    // Offset into PRG ROM, for symbols known to live in a particular bank
+    pub prg_rom_offset: Option<usize>,
+    pub size: u16,
+    pub comment: String,
+}
+
+pub struct SymbolTable {
+    // Symbols with no bank information: RAM, registers, and unbanked ROM labels
+    global: BTreeMap<u16, Symbol>,
+    banked: BTreeMap<usize, Symbol>,
+    by_name: HashMap<String, Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        return SymbolTable {
+            global: BTreeMap::new(),
+            banked: BTreeMap::new(),
+            by_name: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        return self.by_name.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.by_name.is_empty();
+    }
+
+    pub fn clear(&mut self) {
+        self.global.clear();
+        self.banked.clear();
+        self.by_name.clear();
+    }
+
+    pub fn add(&mut self, symbol: Symbol) {
+        self.by_name.insert(symbol.name.clone(), symbol.clone());
+        match symbol.prg_rom_offset {
+            Some(offset) => {self.banked.insert(offset, symbol);},
+            None => {self.global.insert(symbol.address, symbol);}
+        }
+    }
+
+    // The label for whatever is currently mapped at this CPU address
+    pub fn lookup(&self, nes: &NesState, address: u16) -> Option<&Symbol> {
+        if !self.banked.is_empty() {
+            if let Some(offset) = nes.mapper.debug_prg_rom_address(address) {
+                if let Some(symbol) = self.banked.get(&offset) {
+                    return Some(symbol);
+                }
+            }
+        }
+        return self.global.get(&address);
+    }
+
+    // The closest label at or before this address, ie the function it's probably part of
+    pub fn nearest(&self, nes: &NesState, address: u16) -> Option<&Symbol> {
+        if !self.banked.is_empty() {
+            if let Some(offset) = nes.mapper.debug_prg_rom_address(address) {
+                if let Some((_, symbol)) = self.banked.range(..= offset).next_back() {
+                    return Some(symbol);
+                }
+            }
+        }
+        return self.global.range(..= address).next_back().map(|(_, symbol)| symbol);
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&Symbol> {
+        return self.by_name.get(name);
+    }
+
+    // Builds a breakpoint at a named label. For banked symbols, the breakpoint
+    // only fires when the label's own bank is mapped in.
+    pub fn breakpoint(&self, name: &str, access: AccessType) -> Option<Breakpoint> {
+        let symbol = self.by_name(name)?;
+        let last_address = symbol.address.saturating_add(symbol.size.max(1) - 1);
+        let mut breakpoint = Breakpoint::new(access, symbol.address, last_address);
+        if let Some(offset) = symbol.prg_rom_offset {
+            // A one byte "bank" pins the exact ROM location
+            breakpoint = breakpoint.with_condition(Condition::AddressBank{bank_size: 1, bank: offset});
+            breakpoint.last_address = symbol.address;
+        }
+        return Some(breakpoint);
+    }
+
+    // Loads an FCEUX .nl file. FCEUX names these <rom>.nes.ram.nl for unbanked
+    // symbols and <rom>.nes.<bank>.nl for each 16k PRG bank; pass that bank here.
+    pub fn load_nl(&mut self, contents: &str, bank: Option<usize>) -> Result<usize, String> {
+        let mut count = 0;
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if !line.starts_with('$') {
+                continue;
+            }
+            let fields: Vec<&str> = line.splitn(3, '#').collect();
+            if fields.len() < 2 {
+                return Err(format!("Malformed .nl entry on line {}: {}", line_number + 1, line));
+            }
+            // Address, optionally followed by /size for arrays
+            let mut address_parts = fields[0][1..].splitn(2, '/');
+            let address_text = address_parts.next().unwrap_or("");
+            let address = u16::from_str_radix(address_text, 16)
+                .map_err(|_| format!("Bad address on line {}: {}", line_number + 1, address_text))?;
+            let size = match address_parts.next() {
+                Some(size_text) => u16::from_str_radix(size_text, 16)
+                    .map_err(|_| format!("Bad size on line {}: {}", line_number + 1, size_text))?,
+                None => 1
+            };
+            let prg_rom_offset = match bank {
+                Some(bank) if address >= 0x8000 => Some(bank * 0x4000 + (address as usize & 0x3FFF)),
+                _ => None
+            };
+            self.add(Symbol {
+                name: fields[1].to_string(),
+                address: address,
+                prg_rom_offset: prg_rom_offset,
+                size: size,
+                comment: fields.get(2).unwrap_or(&"").to_string(),
+            });
+            count += 1;
+        }
+        return Ok(count);
+    }
+
+    // Convenience wrapper which works out the bank from an FCEUX style filename. FCEUX
+    // writes the bank number in hex: game.nes.A.nl is bank 10.
+    pub fn load_nl_file(&mut self, filename: &str, contents: &str) -> Result<usize, String> {
+        let parts: Vec<&str> = filename.rsplitn(3, '.').collect();
+        let bank = match parts.as_slice() {
+            ["nl", "ram", _] => None,
+            ["nl", bank_text, _] => match usize::from_str_radix(bank_text, 16) {
+                Ok(bank) => Some(bank),
+                Err(_) => None
+            },
+            _ => None
+        };
+        return self.load_nl(contents, bank);
+    }
+
+    // Loads a ca65 debug info file (ld65 --dbgfile). Only labels and equates are
+    // used; everything else in the file is ignored.
+    pub fn load_ca65_dbg(&mut self, contents: &str) -> Result<usize, String> {
+        // id -> (cpu start address, offset into the output file)
+        let mut segments: HashMap<String, (u32, Option<usize>)> = HashMap::new();
+        let mut symbol_lines = Vec::new();
+        for line in contents.lines() {
+            let line = line.trim_end_matches('\r');
+            let mut split = line.splitn(2, '\t');
+            let kind = split.next().unwrap_or("");
+            let attributes = parse_dbg_attributes(split.next().unwrap_or(""));
+            match kind {
+                "seg" => {
+                    let id = attributes.get("id").cloned().unwrap_or_default();
+                    let start = attributes.get("start").and_then(|s| parse_dbg_number(s)).unwrap_or(0);
+                    let output_offset = attributes.get("ooffs").and_then(|s| parse_dbg_number(s));
+                    segments.insert(id, (start, output_offset.map(|offset| offset as usize)));
+                },
+                "sym" => symbol_lines.push(attributes),
+                _ => {}
+            }
+        }
+
+        let mut count = 0;
+        for attributes in symbol_lines {
+            let name = match attributes.get("name") {
+                Some(name) => name.clone(),
+                None => continue
+            };
+            let value = match attributes.get("val").and_then(|s| parse_dbg_number(s)) {
+                Some(value) => value,
+                None => continue
+            };
+            if value > 0xFFFF || attributes.get("type").map(|t| t == "imp").unwrap_or(false) {
+                // Constants too large to be addresses, and imports (which duplicate their exports)
+                continue;
+            }
+            let size = attributes.get("size").and_then(|s| parse_dbg_number(s)).unwrap_or(1) as u16;
+            let mut prg_rom_offset = None;
+            if let Some(segment_id) = attributes.get("seg") {
+                if let Some((start, Some(output_offset))) = segments.get(segment_id) {
+                    // ld65 output offsets include the 16 byte iNES header
+                    if value >= 0x8000 && *output_offset >= 16 && value >= *start {
+                        prg_rom_offset = Some(output_offset - 16 + (value - start) as usize);
+                    }
+                }
+            }
+            self.add(Symbol {
+                name: name,
+                address: value as u16,
+                prg_rom_offset: prg_rom_offset,
+                size: size.max(1),
+                comment: String::new(),
+            });
+            count += 1;
+        }
+        return Ok(count);
+    }
+}
+
+fn parse_dbg_number(text: &str) -> Option<u32> {
+    if text.starts_with("0x") || text.starts_with("0X") {
+        return u32::from_str_radix(&text[2..], 16).ok();
+    }
+    return text.parse::<u32>().ok();
+}
+
+// key=value,key="quoted, value",...
+fn parse_dbg_attributes(text: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    let mut key = String::new();
+    let mut value = String::new();
+    let mut in_value = false;
+    let mut in_quotes = false;
+    for c in text.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '=' if !in_value && !in_quotes => in_value = true,
+            ',' if !in_quotes => {
+                attributes.insert(key.clone(), value.clone());
+                key.clear();
+                value.clear();
+                in_value = false;
+            },
+            _ => {
+                if in_value {
+                    value.push(c);
+                } else {
+                    key.push(c);
+                }
+            }
+        }
+    }
+    if !key.is_empty() {
+        attributes.insert(key, value);
+    }
+    return attributes;
+}
+
+// Disassembly with operand addresses replaced by labels where one is known
+pub fn format_instruction(nes: &NesState, instruction: &Instruction) -> String {
+    let operand = match instruction.target_address() {
+        Some(address) => match nes.symbols.lookup(nes, address) {
+            Some(symbol) => instruction.format_operand(&symbol.name),
+            None => instruction.operand_string()
+        },
+        None => instruction.operand_string()
+    };
+    if operand.is_empty() {
+        return instruction.mnemonic.to_string();
+    }
+    return format!("{} {}", instruction.mnemonic, operand);
+}
@@ -0,0 +1,110 @@
+// Runs test ROMs headlessly and reports their result, for accuracy suites in CI.
+// Understands the protocol blargg's suites (and many newer ones) use to report
+// through cartridge RAM:
+//   $6001-$6003  DE B0 61 once the test has started writing results
+//   $6000        $80 while running, $81 when it wants the reset button pressed,
+//                otherwise the final result: 0 for a pass, anything else a failure
+//   $6004        the text it printed, zero terminated
+// Reference: https://github.com/christopherpow/nes-test-roms/blob/master/README.md
+
+use crate::builder::NesStateBuilder;
+use crate::cartridge;
+use crate::error::Error;
+use crate::memory::debug_read_byte;
+use crate::nes::NesState;
+
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_NEEDS_RESET: u8 = 0x81;
+// The tests ask for the button to be held for at least 100 ms
+const RESET_DELAY_FRAMES: u32 = 8;
+const MESSAGE_START: u16 = 0x6004;
+const MESSAGE_END: u16 = 0x7FFF;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TestRomStatus {
+    Passed,
+    // With the ROM's result code, which it usually explains in the message
+    Failed(u8),
+    // Still running after the frame limit
+    TimedOut,
+    // Never wrote the signature, so it probably doesn't speak this protocol
+    NoProtocol,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TestRomResult {
+    pub status: TestRomStatus,
+    pub message: String,
+    pub frames: u32,
+}
+
+impl TestRomResult {
+    pub fn passed(&self) -> bool {
+        return self.status == TestRomStatus::Passed;
+    }
+}
+
+fn protocol_active(nes: &NesState) -> bool {
+    for i in 0 .. SIGNATURE.len() {
+        if debug_read_byte(nes, 0x6001 + i as u16) != SIGNATURE[i] {
+            return false;
+        }
+    }
+    return true;
+}
+
+// The text at $6004 so far
+pub fn test_rom_message(nes: &NesState) -> String {
+    let mut bytes = Vec::new();
+    for address in MESSAGE_START ..= MESSAGE_END {
+        let byte = debug_read_byte(nes, address);
+        if byte == 0 {
+            break;
+        }
+        bytes.push(byte);
+    }
+    return String::from_utf8_lossy(&bytes).trim_end().to_string();
+}
+
+// Runs an already powered on console for up to max_frames, pressing reset whenever
+// the ROM asks
+pub fn run_test(nes: &mut NesState, max_frames: u32) -> TestRomResult {
+    let mut reset_countdown: Option<u32> = None;
+    let mut seen_protocol = false;
+    for frame in 0 .. max_frames {
+        nes.run_until_vblank();
+        if !protocol_active(nes) {
+            continue;
+        }
+        seen_protocol = true;
+        match debug_read_byte(nes, 0x6000) {
+            STATUS_RUNNING => {},
+            STATUS_NEEDS_RESET => {
+                match reset_countdown {
+                    None => reset_countdown = Some(RESET_DELAY_FRAMES),
+                    Some(0) => {
+                        nes.reset();
+                        reset_countdown = None;
+                    },
+                    Some(frames) => reset_countdown = Some(frames - 1),
+                }
+            },
+            code => {
+                let status = if code == 0 {TestRomStatus::Passed} else {TestRomStatus::Failed(code)};
+                return TestRomResult{status: status, message: test_rom_message(nes), frames: frame + 1};
+            }
+        }
+    }
+    let status = if seen_protocol {TestRomStatus::TimedOut} else {TestRomStatus::NoProtocol};
+    return TestRomResult{status: status, message: test_rom_message(nes), frames: max_frames};
+}
+
+// Loads an iNES file onto a deterministic console and runs it as run_test() does.
+// Most tests finish in well under 60 seconds, ie 3600 frames.
+pub fn run_test_rom(rom: &[u8], max_frames: u32) -> Result<TestRomResult, Error> {
+    let mapper = cartridge::mapper_from_file(rom)?;
+    let mut nes = NesStateBuilder::new().deterministic(true).build(mapper);
+    nes.power_on();
+    return Ok(run_test(&mut nes, max_frames));
+}
@@ -0,0 +1,143 @@
+// Per-instruction CPU trace logging. Lines are written right before each opcode
+// is fetched, so the register state shown is the state the instruction begins
+// with. Layouts follow the default trace loggers of FCEUX and Mesen closely
+// enough that the two can be diffed line by line when hunting accuracy bugs.
+
+use std::io::Write;
+
+use crate::breakpoints::AccessType;
+use crate::disassembler;
+use crate::expression::Access;
+use crate::expression::Expression;
+use crate::memory::debug_read_byte;
+use crate::nes::NesState;
+use crate::symbols;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TraceFormat {
+    // A:00 X:00 Y:00 S:FD P:nvUbdIzc  $C000:BD 00 02  LDA $0200,X @ $0205 = #$00
+    Fceux,
+    // C000  BD 00 02  LDA $0200,X @ $0205 = #$00     A:00 X:00 Y:00 P:nvUbdIzc SP:FD CYC:21  SL:0   CPU Cycle:7
+    Mesen,
+}
+
+pub struct TraceLogger {
+    writer: Box<dyn Write + Send>,
+    pub format: TraceFormat,
+    pub instructions_logged: u64,
+    // Only instructions for which this is true are logged. It's checked as an execute
+    // access of the opcode, so "exec $C000" and "value == $60" work.
+    pub filter: Option<Expression>,
+}
+
+impl TraceLogger {
+    pub fn new(writer: Box<dyn Write + Send>, format: TraceFormat) -> TraceLogger {
+        return TraceLogger {
+            writer: writer,
+            format: format,
+            instructions_logged: 0,
+            filter: None,
+        }
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+// Uppercase when set, lowercase when clear. Bit 5 always reads as set, and B
+// does not exist outside of the copy pushed to the stack.
+pub fn flags_string(status: u8) -> String {
+    let names = ['N', 'V', 'U', 'B', 'D', 'I', 'Z', 'C'];
+    let mut flags = String::with_capacity(8);
+    for i in 0 .. 8 {
+        let set = status & (0x80 >> i) != 0;
+        if set {
+            flags.push(names[i]);
+        } else {
+            flags.push(names[i].to_ascii_lowercase());
+        }
+    }
+    return flags;
+}
+
+// The disassembly plus the effective address and the value found there, in the
+// style both FCEUX and Mesen use: "LDA ($10),Y @ $0305 = #$00"
+pub fn annotated_disassembly(nes: &NesState, address: u16) -> String {
+    let instruction = disassembler::disassemble(nes, address);
+    let mut text = symbols::format_instruction(nes, &instruction);
+    let is_jump = instruction.mnemonic == "JMP" || instruction.mnemonic == "JSR";
+    match instruction.mode {
+        disassembler::AddressingMode::ZeroPage | disassembler::AddressingMode::Absolute => {
+            if !is_jump {
+                let target = instruction.target_address().unwrap_or(0);
+                text.push_str(&format!(" = #${:02X}", debug_read_byte(nes, target)));
+            }
+        },
+        disassembler::AddressingMode::Indirect => {
+            let target = disassembler::effective_address(nes, &instruction).unwrap_or(0);
+            text.push_str(&format!(" = ${:04X}", target));
+        },
+        disassembler::AddressingMode::Implied | disassembler::AddressingMode::Accumulator |
+        disassembler::AddressingMode::Immediate | disassembler::AddressingMode::Relative => {},
+        _ => {
+            let target = disassembler::effective_address(nes, &instruction).unwrap_or(0);
+            text.push_str(&format!(" @ ${:04X} = #${:02X}", target, debug_read_byte(nes, target)));
+        }
+    }
+    return text;
+}
+
+pub fn format_line(nes: &NesState, format: TraceFormat) -> String {
+    let pc = nes.registers.pc;
+    let instruction = disassembler::disassemble(nes, pc);
+    let bytes: Vec<String> = instruction.bytes().iter().map(|b| format!("{:02X}", b)).collect();
+    let bytes = bytes.join(" ");
+    let disassembly = annotated_disassembly(nes, pc);
+    let flags = flags_string(nes.registers.status_as_byte(false));
+    let r = &nes.registers;
+    let cpu_cycle = nes.master_clock / 12;
+    match format {
+        TraceFormat::Fceux => {
+            return format!("A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{}  ${:04X}:{:<9} {}",
+                r.a, r.x, r.y, r.s, flags, pc, bytes, disassembly);
+        },
+        TraceFormat::Mesen => {
+            // Mesen numbers the pre-render line -1
+            let scanline = match nes.ppu.current_scanline {
+                261 => -1,
+                line => line as i32
+            };
+            return format!("{:04X}  {:<9} {:<32} A:{:02X} X:{:02X} Y:{:02X} P:{} SP:{:02X} CYC:{:<3} SL:{:<3} CPU Cycle:{}",
+                pc, bytes, disassembly, r.a, r.x, r.y, flags, r.s,
+                nes.ppu.current_scanline_cycle, scanline, cpu_cycle);
+        }
+    }
+}
+
+// Called by the CPU right before each opcode fetch, only when a trace is active
+pub fn trace_instruction(nes: &mut NesState) {
+    let format = match &nes.tracer {
+        Some(tracer) => {
+            if let Some(filter) = &tracer.filter {
+                let pc = nes.registers.pc;
+                let access = Access{access: AccessType::Execute, address: pc, data: debug_read_byte(nes, pc)};
+                if !filter.is_true(nes, Some(&access)) {
+                    return;
+                }
+            }
+            tracer.format
+        },
+        None => return
+    };
+    let line = format_line(nes, format);
+    let mut failed = false;
+    if let Some(tracer) = nes.tracer.as_mut() {
+        failed = writeln!(tracer.writer, "{}", line).is_err();
+        tracer.instructions_logged += 1;
+    }
+    if failed {
+        println!("Trace log write failed, tracing disabled.");
+        nes.tracer = None;
+    }
+}
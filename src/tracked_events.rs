@@ -1,11 +1,23 @@
 #[derive(Clone, Copy)]
 pub enum EventType {
     NullEvent,
+    // Scroll splits show up here, as writes to $2005 and $2006
     CpuRead{program_counter: u16, address: u16, data: u8},
     CpuWrite{program_counter: u16, address: u16, data: u8},
     CpuExecute{program_counter: u16, data: u8},
+    // The edge the CPU latches, whether or not it's serviced right away
+    Nmi,
+    // A source pulling the IRQ line low, even while the CPU has interrupts disabled
+    Irq{source: IrqSource},
+    SpriteZeroHit,
+    // The DMC stealing the bus to fetch a sample byte (OAM DMA shows up as the
+    // write to $4014 that starts it)
+    DmcDma{address: u16},
 }
 
+pub use crate::irq::IrqSource;
+use crate::irq::IrqLines;
+
 #[derive(Clone, Copy)]
 pub struct TrackedEvent {
     pub scanline: u16,
@@ -22,6 +34,9 @@ pub struct EventTracker {
     pub current_scanline: u16,
     pub current_cycle: u16,
     pub cpu_snoop_list: Vec<u8>,
+    // Levels as of the last snoop_signals(), to spot rising edges
+    irq_lines: IrqLines,
+    sprite_zero_hit: bool,
 }
 
 const CPU_READ: u8    = 0b0000_0001;
@@ -89,6 +104,8 @@ impl EventTracker {
             current_scanline: 0,
             current_cycle: 0,
             cpu_snoop_list: default_cpu_snoops,
+            irq_lines: IrqLines::new(),
+            sprite_zero_hit: false,
         }
     }
 
@@ -172,4 +189,73 @@ impl EventTracker {
             });
         }
     }
-}
\ No newline at end of file
+
+    fn track_here(&mut self, event_type: EventType) {
+        self.track(TrackedEvent{
+            scanline: self.current_scanline,
+            cycle: self.current_cycle,
+            event_type: event_type,
+        });
+    }
+
+    pub fn snoop_dmc_dma(&mut self, address: u16) {
+        self.track_here(EventType::DmcDma{address: address});
+    }
+
+    pub fn snoop_nmi(&mut self) {
+        self.track_here(EventType::Nmi);
+    }
+
+    // Called once per CPU cycle with the IRQ lines and the PPU's sprite zero flag, and
+    // records any that have just gone high
+    pub fn snoop_signals(&mut self, irq_lines: IrqLines, sprite_zero_hit: bool) {
+        for source in irq_lines.rising_since(self.irq_lines).sources() {
+            self.track_here(EventType::Irq{source: source});
+        }
+        self.irq_lines = irq_lines;
+        if sprite_zero_hit && !self.sprite_zero_hit {
+            self.track_here(EventType::SpriteZeroHit);
+        }
+        self.sprite_zero_hit = sprite_zero_hit;
+    }
+}
+
+fn irq_source_name(source: IrqSource) -> &'static str {
+    return match source {
+        IrqSource::Mapper => "mapper",
+        IrqSource::ApuFrameCounter => "apu_frame_counter",
+        IrqSource::Dmc => "dmc",
+    };
+}
+
+// One frame's events as a JSON object, for offline analysis or attaching to a bug
+// report, one event per line:
+// {"frame":60,"events":[
+// {"scanline":0,"dot":21,"type":"cpu_write","pc":"$C0F3","address":"$2005","data":"$00"},
+// {"scanline":241,"dot":2,"type":"nmi"},
+// {"scanline":30,"dot":88,"type":"irq","source":"mapper"}
+// ]}
+pub fn events_to_json(frame: u32, events: &[TrackedEvent]) -> String {
+    let mut json = format!("{{\"frame\":{},\"events\":[", frame);
+    let mut first = true;
+    for event in events.iter() {
+        let details = match event.event_type {
+            EventType::NullEvent => continue,
+            EventType::CpuRead{program_counter, address, data} =>
+                format!("\"type\":\"cpu_read\",\"pc\":\"${:04X}\",\"address\":\"${:04X}\",\"data\":\"${:02X}\"", program_counter, address, data),
+            EventType::CpuWrite{program_counter, address, data} =>
+                format!("\"type\":\"cpu_write\",\"pc\":\"${:04X}\",\"address\":\"${:04X}\",\"data\":\"${:02X}\"", program_counter, address, data),
+            EventType::CpuExecute{program_counter, data} =>
+                format!("\"type\":\"cpu_execute\",\"pc\":\"${:04X}\",\"data\":\"${:02X}\"", program_counter, data),
+            EventType::Nmi => "\"type\":\"nmi\"".to_string(),
+            EventType::Irq{source} => format!("\"type\":\"irq\",\"source\":\"{}\"", irq_source_name(source)),
+            EventType::SpriteZeroHit => "\"type\":\"sprite_zero_hit\"".to_string(),
+            EventType::DmcDma{address} => format!("\"type\":\"dmc_dma\",\"address\":\"${:04X}\"", address),
+        };
+        let separator = if first {""} else {","};
+        json.push_str(&format!("{}\n{{\"scanline\":{},\"dot\":{},{}}}", separator, event.scanline, event.cycle, details));
+        first = false;
+    }
+    json.push_str("\n]}\n");
+    return json;
+}
@@ -0,0 +1,210 @@
+// VGM logging of sound chip register writes, for ripping music out of games. Every
+// write to the 2A03's audio registers (and the Sunsoft 5B, which VGM supports as a
+// YM2149, and FDS audio, which it treats as part of the 2A03) is logged with the
+// sample it landed on, at VGM's fixed 44.1 kHz. DMC sample data is copied into the log
+// as it's played, since players can't see the cartridge. VRC6, MMC5 and Namco 163
+// audio have no VGM chip type, so their writes are skipped.
+// Reference: https://vgmrips.net/wiki/VGM_Specification
+//
+// Logging starts from whatever state the APU is in, so start it before the music
+// does (at power on, ideally) for the rip to sound right from the first note.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+
+use crate::mmc::mapper::ExpansionChip;
+use crate::nes::NesState;
+
+const VGM_SAMPLE_RATE: u64 = 44100;
+const HEADER_SIZE: usize = 0x100;
+
+// Commands
+const NES_APU_WRITE: u8 = 0xB4;
+const AY8910_WRITE: u8 = 0xA0;
+const DATA_BLOCK: u8 = 0x67;
+const WAIT_SAMPLES: u8 = 0x61;
+const WAIT_NTSC_FRAME: u8 = 0x62;
+const WAIT_PAL_FRAME: u8 = 0x63;
+const END_OF_DATA: u8 = 0x66;
+// Data block type for NES APU sample RAM
+const NES_DPCM_BLOCK: u8 = 0xC2;
+
+pub struct VgmLogger {
+    filename: String,
+    cpu_clock_rate: u64,
+    // CPU cycle logging began on
+    start_cycle: u64,
+    // VGM samples waited so far
+    samples_logged: u64,
+    commands: Vec<u8>,
+    sunsoft_5b: bool,
+    sunsoft_5b_register: u8,
+    fds: bool,
+    // DMC samples already copied in, by start address, so that replays of the same
+    // sample don't copy it again. Bank switching can change what's there, so the
+    // contents are compared too.
+    dpcm_blocks: HashMap<u16, Vec<u8>>,
+}
+
+impl VgmLogger {
+    pub fn new(filename: &str, cpu_clock_rate: u64, start_cycle: u64, chips: &[ExpansionChip]) -> VgmLogger {
+        return VgmLogger {
+            filename: filename.to_string(),
+            cpu_clock_rate: cpu_clock_rate,
+            start_cycle: start_cycle,
+            samples_logged: 0,
+            commands: Vec::new(),
+            sunsoft_5b: chips.contains(&ExpansionChip::Sunsoft5B),
+            sunsoft_5b_register: 0,
+            fds: chips.contains(&ExpansionChip::Fds),
+            dpcm_blocks: HashMap::new(),
+        };
+    }
+
+    // Waits until the sample containing cpu_cycle
+    fn wait_until(&mut self, cpu_cycle: u64) {
+        let elapsed_cycles = cpu_cycle.saturating_sub(self.start_cycle);
+        let target_sample = elapsed_cycles * VGM_SAMPLE_RATE / self.cpu_clock_rate;
+        let mut remaining = target_sample.saturating_sub(self.samples_logged);
+        self.samples_logged += remaining;
+        while remaining > 0 {
+            match remaining {
+                735 => {self.commands.push(WAIT_NTSC_FRAME); remaining = 0;},
+                882 => {self.commands.push(WAIT_PAL_FRAME); remaining = 0;},
+                1 ..= 16 => {self.commands.push(0x70 + (remaining - 1) as u8); remaining = 0;},
+                _ => {
+                    let wait = remaining.min(0xFFFF);
+                    self.commands.push(WAIT_SAMPLES);
+                    self.commands.extend_from_slice(&(wait as u16).to_le_bytes());
+                    remaining -= wait;
+                }
+            }
+        }
+    }
+
+    fn log_apu_write(&mut self, cpu_cycle: u64, address: u16, data: u8) {
+        self.wait_until(cpu_cycle);
+        self.commands.extend_from_slice(&[NES_APU_WRITE, (address - 0x4000) as u8, data]);
+    }
+
+    // FDS registers go in the 2A03's unused ones: $4080 - $409E at $20 - $3E, and the
+    // wave table at $40 - $7F
+    fn log_fds_write(&mut self, cpu_cycle: u64, address: u16, data: u8) {
+        let register = match address {
+            0x4040 ..= 0x407F => (address - 0x4000) as u8,
+            0x4080 ..= 0x409E => (address - 0x4080 + 0x20) as u8,
+            _ => return
+        };
+        self.wait_until(cpu_cycle);
+        self.commands.extend_from_slice(&[NES_APU_WRITE, register, data]);
+    }
+
+    fn log_sunsoft_5b_write(&mut self, cpu_cycle: u64, address: u16, data: u8) {
+        match address {
+            0xC000 ..= 0xDFFF => {
+                self.sunsoft_5b_register = data & 0x0F;
+            },
+            0xE000 ..= 0xFFFF => {
+                self.wait_until(cpu_cycle);
+                self.commands.extend_from_slice(&[AY8910_WRITE, self.sunsoft_5b_register, data]);
+            },
+            _ => {}
+        }
+    }
+
+    fn log_dpcm_block(&mut self, start_address: u16, data: Vec<u8>) {
+        if self.dpcm_blocks.get(&start_address) == Some(&data) {
+            return;
+        }
+        // The block's size includes its two byte load address
+        let size = data.len() as u32 + 2;
+        self.commands.extend_from_slice(&[DATA_BLOCK, END_OF_DATA, NES_DPCM_BLOCK]);
+        self.commands.extend_from_slice(&size.to_le_bytes());
+        self.commands.extend_from_slice(&start_address.to_le_bytes());
+        self.commands.extend_from_slice(&data);
+        self.dpcm_blocks.insert(start_address, data);
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let mut header = vec![0u8; HEADER_SIZE];
+        let mut put_u32 = |offset: usize, value: u32| {
+            header[offset .. offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        // Relative to the field's own offset
+        let eof_offset = (HEADER_SIZE + self.commands.len() + 1 - 0x04) as u32;
+        put_u32(0x04, eof_offset);
+        put_u32(0x08, 0x171);
+        put_u32(0x18, self.samples_logged as u32);
+        put_u32(0x24, 60);
+        put_u32(0x34, (HEADER_SIZE - 0x34) as u32);
+        // The top bit of the clock adds FDS audio
+        let fds_flag = if self.fds {0x8000_0000} else {0};
+        put_u32(0x84, self.cpu_clock_rate as u32 | fds_flag);
+        if self.sunsoft_5b {
+            // A YM2149 with its clock divider on, which runs at the 5B's CPU clock / 2
+            put_u32(0x74, self.cpu_clock_rate as u32);
+            header[0x78] = 0x10;
+            header[0x79] = 0x11;
+        }
+        header[0x00 .. 0x04].copy_from_slice(b"Vgm ");
+        return header;
+    }
+
+    // Waits out the rest of the log and writes the file
+    pub fn finish(&mut self, cpu_cycle: u64) -> io::Result<()> {
+        self.wait_until(cpu_cycle);
+        let mut file = File::create(&self.filename)?;
+        file.write_all(&self.header())?;
+        file.write_all(&self.commands)?;
+        file.write_all(&[END_OF_DATA])?;
+        return file.flush();
+    }
+}
+
+// Called for every CPU write, before the APU or mapper have acted on it
+pub fn snoop_write(nes: &mut NesState, address: u16, data: u8) {
+    let cpu_cycle = nes.apu.current_cycle;
+    match address {
+        0x4000 ..= 0x4013 | 0x4015 | 0x4017 => {
+            if address == 0x4015 && (data & 0x10) != 0 {
+                // The DMC is (re)starting; make sure the player has its sample
+                let start_address = nes.apu.dmc.starting_address;
+                let length = nes.apu.dmc.sample_length;
+                let mut sample = Vec::with_capacity(length as usize);
+                for i in 0 .. length {
+                    // Addresses wrap from $FFFF around to $8000, as the DMC's do
+                    let sample_address = 0x8000 | (start_address.wrapping_add(i) & 0x7FFF);
+                    sample.push(nes.mapper.debug_read_cpu(sample_address).unwrap_or(0));
+                }
+                if let Some(logger) = nes.vgm_logger.as_mut() {
+                    let wrapped_length = (0x10000 - start_address as u32).min(length as u32) as usize;
+                    let wrapped = sample.split_off(wrapped_length);
+                    logger.log_dpcm_block(start_address, sample);
+                    if !wrapped.is_empty() {
+                        logger.log_dpcm_block(0x8000, wrapped);
+                    }
+                }
+            }
+            if let Some(logger) = nes.vgm_logger.as_mut() {
+                logger.log_apu_write(cpu_cycle, address, data);
+            }
+        },
+        0x4040 ..= 0x408A => {
+            if let Some(logger) = nes.vgm_logger.as_mut() {
+                if logger.fds {
+                    logger.log_fds_write(cpu_cycle, address, data);
+                }
+            }
+        },
+        0xC000 ..= 0xFFFF => {
+            if let Some(logger) = nes.vgm_logger.as_mut() {
+                if logger.sunsoft_5b {
+                    logger.log_sunsoft_5b_write(cpu_cycle, address, data);
+                }
+            }
+        },
+        _ => {}
+    }
+}
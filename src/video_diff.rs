@@ -0,0 +1,66 @@
+// Frame to frame diffs of video memory, for working out which routine updates what.
+// Capture a snapshot at the end of one frame (a frame hook is a good place), another
+// at the end of the next, and diff them: every run of bytes that changed in the
+// nametables, the palette or OAM comes back as one range.
+
+use crate::memory::AddressSpace;
+use crate::nes::NesState;
+
+// Nametables as the PPU sees them at $2000 - $2FFF, so mirroring and any cartridge
+// nametable RAM are accounted for
+const NAMETABLE_START: usize = 0x2000;
+const NAMETABLE_LENGTH: usize = 0x1000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ChangedRange {
+    // AddressSpace::Ppu for the nametables, or Palette, or Oam
+    pub space: AddressSpace,
+    pub start: usize,
+    pub length: usize,
+}
+
+#[derive(Clone)]
+pub struct VideoSnapshot {
+    pub frame: u32,
+    pub nametables: Vec<u8>,
+    pub palette: Vec<u8>,
+    pub oam: Vec<u8>,
+}
+
+impl VideoSnapshot {
+    pub fn capture(nes: &NesState) -> VideoSnapshot {
+        let mut nametables = vec![0u8; NAMETABLE_LENGTH];
+        nes.debug_peek_range(AddressSpace::Ppu, NAMETABLE_START, &mut nametables);
+        return VideoSnapshot {
+            frame: nes.ppu.current_frame,
+            nametables: nametables,
+            palette: nes.ppu.palette.clone(),
+            oam: nes.ppu.oam.clone(),
+        };
+    }
+
+    // Everything that differs in newer, in the order nametables, palette, OAM
+    pub fn diff(&self, newer: &VideoSnapshot) -> Vec<ChangedRange> {
+        let mut ranges = Vec::new();
+        changed_ranges(&mut ranges, AddressSpace::Ppu, NAMETABLE_START, &self.nametables, &newer.nametables);
+        changed_ranges(&mut ranges, AddressSpace::Palette, 0, &self.palette, &newer.palette);
+        changed_ranges(&mut ranges, AddressSpace::Oam, 0, &self.oam, &newer.oam);
+        return ranges;
+    }
+}
+
+fn changed_ranges(ranges: &mut Vec<ChangedRange>, space: AddressSpace, base: usize, old: &[u8], new: &[u8]) {
+    let mut run_start: Option<usize> = None;
+    let length = old.len().min(new.len());
+    for i in 0 ..= length {
+        let changed = i < length && old[i] != new[i];
+        match (changed, run_start) {
+            (true, None) => run_start = Some(i),
+            (false, Some(start)) => {
+                ranges.push(ChangedRange{space: space, start: base + start, length: i - start});
+                run_start = None;
+            },
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,135 @@
+// RAM watch: a list of addresses to show the values of, for a panel alongside the
+// game. The console samples them all at once, at the same point every frame, so a
+// frontend only has to collect one WatchSnapshot per frame rather than read each
+// address itself, and the values are consistent with each other even when the game
+// updates them partway through the frame. See NesState::set_watch_list().
+
+use crate::memory::debug_read_byte;
+use crate::nes::NesState;
+use crate::nes::SignalTrigger;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WatchFormat {
+    Hex8,
+    Unsigned8,
+    Signed8,
+    Binary8,
+    // Little endian, from the address and the one after it
+    Hex16,
+    Unsigned16,
+    Signed16,
+}
+
+impl WatchFormat {
+    pub fn bytes(&self) -> u16 {
+        return match *self {
+            WatchFormat::Hex8 | WatchFormat::Unsigned8 | WatchFormat::Signed8 | WatchFormat::Binary8 => 1,
+            WatchFormat::Hex16 | WatchFormat::Unsigned16 | WatchFormat::Signed16 => 2,
+        };
+    }
+
+    // As an assembler would write it: $1F, 31, -1, %00011111
+    pub fn format(&self, value: u16) -> String {
+        return match *self {
+            WatchFormat::Hex8 => format!("${:02X}", value),
+            WatchFormat::Unsigned8 | WatchFormat::Unsigned16 => format!("{}", value),
+            WatchFormat::Signed8 => format!("{}", value as u8 as i8),
+            WatchFormat::Binary8 => format!("%{:08b}", value),
+            WatchFormat::Hex16 => format!("${:04X}", value),
+            WatchFormat::Signed16 => format!("{}", value as i16),
+        };
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Watch {
+    pub address: u16,
+    pub format: WatchFormat,
+    // Shown instead of whatever NesState::symbols has for the address
+    pub symbol: Option<String>,
+}
+
+impl Watch {
+    pub fn new(address: u16, format: WatchFormat) -> Watch {
+        return Watch {
+            address: address,
+            format: format,
+            symbol: None,
+        };
+    }
+
+    pub fn with_symbol(mut self, symbol: &str) -> Watch {
+        self.symbol = Some(symbol.to_string());
+        return self;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WatchPoint {
+    // As each frame ends, before frame hooks run
+    EndOfFrame,
+    // Whenever this happens, ie the NMI, so that values are read before the game's
+    // vblank handler changes them. A trigger that happens more than once a frame
+    // samples each time; the snapshot has the last.
+    Signal(SignalTrigger),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct WatchValue {
+    pub address: u16,
+    pub symbol: Option<String>,
+    pub value: u16,
+    // value in the watch's format
+    pub text: String,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct WatchSnapshot {
+    // PpuState::current_frame when the values were read
+    pub frame: u32,
+    // In the same order as WatchList::watches
+    pub values: Vec<WatchValue>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct WatchList {
+    pub watches: Vec<Watch>,
+    pub sample_point: WatchPoint,
+}
+
+impl WatchList {
+    pub fn new(sample_point: WatchPoint) -> WatchList {
+        return WatchList {
+            watches: Vec::new(),
+            sample_point: sample_point,
+        };
+    }
+
+    pub fn add(&mut self, watch: Watch) {
+        self.watches.push(watch);
+    }
+
+    // Reads every watch now, without side effects
+    pub fn sample(&self, nes: &NesState) -> WatchSnapshot {
+        let values = self.watches.iter().map(|watch| {
+            let mut value = 0u16;
+            for i in 0 .. watch.format.bytes() {
+                value |= (debug_read_byte(nes, watch.address.wrapping_add(i)) as u16) << (i * 8);
+            }
+            let symbol = match watch.symbol {
+                Some(ref symbol) => Some(symbol.clone()),
+                None => nes.symbols.lookup(nes, watch.address).map(|symbol| symbol.name.clone())
+            };
+            return WatchValue {
+                address: watch.address,
+                symbol: symbol,
+                value: value,
+                text: watch.format.format(value),
+            };
+        }).collect();
+        return WatchSnapshot {
+            frame: nes.ppu.current_frame,
+            values: values,
+        };
+    }
+}